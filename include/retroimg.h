@@ -0,0 +1,151 @@
+/* retroimg C bindings, generated by cbindgen from src/capi.rs */
+
+#ifndef RETROIMG_H
+#define RETROIMG_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The result of a call to the library.
+ */
+typedef enum RetroimgStatus {
+  /**
+   * The call succeeded
+   */
+  RETROIMG_STATUS_OK = 0,
+  /**
+   * A required pointer was null
+   */
+  RETROIMG_STATUS_NULL_POINTER = 1,
+  /**
+   * An option had an invalid value
+   */
+  RETROIMG_STATUS_INVALID_OPTION = 2,
+  /**
+   * The image could not be converted with the given options
+   */
+  RETROIMG_STATUS_CONVERSION_FAILED = 3,
+  /**
+   * The library panicked
+   */
+  RETROIMG_STATUS_PANIC = 4,
+  /**
+   * The image dimensions were too large
+   */
+  RETROIMG_STATUS_TOO_LARGE = 5,
+} RetroimgStatus;
+
+/**
+ * Conversion options.
+ *
+ * Start from [`retroimg_options_default`] to leave the rest as they are.
+ */
+typedef struct RetroimgOptions {
+  /**
+   * Color standard, as a NUL terminated name (e.g. `"cga"`),
+   * or null for the default (`"vga"`)
+   */
+  const char *standard;
+  /**
+   * Width of the internal resolution, or 0 to keep the image size
+   */
+  uint32_t resolution_width;
+  /**
+   * Height of the internal resolution, or 0 to keep the image size
+   */
+  uint32_t resolution_height;
+  /**
+   * Width of the output image, or 0 to keep the internal resolution.
+   * If only one of the output dimensions is given,
+   * the other one follows the proportions of the image
+   * with the pixel ratio of the color standard or video mode
+   */
+  uint32_t output_width;
+  /**
+   * Height of the output image, or 0 to keep the internal resolution
+   * (see `output_width`)
+   */
+  uint32_t output_height;
+  /**
+   * Maximum number of simultaneous colors, or 0 for no limit
+   */
+  uint32_t num_colors;
+  /**
+   * Dithering mode, as a NUL terminated name (e.g. `"bayer4"`),
+   * or null for the default
+   */
+  const char *dither;
+} RetroimgOptions;
+
+/**
+ * A converted image, owned by the library.
+ *
+ * Release it with [`retroimg_image_free`].
+ */
+typedef struct RetroimgImage {
+  /**
+   * RGB pixels, row by row, 3 bytes each
+   */
+  uint8_t *data;
+  /**
+   * Length of `data`, in bytes
+   */
+  size_t len;
+  /**
+   * Image width, in pixels
+   */
+  uint32_t width;
+  /**
+   * Image height, in pixels
+   */
+  uint32_t height;
+} RetroimgImage;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The default conversion options.
+ */
+struct RetroimgOptions retroimg_options_default(void);
+
+/**
+ * Convert an RGB image of `width` by `height` pixels
+ * (3 bytes per pixel, row by row)
+ * and write the result into `out`.
+ *
+ * `options` may be null for the default options.
+ *
+ * # Safety
+ *
+ * `rgb` must point to `width * height * 3` readable bytes,
+ * the strings in `options` must be null or NUL terminated,
+ * and `out` must point to writable memory for a [`RetroimgImage`].
+ */
+enum RetroimgStatus retroimg_convert(const uint8_t *rgb,
+                                     uint32_t width,
+                                     uint32_t height,
+                                     const struct RetroimgOptions *options,
+                                     struct RetroimgImage *out);
+
+/**
+ * Release the pixels of an image converted by the library.
+ * The image is left empty, so freeing it twice is harmless.
+ *
+ * # Safety
+ *
+ * `image` must be null or point to an image
+ * written by [`retroimg_convert`].
+ */
+void retroimg_image_free(struct RetroimgImage *image);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* RETROIMG_H */
@@ -0,0 +1,146 @@
+//! Basic image adjustments,
+//! applied to the reduced image before color conversion.
+//!
+//! Small palettes tend to flatten dull images into a couple of colors,
+//! so punching them up beforehand often pays off.
+use image::{Rgb, RgbImage};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A set of image adjustments.
+///
+/// The default value leaves the image as is.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Adjustments {
+    /// Offset added to all intensities,
+    /// from -1 (all black) to 1 (all white)
+    pub brightness: f32,
+    /// Contrast multiplier around the middle gray
+    /// (1 keeps the original contrast)
+    pub contrast: f32,
+    /// Saturation multiplier (0 turns the image gray,
+    /// 1 keeps the original saturation)
+    pub saturation: f32,
+    /// Gamma exponent (values above 1 brighten the mid tones)
+    pub gamma: f32,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Adjustments {
+            brightness: 0.,
+            contrast: 1.,
+            saturation: 1.,
+            gamma: 1.,
+        }
+    }
+}
+
+impl Adjustments {
+    /// Whether these adjustments leave the image as is.
+    pub fn is_identity(&self) -> bool {
+        *self == Adjustments::default()
+    }
+
+    /// Apply the adjustments to an image,
+    /// in the order of gamma, contrast, brightness and saturation.
+    pub fn apply(&self, image: &mut RgbImage) {
+        if self.is_identity() {
+            return;
+        }
+        if (self.gamma, self.contrast, self.brightness) != (1., 1., 0.) {
+            apply_lut(image, |v| {
+                let v = gamma_curve(v, self.gamma);
+                (v - 0.5) * self.contrast + 0.5 + self.brightness
+            });
+        }
+        if self.saturation != 1. {
+            saturation(image, self.saturation);
+        }
+    }
+}
+
+/// Shift all intensities of an image by `offset`,
+/// from -1 (all black) to 1 (all white).
+pub fn brightness(image: &mut RgbImage, offset: f32) {
+    apply_lut(image, |v| v + offset);
+}
+
+/// Scale the contrast of an image around the middle gray.
+pub fn contrast(image: &mut RgbImage, factor: f32) {
+    apply_lut(image, |v| (v - 0.5) * factor + 0.5);
+}
+
+/// Apply a gamma curve to an image,
+/// where values above 1 brighten the mid tones
+/// and values below 1 darken them.
+pub fn gamma(image: &mut RgbImage, gamma: f32) {
+    apply_lut(image, |v| gamma_curve(v, gamma));
+}
+
+/// Scale the saturation of an image,
+/// mixing each pixel with its luminance.
+pub fn saturation(image: &mut RgbImage, factor: f32) {
+    for Rgb(pixel) in image.pixels_mut() {
+        let [r, g, b] = pixel.map(f32::from);
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        *pixel = [r, g, b].map(|c| (y + (c - y) * factor).round().clamp(0., 255.) as u8);
+    }
+}
+
+fn gamma_curve(v: f32, gamma: f32) -> f32 {
+    if gamma > 0. {
+        v.powf(1. / gamma)
+    } else {
+        v
+    }
+}
+
+/// Map each sample of an image through a curve
+/// over intensities from 0 to 1.
+fn apply_lut(image: &mut RgbImage, curve: impl Fn(f32) -> f32) {
+    let lut: Vec<u8> = (0..=255_u8)
+        .map(|v| (curve(f32::from(v) / 255.) * 255.).round().clamp(0., 255.) as u8)
+        .collect();
+    for sample in image.iter_mut() {
+        *sample = lut[usize::from(*sample)];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Adjustments;
+    use image::RgbImage;
+
+    #[test]
+    fn test_adjustments() {
+        let original = RgbImage::from_raw(2, 1, vec![0, 64, 128, 200, 100, 50]).unwrap();
+
+        let mut image = original.clone();
+        Adjustments::default().apply(&mut image);
+        assert_eq!(image.as_raw(), original.as_raw());
+
+        let mut image = original.clone();
+        Adjustments {
+            contrast: 1.5,
+            ..Default::default()
+        }
+        .apply(&mut image);
+        assert_eq!(image.as_raw(), &[0, 32, 128, 236, 86, 11]);
+
+        let mut image = original;
+        Adjustments {
+            saturation: 0.,
+            ..Default::default()
+        }
+        .apply(&mut image);
+        let raw = image.as_raw();
+        assert!(raw[..3].iter().all(|&c| c == raw[0]));
+        assert!(raw[3..].iter().all(|&c| c == raw[3]));
+    }
+}
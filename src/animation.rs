@@ -0,0 +1,139 @@
+//! Animated images.
+//!
+//! Animated GIF files are decoded into full frames,
+//! which can go through the pipeline one by one
+//! (see [`Pipeline::run_frames`](crate::Pipeline::run_frames))
+//! and be encoded back into an animated GIF file.
+use crate::color::indexed::{EncodeError, GifSnafu, TooLargeSnafu};
+use crate::color::IndexedImage;
+use exoquant::Color;
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, ImageResult, Rgb, RgbImage, RgbaImage};
+use snafu::{OptionExt, ResultExt};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+/// A frame of an animation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The full image of the frame
+    pub image: RgbImage,
+    /// The frame as a palette and an index per pixel,
+    /// such as from [`Pipeline::run_frames_indexed`](crate::Pipeline::run_frames_indexed),
+    /// which is used when the frames do not share a global palette
+    pub indexed: Option<IndexedImage>,
+    /// How long the frame is shown, in milliseconds
+    pub delay: u32,
+}
+
+/// Decode all frames of a GIF file,
+/// each composed over the previous ones,
+/// with their delays in milliseconds.
+pub fn read_gif<R: Read>(reader: R) -> ImageResult<Vec<(RgbaImage, u32)>> {
+    let frames = GifDecoder::new(reader)?.into_frames().collect_frames()?;
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            (frame.into_buffer(), numer / denom.max(1))
+        })
+        .collect())
+}
+
+/// Write the frames as an animated GIF file which loops forever.
+///
+/// All frames must have the same dimensions.
+/// If all frames together have up to 256 colors,
+/// they share a global palette.
+/// Otherwise, each frame gets its own palette,
+/// taken from its indexed image if given,
+/// and frames with more than 256 colors are quantized.
+pub fn write_gif<W: Write>(writer: W, frames: &[Frame]) -> Result<(), EncodeError> {
+    let (width, height) = frames.first().map_or((0, 0), |f| f.image.dimensions());
+    let gif_width = u16::try_from(width).ok().context(TooLargeSnafu)?;
+    let gif_height = u16::try_from(height).ok().context(TooLargeSnafu)?;
+
+    let colors: Vec<_> = frames
+        .iter()
+        .flat_map(|f| f.image.pixels())
+        .map(|&Rgb([r, g, b])| Color { r, g, b, a: 255 })
+        .collect();
+    let global = IndexedImage::from_colors(width, height * frames.len() as u32, &colors);
+    let global_palette = global
+        .as_ref()
+        .map(|indexed| indexed.palette_rgb())
+        .unwrap_or_default();
+
+    let mut encoder =
+        gif::Encoder::new(writer, gif_width, gif_height, &global_palette).context(GifSnafu)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .context(GifSnafu)?;
+    let frame_size = (width * height) as usize;
+    for (i, frame) in frames.iter().enumerate() {
+        let mut gif_frame = match &global {
+            Some(indexed) => gif::Frame {
+                width: gif_width,
+                height: gif_height,
+                buffer: Cow::Borrowed(&indexed.indices[i * frame_size..(i + 1) * frame_size]),
+                ..gif::Frame::default()
+            },
+            None => match &frame.indexed {
+                Some(indexed) => gif::Frame {
+                    width: gif_width,
+                    height: gif_height,
+                    palette: Some(indexed.palette_rgb()),
+                    transparent: indexed.transparent,
+                    buffer: Cow::Borrowed(&indexed.indices),
+                    ..gif::Frame::default()
+                },
+                None => match IndexedImage::from_image(&frame.image) {
+                    Some(indexed) => gif::Frame {
+                        width: gif_width,
+                        height: gif_height,
+                        palette: Some(indexed.palette_rgb()),
+                        buffer: Cow::Owned(indexed.indices),
+                        ..gif::Frame::default()
+                    },
+                    None => {
+                        gif::Frame::from_rgb_speed(gif_width, gif_height, frame.image.as_raw(), 10)
+                    }
+                },
+            },
+        };
+        // GIF delays are in hundredths of a second
+        gif_frame.delay = u16::try_from((frame.delay + 5) / 10).unwrap_or(u16::MAX);
+        encoder.write_frame(&gif_frame).context(GifSnafu)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_gif, write_gif, Frame};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_gif_round_trip() {
+        let frames: Vec<_> = [Rgb([0, 0, 0]), Rgb([0x55, 0xFF, 0xFF])]
+            .iter()
+            .map(|&color| Frame {
+                image: RgbImage::from_fn(4, 2, |x, _| if x < 2 { color } else { Rgb([255; 3]) }),
+                indexed: None,
+                delay: 100,
+            })
+            .collect();
+        let mut data = Vec::new();
+        write_gif(&mut data, &frames).unwrap();
+
+        let decoded = read_gif(data.as_slice()).unwrap();
+        assert_eq!(decoded.len(), 2);
+        for ((image, delay), frame) in decoded.iter().zip(&frames) {
+            assert_eq!(*delay, 100);
+            for (a, b) in image.pixels().zip(frame.image.pixels()) {
+                assert_eq!(a.0[..3], b.0);
+            }
+        }
+    }
+}
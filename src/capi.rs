@@ -0,0 +1,272 @@
+//! C bindings.
+//!
+//! With the `capi` feature,
+//! the dynamic library build
+//! (`cargo rustc --lib --crate-type cdylib`)
+//! exports a small C ABI
+//! for converting RGB images in memory,
+//! declared in `include/retroimg.h`
+//! (regenerated with `cbindgen --config cbindgen.toml -o include/retroimg.h`).
+//!
+//! ```c
+//! RetroimgOptions options = retroimg_options_default();
+//! options.standard = "ega";
+//! options.resolution_width = 320;
+//! options.resolution_height = 200;
+//! RetroimgImage out;
+//! if (retroimg_convert(rgb, width, height, &options, &out) == RETROIMG_STATUS_OK) {
+//!     /* out.data holds out.width * out.height RGB pixels */
+//!     retroimg_image_free(&out);
+//! }
+//! ```
+use crate::color::{ColorStandard, DitherMode};
+use crate::pipeline::Pipeline;
+use image::RgbImage;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic::AssertUnwindSafe;
+
+/// The result of a call to the library.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RetroimgStatus {
+    /// The call succeeded
+    Ok = 0,
+    /// A required pointer was null
+    NullPointer = 1,
+    /// An option had an invalid value
+    InvalidOption = 2,
+    /// The image could not be converted with the given options
+    ConversionFailed = 3,
+    /// The library panicked
+    Panic = 4,
+    /// The image dimensions were too large
+    TooLarge = 5,
+}
+
+/// Conversion options.
+///
+/// Start from [`retroimg_options_default`] to leave the rest as they are.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct RetroimgOptions {
+    /// Color standard, as a NUL terminated name (e.g. `"cga"`),
+    /// or null for the default (`"vga"`)
+    pub standard: *const c_char,
+    /// Width of the internal resolution, or 0 to keep the image size
+    pub resolution_width: u32,
+    /// Height of the internal resolution, or 0 to keep the image size
+    pub resolution_height: u32,
+    /// Width of the output image, or 0 to keep the internal resolution.
+    /// If only one of the output dimensions is given,
+    /// the other one follows the proportions of the image
+    /// with the pixel ratio of the color standard or video mode
+    pub output_width: u32,
+    /// Height of the output image, or 0 to keep the internal resolution
+    /// (see `output_width`)
+    pub output_height: u32,
+    /// Maximum number of simultaneous colors, or 0 for no limit
+    pub num_colors: u32,
+    /// Dithering mode, as a NUL terminated name (e.g. `"bayer4"`),
+    /// or null for the default
+    pub dither: *const c_char,
+}
+
+/// A converted image, owned by the library.
+///
+/// Release it with [`retroimg_image_free`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct RetroimgImage {
+    /// RGB pixels, row by row, 3 bytes each
+    pub data: *mut u8,
+    /// Length of `data`, in bytes
+    pub len: usize,
+    /// Image width, in pixels
+    pub width: u32,
+    /// Image height, in pixels
+    pub height: u32,
+}
+
+/// The default conversion options.
+#[no_mangle]
+pub extern "C" fn retroimg_options_default() -> RetroimgOptions {
+    RetroimgOptions {
+        standard: std::ptr::null(),
+        resolution_width: 0,
+        resolution_height: 0,
+        output_width: 0,
+        output_height: 0,
+        num_colors: 256,
+        dither: std::ptr::null(),
+    }
+}
+
+/// Convert an RGB image of `width` by `height` pixels
+/// (3 bytes per pixel, row by row)
+/// and write the result into `out`.
+///
+/// `options` may be null for the default options.
+///
+/// # Safety
+///
+/// `rgb` must point to `width * height * 3` readable bytes,
+/// the strings in `options` must be null or NUL terminated,
+/// and `out` must point to writable memory for a [`RetroimgImage`].
+#[no_mangle]
+pub unsafe extern "C" fn retroimg_convert(
+    rgb: *const u8,
+    width: u32,
+    height: u32,
+    options: *const RetroimgOptions,
+    out: *mut RetroimgImage,
+) -> RetroimgStatus {
+    if rgb.is_null() || out.is_null() {
+        return RetroimgStatus::NullPointer;
+    }
+    let options = if options.is_null() {
+        retroimg_options_default()
+    } else {
+        *options
+    };
+    let pipeline = match pipeline_from_options(&options) {
+        Some(pipeline) => pipeline,
+        None => return RetroimgStatus::InvalidOption,
+    };
+    let len = match (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|n| n.checked_mul(3))
+    {
+        Some(len) => len,
+        None => return RetroimgStatus::TooLarge,
+    };
+    let data = std::slice::from_raw_parts(rgb, len).to_vec();
+
+    let result = std::panic::catch_unwind(AssertUnwindSafe(move || {
+        let image = RgbImage::from_raw(width, height, data)?;
+        pipeline.run(&image).ok()
+    }));
+    match result {
+        Ok(Some(image)) => {
+            let (width, height) = image.dimensions();
+            let data = image.into_raw().into_boxed_slice();
+            *out = RetroimgImage {
+                len: data.len(),
+                data: Box::into_raw(data) as *mut u8,
+                width,
+                height,
+            };
+            RetroimgStatus::Ok
+        }
+        Ok(None) => RetroimgStatus::ConversionFailed,
+        Err(_) => RetroimgStatus::Panic,
+    }
+}
+
+/// Release the pixels of an image converted by the library.
+/// The image is left empty, so freeing it twice is harmless.
+///
+/// # Safety
+///
+/// `image` must be null or point to an image
+/// written by [`retroimg_convert`].
+#[no_mangle]
+pub unsafe extern "C" fn retroimg_image_free(image: *mut RetroimgImage) {
+    if image.is_null() || (*image).data.is_null() {
+        return;
+    }
+    let image = &mut *image;
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        image.data, image.len,
+    )));
+    image.data = std::ptr::null_mut();
+    image.len = 0;
+}
+
+/// Build a pipeline from the C options,
+/// or `None` if any of them is invalid.
+///
+/// # Safety
+///
+/// The strings in `options` must be null or NUL terminated.
+unsafe fn pipeline_from_options(options: &RetroimgOptions) -> Option<Pipeline> {
+    let name = |s: *const c_char| {
+        if s.is_null() {
+            Some(None)
+        } else {
+            CStr::from_ptr(s).to_str().ok().map(Some)
+        }
+    };
+    let standard = match name(options.standard)? {
+        Some(s) => s.parse::<ColorStandard>().ok()?,
+        None => ColorStandard::default(),
+    };
+    let dither = match name(options.dither)? {
+        Some(s) => s.parse::<DitherMode>().ok()?,
+        None => DitherMode::default(),
+    };
+
+    let mut pipeline = Pipeline::new(standard).with_dither(dither);
+    if options.resolution_width > 0 && options.resolution_height > 0 {
+        pipeline = pipeline.with_resolution(options.resolution_width, options.resolution_height);
+    }
+    pipeline.output.width = Some(options.output_width).filter(|&w| w > 0);
+    pipeline.output.height = Some(options.output_height).filter(|&h| h > 0);
+    if options.num_colors > 0 {
+        pipeline = pipeline.with_num_colors(options.num_colors);
+    } else {
+        pipeline.no_color_limit = true;
+    }
+    Some(pipeline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        retroimg_convert, retroimg_image_free, retroimg_options_default, RetroimgImage,
+        RetroimgStatus,
+    };
+    use std::ffi::CString;
+
+    #[test]
+    fn test_convert() {
+        let rgb: Vec<u8> = (0..16 * 8)
+            .flat_map(|i| [i as u8, 128, 255 - i as u8])
+            .collect();
+        let standard = CString::new("bw").unwrap();
+        let mut options = retroimg_options_default();
+        options.standard = standard.as_ptr();
+        options.output_width = 32;
+        options.output_height = 16;
+        let mut out = RetroimgImage {
+            data: std::ptr::null_mut(),
+            len: 0,
+            width: 0,
+            height: 0,
+        };
+
+        let status = unsafe { retroimg_convert(rgb.as_ptr(), 16, 8, &options, &mut out) };
+        assert_eq!(status, RetroimgStatus::Ok);
+        assert_eq!((out.width, out.height, out.len), (32, 16, 32 * 16 * 3));
+        let data = unsafe { std::slice::from_raw_parts(out.data, out.len) };
+        assert!(data.iter().all(|&v| v == 0 || v == 255));
+        unsafe { retroimg_image_free(&mut out) };
+        assert!(out.data.is_null());
+
+        // the output height follows the output width
+        options.output_height = 0;
+        let status = unsafe { retroimg_convert(rgb.as_ptr(), 16, 8, &options, &mut out) };
+        assert_eq!(status, RetroimgStatus::Ok);
+        assert_eq!((out.width, out.height), (32, 16));
+        unsafe { retroimg_image_free(&mut out) };
+
+        let status =
+            unsafe { retroimg_convert(rgb.as_ptr(), u32::MAX, u32::MAX, &options, &mut out) };
+        assert_eq!(status, RetroimgStatus::TooLarge);
+
+        let bad = CString::new("nope").unwrap();
+        options.standard = bad.as_ptr();
+        let status = unsafe { retroimg_convert(rgb.as_ptr(), 16, 8, &options, &mut out) };
+        assert_eq!(status, RetroimgStatus::InvalidOption);
+    }
+}
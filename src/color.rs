@@ -1,28 +1,83 @@
 //! Color depth manipulation module
-use exoquant::ditherer::FloydSteinberg;
-use exoquant::optimizer::{KMeans, Optimizer};
-use exoquant::{Color, Histogram, Quantizer, Remapper, SimpleColorSpace};
-use image::{ImageBuffer, Rgb, RgbImage};
+use exoquant::Color;
+use image::{GrayImage, ImageBuffer, Rgb, RgbImage};
 use itertools::Itertools;
 use num_integer::Roots;
+use num_rational::Ratio;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::convert::TryFrom;
+use std::io::Read;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::path::Path;
 use std::str::FromStr;
 
+pub mod amiga;
+pub mod appleii;
+pub mod atarist;
+pub mod attribute;
+pub mod c64;
+pub mod cell;
 pub mod cga;
+pub mod colorplus;
+pub mod composite;
+pub mod dither;
 pub mod ega;
+pub mod gameboy;
+pub mod hercules;
+pub mod indexed;
+pub mod lab;
+pub mod linear;
+pub mod lookup;
+pub mod metrics;
+pub mod mono;
+pub mod msx;
+pub mod nes;
+pub mod palette;
+pub mod quantize;
+pub mod strips;
+pub mod swatch;
+pub mod tandy;
+pub mod temporal;
+pub mod vga;
+
+pub use self::attribute::AttributePalette;
+pub use self::cell::CellPalette;
+pub use self::dither::DitherMode;
+pub use self::indexed::IndexedImage;
+use self::lookup::NearestColor;
+use self::palette::{PaletteError, PaletteFormat};
+pub use self::quantize::PaletteBuilderOptions;
+use self::quantize::{build_palette_with_progress, PaletteColorSpace};
+use crate::progress::{self, ProgressHook, Stage};
 
 /// Enumeration of supported color distance algorithms
-/// for loss calculation.
+/// for loss calculation and nearest color selection.
 ///
-/// The use of one algorithm or the other may affect slightly
+/// The use of one algorithm or the other may affect
 /// which palette colors are chosen,
 /// especially in modes such as CGA.
+/// The Delta E algorithms measure perceptual differences
+/// (see the [`lab`] module).
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
 pub enum LossAlgorithm {
     /// L2, Euclidean distance
     #[default]
     L2,
     /// L1, Manhattan distance
     L1,
+    /// CIE76 Delta E, Euclidean distance in CIE L*a*b*
+    DeltaE76,
+    /// CIEDE2000 Delta E
+    DeltaE2000,
 }
 
 impl std::fmt::Display for LossAlgorithm {
@@ -30,6 +85,8 @@ impl std::fmt::Display for LossAlgorithm {
         match self {
             LossAlgorithm::L1 => f.write_str("L1"),
             LossAlgorithm::L2 => f.write_str("L2"),
+            LossAlgorithm::DeltaE76 => f.write_str("DE76"),
+            LossAlgorithm::DeltaE2000 => f.write_str("DE2000"),
         }
     }
 }
@@ -41,7 +98,9 @@ pub struct LossAlgorithmParseError;
 
 impl std::fmt::Display for LossAlgorithmParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("invalid distance/loss algorithm, should be \"L1\" or \"L2\"")
+        f.write_str(
+            "invalid distance/loss algorithm, should be \"L1\", \"L2\", \"DE76\" or \"DE2000\"",
+        )
     }
 }
 
@@ -51,22 +110,65 @@ impl FromStr for LossAlgorithm {
     type Err = LossAlgorithmParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "L1" | "l1" => Ok(LossAlgorithm::L1),
-            "L2" | "l2" => Ok(LossAlgorithm::L2),
+        match s.to_lowercase().as_str() {
+            "l1" => Ok(LossAlgorithm::L1),
+            "l2" => Ok(LossAlgorithm::L2),
+            "de76" | "deltae76" | "cie76" => Ok(LossAlgorithm::DeltaE76),
+            "de2000" | "deltae2000" | "ciede2000" => Ok(LossAlgorithm::DeltaE2000),
             _ => Err(LossAlgorithmParseError),
         }
     }
 }
 
+impl TryFrom<String> for LossAlgorithm {
+    type Error = LossAlgorithmParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<LossAlgorithm> for String {
+    fn from(loss: LossAlgorithm) -> Self {
+        loss.to_string()
+    }
+}
+
 impl LossAlgorithm {
     /// calculate the difference between 2 colors
     /// using the given loss algorithm
+    ///
+    /// Delta E differences are given in hundredths.
     #[inline]
     pub fn color_diff(self, c1: Color, c2: Color) -> u64 {
         match self {
             LossAlgorithm::L1 => color_diff_l1(c1, c2),
             LossAlgorithm::L2 => color_diff_l2(c1, c2),
+            LossAlgorithm::DeltaE76 => {
+                (lab::delta_e76(lab::to_lab(c1), lab::to_lab(c2)) * 100.).round() as u64
+            }
+            LossAlgorithm::DeltaE2000 => {
+                (lab::delta_e2000(lab::to_lab(c1), lab::to_lab(c2)) * 100.).round() as u64
+            }
+        }
+    }
+
+    /// find the color nearest to `pixel` among the given colors
+    /// using the given loss algorithm
+    ///
+    /// Returns `None` if there are no colors.
+    pub fn nearest(self, pixel: Color, colors: &[[u8; 3]]) -> Option<[u8; 3]> {
+        let color = |[r, g, b]: [u8; 3]| Color { r, g, b, a: 255 };
+        match self {
+            // the squared distance is exact, unlike the L2 loss
+            LossAlgorithm::L2 => colors
+                .iter()
+                .copied()
+                .min_by_key(|&c| color_diff_l2_squared(pixel, color(c))),
+            _ => colors
+                .iter()
+                .copied()
+                .min_by_key(|&c| self.color_diff(pixel, color(c))),
         }
     }
 
@@ -78,9 +180,11 @@ impl LossAlgorithm {
     /// Panics if the two slices of colors do not have the same length.
     pub fn image_diff(self, a: &[Color], b: &[Color]) -> u64 {
         assert_eq!(a.len(), b.len());
-        Iterator::zip(a.iter(), b.iter())
-            .map(|(a, b)| self.color_diff(*a, *b))
-            .sum()
+        #[cfg(feature = "rayon")]
+        let pairs = a.par_iter().zip(b);
+        #[cfg(not(feature = "rayon"))]
+        let pairs = a.iter().zip(b);
+        pairs.map(|(a, b)| self.color_diff(*a, *b)).sum()
     }
 }
 
@@ -106,6 +210,11 @@ fn color_diff_l1(c1: Color, c2: Color) -> u64 {
 
 /// calculate the L2 difference between 2 colors
 fn color_diff_l2(c1: Color, c2: Color) -> u64 {
+    color_diff_l2_squared(c1, c2).sqrt()
+}
+
+/// calculate the squared L2 difference between 2 colors
+fn color_diff_l2_squared(c1: Color, c2: Color) -> u64 {
     let Color {
         r: r1,
         g: g1,
@@ -121,14 +230,11 @@ fn color_diff_l2(c1: Color, c2: Color) -> u64 {
     let (r1, r2) = (i64::from(r1), i64::from(r2));
     let (g1, g2) = (i64::from(g1), i64::from(g2));
     let (b1, b2) = (i64::from(b1), i64::from(b2));
-    let dr = (r1 - r2) as u64;
-    let dg = (g1 - g2) as u64;
-    let db = (b1 - b2) as u64;
+    let dr = (r1 - r2).unsigned_abs();
+    let dg = (g1 - g2).unsigned_abs();
+    let db = (b1 - b2).unsigned_abs();
 
-    (dr.saturating_mul(dr)
-        .saturating_add(dg.saturating_mul(dg))
-        .saturating_add(db.saturating_mul(db)))
-    .sqrt()
+    dr * dr + dg * dg + db * db
 }
 
 /// calculate the median RGB color of the given buffer
@@ -146,8 +252,402 @@ fn color_median(colors: &[Color]) -> Color {
     Color { r, g, b, a: 255 }
 }
 
-/// The options for transforming an image to have a different color depth.
+/// The pixels of an image, as opaque colors.
+pub(crate) fn pixels(image: &RgbImage) -> Vec<Color> {
+    image
+        .pixels()
+        .map(|&Rgb([r, g, b])| Color { r, g, b, a: 255 })
+        .collect()
+}
+
+/// Options for the kind of color palette to be simulated.
+/// This doesn't affect the image's resolution.
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum ColorStandard {
+    /// True color 24-bit RGB (8 bits per channel)
+    True24Bit,
+    /// 18-bit RGB (6 bits per channel)
+    #[default]
+    Vga18Bit,
+    /// 16-bit RGB, also called High color (5-6-5 bits per R-G-B channel)
+    Vga16Bit,
+    /// Mode 4 of CGA: 3 colors from hardcoded sub-palettes + 1 back color
+    CgaMode4,
+    /// Mode 4 of CGA, high intensity of sub-palette 1:
+    /// white, cyan, magenta, and one arbitrary back color
+    CgaMode4High1,
+    /// Monochrome, black and white
+    BlackWhite,
+    /// All 16 colors from the CGA palette
+    FullCga,
+    /// All 64 colors from the EGA palette
+    FullEga,
+    /// CGA on a composite monitor, with NTSC artifact colors
+    /// (black and white mode if the image is at least 640 pixels wide,
+    /// mode 4 otherwise)
+    CgaComposite,
+    /// Commodore 64 standard bitmap mode:
+    /// 2 of its 16 colors in each 8x8 cell
+    C64Hires,
+    /// Commodore 64 multicolor bitmap mode:
+    /// 3 of its 16 colors in each 4x8 cell of double width pixels
+    /// plus a shared background color (160x200)
+    C64Multicolor,
+    /// EGA in its 16 color modes: 16 colors at once out of 64
+    Ega16,
+    /// VGA mode 13h with the default 256 color palette of the BIOS
+    Vga256,
+    /// Monochrome phosphor monitor
+    /// (green with 2 intensity levels, unless configured otherwise)
+    Mono,
+    /// Hercules Graphics Card: black and white at 720x348
+    Hercules,
+    /// Game Boy: 4 shades of green at 160x144
+    GameBoy,
+    /// NES (Famicom): the 54 colors of the master palette
+    Nes,
+    /// NES (Famicom) with the sub-palettes of its attribute table:
+    /// 4 sub-palettes of 3 colors plus the backdrop color, one per 16x16 area
+    NesStrict,
+    /// Apple II high resolution graphics: 6 colors at 280x192,
+    /// with the color of each pixel depending on its column and neighbors
+    AppleII,
+    /// Tandy 1000 and PCjr: all 16 CGA colors at 320x200
+    Tandy,
+    /// Tandy 1000 and PCjr: all 16 CGA colors at 160x200
+    TandyLow,
+    /// Plantronics ColorPlus: all 16 CGA colors at 320x200
+    ColorPlus,
+    /// Plantronics ColorPlus: a CGA mode 4 sub-palette at 640x200
+    ColorPlusHigh,
+    /// Amiga OCS: 32 colors at once out of 4096
+    Amiga,
+    /// Amiga OCS Extra Half-Brite:
+    /// 32 colors out of 4096 plus each of them at half brightness
+    AmigaEhb,
+    /// Amiga OCS Hold-And-Modify (HAM6): any of the 4096 colors,
+    /// each pixel taking one of 16 registers
+    /// or changing one channel of the previous pixel
+    AmigaHam,
+    /// Atari ST low resolution: 16 colors at once out of 512 at 320x200
+    AtariSt,
+    /// MSX1 Screen 2: the 15 colors of the TMS9918 at 256x192,
+    /// 2 of them in each segment of 8x1 pixels
+    MsxScreen2,
+}
+
+impl ColorStandard {
+    /// All supported color standards.
+    pub const ALL: [ColorStandard; 28] = [
+        ColorStandard::True24Bit,
+        ColorStandard::Vga18Bit,
+        ColorStandard::Vga16Bit,
+        ColorStandard::CgaMode4,
+        ColorStandard::CgaMode4High1,
+        ColorStandard::BlackWhite,
+        ColorStandard::FullCga,
+        ColorStandard::FullEga,
+        ColorStandard::CgaComposite,
+        ColorStandard::C64Hires,
+        ColorStandard::C64Multicolor,
+        ColorStandard::Ega16,
+        ColorStandard::Vga256,
+        ColorStandard::Mono,
+        ColorStandard::Hercules,
+        ColorStandard::GameBoy,
+        ColorStandard::Nes,
+        ColorStandard::NesStrict,
+        ColorStandard::AppleII,
+        ColorStandard::Tandy,
+        ColorStandard::TandyLow,
+        ColorStandard::ColorPlus,
+        ColorStandard::ColorPlusHigh,
+        ColorStandard::Amiga,
+        ColorStandard::AmigaEhb,
+        ColorStandard::AmigaHam,
+        ColorStandard::AtariSt,
+        ColorStandard::MsxScreen2,
+    ];
+
+    /// Create the color depth converter for this color standard.
+    pub fn color_depth(self) -> Box<dyn ColorDepth> {
+        match self {
+            ColorStandard::True24Bit => Box::new(TrueColor24Bit::default()),
+            ColorStandard::Vga18Bit => Box::new(Vga18Bit::default()),
+            ColorStandard::Vga16Bit => Box::new(Vga16Bit::default()),
+            ColorStandard::FullEga => Box::new(ega::PALETTE_EGA_6BIT),
+            ColorStandard::FullCga => Box::new(cga::PALETTE_CGA_4BIT),
+            ColorStandard::CgaMode4 => Box::new(cga::PALETTE_CGA_MODE4),
+            ColorStandard::CgaMode4High1 => Box::new(cga::PALETTE_CGA_MODE4_1_HIGH),
+            ColorStandard::BlackWhite => Box::new(PALETTE_BW_1BIT),
+            ColorStandard::CgaComposite => Box::new(composite::Composite),
+            ColorStandard::C64Hires => Box::new(c64::PALETTE_C64_HIRES),
+            ColorStandard::C64Multicolor => Box::new(c64::PALETTE_C64_MULTICOLOR),
+            ColorStandard::Ega16 => Box::new(ega::PALETTE_EGA_16),
+            ColorStandard::Vga256 => Box::new(vga::PALETTE_VGA_256),
+            ColorStandard::Mono => Box::new(mono::Monochrome::default()),
+            ColorStandard::Hercules => Box::new(hercules::Hercules),
+            ColorStandard::GameBoy => Box::new(gameboy::PALETTE_DMG),
+            ColorStandard::Nes => Box::new(nes::PALETTE_NES),
+            ColorStandard::NesStrict => Box::new(nes::PALETTE_NES_STRICT),
+            ColorStandard::AppleII => Box::new(appleii::HiRes),
+            ColorStandard::Tandy => Box::new(cga::PALETTE_CGA_4BIT),
+            ColorStandard::TandyLow => Box::new(cga::PALETTE_CGA_4BIT),
+            ColorStandard::ColorPlus => Box::new(cga::PALETTE_CGA_4BIT),
+            ColorStandard::ColorPlusHigh => Box::new(cga::PALETTE_CGA_MODE4),
+            ColorStandard::Amiga => Box::new(amiga::PALETTE_AMIGA_32),
+            ColorStandard::AmigaEhb => Box::new(amiga::ExtraHalfBrite),
+            ColorStandard::AmigaHam => Box::new(amiga::Ham6),
+            ColorStandard::AtariSt => Box::new(atarist::PALETTE_ATARI_ST),
+            ColorStandard::MsxScreen2 => Box::new(msx::Screen2),
+        }
+    }
+
+    /// Obtain the fixed palette of this color standard,
+    /// if it has one.
+    pub fn palette(self) -> Option<&'static [[u8; 3]]> {
+        match self {
+            ColorStandard::FullCga => Some(&cga::CGA_4BIT),
+            ColorStandard::FullEga => Some(ega::EGA_6BIT),
+            ColorStandard::BlackWhite => Some(BW_1BIT),
+            ColorStandard::C64Hires => Some(&c64::C64),
+            ColorStandard::C64Multicolor => Some(&c64::C64),
+            ColorStandard::Ega16 => Some(ega::EGA_6BIT),
+            ColorStandard::Vga256 => Some(vga::VGA_256),
+            ColorStandard::Hercules => Some(BW_1BIT),
+            ColorStandard::GameBoy => Some(&gameboy::DMG),
+            ColorStandard::Nes => Some(&nes::NES),
+            ColorStandard::NesStrict => Some(&nes::NES),
+            ColorStandard::AppleII => Some(&appleii::HGR),
+            ColorStandard::Tandy => Some(&cga::CGA_4BIT),
+            ColorStandard::TandyLow => Some(&cga::CGA_4BIT),
+            ColorStandard::ColorPlus => Some(&cga::CGA_4BIT),
+            ColorStandard::MsxScreen2 => Some(&msx::TMS9918),
+            _ => None,
+        }
+    }
+
+    /// The pixel ratio of the machines with this color standard,
+    /// if it does not depend on the resolution
+    /// (see [`mode_pixel_ratio`](crate::mode_pixel_ratio) otherwise).
+    pub fn pixel_ratio(self) -> Option<Ratio<u32>> {
+        match self {
+            // PAL Commodore 64
+            ColorStandard::C64Hires => Some(Ratio::new(15, 16)),
+            ColorStandard::C64Multicolor => Some(Ratio::new(15, 8)),
+            _ => None,
+        }
+    }
+
+    /// The internal resolution of the video mode of this color standard,
+    /// as `[width, height]`,
+    /// if the standard is tied to a single mode.
+    pub fn resolution(self) -> Option<[u32; 2]> {
+        match self {
+            ColorStandard::Hercules => Some(hercules::RESOLUTION),
+            ColorStandard::GameBoy => Some(gameboy::RESOLUTION),
+            ColorStandard::AppleII => Some(appleii::RESOLUTION),
+            ColorStandard::Tandy => Some(tandy::RESOLUTION_MEDIUM),
+            ColorStandard::TandyLow => Some(tandy::RESOLUTION_LOW),
+            ColorStandard::ColorPlus => Some(colorplus::RESOLUTION_16_COLORS),
+            ColorStandard::ColorPlusHigh => Some(colorplus::RESOLUTION_4_COLORS),
+            ColorStandard::AtariSt => Some(atarist::RESOLUTION_LOW),
+            ColorStandard::MsxScreen2 => Some(msx::RESOLUTION),
+            ColorStandard::C64Hires => Some(c64::RESOLUTION_HIRES),
+            ColorStandard::C64Multicolor => Some(c64::RESOLUTION_MULTICOLOR),
+            ColorStandard::Nes | ColorStandard::NesStrict => Some(nes::RESOLUTION),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ColorStandard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ColorStandard::True24Bit => "true",
+            ColorStandard::Vga18Bit => "vga",
+            ColorStandard::Vga16Bit => "high",
+            ColorStandard::CgaMode4 => "cga",
+            ColorStandard::CgaMode4High1 => "cgamode4high1",
+            ColorStandard::BlackWhite => "bw",
+            ColorStandard::FullCga => "fullcga",
+            ColorStandard::FullEga => "ega",
+            ColorStandard::CgaComposite => "composite",
+            ColorStandard::C64Hires => "c64",
+            ColorStandard::C64Multicolor => "c64multi",
+            ColorStandard::Ega16 => "ega16",
+            ColorStandard::Vga256 => "vga256",
+            ColorStandard::Mono => "mono",
+            ColorStandard::Hercules => "hercules",
+            ColorStandard::GameBoy => "gameboy",
+            ColorStandard::Nes => "nes",
+            ColorStandard::NesStrict => "nes-strict",
+            ColorStandard::AppleII => "appleii",
+            ColorStandard::Tandy => "tandy",
+            ColorStandard::TandyLow => "tandy160",
+            ColorStandard::ColorPlus => "colorplus",
+            ColorStandard::ColorPlusHigh => "colorplus640",
+            ColorStandard::Amiga => "amiga",
+            ColorStandard::AmigaEhb => "ehb",
+            ColorStandard::AmigaHam => "ham6",
+            ColorStandard::AtariSt => "atarist",
+            ColorStandard::MsxScreen2 => "msx2col",
+        })
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`ColorStandard`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ColorStandardParseError;
+
+impl std::fmt::Display for ColorStandardParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("no such color standard")
+    }
+}
+
+impl std::error::Error for ColorStandardParseError {}
+
+impl FromStr for ColorStandard {
+    type Err = ColorStandardParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "true" | "24bit" => Ok(ColorStandard::True24Bit),
+            "vga" | "18bit" => Ok(ColorStandard::Vga18Bit),
+            "high" | "16bit" => Ok(ColorStandard::Vga16Bit),
+            "cga" | "cgamode4" => Ok(ColorStandard::CgaMode4),
+            "cgamode4high1" => Ok(ColorStandard::CgaMode4High1),
+            "fullcga" => Ok(ColorStandard::FullCga),
+            "ega" => Ok(ColorStandard::FullEga),
+            "bw" => Ok(ColorStandard::BlackWhite),
+            "composite" | "cgacomposite" => Ok(ColorStandard::CgaComposite),
+            "c64" | "c64hires" => Ok(ColorStandard::C64Hires),
+            "c64multi" | "c64multicolor" => Ok(ColorStandard::C64Multicolor),
+            "ega16" => Ok(ColorStandard::Ega16),
+            "vga256" | "mode13h" => Ok(ColorStandard::Vga256),
+            "mono" | "monochrome" => Ok(ColorStandard::Mono),
+            "hercules" | "hgc" => Ok(ColorStandard::Hercules),
+            "gameboy" | "gb" | "dmg" => Ok(ColorStandard::GameBoy),
+            "nes" | "famicom" => Ok(ColorStandard::Nes),
+            "nes-strict" | "nesstrict" => Ok(ColorStandard::NesStrict),
+            "appleii" | "apple2" | "hgr" => Ok(ColorStandard::AppleII),
+            "tandy" | "pcjr" => Ok(ColorStandard::Tandy),
+            "tandy160" | "pcjr160" => Ok(ColorStandard::TandyLow),
+            "colorplus" | "plantronics" => Ok(ColorStandard::ColorPlus),
+            "colorplus640" | "plantronics640" => Ok(ColorStandard::ColorPlusHigh),
+            "amiga" | "ocs" => Ok(ColorStandard::Amiga),
+            "ehb" | "amiga-ehb" | "halfbrite" => Ok(ColorStandard::AmigaEhb),
+            "ham6" | "ham" | "amiga-ham" => Ok(ColorStandard::AmigaHam),
+            "atarist" | "st" => Ok(ColorStandard::AtariSt),
+            "msx2col" | "msx" | "msx1" | "tms9918" => Ok(ColorStandard::MsxScreen2),
+            _ => Err(ColorStandardParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for ColorStandard {
+    type Error = ColorStandardParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ColorStandard> for String {
+    fn from(standard: ColorStandard) -> Self {
+        standard.to_string()
+    }
+}
+
+/// Enumeration of ways to choose the free background color
+/// of color depths such as CGA mode 4.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum BackgroundStrategy {
+    /// The median color of the image
+    #[default]
+    Median,
+    /// The background color which most pixels are closest to
+    MostFrequent,
+    /// Try every background color, keeping the one with the lowest loss
+    /// (slow)
+    Search,
+    /// The background color closest to the given RGB color
+    Color([u8; 3]),
+}
+
+impl std::fmt::Display for BackgroundStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackgroundStrategy::Median => f.write_str("auto"),
+            BackgroundStrategy::MostFrequent => f.write_str("frequent"),
+            BackgroundStrategy::Search => f.write_str("search"),
+            BackgroundStrategy::Color([r, g, b]) => write!(f, "{:02x}{:02x}{:02x}", r, g, b),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`BackgroundStrategy`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct BackgroundStrategyParseError;
+
+impl std::fmt::Display for BackgroundStrategyParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            "invalid background strategy, should be \"auto\", \"frequent\", \"search\" or a color (RRGGBB)",
+        )
+    }
+}
+
+impl std::error::Error for BackgroundStrategyParseError {}
+
+impl FromStr for BackgroundStrategy {
+    type Err = BackgroundStrategyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" | "median" => Ok(BackgroundStrategy::Median),
+            "frequent" | "most-frequent" => Ok(BackgroundStrategy::MostFrequent),
+            "search" => Ok(BackgroundStrategy::Search),
+            s => palette::parse_hex_color(s)
+                .map(BackgroundStrategy::Color)
+                .ok_or(BackgroundStrategyParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for BackgroundStrategy {
+    type Error = BackgroundStrategyParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<BackgroundStrategy> for String {
+    fn from(strategy: BackgroundStrategy) -> Self {
+        strategy.to_string()
+    }
+}
+
+/// The options for transforming an image to have a different color depth.
+///
+/// Since it holds the protected colors and per pixel data,
+/// this type is not `Copy`:
+/// clone it to pass the same options to several conversions.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub struct ColorOptions {
     /// The maximum number of colors to admit.
     /// `None` means no limit
@@ -157,24 +657,156 @@ pub struct ColorOptions {
     ///
     /// The default is L2.
     pub loss: LossAlgorithm,
+
+    /// RGB colors which must survive the conversion.
+    ///
+    /// These are always part of the optimized palette
+    /// (taking up part of the `num_colors` budget)
+    /// and pixels of these exact colors are kept as is.
+    /// Color depths with a fixed hardware palette
+    /// only keep the protected colors which are in that palette.
+    pub protected_colors: Vec<[u8; 3]>,
+
+    /// The importance of each pixel of the image
+    /// when optimizing the palette,
+    /// so that more palette entries are spent on the important regions.
+    ///
+    /// Only used if it has the same dimensions as the image.
+    pub importance: Option<GrayImage>,
+
+    /// The alpha channel of the image.
+    ///
+    /// Fully transparent pixels (alpha 0) are left out
+    /// of the palette optimization and of the loss,
+    /// since they will not be seen.
+    /// Only used if it has the same dimensions as the image.
+    pub alpha: Option<GrayImage>,
+
+    /// The dithering mode used when mapping the image to the palette.
+    pub dither: DitherMode,
+
+    /// The seed for the random number generator
+    /// of the stochastic processes (such as random dithering).
+    /// `None` uses a fixed default seed for those.
+    ///
+    /// A seed also makes palette optimization deterministic
+    /// (see [`PaletteBuilderOptions::seed`]),
+    /// for conversions which are reproducible from one run to the next.
+    pub seed: Option<u64>,
+
+    /// Optimize the palette, dither and measure the loss
+    /// in linear light rather than on sRGB encoded samples,
+    /// so that dithered gradients keep their brightness.
+    ///
+    /// The Delta E loss algorithms are not affected,
+    /// as they already work in a perceptual color space.
+    pub linear: bool,
+
+    /// The options for optimizing the palette,
+    /// which trade quality for speed.
+    ///
+    /// The palette is always built in linear light if `linear` is set.
+    pub palette_builder: PaletteBuilderOptions,
+
+    /// How to choose the free background color
+    /// of color depths which have one (such as CGA mode 4).
+    pub background: BackgroundStrategy,
+
+    /// A callback receiving the progress of the conversion.
+    pub progress: Option<ProgressHook>,
+}
+
+impl ColorOptions {
+    /// Calculate the loss between the original and the converted pixels
+    /// with the loss algorithm of these options,
+    /// in linear light if `linear` is set.
+    pub fn image_loss(&self, original: &[Color], converted: &[Color]) -> u64 {
+        let visible;
+        let (original, converted) = match self.visible_indices(original.len()) {
+            Some(indices) => {
+                let pick = |colors: &[Color]| indices.iter().map(|&i| colors[i]).collect_vec();
+                visible = (pick(original), pick(converted));
+                (&visible.0[..], &visible.1[..])
+            }
+            None => (original, converted),
+        };
+        match self.loss {
+            LossAlgorithm::L1 | LossAlgorithm::L2 if self.linear => {
+                let diff = |(a, b): (&Color, &Color)| {
+                    let (a, b) = (linear::to_linear(*a), linear::to_linear(*b));
+                    let d = a.iter().zip(&b).map(|(x, y)| (x - y).abs());
+                    let diff = if self.loss == LossAlgorithm::L1 {
+                        d.sum::<f32>()
+                    } else {
+                        d.map(|d| d * d).sum::<f32>().sqrt()
+                    };
+                    diff.round() as u64
+                };
+                #[cfg(feature = "rayon")]
+                let pairs = original.par_iter().zip(converted);
+                #[cfg(not(feature = "rayon"))]
+                let pairs = original.iter().zip(converted);
+                pairs.map(diff).sum()
+            }
+            _ => self.loss.image_diff(original, converted),
+        }
+    }
+
+    /// Leave out the protected colors which are not in `palette`,
+    /// since a fixed hardware palette cannot show them.
+    fn keep_protected_colors_in(&mut self, palette: &[[u8; 3]]) {
+        self.protected_colors.retain(|c| palette.contains(c));
+    }
+
+    /// The indices of the pixels which are not fully transparent,
+    /// or `None` if either all or none of the `len` pixels are visible.
+    fn visible_indices(&self, len: usize) -> Option<Vec<usize>> {
+        let alpha = self.alpha.as_ref().filter(|alpha| alpha.len() == len)?;
+        let indices: Vec<_> = (0..len).filter(|&i| alpha.as_raw()[i] > 0).collect();
+        Some(indices).filter(|indices| !indices.is_empty() && indices.len() < len)
+    }
 }
 
 /// Color depth image converter.
 pub trait ColorDepth {
-    /// Convert and retrieve the loss from converting an image.
-    fn convert_image_with_loss(&self, image: &RgbImage, options: ColorOptions)
-        -> (Vec<Color>, u64);
+    /// Convert an image, retrieving the converted pixels,
+    /// the palette they were mapped to, and the loss from the conversion.
+    ///
+    /// The palette is the final one,
+    /// already reduced to this color depth.
+    /// It is empty if the number of colors was not limited.
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64);
 
     /// Convert an RGB image to this color depth.
     fn convert_image(&self, image: &RgbImage, options: ColorOptions) -> Vec<Color> {
         self.convert_image_with_loss(image, options).0
     }
 
+    /// Convert an RGB image to this color depth,
+    /// keeping the result as a palette and an index per pixel,
+    /// along with the loss from the conversion.
+    ///
+    /// The palette starts with the one
+    /// from [`convert_image_with_loss`](ColorDepth::convert_image_with_loss),
+    /// unused colors included.
+    /// Returns `None` if the converted image has more than 256 colors.
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        index_conversion(image, self.convert_image_with_loss(image, options))
+    }
+
     /// Estimate the loss obtained from converting an image.
     /// For the best results, greater discrepancies should result in higher
     /// loss values.
     fn loss(&self, image: &RgbImage, options: ColorOptions) -> u64 {
-        self.convert_image_with_loss(image, options).1
+        self.convert_image_with_loss(image, options).2
     }
 }
 
@@ -183,7 +815,7 @@ impl<'a, T: ColorDepth> ColorDepth for &'a T {
         &self,
         image: &RgbImage,
         options: ColorOptions,
-    ) -> (Vec<Color>, u64) {
+    ) -> (Vec<Color>, Vec<Color>, u64) {
         (**self).convert_image_with_loss(image, options)
     }
 
@@ -192,6 +824,14 @@ impl<'a, T: ColorDepth> ColorDepth for &'a T {
         (**self).convert_image(image, options)
     }
 
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        (**self).convert_image_indexed(image, options)
+    }
+
     /// Estimate the loss obtained from converting an image.
     /// For the best results, greater discrepancies should result in higher
     /// loss values.
@@ -200,6 +840,68 @@ impl<'a, T: ColorDepth> ColorDepth for &'a T {
     }
 }
 
+/// Index the pixels of a conversion by its palette.
+fn index_conversion(
+    image: &RgbImage,
+    (pixels, palette, loss): (Vec<Color>, Vec<Color>, u64),
+) -> Option<(IndexedImage, u64)> {
+    let (width, height) = image.dimensions();
+    let indexed = IndexedImage::from_palette(width, height, &pixels, &palette)
+        .or_else(|| IndexedImage::from_colors(width, height, &pixels))?;
+    Some((indexed, loss))
+}
+
+/// Build the indexed image of a conversion
+/// from the palette index of each pixel,
+/// putting back the protected colors
+/// in the pixels which had them in the original image,
+/// along with the loss from the conversion.
+///
+/// Returns `None` if the palette, with the protected colors,
+/// has more than 256 colors.
+fn indexed_with_loss(
+    image: &RgbImage,
+    original: &[Color],
+    mut palette: Vec<Color>,
+    mut indices: Vec<usize>,
+    options: &ColorOptions,
+) -> Option<(IndexedImage, u64)> {
+    for &[r, g, b] in &options.protected_colors {
+        let is_protected = |c: &Color| [c.r, c.g, c.b] == [r, g, b];
+        if !original.iter().any(is_protected) {
+            continue;
+        }
+        let index = match palette.iter().position(is_protected) {
+            Some(index) => index,
+            None => {
+                palette.push(Color { r, g, b, a: 255 });
+                palette.len() - 1
+            }
+        };
+        for (o, i) in original.iter().zip(&mut indices) {
+            if is_protected(o) {
+                *i = index;
+            }
+        }
+    }
+    let converted = indices.iter().map(|&i| palette[i]).collect_vec();
+    let loss = options.image_loss(original, &converted);
+    let indices = indices
+        .into_iter()
+        .map(u8::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let (width, height) = image.dimensions();
+    let indexed = IndexedImage {
+        width,
+        height,
+        palette,
+        indices,
+        transparent: None,
+    };
+    Some((indexed, loss))
+}
+
 /// Trait for anything which maps one color to another.
 pub trait ColorMapper {
     /// Convert a single color
@@ -231,15 +933,29 @@ where
     }
 }
 
+impl<M> MappingColorDepth<M>
+where
+    M: ColorMapper + Sync,
+{
+    /// Map each of the given colors to this color depth.
+    fn map_colors(&self, original: &[Color]) -> Vec<Color> {
+        #[cfg(feature = "rayon")]
+        let pixels = original.par_iter();
+        #[cfg(not(feature = "rayon"))]
+        let pixels = original.iter();
+        pixels.map(|&c| self.0.convert_color(c)).collect()
+    }
+}
+
 impl<M> ColorDepth for MappingColorDepth<M>
 where
-    M: ColorMapper,
+    M: ColorMapper + Sync,
 {
     fn convert_image_with_loss(
         &self,
         image: &RgbImage,
         options: ColorOptions,
-    ) -> (Vec<Color>, u64) {
+    ) -> (Vec<Color>, Vec<Color>, u64) {
         let original = image
             .pixels()
             .map(|&p| {
@@ -247,36 +963,53 @@ where
                 Color { r, g, b, a: 255 }
             })
             .collect_vec();
-        let pixels = image
+        let pixels = self.map_colors(&original);
+
+        // optimize palette and dither
+        let (mut converted_pixels, mut palette) = if let Some(num_colors) = options.num_colors {
+            // optimize the palette and reduce its color depth
+            let (palette, indexed_data) =
+                remap_optimized(&pixels, image.width(), num_colors, &options, |c| {
+                    self.convert_color(c)
+                });
+            let pixels = indexed_data.into_iter().map(|i| palette[i]).collect_vec();
+            (pixels, palette)
+        } else {
+            (pixels, Vec::new())
+        };
+        restore_protected_colors(
+            &original,
+            &mut converted_pixels,
+            &mut palette,
+            &options.protected_colors,
+        );
+        let loss = options.image_loss(&original, &converted_pixels);
+        (converted_pixels, palette, loss)
+    }
+
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        let num_colors = match options.num_colors {
+            Some(num_colors) => num_colors,
+            // without a palette, the colors are only known after mapping
+            None => return index_conversion(image, self.convert_image_with_loss(image, options)),
+        };
+        let original = image
             .pixels()
             .map(|&p| {
                 let Rgb([r, g, b]) = p;
-                self.0.convert_color(Color { r, g, b, a: 255 })
+                Color { r, g, b, a: 255 }
             })
             .collect_vec();
-
-        // optimize palette and dither
-        let converted_pixels = if let Some(num_colors) = options.num_colors {
-            let mut palette = build_palette(&pixels, num_colors);
-
-            // reduce palette's color depth
-            for c in &mut palette {
-                *c = self.convert_color(*c);
-            }
-
-            let colorspace = SimpleColorSpace::default();
-            let ditherer = FloydSteinberg::new();
-            let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-            let indexed_data = remapper.remap(&pixels, image.width() as usize);
-            indexed_data
-                .into_iter()
-                .map(|i| palette[i as usize])
-                .collect_vec()
-        } else {
-            pixels
-        };
-        let loss = options.loss.image_diff(&original, &converted_pixels);
-        (converted_pixels, loss)
+        let pixels = self.map_colors(&original);
+        let (palette, indices) =
+            remap_optimized(&pixels, image.width(), num_colors, &options, |c| {
+                self.convert_color(c)
+            });
+        indexed_with_loss(image, &original, palette, indices, &options)
     }
 }
 
@@ -352,35 +1085,101 @@ impl Vga16Bit {
     }
 }
 
+/// A color mapper that reduces sample precision
+/// to the same number of bits for all channels (from 1 to 8),
+/// the master color space of machines
+/// with programmable color registers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ChannelBitsMapper(u8);
+
+impl ChannelBitsMapper {
+    /// Create a color mapper to `bits` bits per sample,
+    /// clamped to the range from 1 to 8.
+    pub const fn new(bits: u8) -> Self {
+        ChannelBitsMapper(bits)
+    }
+}
+
+impl ColorMapper for ChannelBitsMapper {
+    fn convert_color(&self, pixel: Color) -> Color {
+        let bits = u32::from(self.0.clamp(1, 8));
+        let reduce = |v: u8| {
+            // repeat the remaining bits all the way down,
+            // so that the highest level is still 255
+            let level = u32::from(v) >> (8 - bits);
+            let (mut out, mut filled) = (0, 0);
+            while filled < 8 {
+                out = out << bits | level;
+                filled += bits;
+            }
+            (out >> (filled - 8)) as u8
+        };
+        let Color { r, g, b, a } = pixel;
+        Color {
+            r: reduce(r),
+            g: reduce(g),
+            b: reduce(b),
+            a,
+        }
+    }
+}
+
+/// Color depth of a number of programmable color registers,
+/// each taking any color of a master space
+/// with the same number of bits per sample
+/// (see [`RegisterPalette::with_bits`]).
+///
+/// The palette is optimized for the image
+/// and then snapped to the master space.
+pub type RegisterPalette = ColorLimit<MappingColorDepth<ChannelBitsMapper>>;
+
+impl RegisterPalette {
+    /// Create a color depth of up to `max_colors` colors at once,
+    /// out of the master space of `bits` bits per sample.
+    pub const fn with_bits(bits: u8, max_colors: u32) -> Self {
+        ColorLimit(MappingColorDepth(ChannelBitsMapper(bits)), max_colors)
+    }
+}
+
 /// Color depth defined by a hardware-level palette of RGB colors.
 #[derive(Debug, Copy, Clone)]
 pub struct FixedPalette<T>(T);
 
+impl<T> FixedPalette<T> {
+    /// Create a color depth from the given palette of RGB colors.
+    pub const fn new(colors: T) -> Self {
+        FixedPalette(colors)
+    }
+}
+
+impl FixedPalette<Vec<[u8; 3]>> {
+    /// Load a palette file
+    /// in any of the formats supported by the [`palette`] module,
+    /// identifying the format by its extension.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, PaletteError> {
+        palette::load_palette(path).map(FixedPalette)
+    }
+
+    /// Read a palette file in the given format.
+    pub fn from_reader<R: Read>(
+        mut reader: R,
+        format: PaletteFormat,
+    ) -> Result<Self, PaletteError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).context(palette::IoSnafu)?;
+        palette::parse_palette(&data, format).map(FixedPalette)
+    }
+}
+
 impl<T> FixedPalette<T>
 where
     T: AsRef<[[u8; 3]]>,
 {
-    fn convert_color(&self, pixel: Color) -> Color {
-        let Color {
-            r: sr,
-            g: sg,
-            b: sb,
-            a: _,
-        } = pixel;
-        let (sr, sg, sb) = (i32::from(sr), i32::from(sg), i32::from(sb));
-        let [r, g, b] = *self
-            .0
-            .as_ref()
-            .iter()
-            .min_by_key(|[pr, pg, pb]| {
-                let (pr, pg, pb) = (i32::from(*pr), i32::from(*pg), i32::from(*pb));
-                let rd = sr - pr;
-                let rg = sg - pg;
-                let rb = sb - pb;
-                rd * rd + rg * rg + rb * rb
-            })
-            .unwrap();
-        Color { r, g, b, a: 255 }
+    /// Prepare the palette for mapping colors to it,
+    /// once per conversion.
+    fn lookup(&self, loss: LossAlgorithm) -> NearestColor<'_> {
+        NearestColor::new(self.0.as_ref(), loss)
     }
 }
 
@@ -391,8 +1190,9 @@ where
     fn convert_image_with_loss(
         &self,
         image: &RgbImage,
-        options: ColorOptions,
-    ) -> (Vec<Color>, u64) {
+        mut options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        options.keep_protected_colors_in(self.0.as_ref());
         let original = image
             .pixels()
             .map(|&p| {
@@ -402,49 +1202,160 @@ where
             .collect_vec();
 
         // optimize palette and dither
-        let converted_pixels = if let Some(num_colors) = options.num_colors {
-            let mut palette = build_palette(&original, num_colors);
-
-            // reduce palette's color depth
-            for c in &mut palette {
-                *c = self.convert_color(*c);
-            }
-
-            let colorspace = SimpleColorSpace::default();
-            let ditherer = FloydSteinberg::new();
-            let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-            let indexed_data = remapper.remap(&original, image.width() as usize);
-            indexed_data
-                .into_iter()
-                .map(|i| palette[i as usize])
-                .collect_vec()
+        let (mut converted_pixels, mut palette) = if let Some(num_colors) = options.num_colors {
+            // optimize the palette and reduce its color depth
+            let lookup = self.lookup(options.loss);
+            let (palette, indexed_data) =
+                remap_optimized(&original, image.width(), num_colors, &options, |c| {
+                    lookup.convert_color(c)
+                });
+            let pixels = indexed_data.into_iter().map(|i| palette[i]).collect_vec();
+            (pixels, palette)
         } else {
-            original.clone()
+            (original.clone(), Vec::new())
+        };
+        restore_protected_colors(
+            &original,
+            &mut converted_pixels,
+            &mut palette,
+            &options.protected_colors,
+        );
+        let loss = options.image_loss(&original, &converted_pixels);
+        (converted_pixels, palette, loss)
+    }
+
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        options.keep_protected_colors_in(self.0.as_ref());
+        let num_colors = match options.num_colors {
+            Some(num_colors) => num_colors,
+            None => return index_conversion(image, self.convert_image_with_loss(image, options)),
         };
-        let loss = options.loss.image_diff(&original, &converted_pixels);
-        (converted_pixels, loss)
-    }
-}
-
-fn build_palette(pixels: &[Color], num_colors: u32) -> Vec<Color> {
-    // optimize palette and dither
-    let mut histogram = Histogram::new();
-    histogram.extend(pixels.iter().cloned());
-    let colorspace = SimpleColorSpace::default();
-    let optimizer = KMeans;
-    let mut quantizer = Quantizer::new(&histogram, &colorspace);
-    while quantizer.num_colors() < num_colors as usize {
-        quantizer.step();
-        // very optional optimization, !very slow!
-        // you probably only want to do this every N steps, if at all.
-        if quantizer.num_colors() % 256 == 0 {
-            quantizer = quantizer.optimize(&optimizer, 16);
+        let original = image
+            .pixels()
+            .map(|&p| {
+                let Rgb([r, g, b]) = p;
+                Color { r, g, b, a: 255 }
+            })
+            .collect_vec();
+        let lookup = self.lookup(options.loss);
+        let (palette, indices) =
+            remap_optimized(&original, image.width(), num_colors, &options, |c| {
+                lookup.convert_color(c)
+            });
+        indexed_with_loss(image, &original, palette, indices, &options)
+    }
+}
+
+/// Build an optimized palette of up to `num_colors` colors
+/// with [`optimized_palette`] and dither the pixels with it,
+/// returning the palette and the palette index of each pixel.
+fn remap_optimized<M>(
+    pixels: &[Color],
+    width: u32,
+    num_colors: u32,
+    options: &ColorOptions,
+    convert: M,
+) -> (Vec<Color>, Vec<usize>)
+where
+    M: Fn(Color) -> Color,
+{
+    let palette = optimized_palette(pixels, num_colors, options, convert);
+    let indices = dither::remap(pixels, width, &palette, options);
+    (palette, indices)
+}
+
+/// Build an optimized palette of up to `num_colors` colors,
+/// mapped with `convert` to the target color depth,
+/// plus the protected colors (which take part of the budget).
+fn optimized_palette<M>(
+    pixels: &[Color],
+    num_colors: u32,
+    options: &ColorOptions,
+    convert: M,
+) -> Vec<Color>
+where
+    M: Fn(Color) -> Color,
+{
+    let protected_colors = &options.protected_colors;
+    let budget = num_colors.saturating_sub(protected_colors.len() as u32);
+    // transparent pixels do not compete for palette entries
+    let visible = options.visible_indices(pixels.len()).map(|indices| {
+        let importance = options
+            .importance
+            .as_ref()
+            .filter(|importance| importance.len() == pixels.len())
+            .map(|importance| {
+                let values = indices.iter().map(|&i| importance.as_raw()[i]).collect();
+                GrayImage::from_raw(indices.len() as u32, 1, values).unwrap()
+            });
+        (indices.iter().map(|&i| pixels[i]).collect_vec(), importance)
+    });
+    let (pixels, importance) = match &visible {
+        Some((pixels, importance)) => (&pixels[..], importance.as_ref()),
+        None => (pixels, options.importance.as_ref()),
+    };
+    let mut palette = if budget == 0 && !protected_colors.is_empty() {
+        Vec::new()
+    } else {
+        let mut builder = options.palette_builder;
+        if options.linear {
+            builder.colorspace = PaletteColorSpace::Linear;
         }
+        builder.seed = builder.seed.or(options.seed);
+        build_palette_with_progress(
+            pixels,
+            budget,
+            importance,
+            &builder,
+            options.progress.as_ref(),
+        )
+    };
+    for c in &mut palette {
+        *c = convert(*c);
     }
+    palette.extend(
+        protected_colors
+            .iter()
+            .map(|&[r, g, b]| Color { r, g, b, a: 255 }),
+    );
+    palette
+}
 
-    let palette = quantizer.colors(&colorspace);
-    // this optimization is more useful than the above and a lot less slow
-    optimizer.optimize_palette(&colorspace, &palette, &histogram, 8)
+/// Put back the protected colors
+/// in the pixels which had them in the original image,
+/// adding the colors put back to the palette
+/// unless it is empty (as when the number of colors is not limited).
+fn restore_protected_colors(
+    original: &[Color],
+    converted: &mut [Color],
+    palette: &mut Vec<Color>,
+    protected_colors: &[[u8; 3]],
+) {
+    if protected_colors.is_empty() {
+        return;
+    }
+    let mut restored = Vec::new();
+    for (o, c) in original.iter().zip(converted) {
+        let color = [o.r, o.g, o.b];
+        if protected_colors.contains(&color) {
+            *c = *o;
+            if !restored.contains(&color) {
+                restored.push(color);
+            }
+        }
+    }
+    if palette.is_empty() {
+        return;
+    }
+    for [r, g, b] in restored {
+        if !palette.iter().any(|p| [p.r, p.g, p.b] == [r, g, b]) {
+            palette.push(Color { r, g, b, a: 255 });
+        }
+    }
 }
 
 /// Color depth emulating a combination of one freely selectable
@@ -457,38 +1368,27 @@ where
     B: AsRef<[[u8; 3]]>,
     F: AsRef<[[u8; 3]]>,
 {
-    fn convert_color<T>(pixel: Color, palette: T) -> Color
-    where
-        T: AsRef<[[u8; 3]]>,
-    {
-        let Color {
-            r: sr,
-            g: sg,
-            b: sb,
-            a: _,
-        } = pixel;
-        let (sr, sg, sb) = (i32::from(sr), i32::from(sg), i32::from(sb));
-        let [r, g, b] = *palette
-            .as_ref()
-            .iter()
-            .min_by_key(|[pr, pg, pb]| {
-                let (pr, pg, pb) = (i32::from(*pr), i32::from(*pg), i32::from(*pb));
-                let rd = sr - pr;
-                let rg = sg - pg;
-                let rb = sb - pb;
-                rd * rd + rg * rg + rb * rb
-            })
-            .unwrap();
-        Color { r, g, b, a: 255 }
+    /// Build the fixed palette of the foreground colors
+    /// plus the given background color.
+    fn with_color(&self, background: [u8; 3]) -> FixedPalette<Vec<[u8; 3]>> {
+        let mut fixed = self.1.as_ref().to_vec();
+        fixed.push(background);
+        FixedPalette(fixed)
     }
 
-    fn convert_color_back(&self, pixel: Color) -> Color {
-        BackForePalette::<B, F>::convert_color(pixel, &self.0)
+    /// Build the fixed palette of the foreground colors
+    /// plus the background color which fits the image best.
+    fn with_background(
+        &self,
+        image: &RgbImage,
+        options: &ColorOptions,
+    ) -> FixedPalette<Vec<[u8; 3]>> {
+        self.with_color(self.background_color(image, options))
     }
 
     /// Identify the best background color
-    fn background_color(&self, image: &RgbImage) -> Color {
-        // we'll fetch the median color of the image for the time being
+    /// according to the background strategy in the options.
+    fn background_color(&self, image: &RgbImage, options: &ColorOptions) -> [u8; 3] {
         let original = image
             .pixels()
             .map(|&p| {
@@ -496,7 +1396,48 @@ where
                 Color { r, g, b, a: 255 }
             })
             .collect_vec();
-        color_median(&original)
+        let backgrounds = self.0.as_ref();
+        let lookup = NearestColor::new(backgrounds, options.loss);
+        let color = match options.background {
+            BackgroundStrategy::Median => color_median(&original),
+            BackgroundStrategy::MostFrequent => {
+                let mut counts = vec![0_usize; backgrounds.len()];
+                for &c in &original {
+                    if let Some(i) = lookup.nearest_index(c) {
+                        counts[i] += 1;
+                    }
+                }
+                // the first of the most frequent colors
+                let (i, _) = counts
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|&(i, &count)| (count, std::cmp::Reverse(i)))
+                    .unwrap();
+                return backgrounds[i];
+            }
+            BackgroundStrategy::Search => {
+                // the trials count as palette optimization
+                let mut trial_options = options.clone();
+                trial_options.progress = None;
+                let count = backgrounds.len();
+                return *backgrounds
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|&(i, &b)| {
+                        progress::report(
+                            options.progress.as_ref(),
+                            Stage::Palette,
+                            i as f32 / count as f32,
+                        );
+                        self.with_color(b).loss(image, trial_options.clone())
+                    })
+                    .unwrap()
+                    .1;
+            }
+            BackgroundStrategy::Color([r, g, b]) => Color { r, g, b, a: 255 },
+        };
+        let Color { r, g, b, .. } = lookup.convert_color(color);
+        [r, g, b]
     }
 }
 
@@ -509,69 +1450,124 @@ where
         &self,
         image: &RgbImage,
         options: ColorOptions,
-    ) -> (Vec<Color>, u64) {
-        // first try to identify the background color
-        let bkg_color = self.background_color(image);
-        let bkg_color = self.convert_color_back(bkg_color);
-
-        // then build a palette with the extra color
-        let mut fixed = self.1.as_ref().to_vec();
-        fixed.push([bkg_color.r, bkg_color.g, bkg_color.b]);
-        let fixed = FixedPalette(fixed);
-
-        let original = image
-            .pixels()
-            .map(|&p| {
-                let Rgb([r, g, b]) = p;
-                Color { r, g, b, a: 255 }
-            })
-            .collect_vec();
-
-        // optimize palette and dither
-        let converted_pixels = if let Some(num_colors) = options.num_colors {
-            let mut palette = build_palette(&original, num_colors);
-
-            // reduce palette's color depth
-            for c in &mut palette {
-                *c = fixed.convert_color(*c);
-            }
-
-            let colorspace = SimpleColorSpace::default();
-            let ditherer = FloydSteinberg::new();
-            let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-            let indexed_data = remapper.remap(&original, image.width() as usize);
-            indexed_data
-                .into_iter()
-                .map(|i| palette[i as usize])
-                .collect_vec()
-        } else {
-            original.clone()
-        };
-        let loss = options.loss.image_diff(&original, &converted_pixels);
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        self.with_background(image, &options)
+            .convert_image_with_loss(image, options)
+    }
 
-        (converted_pixels, loss)
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        self.with_background(image, &options)
+            .convert_image_indexed(image, options)
     }
 }
 
 /// A collection of palettes, the one yielding the lowest loss is used.
+///
+/// With the `rayon` feature,
+/// the candidate palettes are tried concurrently.
 #[derive(Debug, Copy, Clone)]
 pub struct BestPalette<C>(C);
 
+impl<C, P> BestPalette<C>
+where
+    C: std::ops::Deref<Target = [P]>,
+    P: ColorDepth + Sync,
+{
+    /// Convert an image with each of the candidate palettes,
+    /// retrieving the index of the one yielding the lowest loss
+    /// (the first one in case of a tie)
+    /// along with its converted pixels, palette and loss.
+    pub fn convert_image_best(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> (usize, Vec<Color>, Vec<Color>, u64) {
+        #[cfg(feature = "rayon")]
+        let candidates = self.0.par_iter();
+        #[cfg(not(feature = "rayon"))]
+        let candidates = self.0.iter();
+        let count = self.0.len();
+        let (index, (pixels, palette, loss)) = candidates
+            .enumerate()
+            .map(|(i, cd)| {
+                let mut options = options.clone();
+                options.progress = options.progress.map(|hook| hook.part(i, count));
+                (i, cd.convert_image_with_loss(image, options))
+            })
+            .min_by_key(|(i, (_pixels, _palette, loss))| (*loss, *i))
+            .unwrap();
+        (index, pixels, palette, loss)
+    }
+}
+
 impl<C, P> ColorDepth for BestPalette<C>
 where
     C: std::ops::Deref<Target = [P]>,
-    P: ColorDepth,
+    P: ColorDepth + Sync,
 {
     fn convert_image_with_loss(
         &self,
         image: &RgbImage,
         options: ColorOptions,
-    ) -> (Vec<Color>, u64) {
-        self.0
-            .iter()
-            .map(|cd| cd.convert_image_with_loss(image, options))
-            .min_by_key(|(_pixels, loss)| *loss)
-            .unwrap()
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        let (_index, pixels, palette, loss) = self.convert_image_best(image, options);
+        (pixels, palette, loss)
+    }
+
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        #[cfg(feature = "rayon")]
+        let candidates = self.0.par_iter();
+        #[cfg(not(feature = "rayon"))]
+        let candidates = self.0.iter();
+        candidates
+            .filter_map(|cd| cd.convert_image_indexed(image, options.clone()))
+            .min_by_key(|(_indexed, loss)| *loss)
+    }
+}
+
+/// A color depth restricted to a maximum number of simultaneous colors,
+/// regardless of the `num_colors` option
+/// (which may still lower the limit further).
+#[derive(Debug, Copy, Clone)]
+pub struct ColorLimit<D>(D, u32);
+
+impl<D> ColorLimit<D> {
+    /// Create a color depth admitting up to `max_colors` colors of `depth`.
+    pub const fn new(depth: D, max_colors: u32) -> Self {
+        ColorLimit(depth, max_colors)
+    }
+}
+
+impl<D> ColorDepth for ColorLimit<D>
+where
+    D: ColorDepth,
+{
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        let num_colors = options.num_colors.map_or(self.1, |n| n.min(self.1));
+        options.num_colors = Some(num_colors);
+        self.0.convert_image_with_loss(image, options)
+    }
+
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        let num_colors = options.num_colors.map_or(self.1, |n| n.min(self.1));
+        options.num_colors = Some(num_colors);
+        self.0.convert_image_indexed(image, options)
     }
 }
 
@@ -0,0 +1,424 @@
+//! Amiga Original Chip Set (OCS) modes.
+//!
+//! The OCS takes its colors from a master space of 4096 colors
+//! (4 bits per channel),
+//! with up to 32 color registers in the usual bitplane modes.
+//! Extra Half-Brite (EHB) adds a sixth bitplane
+//! which shows each of the 32 registers at half brightness,
+//! for 64 colors at once.
+//! Hold-And-Modify (HAM6) shows any of the 4096 colors,
+//! but each pixel either takes one of 16 color registers
+//! or copies the previous pixel with one channel modified,
+//! so sharp color changes leave fringes behind.
+use super::{
+    dither, indexed_with_loss, optimized_palette, pixels, restore_protected_colors,
+    ChannelBitsMapper, ColorDepth, ColorMapper, ColorOptions, DitherMode, IndexedImage,
+    RegisterPalette,
+};
+use exoquant::Color;
+use image::{Rgb, RgbImage};
+use itertools::Itertools;
+
+/// The number of color registers in the bitplane modes.
+pub const REGISTERS: u32 = 32;
+
+/// The number of color registers which HAM6 pixels can take.
+pub const HAM_REGISTERS: usize = 16;
+
+/// How many times the Extra Half-Brite registers are refined
+/// after the initial palette.
+const EHB_REFINE_STEPS: usize = 4;
+
+/// The master space of the Amiga:
+/// 4 bits per sample (12 bits per pixel).
+pub const MASTER_SPACE: ChannelBitsMapper = ChannelBitsMapper::new(4);
+
+/// The Amiga in its 32 color modes: 32 colors at once out of 4096.
+pub static PALETTE_AMIGA_32: RegisterPalette = RegisterPalette::with_bits(4, REGISTERS);
+
+/// The Amiga in Extra Half-Brite mode:
+/// 32 color registers out of 4096,
+/// plus each of them at half brightness.
+///
+/// The `num_colors` option limits the number of colors including the halves,
+/// up to 64.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ExtraHalfBrite;
+
+impl ExtraHalfBrite {
+    /// Choose the color registers for the pixels
+    /// and extend them with their halves.
+    fn palette(&self, original: &[Color], options: &ColorOptions) -> Vec<Color> {
+        let registers = options
+            .num_colors
+            .map_or(REGISTERS, |n| (n / 2).clamp(1, REGISTERS));
+        let mut palette = optimized_palette(original, registers, options, |c| {
+            MASTER_SPACE.convert_color(c)
+        });
+        let free = palette.len().saturating_sub(options.protected_colors.len());
+
+        // a dark area can take the half of a register which fits a bright one,
+        // so the registers are refined as the mean of the pixels
+        // mapped to them or to their halves (at double brightness)
+        let visible = options.visible_indices(original.len());
+        let pixels = match &visible {
+            Some(indices) => indices.iter().map(|&i| original[i]).collect_vec(),
+            None => original.to_vec(),
+        };
+        for _ in 0..EHB_REFINE_STEPS {
+            let full = with_halves(&palette)
+                .into_iter()
+                .map(dither::sample)
+                .collect_vec();
+            let mut sums = vec![[0_u64; 3]; palette.len()];
+            let mut counts = vec![0_u64; palette.len()];
+            for c in &pixels {
+                let index = dither::nearest(&full, dither::sample(*c));
+                let (register, scale) = if index < palette.len() {
+                    (index, 1)
+                } else {
+                    (index - palette.len(), 2)
+                };
+                for (sum, v) in sums[register].iter_mut().zip(&[c.r, c.g, c.b]) {
+                    *sum += u64::from(*v) * scale;
+                }
+                counts[register] += 1;
+            }
+            for (i, register) in palette.iter_mut().enumerate().take(free) {
+                if counts[i] == 0 {
+                    continue;
+                }
+                let mean = |sum: u64| (sum / counts[i]).min(255) as u8;
+                let [r, g, b] = sums[i];
+                *register = MASTER_SPACE.convert_color(Color {
+                    r: mean(r),
+                    g: mean(g),
+                    b: mean(b),
+                    a: 255,
+                });
+            }
+        }
+        with_halves(&palette)
+    }
+}
+
+impl ColorDepth for ExtraHalfBrite {
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        let original = pixels(image);
+        let mut palette = self.palette(&original, &options);
+        let mut converted_pixels = dither::remap(&original, image.width(), &palette, &options)
+            .into_iter()
+            .map(|i| palette[i])
+            .collect_vec();
+        restore_protected_colors(
+            &original,
+            &mut converted_pixels,
+            &mut palette,
+            &options.protected_colors,
+        );
+        let loss = options.image_loss(&original, &converted_pixels);
+        (converted_pixels, palette, loss)
+    }
+
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        let original = pixels(image);
+        let palette = self.palette(&original, &options);
+        let indices = dither::remap(&original, image.width(), &palette, &options);
+        indexed_with_loss(image, &original, palette, indices, &options)
+    }
+}
+
+/// The registers followed by their halves,
+/// each channel shifted right by one bit in the 4-bit master space.
+fn with_halves(registers: &[Color]) -> Vec<Color> {
+    let half = |v: u8| ((v >> 4) >> 1) * 0x11;
+    registers
+        .iter()
+        .copied()
+        .chain(registers.iter().map(|c| Color {
+            r: half(c.r),
+            g: half(c.g),
+            b: half(c.b),
+            a: 255,
+        }))
+        .collect()
+}
+
+/// The code of a HAM6 pixel.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HamCode {
+    /// Take the color of a register, from 0 to 15
+    Register(u8),
+    /// Copy the previous pixel, setting red to the given 4-bit level
+    Red(u8),
+    /// Copy the previous pixel, setting green to the given 4-bit level
+    Green(u8),
+    /// Copy the previous pixel, setting blue to the given 4-bit level
+    Blue(u8),
+}
+
+impl HamCode {
+    /// The color shown for this code after the given color.
+    pub fn apply(self, registers: &[[u8; 3]], previous: [u8; 3]) -> [u8; 3] {
+        let [r, g, b] = previous;
+        match self {
+            HamCode::Register(i) => registers[usize::from(i)],
+            HamCode::Red(level) => [level * 0x11, g, b],
+            HamCode::Green(level) => [r, level * 0x11, b],
+            HamCode::Blue(level) => [r, g, level * 0x11],
+        }
+    }
+}
+
+/// An image encoded in HAM6.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HamImage {
+    /// The image width
+    pub width: u32,
+    /// The image height
+    pub height: u32,
+    /// The 16 color registers, in the 4096 color master space
+    pub registers: Vec<[u8; 3]>,
+    /// The code of each pixel, row by row
+    pub codes: Vec<HamCode>,
+}
+
+impl HamImage {
+    /// Encode an image with the given registers
+    /// (at most 16, already in the master space),
+    /// using the loss algorithm and dithering of the options.
+    ///
+    /// Each line starts from the color of register 0, like the border.
+    /// The codes are chosen one pixel at a time,
+    /// looking one pixel ahead to avoid leaving the next pixel stranded.
+    /// Any dithering mode other than `None` diffuses the error
+    /// in the manner of Floyd–Steinberg,
+    /// since the colors available for a pixel depend on the previous one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no registers or more than 16 of them.
+    pub fn encode(image: &RgbImage, registers: &[[u8; 3]], options: &ColorOptions) -> Self {
+        assert!(
+            !registers.is_empty() && registers.len() <= HAM_REGISTERS,
+            "there should be 1 to 16 registers"
+        );
+        let (width, height) = image.dimensions();
+        let w = width as usize;
+        let diffuse = options.dither != DitherMode::None;
+        let mut errors = vec![[0_f32; 3]; w + 2];
+        let mut codes = Vec::with_capacity(w * height as usize);
+        for y in 0..height {
+            let mut next_errors = vec![[0_f32; 3]; w + 2];
+            let mut previous = registers[0];
+            for x in 0..width {
+                let xi = x as usize;
+                let target = |x: u32, error: [f32; 3]| {
+                    let Rgb(p) = *image.get_pixel(x, y);
+                    let mut target = [0.; 3];
+                    for ((t, &v), e) in target.iter_mut().zip(&p).zip(&error) {
+                        *t = (f32::from(v) + e).clamp(0., 255.);
+                    }
+                    target
+                };
+                let current = target(x, errors[xi + 1]);
+                let ahead = if x + 1 < width {
+                    Some(target(x + 1, errors[xi + 2]))
+                } else {
+                    None
+                };
+                let (code, color) = best_code(registers, previous, current, ahead, options);
+                codes.push(code);
+
+                if diffuse {
+                    for (k, (&t, &c)) in current.iter().zip(&color).enumerate() {
+                        let error = t - f32::from(c);
+                        errors[xi + 2][k] += error * 7. / 16.;
+                        next_errors[xi][k] += error * 3. / 16.;
+                        next_errors[xi + 1][k] += error * 5. / 16.;
+                        next_errors[xi + 2][k] += error / 16.;
+                    }
+                }
+                previous = color;
+            }
+            errors = next_errors;
+        }
+        HamImage {
+            width,
+            height,
+            registers: registers.to_vec(),
+            codes,
+        }
+    }
+
+    /// Decode the pixels of the image.
+    pub fn decode(&self) -> Vec<Color> {
+        let width = self.width.max(1) as usize;
+        self.codes
+            .chunks(width)
+            .flat_map(|line| {
+                line.iter().scan(self.registers[0], move |previous, &code| {
+                    *previous = code.apply(&self.registers, *previous);
+                    let [r, g, b] = *previous;
+                    Some(Color { r, g, b, a: 255 })
+                })
+            })
+            .collect()
+    }
+}
+
+/// The codes which can follow the given color,
+/// with the color they show,
+/// modifying a channel only to its level nearest to the target.
+fn candidates(
+    registers: &[[u8; 3]],
+    previous: [u8; 3],
+    target: [f32; 3],
+) -> impl Iterator<Item = (HamCode, [u8; 3])> + '_ {
+    let level = |v: f32| (v / 17.).round() as u8;
+    let modify = vec![
+        HamCode::Red(level(target[0])),
+        HamCode::Green(level(target[1])),
+        HamCode::Blue(level(target[2])),
+    ];
+    (0..registers.len() as u8)
+        .map(HamCode::Register)
+        .chain(modify)
+        .map(move |code| (code, code.apply(registers, previous)))
+}
+
+/// Choose the code for a pixel,
+/// adding the best cost of the next pixel if there is one.
+fn best_code(
+    registers: &[[u8; 3]],
+    previous: [u8; 3],
+    target: [f32; 3],
+    ahead: Option<[f32; 3]>,
+    options: &ColorOptions,
+) -> (HamCode, [u8; 3]) {
+    let cost = |color: [u8; 3], target: [f32; 3]| {
+        let [r, g, b] = target;
+        let t = Color {
+            r: r.round() as u8,
+            g: g.round() as u8,
+            b: b.round() as u8,
+            a: 255,
+        };
+        let [r, g, b] = color;
+        options.loss.color_diff(t, Color { r, g, b, a: 255 })
+    };
+    candidates(registers, previous, target)
+        .min_by_key(|&(_, color)| {
+            let next = ahead.map_or(0, |ahead| {
+                candidates(registers, color, ahead)
+                    .map(|(_, next)| cost(next, ahead))
+                    .min()
+                    .unwrap_or(0)
+            });
+            cost(color, target) + next
+        })
+        .unwrap()
+}
+
+/// The Amiga in Hold-And-Modify mode with 6 bitplanes:
+/// any of the 4096 colors,
+/// as long as each pixel takes one of 16 registers
+/// or differs from the previous one in a single channel.
+///
+/// The registers are chosen like a 16 color palette for the image,
+/// regardless of the `num_colors` option.
+/// The palette of the conversion is empty,
+/// as the pixels are not limited to the registers.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Ham6;
+
+impl Ham6 {
+    /// Choose the registers and encode an image in HAM6.
+    pub fn encode(&self, image: &RgbImage, options: &ColorOptions) -> HamImage {
+        let original = pixels(image);
+        let registers = optimized_palette(&original, HAM_REGISTERS as u32, options, |c| {
+            MASTER_SPACE.convert_color(c)
+        })
+        .into_iter()
+        .take(HAM_REGISTERS)
+        .map(|c| [c.r, c.g, c.b])
+        .collect_vec();
+        let registers = if registers.is_empty() {
+            vec![[0, 0, 0]]
+        } else {
+            registers
+        };
+        HamImage::encode(image, &registers, options)
+    }
+}
+
+impl ColorDepth for Ham6 {
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        let original = pixels(image);
+        let mut converted_pixels = self.encode(image, &options).decode();
+        restore_protected_colors(
+            &original,
+            &mut converted_pixels,
+            &mut Vec::new(),
+            &options.protected_colors,
+        );
+        let loss = options.image_loss(&original, &converted_pixels);
+        (converted_pixels, Vec::new(), loss)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExtraHalfBrite, HamCode, HamImage};
+    use crate::color::{pixels, ColorDepth, ColorOptions, DitherMode};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_ham6_encode() {
+        // a red ramp over a color held in a register
+        let image = RgbImage::from_fn(16, 2, |x, _| Rgb([x as u8 * 0x11, 0x33, 0xCC]));
+        let mut registers = vec![[0, 0, 0]; 16];
+        registers[1] = [0, 0x33, 0xCC];
+        let options = ColorOptions {
+            dither: DitherMode::None,
+            ..Default::default()
+        };
+        let ham = HamImage::encode(&image, &registers, &options);
+        assert_eq!(ham.codes[0], HamCode::Register(1));
+        assert_eq!(ham.codes[1], HamCode::Red(1));
+        assert_eq!(ham.codes[16], HamCode::Register(1));
+        assert_eq!(ham.decode(), pixels(&image));
+    }
+
+    #[test]
+    fn test_extra_half_brite() {
+        let image = RgbImage::from_fn(16, 16, |x, y| {
+            Rgb([(x * 16) as u8, (y * 16) as u8, ((x + y) * 8) as u8])
+        });
+        let options = ColorOptions {
+            num_colors: Some(16),
+            ..Default::default()
+        };
+        let (pixels, palette, _) = ExtraHalfBrite.convert_image_with_loss(&image, options);
+        assert_eq!(palette.len(), 16);
+        for (c, half) in palette[..8].iter().zip(&palette[8..]) {
+            for (&v, &h) in [c.r, c.g, c.b].iter().zip(&[half.r, half.g, half.b]) {
+                assert_eq!(v % 0x11, 0);
+                assert_eq!(h, v / 0x11 / 2 * 0x11);
+            }
+        }
+        assert!(pixels.iter().all(|c| palette.contains(c)));
+    }
+}
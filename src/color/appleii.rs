@@ -0,0 +1,236 @@
+//! Apple II high resolution graphics (HGR) emulation.
+//!
+//! Each byte of HGR video memory holds 7 pixels
+//! plus a bit which shifts the color of all of them.
+//! A pixel which is on shows as violet or green
+//! (blue or orange with the shift bit set),
+//! depending on whether its column is even or odd,
+//! and adjacent pixels which are on show as white.
+//!
+//! The conversion first maps the image to the 6 colors of the mode,
+//! then searches each line for the bits which reproduce it best,
+//! since the color of a pixel depends on its neighbors
+//! and on the group of 7 pixels it belongs to.
+use super::{dither, restore_protected_colors, ColorDepth, ColorOptions, LossAlgorithm};
+use exoquant::Color;
+use image::{Rgb, RgbImage};
+use itertools::Itertools;
+
+/// The resolution of the high resolution mode, as `[width, height]`.
+pub const RESOLUTION: [u32; 2] = [280, 192];
+
+/// The number of pixels per byte of video memory
+const GROUP: usize = 7;
+
+/// The 6 colors of the high resolution mode:
+/// black, white, violet, green, blue and orange.
+pub static HGR: [[u8; 3]; 6] = [
+    [0, 0, 0],
+    [0xFF, 0xFF, 0xFF],
+    [0xFF, 0x44, 0xFD],
+    [0x14, 0xF5, 0x3C],
+    [0x14, 0xCF, 0xFD],
+    [0xFF, 0x6A, 0x3C],
+];
+
+/// Apple II high resolution graphics.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct HiRes;
+
+impl ColorDepth for HiRes {
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        options.keep_protected_colors_in(&HGR);
+        let width = image.width() as usize;
+        let original = image
+            .pixels()
+            .map(|&Rgb([r, g, b])| Color { r, g, b, a: 255 })
+            .collect_vec();
+        let mut palette = HGR
+            .iter()
+            .map(|&[r, g, b]| Color { r, g, b, a: 255 })
+            .collect_vec();
+
+        // the image as it would look without the constraints of the mode
+        let target = dither::remap(&original, image.width(), &palette, &options)
+            .into_iter()
+            .map(|i| palette[i])
+            .collect_vec();
+
+        let mut converted_pixels = if width == 0 {
+            Vec::new()
+        } else {
+            target
+                .chunks(width)
+                .flat_map(|line| {
+                    let mut pixels = render_line(&fit_line(line, options.loss));
+                    pixels.truncate(line.len());
+                    pixels
+                })
+                .collect_vec()
+        };
+        restore_protected_colors(
+            &original,
+            &mut converted_pixels,
+            &mut palette,
+            &options.protected_colors,
+        );
+        let loss = options.image_loss(&original, &converted_pixels);
+        (converted_pixels, palette, loss)
+    }
+}
+
+/// A byte of video memory:
+/// the state of 7 pixels (least significant bit first)
+/// and the color shift bit (the most significant bit).
+type Byte = u8;
+
+fn is_on(byte: Byte, i: usize) -> bool {
+    byte & (1 << i) != 0
+}
+
+fn is_shifted(byte: Byte) -> bool {
+    byte & 0x80 != 0
+}
+
+/// The color shown for a pixel in column `x`,
+/// given whether it and its neighbors are on.
+fn pixel_color(x: usize, left: bool, on: bool, right: bool, shifted: bool) -> [u8; 3] {
+    match (on, left || right, x % 2 == 0, shifted) {
+        (false, _, _, _) => HGR[0],
+        (true, true, _, _) => HGR[1],
+        (true, false, true, false) => HGR[2],
+        (true, false, false, false) => HGR[3],
+        (true, false, true, true) => HGR[4],
+        (true, false, false, true) => HGR[5],
+    }
+}
+
+/// Render a line of video memory into pixels, 7 per byte.
+fn render_line(bytes: &[Byte]) -> Vec<Color> {
+    let bits = bytes
+        .iter()
+        .flat_map(|&byte| (0..GROUP).map(move |i| (is_on(byte, i), is_shifted(byte))))
+        .collect_vec();
+    let on = |x: usize| matches!(bits.get(x), Some(&(true, _)));
+    (0..bits.len())
+        .map(|x| {
+            let left = x > 0 && on(x - 1);
+            let [r, g, b] = pixel_color(x, left, on(x), on(x + 1), bits[x].1);
+            Color { r, g, b, a: 255 }
+        })
+        .collect()
+}
+
+/// Choose the bytes of a line which reproduce the target pixels best,
+/// with any pixels past the end of the line left off.
+fn fit_line(target: &[Color], loss: LossAlgorithm) -> Vec<Byte> {
+    let width = target.len();
+    let groups = (width + GROUP - 1) / GROUP;
+    let cost = |x: usize, left: bool, on: bool, right: bool, shifted: bool| {
+        target.get(x).map_or(0, |&t| {
+            let [r, g, b] = pixel_color(x, left, on, right, shifted);
+            loss.color_diff(t, Color { r, g, b, a: 255 })
+        })
+    };
+    // the last two pixels of a byte and its shift bit,
+    // which is all that affects the cost of the next byte
+    let state = |byte: Byte| {
+        usize::from(is_on(byte, GROUP - 2))
+            | (usize::from(is_on(byte, GROUP - 1)) << 1)
+            | (usize::from(is_shifted(byte)) << 2)
+    };
+
+    // the lowest cost of each state after each group,
+    // and the byte and previous state it came from
+    let mut costs = vec![u64::MAX; 8];
+    costs[0] = 0;
+    let mut trace: Vec<[(Byte, usize); 8]> = Vec::with_capacity(groups);
+    for g in 0..groups {
+        let base = g * GROUP;
+        // bytes with pixels beyond the end of the line turned on are not used
+        let valid =
+            (0..=255_u8).filter(|&byte| (0..GROUP).all(|i| base + i < width || !is_on(byte, i)));
+        let mut next_costs = vec![u64::MAX; 8];
+        let mut next_trace = [(0, 0); 8];
+        for byte in valid {
+            let shifted = is_shifted(byte);
+            // pixels 1 to 5 do not depend on the neighboring bytes
+            let inner: u64 = (1..GROUP - 1)
+                .map(|i| {
+                    cost(
+                        base + i,
+                        is_on(byte, i - 1),
+                        is_on(byte, i),
+                        is_on(byte, i + 1),
+                        shifted,
+                    )
+                })
+                .sum();
+            for (prev, &prev_cost) in costs.iter().enumerate() {
+                if prev_cost == u64::MAX {
+                    continue;
+                }
+                let (prev_5, prev_6, prev_shifted) = (prev & 1 != 0, prev & 2 != 0, prev & 4 != 0);
+                // the last pixel of the previous byte
+                let deferred = if g > 0 {
+                    cost(base - 1, prev_5, prev_6, is_on(byte, 0), prev_shifted)
+                } else {
+                    0
+                };
+                let first = cost(base, prev_6, is_on(byte, 0), is_on(byte, 1), shifted);
+                let total = prev_cost + deferred + first + inner;
+                let s = state(byte);
+                if total < next_costs[s] {
+                    next_costs[s] = total;
+                    next_trace[s] = (byte, prev);
+                }
+            }
+        }
+        costs = next_costs;
+        trace.push(next_trace);
+    }
+
+    // the last pixel of the line has no right neighbor
+    let last = groups * GROUP - 1;
+    let mut s = (0..8)
+        .filter(|&s| costs[s] != u64::MAX)
+        .min_by_key(|&s| costs[s] + cost(last, s & 1 != 0, s & 2 != 0, false, s & 4 != 0))
+        .unwrap_or(0);
+    let mut bytes = vec![0; groups];
+    for (g, step) in trace.iter().enumerate().rev() {
+        let (byte, prev) = step[s];
+        bytes[g] = byte;
+        s = prev;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fit_line, render_line, HGR};
+    use crate::color::LossAlgorithm;
+    use exoquant::Color;
+
+    #[test]
+    fn test_hires_line() {
+        let color = |[r, g, b]: [u8; 3]| Color { r, g, b, a: 255 };
+        // violet, then white, then orange
+        let target = (0..21)
+            .map(|x| match x {
+                0..=4 if x % 2 == 0 => color(HGR[2]),
+                7..=13 => color(HGR[1]),
+                15..=19 if x % 2 == 1 => color(HGR[5]),
+                _ => color(HGR[0]),
+            })
+            .collect::<Vec<_>>();
+        let bytes = fit_line(&target, LossAlgorithm::L2);
+        assert_eq!(bytes.len(), 3);
+        assert_eq!(render_line(&bytes), target);
+        // the orange byte must have its shift bit set
+        assert!(bytes[2] & 0x80 != 0);
+    }
+}
@@ -0,0 +1,42 @@
+//! Atari ST video modes.
+//!
+//! The Atari ST takes its colors from a master space of 512 colors
+//! (3 bits per channel),
+//! showing 16 of them at once in its low resolution mode.
+
+/// The master space of the Atari ST:
+/// 3 bits per sample (9 bits per pixel).
+pub const MASTER_SPACE_BITS: u8 = 3;
+
+/// The number of color registers in the low resolution mode.
+pub const REGISTERS: u32 = 16;
+
+/// The resolution of the low resolution mode, as `[width, height]`.
+pub const RESOLUTION_LOW: [u32; 2] = [320, 200];
+
+/// The Atari ST in its low resolution mode: 16 colors at once out of 512.
+pub static PALETTE_ATARI_ST: super::RegisterPalette =
+    super::RegisterPalette::with_bits(MASTER_SPACE_BITS, REGISTERS);
+
+#[cfg(test)]
+mod tests {
+    use super::PALETTE_ATARI_ST;
+    use crate::color::{ColorDepth, ColorOptions};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_atari_st_palette() {
+        let image = RgbImage::from_fn(32, 32, |x, y| {
+            Rgb([(x * 8) as u8, (y * 8) as u8, ((x + y) * 4) as u8])
+        });
+        let (pixels, palette, _) =
+            PALETTE_ATARI_ST.convert_image_with_loss(&image, ColorOptions::default());
+        assert!(!palette.is_empty() && palette.len() <= 16);
+        // all samples are in the 8 levels of the master space
+        let levels = [0x00, 0x24, 0x49, 0x6D, 0x92, 0xB6, 0xDB, 0xFF];
+        for c in &palette {
+            assert!([c.r, c.g, c.b].iter().all(|v| levels.contains(v)));
+        }
+        assert!(pixels.iter().all(|c| palette.contains(c)));
+    }
+}
@@ -0,0 +1,345 @@
+//! Color depths with a few sub-palettes shared by the whole screen,
+//! where each area of the screen picks one of them,
+//! as in the attribute tables of the NES.
+use super::{dither, pixels, restore_protected_colors, ColorDepth, ColorOptions, LossAlgorithm};
+use exoquant::Color;
+use image::RgbImage;
+use itertools::Itertools;
+
+/// The number of refinement passes over the sub-palettes
+const ITERATIONS: usize = 4;
+
+/// Color depth defined by a hardware-level palette of RGB colors,
+/// from which a few sub-palettes are chosen for the whole image.
+/// Each area of the screen uses the colors of one sub-palette
+/// plus a backdrop color shared by all of them.
+///
+/// The sub-palettes are optimized together with the choice of sub-palette
+/// for each area,
+/// alternating between assigning each area to the sub-palette
+/// which fits it best
+/// and refitting each sub-palette to the areas assigned to it.
+#[derive(Debug, Copy, Clone)]
+pub struct AttributePalette<T> {
+    colors: T,
+    area_width: u32,
+    area_height: u32,
+    sub_palettes: usize,
+    colors_per_sub_palette: usize,
+}
+
+impl<T> AttributePalette<T> {
+    /// Create a color depth from the given palette of RGB colors,
+    /// with `sub_palettes` sub-palettes of `colors_per_sub_palette` colors
+    /// (not counting the backdrop color),
+    /// chosen for each area of `area_width` x `area_height` pixels.
+    pub const fn new(
+        colors: T,
+        area_width: u32,
+        area_height: u32,
+        sub_palettes: usize,
+        colors_per_sub_palette: usize,
+    ) -> Self {
+        AttributePalette {
+            colors,
+            area_width,
+            area_height,
+            sub_palettes,
+            colors_per_sub_palette,
+        }
+    }
+}
+
+impl<T> ColorDepth for AttributePalette<T>
+where
+    T: AsRef<[[u8; 3]]>,
+{
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        options.keep_protected_colors_in(self.colors.as_ref());
+        let (width, height) = image.dimensions();
+        let original = pixels(image);
+        let palette = self
+            .colors
+            .as_ref()
+            .iter()
+            .map(|&[r, g, b]| Color { r, g, b, a: 255 })
+            .collect_vec();
+        if palette.is_empty() {
+            return (original, palette, 0);
+        }
+
+        // transparent pixels do not count towards the colors of any area
+        let alpha = options
+            .alpha
+            .as_deref()
+            .filter(|alpha| alpha.len() == original.len());
+        let visible = |p: usize| alpha.map_or(true, |alpha| alpha[p] > 0);
+
+        // the backdrop is the most common nearest color
+        let colors = self.colors.as_ref();
+        let backdrop = (0..original.len())
+            .filter(|&p| visible(p))
+            .filter_map(|p| options.loss.nearest(original[p], colors))
+            .counts()
+            .into_iter()
+            .map(|(c, n)| (colors.iter().position(|&q| q == c).unwrap_or(0), n))
+            .max_by_key(|&(i, n)| (n, !i))
+            .map_or(0, |(i, _)| i);
+        let fitter = Fitter {
+            palette: &palette,
+            loss: options.loss,
+            backdrop,
+            num_colors: self.colors_per_sub_palette,
+        };
+
+        // the pixel positions and the visible colors of each area
+        let area_width = self.area_width.max(1);
+        let area_height = self.area_height.max(1);
+        let areas = (0..height)
+            .step_by(area_height as usize)
+            .flat_map(|ay| {
+                (0..width)
+                    .step_by(area_width as usize)
+                    .map(move |ax| (ax, ay))
+            })
+            .map(|(ax, ay)| {
+                let w = area_width.min(width - ax);
+                let h = area_height.min(height - ay);
+                let positions = (ay..ay + h)
+                    .flat_map(|y| (ax..ax + w).map(move |x| (y * width + x) as usize))
+                    .collect_vec();
+                let colors = histogram(
+                    positions
+                        .iter()
+                        .filter(|&&p| visible(p))
+                        .map(|&p| (original[p], 1)),
+                );
+                Area {
+                    width: w,
+                    positions,
+                    colors,
+                }
+            })
+            .collect_vec();
+
+        // start with a sub-palette for the whole image,
+        // then one for each of the areas served worst so far
+        let all = histogram(areas.iter().flat_map(|area| area.colors.iter().copied()));
+        let mut sub_palettes = vec![fitter.fit(&all)];
+        while sub_palettes.len() < self.sub_palettes {
+            let worst = areas.iter().max_by_key(|area| {
+                sub_palettes
+                    .iter()
+                    .map(|s| fitter.error(&area.colors, s))
+                    .min()
+                    .unwrap_or(0)
+            });
+            match worst {
+                Some(area) => sub_palettes.push(fitter.fit(&area.colors)),
+                None => break,
+            }
+        }
+
+        let assign = |sub_palettes: &[Vec<usize>]| {
+            areas
+                .iter()
+                .map(|area| {
+                    sub_palettes
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, s)| fitter.error(&area.colors, s))
+                        .map_or(0, |(i, _)| i)
+                })
+                .collect_vec()
+        };
+        for _ in 0..ITERATIONS {
+            let assignment = assign(&sub_palettes);
+            for (i, sub_palette) in sub_palettes.iter_mut().enumerate() {
+                let colors = histogram(
+                    areas
+                        .iter()
+                        .zip(&assignment)
+                        .filter(|(_, &a)| a == i)
+                        .flat_map(|(area, _)| area.colors.iter().copied()),
+                );
+                if !colors.is_empty() {
+                    *sub_palette = fitter.fit(&colors);
+                }
+            }
+        }
+        let assignment = assign(&sub_palettes);
+
+        let mut converted_pixels = original.clone();
+        for (area, &a) in areas.iter().zip(&assignment) {
+            let area_palette = sub_palettes[a]
+                .iter()
+                .chain(std::iter::once(&backdrop))
+                .map(|&i| palette[i])
+                .collect_vec();
+            let area_pixels = area.positions.iter().map(|&p| original[p]).collect_vec();
+            let area_alpha =
+                alpha.map(|alpha| area.positions.iter().map(|&p| alpha[p]).collect_vec());
+            let indices = dither::remap_masked(
+                &area_pixels,
+                area.width,
+                &area_palette,
+                &options,
+                area_alpha.as_deref(),
+            );
+            for (&p, i) in area.positions.iter().zip(indices) {
+                converted_pixels[p] = area_palette[i];
+            }
+        }
+        let mut final_palette = std::iter::once(backdrop)
+            .chain(sub_palettes.into_iter().flatten())
+            .unique()
+            .map(|i| palette[i])
+            .collect();
+        restore_protected_colors(
+            &original,
+            &mut converted_pixels,
+            &mut final_palette,
+            &options.protected_colors,
+        );
+        let loss = options.image_loss(&original, &converted_pixels);
+        (converted_pixels, final_palette, loss)
+    }
+}
+
+/// An area of the screen which picks one of the sub-palettes.
+struct Area {
+    width: u32,
+    /// The pixel positions of the area, row by row
+    positions: Vec<usize>,
+    /// The distinct visible colors of the area, with their pixel counts
+    colors: Vec<(Color, u64)>,
+}
+
+/// Merge colors with their pixel counts into distinct colors.
+fn histogram(colors: impl IntoIterator<Item = (Color, u64)>) -> Vec<(Color, u64)> {
+    colors
+        .into_iter()
+        .map(|(c, n)| ([c.r, c.g, c.b], n))
+        .into_grouping_map()
+        .sum()
+        .into_iter()
+        .sorted()
+        .map(|([r, g, b], n)| (Color { r, g, b, a: 255 }, n))
+        .collect()
+}
+
+/// Fits sub-palettes (as indices into the palette) to sets of colors
+/// weighted by their pixel counts.
+struct Fitter<'a> {
+    palette: &'a [Color],
+    loss: LossAlgorithm,
+    backdrop: usize,
+    num_colors: usize,
+}
+
+impl Fitter<'_> {
+    /// The error of mapping the given colors
+    /// to a sub-palette plus the backdrop.
+    fn error(&self, colors: &[(Color, u64)], sub_palette: &[usize]) -> u64 {
+        colors
+            .iter()
+            .map(|&(c, n)| {
+                let d = |i: usize| self.loss.color_diff(c, self.palette[i]);
+                n * sub_palette
+                    .iter()
+                    .map(|&i| d(i))
+                    .fold(d(self.backdrop), u64::min)
+            })
+            .sum()
+    }
+
+    /// Choose the colors of a sub-palette for the given colors,
+    /// greedily adding the color which reduces the error the most.
+    fn fit(&self, colors: &[(Color, u64)]) -> Vec<usize> {
+        // distance from each of the colors to each palette color
+        let distances = colors
+            .iter()
+            .map(|&(c, _)| {
+                self.palette
+                    .iter()
+                    .map(|&q| self.loss.color_diff(c, q))
+                    .collect_vec()
+            })
+            .collect_vec();
+        let mut best = distances.iter().map(|d| d[self.backdrop]).collect_vec();
+        let mut sub_palette = Vec::with_capacity(self.num_colors);
+        for _ in 0..self.num_colors {
+            let choice = (0..self.palette.len())
+                .filter(|&c| c != self.backdrop && !sub_palette.contains(&c))
+                .min_by_key(|&c| {
+                    colors
+                        .iter()
+                        .zip(&distances)
+                        .zip(&best)
+                        .map(|((&(_, n), d), &b)| n * b.min(d[c]))
+                        .sum::<u64>()
+                });
+            let c = match choice {
+                Some(c) => c,
+                None => break,
+            };
+            for (d, b) in distances.iter().zip(&mut best) {
+                *b = (*b).min(d[c]);
+            }
+            sub_palette.push(c);
+        }
+        sub_palette
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AttributePalette;
+    use crate::color::{ColorDepth, ColorOptions, DitherMode};
+    use image::{Rgb, RgbImage};
+    use itertools::Itertools;
+
+    #[test]
+    fn test_sub_palette_per_area() {
+        let colors = [
+            [0, 0, 0],
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 0],
+        ];
+        let depth = AttributePalette::new(colors, 4, 4, 2, 1);
+        // black background, one colored stripe per area
+        let image = RgbImage::from_fn(12, 4, |x, y| match (x / 4, y % 2) {
+            (_, 0) => Rgb([5, 5, 5]),
+            (0, _) => Rgb([250, 0, 0]),
+            (1, _) => Rgb([0, 250, 0]),
+            _ => Rgb([240, 10, 0]),
+        });
+        let options = ColorOptions {
+            dither: DitherMode::None,
+            ..Default::default()
+        };
+        let (out, palette, _) = depth.convert_image_with_loss(&image, options);
+
+        // black backdrop plus 2 sub-palettes of 1 color
+        assert_eq!(palette.len(), 3);
+        for area in 0..3 {
+            let colors = (0..4)
+                .flat_map(|y| (area * 4..area * 4 + 4).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let c = out[(y * 12 + x) as usize];
+                    [c.r, c.g, c.b]
+                })
+                .unique()
+                .count();
+            assert!(colors <= 2);
+        }
+        let c = out[12 + 8];
+        assert_eq!([c.r, c.g, c.b], [255, 0, 0]);
+    }
+}
@@ -0,0 +1,42 @@
+//! Commodore 64 color palette and video modes.
+use super::CellPalette;
+
+/// The 16 colors of the VIC-II chip
+/// (as measured by Philip "Pepto" Timmermann).
+pub static C64: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00], // black
+    [0xFF, 0xFF, 0xFF], // white
+    [0x68, 0x37, 0x2B], // red
+    [0x70, 0xA4, 0xB2], // cyan
+    [0x6F, 0x3D, 0x86], // purple
+    [0x58, 0x8D, 0x43], // green
+    [0x35, 0x28, 0x79], // blue
+    [0xB8, 0xC7, 0x6F], // yellow
+    [0x6F, 0x4F, 0x25], // orange
+    [0x43, 0x39, 0x00], // brown
+    [0x9A, 0x67, 0x59], // light red
+    [0x44, 0x44, 0x44], // dark grey
+    [0x6C, 0x6C, 0x6C], // grey
+    [0x9A, 0xD2, 0x84], // light green
+    [0x6C, 0x5E, 0xB5], // light blue
+    [0x95, 0x95, 0x95], // light grey
+];
+
+/// The Commodore 64 standard bitmap mode:
+/// 2 colors in each 8x8 cell.
+pub static PALETTE_C64_HIRES: CellPalette<[[u8; 3]; 16]> = CellPalette::new(C64, 8, 8, 2);
+
+/// The resolution of the standard bitmap mode, as `[width, height]`.
+pub const RESOLUTION_HIRES: [u32; 2] = [320, 200];
+
+/// The resolution of the multicolor bitmap mode, as `[width, height]`.
+pub const RESOLUTION_MULTICOLOR: [u32; 2] = [160, 200];
+
+/// The Commodore 64 multicolor bitmap mode:
+/// 3 colors in each cell of 4x8 double width pixels
+/// plus a shared background color.
+///
+/// The pixels of the image are taken as the double width pixels,
+/// as at the 160x200 resolution of the mode.
+pub static PALETTE_C64_MULTICOLOR: CellPalette<[[u8; 3]; 16]> =
+    CellPalette::new(C64, 4, 8, 4).with_shared_background();
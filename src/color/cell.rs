@@ -0,0 +1,266 @@
+//! Color depths with a color limit per cell of the screen,
+//! as in the character based video modes of many home computers.
+use super::{dither, pixels, restore_protected_colors, ColorDepth, ColorOptions};
+use exoquant::Color;
+use image::RgbImage;
+use itertools::Itertools;
+
+/// Color depth defined by a hardware-level palette of RGB colors,
+/// where each cell of the screen
+/// may only use a few of those colors.
+///
+/// Optionally, one of the colors of each cell is a background color
+/// shared by the whole screen,
+/// and pixels may be wider than the pixels of the image
+/// (as in the multicolor modes of the Commodore 64).
+#[derive(Debug, Copy, Clone)]
+pub struct CellPalette<T> {
+    colors: T,
+    cell_width: u32,
+    cell_height: u32,
+    colors_per_cell: usize,
+    shared_background: bool,
+    pixel_width: u32,
+}
+
+impl<T> CellPalette<T> {
+    /// Create a color depth from the given palette of RGB colors,
+    /// admitting up to `colors_per_cell` of them
+    /// in each cell of `cell_width` x `cell_height` pixels.
+    pub const fn new(colors: T, cell_width: u32, cell_height: u32, colors_per_cell: usize) -> Self {
+        CellPalette {
+            colors,
+            cell_width,
+            cell_height,
+            colors_per_cell,
+            shared_background: false,
+            pixel_width: 1,
+        }
+    }
+
+    /// Make one of the colors of each cell
+    /// a background color shared by the whole image.
+    pub const fn with_shared_background(self) -> Self {
+        CellPalette {
+            colors: self.colors,
+            cell_width: self.cell_width,
+            cell_height: self.cell_height,
+            colors_per_cell: self.colors_per_cell,
+            shared_background: true,
+            pixel_width: self.pixel_width,
+        }
+    }
+
+    /// Make each pixel as wide as `pixel_width` pixels of the image.
+    /// The cell width is still given in pixels of the image.
+    pub const fn with_pixel_width(self, pixel_width: u32) -> Self {
+        CellPalette {
+            colors: self.colors,
+            cell_width: self.cell_width,
+            cell_height: self.cell_height,
+            colors_per_cell: self.colors_per_cell,
+            shared_background: self.shared_background,
+            pixel_width,
+        }
+    }
+}
+
+impl<T> ColorDepth for CellPalette<T>
+where
+    T: AsRef<[[u8; 3]]>,
+{
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        options.keep_protected_colors_in(self.colors.as_ref());
+        let (width, height) = image.dimensions();
+        let original = pixels(image);
+        let mut palette = self
+            .colors
+            .as_ref()
+            .iter()
+            .map(|&[r, g, b]| Color { r, g, b, a: 255 })
+            .collect_vec();
+
+        // merge wide pixels
+        let pixel_width = self.pixel_width.max(1);
+        let wide_width = (width + pixel_width - 1) / pixel_width;
+        let wide = (0..height)
+            .flat_map(|y| (0..wide_width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let xs = x * pixel_width..((x + 1) * pixel_width).min(width);
+                let n = xs.len() as u32;
+                let sum = xs.fold([0_u32; 3], |[r, g, b], x| {
+                    let c = original[(y * width + x) as usize];
+                    [r + u32::from(c.r), g + u32::from(c.g), b + u32::from(c.b)]
+                });
+                Color {
+                    r: (sum[0] / n) as u8,
+                    g: (sum[1] / n) as u8,
+                    b: (sum[2] / n) as u8,
+                    a: 255,
+                }
+            })
+            .collect_vec();
+
+        // wide pixels are transparent if all of their pixels are,
+        // and then do not count towards the colors of their cell
+        let wide_alpha = options
+            .alpha
+            .as_deref()
+            .filter(|alpha| alpha.len() == original.len())
+            .map(|alpha| {
+                (0..height)
+                    .flat_map(|y| (0..wide_width).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        (x * pixel_width..((x + 1) * pixel_width).min(width))
+                            .map(|x| alpha[(y * width + x) as usize])
+                            .max()
+                            .unwrap_or(0)
+                    })
+                    .collect_vec()
+            });
+        let visible = |p: usize| wide_alpha.as_ref().map_or(true, |alpha| alpha[p] > 0);
+
+        let background = if self.shared_background {
+            // the most common nearest color
+            let colors = self.colors.as_ref();
+            let counts = (0..wide.len())
+                .filter(|&p| visible(p))
+                .filter_map(|p| options.loss.nearest(wide[p], colors))
+                .counts();
+            counts
+                .into_iter()
+                .map(|(c, n)| (colors.iter().position(|&q| q == c).unwrap_or(0), n))
+                .max_by_key(|&(i, n)| (n, !i))
+                .map(|(i, _)| i)
+        } else {
+            None
+        };
+        let candidates = (0..palette.len())
+            .filter(|&i| Some(i) != background)
+            .collect_vec();
+        let free = self
+            .colors_per_cell
+            .saturating_sub(background.iter().len())
+            .min(candidates.len());
+
+        let mut converted_wide = wide.clone();
+        let cell_width = (self.cell_width / pixel_width).max(1);
+        let cell_height = self.cell_height.max(1);
+        for cy in (0..height).step_by(cell_height as usize) {
+            for cx in (0..wide_width).step_by(cell_width as usize) {
+                let cell_w = cell_width.min(wide_width - cx);
+                let cell_h = cell_height.min(height - cy);
+                let positions = (cy..cy + cell_h)
+                    .flat_map(|y| (cx..cx + cell_w).map(move |x| (y * wide_width + x) as usize))
+                    .collect_vec();
+
+                // distance from each visible pixel of the cell to each palette color
+                let distances = positions
+                    .iter()
+                    .filter(|&&p| visible(p))
+                    .map(|&p| {
+                        palette
+                            .iter()
+                            .map(|&q| options.loss.color_diff(wide[p], q))
+                            .collect_vec()
+                    })
+                    .collect_vec();
+
+                // choose the colors which fit the cell best
+                let best = candidates
+                    .iter()
+                    .copied()
+                    .combinations(free)
+                    .map(|mut combination| {
+                        combination.extend(background);
+                        combination
+                    })
+                    .min_by_key(|combination| {
+                        distances
+                            .iter()
+                            .map(|d| combination.iter().map(|&i| d[i]).min().unwrap_or(0))
+                            .sum::<u64>()
+                    })
+                    .unwrap_or_default();
+                let cell_palette = best.iter().map(|&i| palette[i]).collect_vec();
+                if cell_palette.is_empty() {
+                    continue;
+                }
+
+                let cell_pixels = positions.iter().map(|&p| wide[p]).collect_vec();
+                let cell_alpha = wide_alpha
+                    .as_ref()
+                    .map(|alpha| positions.iter().map(|&p| alpha[p]).collect_vec());
+                let indices = dither::remap_masked(
+                    &cell_pixels,
+                    cell_w,
+                    &cell_palette,
+                    &options,
+                    cell_alpha.as_deref(),
+                );
+                for (&p, i) in positions.iter().zip(indices) {
+                    converted_wide[p] = cell_palette[i];
+                }
+            }
+        }
+
+        // widen the pixels back
+        let mut converted_pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| converted_wide[(y * wide_width + x / pixel_width) as usize])
+            .collect_vec();
+        restore_protected_colors(
+            &original,
+            &mut converted_pixels,
+            &mut palette,
+            &options.protected_colors,
+        );
+        let loss = options.image_loss(&original, &converted_pixels);
+        (converted_pixels, palette, loss)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CellPalette;
+    use crate::color::{ColorDepth, ColorOptions, DitherMode};
+    use image::{Rgb, RgbImage};
+    use itertools::Itertools;
+
+    #[test]
+    fn test_colors_per_cell() {
+        let colors = [[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        let depth = CellPalette::new(colors, 4, 4, 2);
+        // each quadrant of the image has its own pair of colors
+        let image = RgbImage::from_fn(8, 8, |x, y| match (x / 4, y / 4, x % 2) {
+            (0, 0, 0) => Rgb([250, 10, 0]),
+            (0, 0, _) => Rgb([0, 240, 10]),
+            (1, _, 0) => Rgb([0, 0, 250]),
+            _ => Rgb([10, 0, 0]),
+        });
+        let options = ColorOptions {
+            dither: DitherMode::None,
+            ..Default::default()
+        };
+        let out = depth.convert_image(&image, options);
+
+        let cell_colors = |cx: u32, cy: u32| {
+            (cy * 4..cy * 4 + 4)
+                .flat_map(|y| (cx * 4..cx * 4 + 4).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let c = out[(y * 8 + x) as usize];
+                    [c.r, c.g, c.b]
+                })
+                .unique()
+                .count()
+        };
+        for (cx, cy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            assert!(cell_colors(cx, cy) <= 2);
+        }
+        assert_eq!([out[0].r, out[0].g, out[0].b], [255, 0, 0]);
+    }
+}
@@ -80,3 +80,72 @@ pub static PALETTE_CGA_MODE4: BestPalette<&[CgaMod4Subpalette]> = BestPalette(&[
     BackForePalette(CGA_4BIT, CGA_MODE4_1_LOW),
     BackForePalette(CGA_4BIT, CGA_MODE4_1_HIGH),
 ]);
+
+/// The names of the sub-palettes of [`PALETTE_CGA_MODE4`], in the same order,
+/// as reported by [`BestPalette::convert_image_best`].
+pub static CGA_MODE4_SUBPALETTE_NAMES: [&str; 4] = [
+    "palette 0, low intensity",
+    "palette 0, high intensity",
+    "palette 1, low intensity",
+    "palette 1, high intensity",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{CGA_MODE4_SUBPALETTE_NAMES, PALETTE_CGA_MODE4, PALETTE_CGA_MODE4_0_HIGH};
+    use crate::color::{BackgroundStrategy, ColorDepth, ColorOptions, DitherMode};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_best_subpalette() {
+        let image = RgbImage::from_fn(8, 8, |x, _| match x % 4 {
+            0 => Rgb([0, 0, 0]),
+            1 => Rgb([0x55, 0xFF, 0x55]),
+            2 => Rgb([0xFF, 0x55, 0x55]),
+            _ => Rgb([0xFF, 0xFF, 0x55]),
+        });
+        let options = ColorOptions {
+            num_colors: Some(4),
+            dither: DitherMode::None,
+            ..Default::default()
+        };
+        let (index, pixels, _palette, _loss) =
+            PALETTE_CGA_MODE4.convert_image_best(&image, options);
+        assert_eq!(
+            CGA_MODE4_SUBPALETTE_NAMES[index],
+            "palette 0, high intensity"
+        );
+        assert_eq!(pixels.len(), 64);
+    }
+
+    #[test]
+    fn test_background_strategies() {
+        // mostly red, then blue, then black
+        let image = RgbImage::from_fn(8, 8, |x, y| match y * 8 + x {
+            0..=27 => Rgb([0xAA, 0, 0]),
+            28..=47 => Rgb([0, 0, 0xAA]),
+            _ => Rgb([0, 0, 0]),
+        });
+        let depth = &PALETTE_CGA_MODE4_0_HIGH;
+        let background = |background: BackgroundStrategy| {
+            let options = ColorOptions {
+                num_colors: Some(4),
+                background,
+                ..Default::default()
+            };
+            (
+                depth.background_color(&image, &options),
+                depth.loss(&image, options),
+            )
+        };
+        let (median, median_loss) = background(BackgroundStrategy::Median);
+        assert_eq!(median, [0, 0, 0]);
+        assert_eq!(background(BackgroundStrategy::MostFrequent).0, [0xAA, 0, 0]);
+        assert_eq!(
+            background(BackgroundStrategy::Color([0xFF, 0xFF, 0xF0])).0,
+            [0xFF, 0xFF, 0xFF]
+        );
+        let (_, search_loss) = background(BackgroundStrategy::Search);
+        assert!(search_loss <= median_loss);
+    }
+}
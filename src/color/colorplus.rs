@@ -0,0 +1,16 @@
+//! Plantronics ColorPlus video modes.
+//!
+//! The ColorPlus card doubles the video memory of CGA
+//! to show all 16 colors of the CGA master palette at 320x200
+//! (see [`PALETTE_CGA_4BIT`](super::cga::PALETTE_CGA_4BIT)),
+//! or the 4 colors of a mode 4 sub-palette at 640x200
+//! (see [`PALETTE_CGA_MODE4`](super::cga::PALETTE_CGA_MODE4)).
+
+/// The resolution of the 16 color mode,
+/// as `[width, height]`.
+pub const RESOLUTION_16_COLORS: [u32; 2] = [320, 200];
+
+/// The resolution of the 4 color mode,
+/// with pixels half as wide as in the 16 color mode,
+/// as `[width, height]`.
+pub const RESOLUTION_4_COLORS: [u32; 2] = [640, 200];
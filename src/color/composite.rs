@@ -0,0 +1,178 @@
+//! CGA composite output emulation.
+//!
+//! On a composite monitor,
+//! the CGA signal is encoded as NTSC video,
+//! where fine horizontal detail in the luminance
+//! is mistaken for the color carrier.
+//! This turns patterns of the 640x200 black and white mode
+//! into solid "artifact" colors,
+//! and adds color fringes to the edges in the 320x200 mode.
+//!
+//! The emulation converts the image to the RGB palette of the CGA mode first,
+//! then encodes each scanline into a composite signal
+//! and decodes it back with a low-pass filter
+//! spanning one cycle of the color carrier.
+use super::{cga, ColorDepth, ColorOptions, PALETTE_BW_1BIT};
+use exoquant::Color;
+use image::{Rgb, RgbImage};
+use itertools::Itertools;
+
+/// The number of signal samples per cycle of the color carrier,
+/// which is the width of a pixel in the 640 pixel wide mode
+const SAMPLES_PER_CYCLE: usize = 4;
+
+/// The number of signal samples in a scanline of active video
+const LINE_SAMPLES: u32 = 640;
+
+/// CGA on a composite monitor.
+///
+/// Images at least 640 pixels wide are treated as the black and white mode,
+/// narrower images as mode 4 (4 colors).
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Composite;
+
+impl ColorDepth for Composite {
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        let (colors, _palette, _loss) = if image.width() >= LINE_SAMPLES {
+            PALETTE_BW_1BIT.convert_image_with_loss(image, options.clone())
+        } else {
+            cga::PALETTE_CGA_MODE4.convert_image_with_loss(image, options.clone())
+        };
+        let converted = simulate(image.width(), &colors);
+
+        let original = image
+            .pixels()
+            .map(|&Rgb([r, g, b])| Color { r, g, b, a: 255 })
+            .collect_vec();
+        let loss = options.image_loss(&original, &converted);
+        // artifact colors are not limited to a palette
+        (converted, Vec::new(), loss)
+    }
+}
+
+/// Pass the pixels of an RGB image (in row major order)
+/// through the composite signal,
+/// returning the colors seen on the monitor.
+pub fn simulate(width: u32, pixels: &[Color]) -> Vec<Color> {
+    if width == 0 {
+        return Vec::new();
+    }
+    let samples_per_pixel = (LINE_SAMPLES / width).max(1) as usize;
+    pixels
+        .chunks(width as usize)
+        .flat_map(|line| simulate_line(line, samples_per_pixel))
+        .collect()
+}
+
+fn simulate_line(line: &[Color], samples_per_pixel: usize) -> Vec<Color> {
+    let carrier = |k: usize| {
+        let phase =
+            2. * std::f32::consts::PI * (k % SAMPLES_PER_CYCLE) as f32 / SAMPLES_PER_CYCLE as f32;
+        (phase.cos(), phase.sin())
+    };
+
+    // encode
+    let signal = line
+        .iter()
+        .flat_map(|&c| std::iter::repeat(to_yiq(c)).take(samples_per_pixel))
+        .enumerate()
+        .map(|(k, [y, i, q])| {
+            let (cos, sin) = carrier(k);
+            y + i * cos + q * sin
+        })
+        .collect_vec();
+
+    // decode, averaging over one carrier cycle
+    let decoded = (0..signal.len())
+        .map(|k| {
+            let start = k.saturating_sub(SAMPLES_PER_CYCLE / 2 - 1);
+            let end = (start + SAMPLES_PER_CYCLE).min(signal.len());
+            let start = end.saturating_sub(SAMPLES_PER_CYCLE);
+            let n = (end - start) as f32;
+            let mut yiq = [0_f32; 3];
+            for (j, s) in signal.iter().enumerate().take(end).skip(start) {
+                let (cos, sin) = carrier(j);
+                yiq[0] += s / n;
+                yiq[1] += 2. * s * cos / n;
+                yiq[2] += 2. * s * sin / n;
+            }
+            from_yiq(yiq)
+        })
+        .collect_vec();
+
+    decoded
+        .chunks(samples_per_pixel)
+        .map(|samples| {
+            let n = samples.len() as f32;
+            let mean = |channel: usize| {
+                let sum: f32 = samples.iter().map(|s| s[channel]).sum();
+                (sum / n).round().clamp(0., 255.) as u8
+            };
+            Color {
+                r: mean(0),
+                g: mean(1),
+                b: mean(2),
+                a: 255,
+            }
+        })
+        .collect()
+}
+
+fn to_yiq(c: Color) -> [f32; 3] {
+    let (r, g, b) = (f32::from(c.r), f32::from(c.g), f32::from(c.b));
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        0.596 * r - 0.274 * g - 0.322 * b,
+        0.211 * r - 0.523 * g + 0.312 * b,
+    ]
+}
+
+fn from_yiq([y, i, q]: [f32; 3]) -> [f32; 3] {
+    [
+        y + 0.956 * i + 0.621 * q,
+        y - 0.272 * i - 0.647 * q,
+        y - 1.106 * i + 1.703 * q,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::simulate;
+    use exoquant::Color;
+
+    #[test]
+    fn test_artifact_colors() {
+        let black = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+
+        // flat areas keep their color
+        let flat = simulate(640, &[white; 640]);
+        assert!(flat[320..330]
+            .iter()
+            .all(|c| c.r >= 250 && c.g >= 250 && c.b >= 250));
+
+        // a pattern of 2 white and 2 black pixels turns into a saturated color
+        let pattern: Vec<_> = (0..640)
+            .map(|x| if x % 4 < 2 { white } else { black })
+            .collect();
+        let out = simulate(640, &pattern);
+        let c = out[320];
+        let max = c.r.max(c.g).max(c.b);
+        let min = c.r.min(c.g).min(c.b);
+        assert!(max - min > 100, "expected a saturated color, got {:?}", c);
+    }
+}
@@ -0,0 +1,626 @@
+//! Dithering, the mapping of image pixels to palette indices.
+use super::linear::to_linear;
+use super::ColorOptions;
+use crate::progress::{self, Stage};
+use exoquant::Color;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Enumeration of dithering modes,
+/// used when mapping the image to a limited palette.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum DitherMode {
+    /// Floyd–Steinberg error diffusion
+    #[default]
+    FloydSteinberg,
+    /// Atkinson error diffusion,
+    /// which only propagates part of the error for a higher contrast
+    Atkinson,
+    /// Jarvis–Judice–Ninke error diffusion,
+    /// spreading the error over a wider neighborhood
+    JarvisJudiceNinke,
+    /// Sierra Lite error diffusion
+    SierraLite,
+    /// Random (white noise) threshold dithering,
+    /// reproducible with the seed in the color options
+    Random,
+    /// Ordered dithering with a 2x2 Bayer matrix
+    Bayer2,
+    /// Ordered dithering with a 4x4 Bayer matrix
+    Bayer4,
+    /// Ordered dithering with an 8x8 Bayer matrix
+    Bayer8,
+    /// No dithering, each pixel is mapped to the nearest color
+    None,
+}
+
+impl DitherMode {
+    /// All dithering modes.
+    pub const ALL: [DitherMode; 9] = [
+        DitherMode::FloydSteinberg,
+        DitherMode::Atkinson,
+        DitherMode::JarvisJudiceNinke,
+        DitherMode::SierraLite,
+        DitherMode::Random,
+        DitherMode::Bayer2,
+        DitherMode::Bayer4,
+        DitherMode::Bayer8,
+        DitherMode::None,
+    ];
+}
+
+impl std::fmt::Display for DitherMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DitherMode::FloydSteinberg => f.write_str("floyd-steinberg"),
+            DitherMode::Atkinson => f.write_str("atkinson"),
+            DitherMode::JarvisJudiceNinke => f.write_str("jarvis-judice-ninke"),
+            DitherMode::SierraLite => f.write_str("sierra-lite"),
+            DitherMode::Random => f.write_str("random"),
+            DitherMode::Bayer2 => f.write_str("bayer2"),
+            DitherMode::Bayer4 => f.write_str("bayer4"),
+            DitherMode::Bayer8 => f.write_str("bayer8"),
+            DitherMode::None => f.write_str("none"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`DitherMode`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct DitherModeParseError;
+
+impl std::fmt::Display for DitherModeParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("no such dithering mode")
+    }
+}
+
+impl std::error::Error for DitherModeParseError {}
+
+impl FromStr for DitherMode {
+    type Err = DitherModeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "floyd-steinberg" | "fs" => Ok(DitherMode::FloydSteinberg),
+            "atkinson" => Ok(DitherMode::Atkinson),
+            "jarvis-judice-ninke" | "jjn" => Ok(DitherMode::JarvisJudiceNinke),
+            "sierra-lite" | "sierra" => Ok(DitherMode::SierraLite),
+            "random" | "noise" => Ok(DitherMode::Random),
+            "bayer2" | "ordered2" => Ok(DitherMode::Bayer2),
+            "bayer4" | "ordered4" | "bayer" | "ordered" => Ok(DitherMode::Bayer4),
+            "bayer8" | "ordered8" => Ok(DitherMode::Bayer8),
+            "none" | "off" => Ok(DitherMode::None),
+            _ => Err(DitherModeParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for DitherMode {
+    type Error = DitherModeParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<DitherMode> for String {
+    fn from(mode: DitherMode) -> Self {
+        mode.to_string()
+    }
+}
+
+/// The RGB samples of a color,
+/// on the scale of 8-bit samples.
+type Sample = [f32; 3];
+
+/// The samples of a color as they are encoded.
+pub(crate) fn sample(c: Color) -> Sample {
+    [f32::from(c.r), f32::from(c.g), f32::from(c.b)]
+}
+
+/// The samples of the colors, in linear light if `linear` is set.
+fn samples(colors: &[Color], linear: bool) -> Vec<Sample> {
+    if linear {
+        colors.iter().map(|&c| to_linear(c)).collect()
+    } else {
+        colors.iter().map(|&c| sample(c)).collect()
+    }
+}
+
+/// Map each pixel to the index of a palette color,
+/// with the dithering mode of the given options,
+/// in linear light if requested.
+///
+/// The transparent pixels in the alpha channel of the options
+/// neither take nor spread any error.
+pub(crate) fn remap(
+    pixels: &[Color],
+    width: u32,
+    palette: &[Color],
+    options: &ColorOptions,
+) -> Vec<usize> {
+    remap_masked(pixels, width, palette, options, options.alpha.as_deref())
+}
+
+/// Map each pixel to the index of a palette color like [`remap`],
+/// with the alpha value of each pixel given apart from the options.
+pub(crate) fn remap_masked(
+    pixels: &[Color],
+    width: u32,
+    palette: &[Color],
+    options: &ColorOptions,
+    alpha: Option<&[u8]>,
+) -> Vec<usize> {
+    progress::report(options.progress.as_ref(), Stage::Dither, 0.);
+    let alpha = alpha.filter(|alpha| alpha.len() == pixels.len());
+    let indices = remap_strip(
+        &samples(pixels, options.linear),
+        width,
+        &samples(palette, options.linear),
+        options.dither,
+        alpha,
+        &mut StripState::new(options.seed),
+    );
+    progress::report(options.progress.as_ref(), Stage::Dither, 1.);
+    indices
+}
+
+/// The dithering state carried over from one strip of rows to the next.
+struct StripState {
+    /// The row at which the next strip starts
+    row: u32,
+    /// The error diffused into the rows below the last strip
+    carry: Vec<[f32; 3]>,
+    rng: SplitMix64,
+}
+
+impl StripState {
+    fn new(seed: Option<u64>) -> Self {
+        StripState {
+            row: 0,
+            carry: Vec::new(),
+            rng: SplitMix64(seed.unwrap_or(0)),
+        }
+    }
+}
+
+/// Map the pixels of the next strip of whole rows,
+/// continuing from the dithering state of the rows above.
+///
+/// The pixels which are transparent in `alpha`
+/// neither take nor spread any diffused error.
+fn remap_strip(
+    pixels: &[Sample],
+    width: u32,
+    palette: &[Sample],
+    mode: DitherMode,
+    alpha: Option<&[u8]>,
+    state: &mut StripState,
+) -> Vec<usize> {
+    let carry = &mut state.carry;
+    let indices = match mode {
+        DitherMode::FloydSteinberg => {
+            remap_diffusion(pixels, width, palette, alpha, &FLOYD_STEINBERG, 16., carry)
+        }
+        DitherMode::Atkinson => {
+            remap_diffusion(pixels, width, palette, alpha, &ATKINSON, 8., carry)
+        }
+        DitherMode::JarvisJudiceNinke => {
+            let kernel = &JARVIS_JUDICE_NINKE;
+            remap_diffusion(pixels, width, palette, alpha, kernel, 48., carry)
+        }
+        DitherMode::SierraLite => {
+            remap_diffusion(pixels, width, palette, alpha, &SIERRA_LITE, 4., carry)
+        }
+        DitherMode::Bayer2 => remap_ordered(pixels, width, palette, 2, state.row),
+        DitherMode::Bayer4 => remap_ordered(pixels, width, palette, 4, state.row),
+        DitherMode::Bayer8 => remap_ordered(pixels, width, palette, 8, state.row),
+        DitherMode::Random => remap_random(pixels, palette, &mut state.rng),
+        DitherMode::None => {
+            #[cfg(feature = "rayon")]
+            let pixels = pixels.par_iter();
+            #[cfg(not(feature = "rayon"))]
+            let pixels = pixels.iter();
+            pixels.map(|&c| nearest(palette, c)).collect()
+        }
+    };
+    state.row += (pixels.len() / width.max(1) as usize) as u32;
+    indices
+}
+
+/// Maps an image to palette indices one strip of rows at a time,
+/// carrying the dithering state over from each strip to the next
+/// so that there are no seams between them.
+pub(crate) struct StripRemapper {
+    /// The palette, in linear light if asked for
+    palette: Vec<Sample>,
+    width: u32,
+    mode: DitherMode,
+    linear: bool,
+    state: StripState,
+}
+
+impl StripRemapper {
+    /// Prepare to map an image of the given width to the palette,
+    /// with the dithering mode of the given options,
+    /// in linear light if requested.
+    pub(crate) fn new(width: u32, palette: &[Color], options: &ColorOptions) -> Self {
+        StripRemapper {
+            palette: samples(palette, options.linear),
+            width,
+            mode: options.dither,
+            linear: options.linear,
+            state: StripState::new(options.seed),
+        }
+    }
+
+    /// Map the pixels of the next strip of whole rows
+    /// to the index of a palette color each.
+    pub(crate) fn remap(&mut self, pixels: &[Color]) -> Vec<usize> {
+        remap_strip(
+            &samples(pixels, self.linear),
+            self.width,
+            &self.palette,
+            self.mode,
+            None,
+            &mut self.state,
+        )
+    }
+}
+
+/// The index of the palette color nearest to the given samples.
+pub(crate) fn nearest(palette: &[Sample], [r, g, b]: Sample) -> usize {
+    palette
+        .iter()
+        .map(|c| {
+            let (dr, dg, db) = (r - c[0], g - c[1], b - c[2]);
+            dr * dr + dg * dg + db * db
+        })
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(0, |(i, _)| i)
+}
+
+/// Error diffusion kernels,
+/// as `(dx, dy, weight)` relative to the current pixel.
+type Kernel = [(i32, i32, f32)];
+
+const ATKINSON: [(i32, i32, f32); 6] = [
+    (1, 0, 1.),
+    (2, 0, 1.),
+    (-1, 1, 1.),
+    (0, 1, 1.),
+    (1, 1, 1.),
+    (0, 2, 1.),
+];
+
+const JARVIS_JUDICE_NINKE: [(i32, i32, f32); 12] = [
+    (1, 0, 7.),
+    (2, 0, 5.),
+    (-2, 1, 3.),
+    (-1, 1, 5.),
+    (0, 1, 7.),
+    (1, 1, 5.),
+    (2, 1, 3.),
+    (-2, 2, 1.),
+    (-1, 2, 3.),
+    (0, 2, 5.),
+    (1, 2, 3.),
+    (2, 2, 1.),
+];
+
+const SIERRA_LITE: [(i32, i32, f32); 3] = [(1, 0, 2.), (-1, 1, 1.), (0, 1, 1.)];
+
+const FLOYD_STEINBERG: [(i32, i32, f32); 4] = [(1, 0, 7.), (-1, 1, 3.), (0, 1, 5.), (1, 1, 1.)];
+
+/// Map the pixels with error diffusion,
+/// starting from the error in `carry` diffused by the rows above
+/// and leaving in it the error diffused into the rows below.
+///
+/// The pixels which are transparent in `alpha`
+/// are mapped to the nearest color,
+/// ignoring the error diffused into them
+/// and spreading none of their own.
+fn remap_diffusion(
+    pixels: &[Sample],
+    width: u32,
+    palette: &[Sample],
+    alpha: Option<&[u8]>,
+    kernel: &Kernel,
+    divisor: f32,
+    carry: &mut Vec<[f32; 3]>,
+) -> Vec<usize> {
+    let width = width.max(1) as i32;
+    let rows_below = kernel.iter().map(|&(_, dy, _)| dy).max().unwrap_or(0);
+    let mut errors = vec![[0_f32; 3]; pixels.len() + (rows_below * width) as usize];
+    for (e, carried) in errors.iter_mut().zip(carry.iter()) {
+        *e = *carried;
+    }
+    let indices = pixels
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            if alpha.map_or(false, |alpha| alpha[i] == 0) {
+                return nearest(palette, *c);
+            }
+            let mut value = *c;
+            for (v, e) in value.iter_mut().zip(&errors[i]) {
+                *v = (*v + e).clamp(0., 255.);
+            }
+            let index = nearest(palette, value);
+            let chosen = palette[index];
+            let error = [
+                value[0] - chosen[0],
+                value[1] - chosen[1],
+                value[2] - chosen[2],
+            ];
+
+            let (x, y) = (i as i32 % width, i as i32 / width);
+            for &(dx, dy, weight) in kernel {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || nx >= width {
+                    continue;
+                }
+                if let Some(e) = errors.get_mut((ny * width + nx) as usize) {
+                    for (e, error) in e.iter_mut().zip(&error) {
+                        *e += error * weight / divisor;
+                    }
+                }
+            }
+            index
+        })
+        .collect();
+    *carry = errors.split_off(pixels.len());
+    indices
+}
+
+fn remap_random(pixels: &[Sample], palette: &[Sample], rng: &mut SplitMix64) -> Vec<usize> {
+    let amplitude = noise_amplitude(palette);
+    pixels
+        .iter()
+        .map(|c| {
+            // the same offset on all channels keeps the grain neutral
+            let noise = rng.next_f32() * 2. - 1.;
+            let offset = noise * amplitude;
+            nearest(palette, [c[0] + offset, c[1] + offset, c[2] + offset])
+        })
+        .collect()
+}
+
+fn remap_ordered(
+    pixels: &[Sample],
+    width: u32,
+    palette: &[Sample],
+    size: u32,
+    first_row: u32,
+) -> Vec<usize> {
+    let amplitude = noise_amplitude(palette);
+    let levels = (size * size) as f32;
+    let width = width.max(1) as usize;
+    #[cfg(feature = "rayon")]
+    let pixels = pixels.par_iter();
+    #[cfg(not(feature = "rayon"))]
+    let pixels = pixels.iter();
+    pixels
+        .enumerate()
+        .map(|(i, c)| {
+            let (x, y) = ((i % width) as u32, first_row + (i / width) as u32);
+            // centered so that flat areas keep their mean brightness
+            let threshold = (bayer(x, y, size) as f32 + 0.5) / levels * 2. - 1.;
+            let offset = threshold * amplitude;
+            nearest(palette, [c[0] + offset, c[1] + offset, c[2] + offset])
+        })
+        .collect()
+}
+
+/// The value of the Bayer threshold matrix of the given size
+/// (a power of two) at `(x, y)`, in `0..size * size`.
+fn bayer(x: u32, y: u32, size: u32) -> u32 {
+    const BASE: [[u32; 2]; 2] = [[0, 2], [3, 1]];
+    let (x, y) = (x % size, y % size);
+    let mut value = 0;
+    let mut weight = 1;
+    let mut step = size;
+    while step > 1 {
+        step /= 2;
+        value += weight * BASE[((y / step) % 2) as usize][((x / step) % 2) as usize];
+        weight *= 4;
+    }
+    value
+}
+
+/// Half of the mean distance from each palette color to its nearest neighbor,
+/// so that the noise is enough to reach adjacent colors
+/// but not much further.
+fn noise_amplitude(palette: &[Sample]) -> f32 {
+    if palette.len() < 2 {
+        return 0.;
+    }
+    let total: f32 = palette
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            palette
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, b)| {
+                    let (dr, dg, db) = (a[0] - b[0], a[1] - b[1], a[2] - b[2]);
+                    (dr * dr + dg * dg + db * db).sqrt()
+                })
+                .fold(f32::INFINITY, f32::min)
+        })
+        .sum();
+    total / palette.len() as f32 / 2.
+}
+
+/// A small, seedable pseudo-random number generator
+/// (SplitMix64), enough for dithering noise.
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, 1)`.
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1_u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bayer, remap, remap_masked, DitherMode};
+    use crate::color::ColorOptions;
+    use exoquant::Color;
+
+    #[test]
+    fn test_random_dither_is_reproducible() {
+        let palette = [
+            Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+            Color {
+                r: 255,
+                g: 255,
+                b: 255,
+                a: 255,
+            },
+        ];
+        let gray = Color {
+            r: 128,
+            g: 128,
+            b: 128,
+            a: 255,
+        };
+        let pixels = vec![gray; 64];
+        let options = ColorOptions {
+            dither: DitherMode::Random,
+            seed: Some(7),
+            ..Default::default()
+        };
+
+        let a = remap(&pixels, 8, &palette, &options);
+        let b = remap(&pixels, 8, &palette, &options);
+        assert_eq!(a, b);
+        // mid gray should turn into a mix of both colors
+        assert!(a.contains(&0) && a.contains(&1));
+    }
+
+    #[test]
+    fn test_no_dither() {
+        let palette = [
+            Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+            Color {
+                r: 255,
+                g: 255,
+                b: 255,
+                a: 255,
+            },
+        ];
+        let gray = Color {
+            r: 100,
+            g: 100,
+            b: 100,
+            a: 255,
+        };
+        let pixels = vec![gray; 16];
+        let options = ColorOptions {
+            dither: DitherMode::None,
+            ..Default::default()
+        };
+        assert_eq!(remap(&pixels, 4, &palette, &options), vec![0; 16]);
+
+        let options = ColorOptions {
+            dither: DitherMode::Atkinson,
+            ..Default::default()
+        };
+        assert!(remap(&pixels, 4, &palette, &options).contains(&1));
+    }
+
+    #[test]
+    fn test_transparent_pixels_spread_no_error() {
+        let palette = [
+            Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+            Color {
+                r: 255,
+                g: 255,
+                b: 255,
+                a: 255,
+            },
+        ];
+        let gray = Color {
+            r: 100,
+            g: 100,
+            b: 100,
+            a: 255,
+        };
+        let options = ColorOptions::default();
+        assert_eq!(remap(&[gray; 2], 2, &palette, &options), vec![0, 1]);
+        let alpha = [0, 255];
+        assert_eq!(
+            remap_masked(&[gray; 2], 2, &palette, &options, Some(&alpha)),
+            vec![0, 0]
+        );
+    }
+
+    #[test]
+    fn test_remap_large_palette() {
+        let palette = (0..512)
+            .map(|i| Color {
+                r: (i / 2) as u8,
+                g: (i % 2) as u8,
+                b: 0,
+                a: 255,
+            })
+            .collect::<Vec<_>>();
+        let last = palette[511];
+        for &dither in &[DitherMode::FloydSteinberg, DitherMode::None] {
+            let options = ColorOptions {
+                dither,
+                ..Default::default()
+            };
+            assert_eq!(remap(&[last; 4], 2, &palette, &options), vec![511; 4]);
+        }
+    }
+
+    #[test]
+    fn test_bayer_matrix() {
+        assert_eq!(
+            (0..4).map(|x| bayer(x, 0, 4)).collect::<Vec<_>>(),
+            vec![0, 8, 2, 10]
+        );
+        let mut values: Vec<_> = (0..64).map(|i| bayer(i % 8, i / 8, 8)).collect();
+        values.sort_unstable();
+        assert_eq!(values, (0..64).collect::<Vec<_>>());
+    }
+}
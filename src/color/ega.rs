@@ -1,9 +1,14 @@
 //! EGA color palettes.
-use super::FixedPalette;
+use super::{FixedPalette, RegisterPalette};
 
 /// 64 color palette established by the full-color EGA standard.
 pub static PALETTE_EGA_6BIT: FixedPalette<&[[u8; 3]]> = FixedPalette(EGA_6BIT);
 
+/// EGA as it was actually displayed:
+/// 16 colors at once, chosen from the 64 color palette
+/// (2 bits per sample).
+pub static PALETTE_EGA_16: RegisterPalette = RegisterPalette::with_bits(2, 16);
+
 /// 64 color palette established by the full-color EGA standard.
 pub static EGA_6BIT: &[[u8; 3]] = &[
     [0, 0, 0],       // CGA 0
@@ -71,3 +76,25 @@ pub static EGA_6BIT: &[[u8; 3]] = &[
     [0xFF, 0xFF, 0x55], // CGA 14
     [0xFF, 0xFF, 0xFF], // CGA 15
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::{EGA_6BIT, PALETTE_EGA_16};
+    use crate::color::{ColorDepth, ColorOptions};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_ega_16_palette() {
+        let image = RgbImage::from_fn(32, 32, |x, y| {
+            Rgb([(x * 8) as u8, (y * 8) as u8, ((x + y) * 4) as u8])
+        });
+        let (pixels, palette, _) =
+            PALETTE_EGA_16.convert_image_with_loss(&image, ColorOptions::default());
+        assert!(!palette.is_empty() && palette.len() <= 16);
+        // all colors are in the 64 color palette
+        for c in &palette {
+            assert!(EGA_6BIT.contains(&[c.r, c.g, c.b]));
+        }
+        assert!(pixels.iter().all(|c| palette.contains(c)));
+    }
+}
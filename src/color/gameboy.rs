@@ -0,0 +1,18 @@
+//! Game Boy (DMG) shades.
+use super::mono::ShadePalette;
+
+/// The resolution of the Game Boy screen, as `[width, height]`.
+pub const RESOLUTION: [u32; 2] = [160, 144];
+
+/// The 4 shades of green of the original Game Boy,
+/// mapped by luminance.
+pub static PALETTE_DMG: ShadePalette<&[[u8; 3]]> = ShadePalette::new(&DMG);
+
+/// The 4 shades of green of the original Game Boy,
+/// from darkest to lightest.
+pub static DMG: [[u8; 3]; 4] = [
+    [0x0F, 0x38, 0x0F],
+    [0x30, 0x62, 0x30],
+    [0x8B, 0xAC, 0x0F],
+    [0x9B, 0xBC, 0x0F],
+];
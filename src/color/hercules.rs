@@ -0,0 +1,99 @@
+//! Hercules Graphics Card emulation.
+//!
+//! The HGC shows 720x348 monochrome pixels on a 4:3 screen,
+//! so its pixels are much taller than they are wide.
+use super::{
+    dither, indexed_with_loss, restore_protected_colors, ColorDepth, ColorOptions, DitherMode,
+    IndexedImage, BW_1BIT,
+};
+use exoquant::Color;
+use image::{Rgb, RgbImage};
+use itertools::Itertools;
+
+/// The resolution of the Hercules graphics mode, as `[width, height]`.
+pub const RESOLUTION: [u32; 2] = [720, 348];
+
+/// Hercules graphics: 1 bit per pixel, with ordered dithering.
+///
+/// Error diffusion and random dithering modes are replaced
+/// by an 8x8 Bayer matrix,
+/// which fits the high resolution of the card
+/// and keeps its regular look.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Hercules;
+
+impl Hercules {
+    /// Dither an image to black and white,
+    /// returning the original colors, the palette
+    /// and the palette index of each pixel.
+    fn remap(image: &RgbImage, options: &mut ColorOptions) -> (Vec<Color>, Vec<Color>, Vec<usize>) {
+        options.dither = match options.dither {
+            dither @ (DitherMode::Bayer2
+            | DitherMode::Bayer4
+            | DitherMode::Bayer8
+            | DitherMode::None) => dither,
+            _ => DitherMode::Bayer8,
+        };
+        options.keep_protected_colors_in(BW_1BIT);
+        let original = image
+            .pixels()
+            .map(|&Rgb([r, g, b])| Color { r, g, b, a: 255 })
+            .collect_vec();
+        let palette = BW_1BIT
+            .iter()
+            .map(|&[r, g, b]| Color { r, g, b, a: 255 })
+            .collect_vec();
+
+        let indices = dither::remap(&original, image.width(), &palette, options);
+        (original, palette, indices)
+    }
+}
+
+impl ColorDepth for Hercules {
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        let (original, mut palette, indices) = Hercules::remap(image, &mut options);
+        let mut converted_pixels = indices.into_iter().map(|i| palette[i]).collect_vec();
+        restore_protected_colors(
+            &original,
+            &mut converted_pixels,
+            &mut palette,
+            &options.protected_colors,
+        );
+        let loss = options.image_loss(&original, &converted_pixels);
+        (converted_pixels, palette, loss)
+    }
+
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        let (original, palette, indices) = Hercules::remap(image, &mut options);
+        indexed_with_loss(image, &original, palette, indices, &options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Hercules;
+    use crate::color::{ColorDepth, ColorOptions};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_ordered_black_and_white() {
+        let image = RgbImage::from_pixel(16, 16, Rgb([128, 128, 128]));
+        let out = Hercules.convert_image(&image, ColorOptions::default());
+
+        assert!(out
+            .iter()
+            .all(|c| [c.r, c.g, c.b] == [0, 0, 0] || [c.r, c.g, c.b] == [255, 255, 255]));
+        let white = out.iter().filter(|c| c.r == 255).count();
+        assert!((96..=160).contains(&white), "{} white pixels", white);
+        // the pattern repeats every 8 pixels
+        assert_eq!(out[..8], out[8..16]);
+    }
+}
@@ -0,0 +1,283 @@
+//! Palette-indexed images.
+//!
+//! An image converted to a limited palette can be kept
+//! as the list of its colors plus one palette index per pixel,
+//! and saved as an indexed PNG or GIF file,
+//! which is much smaller than a true color file
+//! and can be loaded as is by palette based tools.
+use exoquant::Color;
+use image::{Rgb, RgbImage};
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Write;
+
+/// An error returned when encoding an indexed image.
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum EncodeError {
+    #[snafu(display("failed to encode PNG: {}", source))]
+    Png { source: png::EncodingError },
+    #[snafu(display("failed to encode GIF: {}", source))]
+    Gif { source: gif::EncodingError },
+    /// image is too large for the GIF format
+    TooLarge,
+}
+
+/// An image made of palette indices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedImage {
+    /// The width of the image
+    pub width: u32,
+    /// The height of the image
+    pub height: u32,
+    /// The colors of the image, up to 256
+    pub palette: Vec<Color>,
+    /// The palette index of each pixel, in row major order
+    pub indices: Vec<u8>,
+    /// The palette index shown as transparent, if any
+    pub transparent: Option<u8>,
+}
+
+impl IndexedImage {
+    /// Build an indexed image from its pixel colors,
+    /// with the palette in order of first appearance.
+    ///
+    /// Returns `None` if there are more than 256 distinct colors.
+    pub fn from_colors(width: u32, height: u32, colors: &[Color]) -> Option<Self> {
+        Self::from_palette(width, height, colors, &[])
+    }
+
+    /// Build an indexed image from its pixel colors,
+    /// starting from the given palette
+    /// (unused colors included, in the same order).
+    /// Colors missing from the palette are appended to it
+    /// in order of first appearance.
+    ///
+    /// Returns `None` if the palette ends up with more than 256 colors.
+    pub fn from_palette(
+        width: u32,
+        height: u32,
+        colors: &[Color],
+        palette: &[Color],
+    ) -> Option<Self> {
+        if palette.len() > 256 {
+            return None;
+        }
+        let mut lookup = HashMap::new();
+        for (i, &Color { r, g, b, .. }) in palette.iter().enumerate().rev() {
+            lookup.insert([r, g, b], i as u8);
+        }
+        let mut palette = palette.to_vec();
+        let indices = colors
+            .iter()
+            .map(|&Color { r, g, b, .. }| {
+                if let Some(&index) = lookup.get(&[r, g, b]) {
+                    return Some(index);
+                }
+                let index = u8::try_from(palette.len()).ok()?;
+                palette.push(Color { r, g, b, a: 255 });
+                lookup.insert([r, g, b], index);
+                Some(index)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(IndexedImage {
+            width,
+            height,
+            palette,
+            indices,
+            transparent: None,
+        })
+    }
+
+    /// Build an indexed image from an RGB image.
+    ///
+    /// Returns `None` if there are more than 256 distinct colors.
+    pub fn from_image(image: &RgbImage) -> Option<Self> {
+        let colors: Vec<_> = image
+            .pixels()
+            .map(|&Rgb([r, g, b])| Color { r, g, b, a: 255 })
+            .collect();
+        Self::from_colors(image.width(), image.height(), &colors)
+    }
+
+    /// Mark the given color as transparent,
+    /// moving it to the reserved palette index 0.
+    ///
+    /// The image is left as is if the color is not in the palette.
+    pub fn with_transparent_color(mut self, color: [u8; 3]) -> Self {
+        let index = match self
+            .palette
+            .iter()
+            .position(|&Color { r, g, b, .. }| [r, g, b] == color)
+        {
+            Some(index) => index as u8,
+            None => return self,
+        };
+        self.palette.swap(0, usize::from(index));
+        for i in &mut self.indices {
+            if *i == index {
+                *i = 0;
+            } else if *i == 0 {
+                *i = index;
+            }
+        }
+        self.transparent = Some(0);
+        self
+    }
+
+    /// Turn this into an RGB image.
+    pub fn to_image(&self) -> RgbImage {
+        let mut image = RgbImage::new(self.width, self.height);
+        for (pixel, &index) in image.pixels_mut().zip(&self.indices) {
+            let Color { r, g, b, .. } = self.palette[usize::from(index)];
+            *pixel = Rgb([r, g, b]);
+        }
+        image
+    }
+
+    /// The palette as a flat sequence of RGB samples.
+    pub(crate) fn palette_rgb(&self) -> Vec<u8> {
+        self.palette
+            .iter()
+            .flat_map(|&Color { r, g, b, .. }| [r, g, b])
+            .collect()
+    }
+
+    /// The smallest number of bits per pixel
+    /// which fits all palette indices.
+    fn bit_depth(&self) -> u8 {
+        match self.palette.len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        }
+    }
+
+    /// Write the image as an indexed PNG file,
+    /// with the fewest bits per pixel possible.
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), EncodeError> {
+        let bits = self.bit_depth();
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(match bits {
+            1 => png::BitDepth::One,
+            2 => png::BitDepth::Two,
+            4 => png::BitDepth::Four,
+            _ => png::BitDepth::Eight,
+        });
+        encoder.set_palette(self.palette_rgb());
+        if let Some(index) = self.transparent {
+            let mut alpha = vec![255; usize::from(index) + 1];
+            alpha[usize::from(index)] = 0;
+            encoder.set_trns(alpha);
+        }
+
+        // pack the indices of each row, most significant bits first
+        let per_byte = usize::from(8 / bits);
+        let mut data = Vec::new();
+        for row in self.indices.chunks(self.width.max(1) as usize) {
+            for pixels in row.chunks(per_byte) {
+                let byte = pixels.iter().enumerate().fold(0_u8, |byte, (i, &index)| {
+                    byte | (index << (8 - usize::from(bits) * (i + 1)))
+                });
+                data.push(byte);
+            }
+        }
+
+        let mut writer = encoder.write_header().context(PngSnafu)?;
+        writer.write_image_data(&data).context(PngSnafu)?;
+        writer.finish().context(PngSnafu)
+    }
+
+    /// Write the image as a GIF file with a global palette.
+    pub fn write_gif<W: Write>(&self, writer: W) -> Result<(), EncodeError> {
+        let width = u16::try_from(self.width).ok().context(TooLargeSnafu)?;
+        let height = u16::try_from(self.height).ok().context(TooLargeSnafu)?;
+        let mut encoder =
+            gif::Encoder::new(writer, width, height, &self.palette_rgb()).context(GifSnafu)?;
+        let frame = gif::Frame {
+            width,
+            height,
+            buffer: Cow::Borrowed(&self.indices),
+            transparent: self.transparent,
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).context(GifSnafu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IndexedImage;
+    use crate::color::hercules::Hercules;
+    use crate::color::{colors_to_image, ColorDepth, ColorOptions};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_indexed_png_round_trip() {
+        let image = RgbImage::from_fn(5, 3, |x, y| match (x + y) % 3 {
+            0 => Rgb([0, 0, 0]),
+            1 => Rgb([0x55, 0xFF, 0xFF]),
+            _ => Rgb([0xFF, 0x55, 0xFF]),
+        });
+        let indexed = IndexedImage::from_image(&image).unwrap();
+        assert_eq!(indexed.palette.len(), 3);
+        assert_eq!(indexed.to_image(), image);
+
+        let mut data = Vec::new();
+        indexed.write_png(&mut data).unwrap();
+        let decoded = image::load_from_memory(&data).unwrap().to_rgb8();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn test_indexed_png_transparency() {
+        let image = RgbImage::from_fn(4, 2, |x, _| {
+            if x < 2 {
+                Rgb([0xFF, 0xFF, 0xFF])
+            } else {
+                Rgb([0xFF, 0, 0xFF])
+            }
+        });
+        let indexed = IndexedImage::from_image(&image)
+            .unwrap()
+            .with_transparent_color([0xFF, 0, 0xFF]);
+        assert_eq!(indexed.transparent, Some(0));
+        assert_eq!(indexed.to_image(), image);
+
+        let mut data = Vec::new();
+        indexed.write_png(&mut data).unwrap();
+        let decoded = image::load_from_memory(&data).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(0, 0).0, [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(decoded.get_pixel(3, 1).0[3], 0);
+    }
+
+    #[test]
+    fn test_convert_image_indexed() {
+        let image = RgbImage::from_fn(8, 8, |x, _| {
+            if x == 0 {
+                Rgb([0xFF, 0, 0])
+            } else {
+                Rgb([0x80, 0x80, 0x80])
+            }
+        });
+        let options = ColorOptions {
+            protected_colors: vec![[0xFF, 0, 0]],
+            ..Default::default()
+        };
+        let (indexed, loss) = Hercules
+            .convert_image_indexed(&image, options.clone())
+            .unwrap();
+        // black and white in palette order,
+        // without the protected color which the card cannot show
+        let palette: Vec<_> = indexed.palette.iter().map(|c| [c.r, c.g, c.b]).collect();
+        assert_eq!(palette, [[0, 0, 0], [0xFF, 0xFF, 0xFF]]);
+
+        let (colors, _palette, expected_loss) = Hercules.convert_image_with_loss(&image, options);
+        assert_eq!(indexed.to_image(), colors_to_image(8, 8, colors));
+        assert_eq!(loss, expected_loss);
+    }
+}
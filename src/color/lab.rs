@@ -0,0 +1,144 @@
+//! CIE L*a*b* color space and perceptual color differences.
+//!
+//! Distances between sRGB colors do not match
+//! how different they look,
+//! so nearest color searches in RGB
+//! may pick visibly wrong colors out of a small palette.
+//! The Delta E formulas measure differences in L*a*b* instead,
+//! which was designed to be perceptually uniform.
+use exoquant::Color;
+
+/// The CIE L*a*b* coordinates of a color.
+pub type Lab = [f64; 3];
+
+/// Reference white (D65)
+const WHITE: [f64; 3] = [0.950_47, 1., 1.088_83];
+
+/// Convert an sRGB color to CIE L*a*b* (D65 white point).
+pub fn to_lab(Color { r, g, b, .. }: Color) -> Lab {
+    let linear = |c: u8| {
+        let c = f64::from(c) / 255.;
+        if c <= 0.040_45 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let x = 0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+    let z = 0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b;
+
+    let f = |t: f64| {
+        const DELTA: f64 = 6. / 29.;
+        if t > DELTA * DELTA * DELTA {
+            t.cbrt()
+        } else {
+            t / (3. * DELTA * DELTA) + 4. / 29.
+        }
+    };
+    let (fx, fy, fz) = (f(x / WHITE[0]), f(y / WHITE[1]), f(z / WHITE[2]));
+    [116. * fy - 16., 500. * (fx - fy), 200. * (fy - fz)]
+}
+
+/// The CIE76 color difference:
+/// the Euclidean distance in L*a*b*.
+pub fn delta_e76(lab1: Lab, lab2: Lab) -> f64 {
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+    ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt()
+}
+
+/// The CIEDE2000 color difference,
+/// with unit weighting factors.
+pub fn delta_e2000(lab1: Lab, lab2: Lab) -> f64 {
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+    let pow7 = |x: f64| x.powi(7);
+    let pow25_7 = pow7(25.);
+
+    let c_mean = (a1.hypot(b1) + a2.hypot(b2)) / 2.;
+    let g = 0.5 * (1. - (pow7(c_mean) / (pow7(c_mean) + pow25_7)).sqrt());
+    let (a1, a2) = (a1 * (1. + g), a2 * (1. + g));
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let hue = |a: f64, b: f64| {
+        if a == 0. && b == 0. {
+            0.
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.)
+        }
+    };
+    let (h1, h2) = (hue(a1, b1), hue(a2, b2));
+
+    let dl = l2 - l1;
+    let dc = c2 - c1;
+    let dh = if c1 * c2 == 0. {
+        0.
+    } else if (h2 - h1).abs() <= 180. {
+        h2 - h1
+    } else if h2 - h1 > 180. {
+        h2 - h1 - 360.
+    } else {
+        h2 - h1 + 360.
+    };
+    let dh = 2. * (c1 * c2).sqrt() * (dh / 2.).to_radians().sin();
+
+    let l_mean = (l1 + l2) / 2.;
+    let c_mean = (c1 + c2) / 2.;
+    let h_mean = if c1 * c2 == 0. {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180. {
+        (h1 + h2) / 2.
+    } else if h1 + h2 < 360. {
+        (h1 + h2 + 360.) / 2.
+    } else {
+        (h1 + h2 - 360.) / 2.
+    };
+
+    let cos = |deg: f64| deg.to_radians().cos();
+    let t = 1. - 0.17 * cos(h_mean - 30.) + 0.24 * cos(2. * h_mean) + 0.32 * cos(3. * h_mean + 6.)
+        - 0.20 * cos(4. * h_mean - 63.);
+    let d_theta = 30. * (-((h_mean - 275.) / 25.).powi(2)).exp();
+    let rc = 2. * (pow7(c_mean) / (pow7(c_mean) + pow25_7)).sqrt();
+    let sl = 1. + 0.015 * (l_mean - 50.).powi(2) / (20. + (l_mean - 50.).powi(2)).sqrt();
+    let sc = 1. + 0.045 * c_mean;
+    let sh = 1. + 0.015 * c_mean * t;
+    let rt = -(2. * d_theta).to_radians().sin() * rc;
+
+    let (l, c, h) = (dl / sl, dc / sc, dh / sh);
+    (l * l + c * c + h * h + rt * c * h).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{delta_e2000, to_lab};
+    use exoquant::Color;
+
+    #[test]
+    fn test_delta_e2000() {
+        // from the test data of Sharma, Wu and Dalal (2005)
+        let pairs = [
+            ([50., 2.6772, -79.7751], [50., 0., -82.7485], 2.0425),
+            ([50., 3.1571, -77.2803], [50., 0., -82.7485], 2.8615),
+            ([50., 2.5, 0.], [50., 0., -2.5], 4.3065),
+            (
+                [60.2574, -34.0099, 36.2677],
+                [60.4626, -34.1751, 39.4387],
+                1.2644,
+            ),
+        ];
+        for (lab1, lab2, expected) in pairs {
+            let de = delta_e2000(lab1, lab2);
+            assert!((de - expected).abs() < 1e-4, "{} != {}", de, expected);
+        }
+
+        let white = to_lab(Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        });
+        assert!((white[0] - 100.).abs() < 1e-3);
+        assert!(white[1].abs() < 1e-3 && white[2].abs() < 1e-3);
+    }
+}
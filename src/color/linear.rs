@@ -0,0 +1,92 @@
+//! Conversion between sRGB encoded and linear light colors.
+//!
+//! Averaging sRGB encoded samples, as dithering does,
+//! yields colors darker than the average of the light they represent.
+//! Doing the color math on linear light samples avoids this.
+//! Linear samples are kept as floating point numbers
+//! on the same scale as 8-bit samples (0 to 255),
+//! as 8 bits would lose most of the darkest shades.
+use exoquant::Color;
+
+fn decode(c: u8) -> f32 {
+    let c = f32::from(c) / 255.;
+    let linear = if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    };
+    linear * 255.
+}
+
+fn encode(c: f32) -> u8 {
+    let c = (c / 255.).clamp(0., 1.);
+    let srgb = if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    };
+    (srgb * 255.).round().clamp(0., 255.) as u8
+}
+
+/// Convert an sRGB encoded color to linear light samples.
+pub fn to_linear(Color { r, g, b, .. }: Color) -> [f32; 3] {
+    [decode(r), decode(g), decode(b)]
+}
+
+/// Convert linear light samples to an opaque sRGB encoded color.
+pub fn to_srgb([r, g, b]: [f32; 3]) -> Color {
+    Color {
+        r: encode(r),
+        g: encode(g),
+        b: encode(b),
+        a: 255,
+    }
+}
+
+/// Convert an sRGB encoded color to linear light,
+/// rounded to 8 bits for the palette optimizers
+/// (some precision is lost in the darkest shades).
+pub(crate) fn to_linear8(c: Color) -> Color {
+    let [r, g, b] = to_linear(c);
+    let round = |v: f32| v.round() as u8;
+    Color {
+        r: round(r),
+        g: round(g),
+        b: round(b),
+        a: c.a,
+    }
+}
+
+/// Convert an 8-bit linear light color to sRGB encoding.
+pub(crate) fn to_srgb8(c: Color) -> Color {
+    Color {
+        a: c.a,
+        ..to_srgb([f32::from(c.r), f32::from(c.g), f32::from(c.b)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_linear, to_linear8, to_srgb, to_srgb8};
+    use exoquant::Color;
+
+    #[test]
+    fn test_linear_round_trip() {
+        let gray = |v| Color {
+            r: v,
+            g: v,
+            b: v,
+            a: 255,
+        };
+        // 50% light is much brighter than 50% sRGB
+        assert_eq!(to_srgb([128.; 3]).r, 188);
+        assert_eq!(to_linear8(gray(188)).r, 128);
+        assert_eq!(to_srgb8(gray(128)).r, 188);
+        for v in 0..=255 {
+            assert_eq!(to_srgb(to_linear(gray(v))).r, v);
+        }
+        // the darkest shades stay apart
+        assert!(to_linear(gray(1))[0] < to_linear(gray(2))[0]);
+        assert!(to_linear(gray(2))[0] < 1.);
+    }
+}
@@ -0,0 +1,207 @@
+//! Fast nearest color lookup.
+//!
+//! Finding the color of a palette nearest to another one
+//! by comparing it with every palette color
+//! gets slow for large palettes such as the 256 colors of VGA,
+//! and it is done for every color mapped to a fixed palette.
+//! Instead, the palette is arranged once into a k-d tree,
+//! which narrows each search down to the few colors
+//! that can be the nearest.
+use super::{ColorMapper, LossAlgorithm};
+use exoquant::Color;
+
+/// Palettes up to this size are searched linearly,
+/// which is just as fast.
+const MAX_LINEAR_LEN: usize = 16;
+
+/// A palette of RGB colors prepared for nearest color lookups,
+/// which yield the same colors as [`LossAlgorithm::nearest`].
+///
+/// The Delta E losses are not measured along the RGB axes,
+/// so palettes are always searched linearly with them.
+#[derive(Debug, Clone)]
+pub struct NearestColor<'a> {
+    palette: &'a [[u8; 3]],
+    loss: LossAlgorithm,
+    /// The palette indices in k-d tree order:
+    /// each range of nodes is split at its middle node
+    /// into the ranges before and after it,
+    /// empty if the palette is searched linearly
+    nodes: Vec<u32>,
+    /// The axis on which each node splits its range
+    axes: Vec<u8>,
+}
+
+impl<'a> NearestColor<'a> {
+    /// Prepare the given palette for nearest color lookups
+    /// with the given loss algorithm.
+    pub fn new(palette: &'a [[u8; 3]], loss: LossAlgorithm) -> Self {
+        let tree =
+            palette.len() > MAX_LINEAR_LEN && matches!(loss, LossAlgorithm::L1 | LossAlgorithm::L2);
+        let mut nodes = Vec::new();
+        let mut axes = Vec::new();
+        if tree {
+            nodes = (0..palette.len() as u32).collect();
+            axes = vec![0; palette.len()];
+            build(palette, &mut nodes, &mut axes);
+        }
+        NearestColor {
+            palette,
+            loss,
+            nodes,
+            axes,
+        }
+    }
+
+    /// Find the index of the palette color nearest to `pixel`,
+    /// the first one if there is a tie.
+    ///
+    /// Returns `None` if the palette is empty.
+    pub fn nearest_index(&self, pixel: Color) -> Option<usize> {
+        if self.nodes.is_empty() {
+            let nearest = self.loss.nearest(pixel, self.palette)?;
+            return self.palette.iter().position(|&c| c == nearest);
+        }
+        let mut best = None;
+        self.search(
+            &self.nodes,
+            &self.axes,
+            [pixel.r, pixel.g, pixel.b],
+            &mut best,
+        );
+        best.map(|(_, i)| i as usize)
+    }
+
+    /// Find the palette color nearest to `pixel`.
+    ///
+    /// Returns `None` if the palette is empty.
+    pub fn nearest(&self, pixel: Color) -> Option<[u8; 3]> {
+        self.nearest_index(pixel).map(|i| self.palette[i])
+    }
+
+    /// Search a range of the tree,
+    /// keeping the lowest distance and palette index in `best`.
+    fn search(&self, nodes: &[u32], axes: &[u8], pixel: [u8; 3], best: &mut Option<(u64, u32)>) {
+        if nodes.is_empty() {
+            return;
+        }
+        let mid = nodes.len() / 2;
+        let index = nodes[mid];
+        let candidate = (self.distance(pixel, self.palette[index as usize]), index);
+        if best.map_or(true, |best| candidate < best) {
+            *best = Some(candidate);
+        }
+
+        let axis = usize::from(axes[mid]);
+        let diff = i64::from(pixel[axis]) - i64::from(self.palette[index as usize][axis]);
+        let (before, after) = (0..mid, mid + 1..nodes.len());
+        let (near, far) = if diff < 0 {
+            (before, after)
+        } else {
+            (after, before)
+        };
+        self.search(&nodes[near.clone()], &axes[near], pixel, best);
+        // the colors on the far side are at least this far along the axis,
+        // but may still tie with the best one
+        let bound = match self.loss {
+            LossAlgorithm::L2 => diff.unsigned_abs() * diff.unsigned_abs(),
+            _ => diff.unsigned_abs(),
+        };
+        if best.map_or(true, |(distance, _)| bound <= distance) {
+            self.search(&nodes[far.clone()], &axes[far], pixel, best);
+        }
+    }
+
+    /// The distance between two colors,
+    /// squared for the L2 loss like in [`LossAlgorithm::nearest`].
+    fn distance(&self, a: [u8; 3], b: [u8; 3]) -> u64 {
+        let diffs = a
+            .iter()
+            .zip(&b)
+            .map(|(&a, &b)| (i64::from(a) - i64::from(b)).unsigned_abs());
+        match self.loss {
+            LossAlgorithm::L2 => diffs.map(|d| d * d).sum(),
+            _ => diffs.sum(),
+        }
+    }
+}
+
+impl ColorMapper for NearestColor<'_> {
+    /// Convert a color to the nearest palette color.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the palette is empty.
+    fn convert_color(&self, c: Color) -> Color {
+        let [r, g, b] = self.nearest(c).expect("the palette should not be empty");
+        Color { r, g, b, a: 255 }
+    }
+}
+
+/// Arrange the palette indices in `nodes` into a k-d tree,
+/// splitting each range on the axis where its colors spread the most.
+fn build(palette: &[[u8; 3]], nodes: &mut [u32], axes: &mut [u8]) {
+    if nodes.is_empty() {
+        return;
+    }
+    let axis = (0..3)
+        .max_by_key(|&axis| {
+            let values = nodes.iter().map(|&i| palette[i as usize][axis]);
+            let min = values.clone().min().unwrap();
+            let max = values.max().unwrap();
+            max - min
+        })
+        .unwrap();
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by_key(mid, |&i| palette[i as usize][axis]);
+    axes[mid] = axis as u8;
+    let (left, right) = nodes.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(palette, left, left_axes);
+    build(palette, &mut right[1..], &mut right_axes[1..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NearestColor;
+    use crate::color::vga::VGA_256;
+    use crate::color::LossAlgorithm;
+    use exoquant::Color;
+
+    #[test]
+    fn test_nearest_matches_linear_search() {
+        // a palette with duplicates and ties, besides the VGA palette
+        let grid: Vec<[u8; 3]> = (0..64_u8)
+            .map(|i| [(i % 4) * 85, (i / 4 % 4) * 85, (i / 16) * 85])
+            .chain(vec![[0, 0, 0], [85, 85, 85]])
+            .collect();
+        for palette in [&VGA_256[..], &grid[..]] {
+            for &loss in &[LossAlgorithm::L1, LossAlgorithm::L2] {
+                let lookup = NearestColor::new(palette, loss);
+                for i in 0..4096_u32 {
+                    let pixel = Color {
+                        r: (i * 37 % 256) as u8,
+                        g: (i * 101 % 256) as u8,
+                        b: (i * 13 % 256) as u8,
+                        a: 255,
+                    };
+                    let expected = loss.nearest(pixel, palette);
+                    assert_eq!(lookup.nearest(pixel), expected);
+                    let index = lookup.nearest_index(pixel).unwrap();
+                    assert_eq!(
+                        palette.iter().position(|&c| Some(c) == expected),
+                        Some(index)
+                    );
+                }
+            }
+        }
+        let empty = NearestColor::new(&[], LossAlgorithm::L2);
+        let black = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        assert_eq!(empty.nearest(black), None);
+    }
+}
@@ -0,0 +1,161 @@
+//! Image quality metrics.
+//!
+//! The loss of a color conversion
+//! depends on the chosen [loss algorithm](super::LossAlgorithm)
+//! and on the size of the image,
+//! so it only compares conversions of the same image.
+//! The peak signal-to-noise ratio (PSNR)
+//! and the structural similarity index (SSIM)
+//! are standard measures which can be compared across images.
+use image::RgbImage;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// The side of the square windows over which SSIM is computed
+const SSIM_WINDOW: u32 = 8;
+
+/// Quality metrics of an image against a reference image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Metrics {
+    /// Peak signal-to-noise ratio in decibels,
+    /// or `None` (infinite) if the images are the same
+    pub psnr: Option<f64>,
+    /// Mean structural similarity of the luminance,
+    /// from -1 to 1 (the same image)
+    pub ssim: f64,
+}
+
+impl Metrics {
+    /// Measure the quality of an image against the original.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the images do not have the same dimensions.
+    pub fn measure(original: &RgbImage, converted: &RgbImage) -> Self {
+        Metrics {
+            psnr: psnr(original, converted),
+            ssim: ssim(original, converted),
+        }
+    }
+}
+
+/// The peak signal-to-noise ratio of an image against the original,
+/// in decibels over all RGB samples.
+/// It is infinite if the images are the same,
+/// which is reported as `None`.
+///
+/// # Panics
+///
+/// Panics if the images do not have the same dimensions.
+pub fn psnr(original: &RgbImage, converted: &RgbImage) -> Option<f64> {
+    assert_eq!(original.dimensions(), converted.dimensions());
+    let samples = original.as_raw().len();
+    let squared_error: u64 = original
+        .as_raw()
+        .iter()
+        .zip(converted.as_raw())
+        .map(|(&a, &b)| {
+            let d = i64::from(a) - i64::from(b);
+            (d * d) as u64
+        })
+        .sum();
+    if squared_error == 0 {
+        return None;
+    }
+    let mse = squared_error as f64 / samples as f64;
+    Some(10. * (255. * 255. / mse).log10())
+}
+
+/// The mean structural similarity of the luminance of an image
+/// against the original, over half-overlapping 8x8 windows
+/// (or a single window, if the image is smaller).
+///
+/// # Panics
+///
+/// Panics if the images do not have the same dimensions.
+pub fn ssim(original: &RgbImage, converted: &RgbImage) -> f64 {
+    assert_eq!(original.dimensions(), converted.dimensions());
+    let (width, height) = original.dimensions();
+    if width == 0 || height == 0 {
+        return 1.;
+    }
+    let a = luminance(original);
+    let b = luminance(converted);
+
+    const C1: f64 = (0.01 * 255.) * (0.01 * 255.);
+    const C2: f64 = (0.03 * 255.) * (0.03 * 255.);
+    let window_width = SSIM_WINDOW.min(width);
+    let window_height = SSIM_WINDOW.min(height);
+    let step = |window: u32| (window / 2).max(1) as usize;
+
+    let mut total = 0.;
+    let mut count = 0;
+    for y in (0..=height - window_height).step_by(step(window_height)) {
+        for x in (0..=width - window_width).step_by(step(window_width)) {
+            let window = (y..y + window_height).flat_map(|y| {
+                let row = (y * width) as usize;
+                (row + x as usize..row + (x + window_width) as usize).map(|i| (a[i], b[i]))
+            });
+            let n = f64::from(window_width * window_height);
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0., 0., 0., 0., 0.);
+            for (a, b) in window {
+                sum_a += a;
+                sum_b += b;
+                sum_aa += a * a;
+                sum_bb += b * b;
+                sum_ab += a * b;
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2. * mean_a * mean_b + C1) * (2. * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            count += 1;
+        }
+    }
+    total / f64::from(count)
+}
+
+/// The luminance of each pixel (Rec. 601).
+fn luminance(image: &RgbImage) -> Vec<f64> {
+    image
+        .pixels()
+        .map(|p| 0.299 * f64::from(p[0]) + 0.587 * f64::from(p[1]) + 0.114 * f64::from(p[2]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{psnr, ssim, Metrics};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_metrics() {
+        let original = RgbImage::from_fn(16, 16, |x, y| {
+            let v = ((x * 16 + y * 8) % 256) as u8;
+            Rgb([v, v / 2, 255 - v])
+        });
+        let same = Metrics::measure(&original, &original);
+        assert_eq!(same.psnr, None);
+        assert!((same.ssim - 1.).abs() < 1e-9);
+
+        // every sample off by 16: MSE of 256
+        let mut shifted = original.clone();
+        for sample in shifted.iter_mut() {
+            *sample = if *sample < 128 {
+                *sample + 16
+            } else {
+                *sample - 16
+            };
+        }
+        let expected = 10. * (255. * 255. / 256_f64).log10();
+        assert!((psnr(&original, &shifted).unwrap() - expected).abs() < 1e-9);
+
+        // losing the structure is worse than a slight shift
+        let flat = RgbImage::from_pixel(16, 16, Rgb([128, 64, 127]));
+        assert!(ssim(&original, &flat) < ssim(&original, &shifted));
+        assert!(ssim(&original, &shifted) < 1.);
+    }
+}
@@ -0,0 +1,330 @@
+//! Luminance based color depths.
+//!
+//! The image is reduced to its luminance,
+//! quantized to a number of intensity levels,
+//! and each level is shown as a fixed shade,
+//! such as the tint of a monochrome monitor's phosphor.
+use super::{
+    dither, indexed_with_loss, restore_protected_colors, ColorDepth, ColorOptions, IndexedImage,
+};
+use exoquant::Color;
+use image::{Rgb, RgbImage};
+use itertools::Itertools;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Enumeration of monitor phosphors.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum Phosphor {
+    /// P1 green phosphor
+    #[default]
+    Green,
+    /// P3 amber phosphor
+    Amber,
+    /// P4 white phosphor, slightly blue
+    White,
+    /// Paper white phosphor, slightly warm
+    PaperWhite,
+}
+
+impl Phosphor {
+    /// The RGB color of the phosphor at full intensity.
+    pub fn color(self) -> [u8; 3] {
+        match self {
+            Phosphor::Green => [0x33, 0xFF, 0x33],
+            Phosphor::Amber => [0xFF, 0xB0, 0x00],
+            Phosphor::White => [0xE8, 0xF0, 0xFF],
+            Phosphor::PaperWhite => [0xFF, 0xF8, 0xE6],
+        }
+    }
+}
+
+impl std::fmt::Display for Phosphor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phosphor::Green => f.write_str("green"),
+            Phosphor::Amber => f.write_str("amber"),
+            Phosphor::White => f.write_str("white"),
+            Phosphor::PaperWhite => f.write_str("paper-white"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`Phosphor`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct PhosphorParseError;
+
+impl std::fmt::Display for PhosphorParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            "invalid phosphor, should be \"green\", \"amber\", \"white\" or \"paper-white\"",
+        )
+    }
+}
+
+impl std::error::Error for PhosphorParseError {}
+
+impl FromStr for Phosphor {
+    type Err = PhosphorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "green" | "p1" => Ok(Phosphor::Green),
+            "amber" | "p3" => Ok(Phosphor::Amber),
+            "white" | "p4" => Ok(Phosphor::White),
+            "paper-white" | "paperwhite" | "paper" => Ok(Phosphor::PaperWhite),
+            _ => Err(PhosphorParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for Phosphor {
+    type Error = PhosphorParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Phosphor> for String {
+    fn from(phosphor: Phosphor) -> Self {
+        phosphor.to_string()
+    }
+}
+
+/// A monochrome monitor,
+/// showing a number of intensity levels of a single phosphor color.
+///
+/// The number of levels is further limited by the `num_colors` option.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Monochrome {
+    /// The phosphor color
+    pub phosphor: Phosphor,
+    /// The number of intensity levels, including black (2 to 256)
+    pub levels: u32,
+}
+
+impl Default for Monochrome {
+    fn default() -> Self {
+        Monochrome {
+            phosphor: Phosphor::default(),
+            levels: 2,
+        }
+    }
+}
+
+impl Monochrome {
+    /// The colors of the monitor, from black to full intensity.
+    pub fn palette(&self) -> Vec<[u8; 3]> {
+        let levels = self.levels.clamp(2, 256);
+        let [r, g, b] = self.phosphor.color();
+        (0..levels)
+            .map(|i| {
+                let tint = |c: u8| (u32::from(c) * i / (levels - 1)) as u8;
+                [tint(r), tint(g), tint(b)]
+            })
+            .collect()
+    }
+}
+
+/// The luminance of a color, as a gray color.
+fn luma(Color { r, g, b, .. }: Color) -> Color {
+    let y = ((u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114 + 500) / 1000) as u8;
+    Color {
+        r: y,
+        g: y,
+        b: y,
+        a: 255,
+    }
+}
+
+impl Monochrome {
+    /// The shades of this monitor,
+    /// with no more levels than the `num_colors` option.
+    fn shades(&self, options: &ColorOptions) -> ShadePalette<Vec<[u8; 3]>> {
+        let monitor = Monochrome {
+            levels: options
+                .num_colors
+                .map_or(self.levels, |n| n.min(self.levels)),
+            ..*self
+        };
+        ShadePalette::new(monitor.palette())
+    }
+}
+
+impl ColorDepth for Monochrome {
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        self.shades(&options)
+            .convert_image_with_loss(image, options)
+    }
+
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        self.shades(&options).convert_image_indexed(image, options)
+    }
+}
+
+/// Color depth defined by a list of shades, from darkest to lightest,
+/// which pixels are mapped to by their luminance alone.
+///
+/// The luminance of the image is spread evenly over the shades,
+/// regardless of their actual colors,
+/// as on displays which tint intensity levels
+/// (such as monochrome monitors and the original Game Boy).
+/// The `num_colors` option is ignored,
+/// and an empty list of shades leaves the image as is.
+#[derive(Debug, Copy, Clone)]
+pub struct ShadePalette<T>(T);
+
+impl<T> ShadePalette<T> {
+    /// Create a color depth from the given shades,
+    /// from darkest to lightest.
+    pub const fn new(shades: T) -> Self {
+        ShadePalette(shades)
+    }
+}
+
+impl<T> ShadePalette<T>
+where
+    T: AsRef<[[u8; 3]]>,
+{
+    /// Dither the given colors to the shades,
+    /// returning the palette and the palette index of each pixel,
+    /// or `None` if there are no shades.
+    fn remap(
+        &self,
+        original: &[Color],
+        width: u32,
+        options: &ColorOptions,
+    ) -> Option<(Vec<Color>, Vec<usize>)> {
+        let palette = self
+            .0
+            .as_ref()
+            .iter()
+            .take(256)
+            .map(|&[r, g, b]| Color { r, g, b, a: 255 })
+            .collect_vec();
+        if palette.is_empty() {
+            return None;
+        }
+
+        // dither the luminance against evenly spaced levels of gray,
+        // then replace them with the shades
+        let levels = palette.len() as u32;
+        let grays = (0..levels)
+            .map(|i| {
+                let v = (i * 255 / (levels - 1).max(1)) as u8;
+                Color {
+                    r: v,
+                    g: v,
+                    b: v,
+                    a: 255,
+                }
+            })
+            .collect_vec();
+        let luminance = original.iter().map(|&c| luma(c)).collect_vec();
+        let indices = dither::remap(&luminance, width, &grays, options);
+        Some((palette, indices))
+    }
+}
+
+impl<T> ColorDepth for ShadePalette<T>
+where
+    T: AsRef<[[u8; 3]]>,
+{
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        options.keep_protected_colors_in(self.0.as_ref());
+        let original = image
+            .pixels()
+            .map(|&Rgb([r, g, b])| Color { r, g, b, a: 255 })
+            .collect_vec();
+        let (mut palette, indices) = match self.remap(&original, image.width(), &options) {
+            Some(remapped) => remapped,
+            None => return (original, Vec::new(), 0),
+        };
+        let mut converted_pixels = indices.into_iter().map(|i| palette[i]).collect_vec();
+
+        restore_protected_colors(
+            &original,
+            &mut converted_pixels,
+            &mut palette,
+            &options.protected_colors,
+        );
+        let loss = options.image_loss(&original, &converted_pixels);
+        (converted_pixels, palette, loss)
+    }
+
+    fn convert_image_indexed(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> Option<(IndexedImage, u64)> {
+        options.keep_protected_colors_in(self.0.as_ref());
+        let original = image
+            .pixels()
+            .map(|&Rgb([r, g, b])| Color { r, g, b, a: 255 })
+            .collect_vec();
+        match self.remap(&original, image.width(), &options) {
+            Some((palette, indices)) => {
+                indexed_with_loss(image, &original, palette, indices, &options)
+            }
+            None => IndexedImage::from_colors(image.width(), image.height(), &original)
+                .map(|indexed| (indexed, 0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Monochrome, Phosphor};
+    use crate::color::{ColorDepth, ColorOptions, DitherMode};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_amber_levels() {
+        let mono = Monochrome {
+            phosphor: Phosphor::Amber,
+            levels: 4,
+        };
+        assert_eq!(
+            mono.palette(),
+            vec![[0, 0, 0], [0x55, 0x3A, 0], [0xAA, 0x75, 0], [0xFF, 0xB0, 0]]
+        );
+
+        let image = RgbImage::from_fn(4, 1, |x, _| {
+            let v = (x * 85) as u8;
+            Rgb([v, v, v])
+        });
+        let options = ColorOptions {
+            dither: DitherMode::None,
+            ..Default::default()
+        };
+        let out = mono.convert_image(&image, options);
+        let out: Vec<_> = out.iter().map(|c| [c.r, c.g, c.b]).collect();
+        assert_eq!(out, mono.palette());
+    }
+}
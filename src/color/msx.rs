@@ -0,0 +1,206 @@
+//! MSX1 (TMS9918) Screen 2 emulation.
+//!
+//! The TMS9918 video chip of the MSX1 has a fixed palette of 15 colors,
+//! and its Screen 2 bitmap mode takes a foreground and a background color
+//! for each segment of 8x1 pixels.
+//!
+//! Converting each segment on its own
+//! would leave no room for dithering across segments and lines,
+//! so the segments are converted in order,
+//! choosing the pair of colors which fits each one best
+//! given the error diffused into it so far.
+use super::{
+    pixels, restore_protected_colors, ColorDepth, ColorOptions, DitherMode, LossAlgorithm,
+};
+use exoquant::Color;
+use image::RgbImage;
+use itertools::Itertools;
+
+/// The resolution of Screen 2, as `[width, height]`.
+pub const RESOLUTION: [u32; 2] = [256, 192];
+
+/// The width of a segment sharing the same two colors.
+pub const SEGMENT_WIDTH: usize = 8;
+
+/// The 15 colors of the TMS9918
+/// (leaving out color 0, which is transparent).
+pub static TMS9918: [[u8; 3]; 15] = [
+    [0x00, 0x00, 0x00], // black
+    [0x21, 0xC8, 0x42], // medium green
+    [0x5E, 0xDC, 0x78], // light green
+    [0x54, 0x55, 0xED], // dark blue
+    [0x7D, 0x76, 0xFC], // light blue
+    [0xD4, 0x52, 0x4D], // dark red
+    [0x42, 0xEB, 0xF5], // cyan
+    [0xFC, 0x55, 0x54], // medium red
+    [0xFF, 0x79, 0x78], // light red
+    [0xD4, 0xC1, 0x54], // dark yellow
+    [0xE6, 0xCE, 0x80], // light yellow
+    [0x21, 0xB0, 0x3B], // dark green
+    [0xC9, 0x5B, 0xBA], // magenta
+    [0xCC, 0xCC, 0xCC], // gray
+    [0xFF, 0xFF, 0xFF], // white
+];
+
+/// MSX1 Screen 2: the 15 colors of the TMS9918,
+/// with at most 2 of them in each segment of 8x1 pixels.
+///
+/// Any dithering mode other than `None` diffuses the error
+/// in the manner of Floyd–Steinberg,
+/// since the colors of each segment are only chosen
+/// once the error from the previous ones is known.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Screen2;
+
+impl ColorDepth for Screen2 {
+    fn convert_image_with_loss(
+        &self,
+        image: &RgbImage,
+        mut options: ColorOptions,
+    ) -> (Vec<Color>, Vec<Color>, u64) {
+        options.keep_protected_colors_in(&TMS9918);
+        let (width, height) = image.dimensions();
+        let w = width as usize;
+        let original = pixels(image);
+        let mut palette = TMS9918
+            .iter()
+            .map(|&[r, g, b]| Color { r, g, b, a: 255 })
+            .collect_vec();
+        let pairs = (0..palette.len())
+            .flat_map(|i| (i..palette.len()).map(move |j| [i, j]))
+            .collect_vec();
+        let diffuse = options.dither != DitherMode::None;
+
+        let mut converted_pixels = Vec::with_capacity(original.len());
+        // the error diffused into the current and the next line,
+        // with a margin of one pixel on each side
+        let mut errors = vec![[0_f32; 3]; w + 2];
+        for y in 0..height as usize {
+            let mut next_errors = vec![[0_f32; 3]; w + 2];
+            let line = &original[y * w..(y + 1) * w];
+            for (s, segment) in line.chunks(SEGMENT_WIDTH).enumerate() {
+                let x0 = s * SEGMENT_WIDTH;
+                let carried = &errors[x0..x0 + segment.len() + 2];
+                let pair = pairs
+                    .iter()
+                    .min_by_key(|&&pair| {
+                        let mut trial = carried.to_vec();
+                        let mut colors = Vec::with_capacity(segment.len());
+                        dither_segment(
+                            segment,
+                            &mut trial,
+                            None,
+                            [palette[pair[0]], palette[pair[1]]],
+                            diffuse,
+                            options.loss,
+                            &mut colors,
+                        )
+                    })
+                    .map(|&[i, j]| [palette[i], palette[j]])
+                    .unwrap();
+                dither_segment(
+                    segment,
+                    &mut errors[x0..x0 + segment.len() + 2],
+                    Some(&mut next_errors[x0..x0 + segment.len() + 2]),
+                    pair,
+                    diffuse,
+                    options.loss,
+                    &mut converted_pixels,
+                );
+            }
+            errors = next_errors;
+        }
+
+        restore_protected_colors(
+            &original,
+            &mut converted_pixels,
+            &mut palette,
+            &options.protected_colors,
+        );
+        let loss = options.image_loss(&original, &converted_pixels);
+        (converted_pixels, palette, loss)
+    }
+}
+
+/// Map the pixels of a segment to the nearer of two colors,
+/// pushing them to `out` and returning the total loss.
+///
+/// `errors` holds the error diffused into the segment,
+/// from one pixel before it to one pixel after it.
+/// With `diffuse`, the error of each pixel is spread
+/// into the next pixel of `errors`
+/// and into `next` (the same pixels of the next line) if given.
+fn dither_segment(
+    segment: &[Color],
+    errors: &mut [[f32; 3]],
+    mut next: Option<&mut [[f32; 3]]>,
+    pair: [Color; 2],
+    diffuse: bool,
+    loss: LossAlgorithm,
+    out: &mut Vec<Color>,
+) -> u64 {
+    let mut total = 0;
+    for (i, c) in segment.iter().enumerate() {
+        let e = errors[i + 1];
+        let value = [
+            (f32::from(c.r) + e[0]).clamp(0., 255.),
+            (f32::from(c.g) + e[1]).clamp(0., 255.),
+            (f32::from(c.b) + e[2]).clamp(0., 255.),
+        ];
+        let target = Color {
+            r: value[0].round() as u8,
+            g: value[1].round() as u8,
+            b: value[2].round() as u8,
+            a: 255,
+        };
+        let (cost, chosen) = pair
+            .iter()
+            .map(|&p| (loss.color_diff(target, p), p))
+            .min_by_key(|&(cost, _)| cost)
+            .unwrap();
+        total += cost;
+        out.push(chosen);
+
+        if diffuse {
+            let chosen = [chosen.r, chosen.g, chosen.b];
+            for (k, (&v, &p)) in value.iter().zip(&chosen).enumerate() {
+                let error = v - f32::from(p);
+                errors[i + 2][k] += error * 7. / 16.;
+                if let Some(next) = next.as_mut() {
+                    next[i][k] += error * 3. / 16.;
+                    next[i + 1][k] += error * 5. / 16.;
+                    next[i + 2][k] += error / 16.;
+                }
+            }
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Screen2, SEGMENT_WIDTH};
+    use crate::color::ColorDepth;
+    use image::{Rgb, RgbImage};
+    use itertools::Itertools;
+
+    #[test]
+    fn test_two_colors_per_segment() {
+        let image = RgbImage::from_fn(20, 4, |x, y| {
+            Rgb([
+                (x * 12) as u8,
+                (y * 60) as u8,
+                ((x * 7 + y * 30) % 256) as u8,
+            ])
+        });
+        let (pixels, palette, _) = Screen2.convert_image_with_loss(&image, Default::default());
+        assert_eq!(palette.len(), 15);
+        assert!(pixels.iter().all(|c| palette.contains(c)));
+        for line in pixels.chunks(20) {
+            for segment in line.chunks(SEGMENT_WIDTH) {
+                let colors = segment.iter().map(|c| [c.r, c.g, c.b]).unique().count();
+                assert!(colors <= 2);
+            }
+        }
+    }
+}
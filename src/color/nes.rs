@@ -0,0 +1,79 @@
+//! NES (Famicom) color palette and video modes.
+use super::{AttributePalette, FixedPalette};
+
+/// The resolution of the NES picture, as `[width, height]`.
+pub const RESOLUTION: [u32; 2] = [256, 240];
+
+/// The colors of the NES master palette, up to the number of colors.
+pub static PALETTE_NES: FixedPalette<&[[u8; 3]]> = FixedPalette(&NES);
+
+/// The NES with the constraints of its attribute table:
+/// each 16x16 area uses one of 4 sub-palettes of 3 colors,
+/// plus a backdrop color shared by the whole screen.
+pub static PALETTE_NES_STRICT: AttributePalette<&[[u8; 3]]> =
+    AttributePalette::new(&NES, 16, 16, 4, 3);
+
+/// The 54 distinct colors of the NES master palette.
+///
+/// The many black entries of the 64 entry palette appear only once,
+/// and so does white (both $20 and $30).
+pub static NES: [[u8; 3]; 54] = [
+    [0, 0, 0], // black
+    // $00-$0C
+    [0x7C, 0x7C, 0x7C],
+    [0, 0, 0xFC],
+    [0, 0, 0xBC],
+    [0x44, 0x28, 0xBC],
+    [0x94, 0, 0x84],
+    [0xA8, 0, 0x20],
+    [0xA8, 0x10, 0],
+    [0x88, 0x14, 0],
+    [0x50, 0x30, 0],
+    [0, 0x78, 0],
+    [0, 0x68, 0],
+    [0, 0x58, 0],
+    [0, 0x40, 0x58],
+    // $10-$1C
+    [0xBC, 0xBC, 0xBC],
+    [0, 0x78, 0xF8],
+    [0, 0x58, 0xF8],
+    [0x68, 0x44, 0xFC],
+    [0xD8, 0, 0xCC],
+    [0xE4, 0, 0x58],
+    [0xF8, 0x38, 0],
+    [0xE4, 0x5C, 0x10],
+    [0xAC, 0x7C, 0],
+    [0, 0xB8, 0],
+    [0, 0xA8, 0],
+    [0, 0xA8, 0x44],
+    [0, 0x88, 0x88],
+    // $21-$2D
+    [0x3C, 0xBC, 0xFC],
+    [0x68, 0x88, 0xFC],
+    [0x98, 0x78, 0xF8],
+    [0xF8, 0x78, 0xF8],
+    [0xF8, 0x58, 0x98],
+    [0xF8, 0x78, 0x58],
+    [0xFC, 0xA0, 0x44],
+    [0xF8, 0xB8, 0],
+    [0xB8, 0xF8, 0x18],
+    [0x58, 0xD8, 0x54],
+    [0x58, 0xF8, 0x98],
+    [0, 0xE8, 0xD8],
+    [0x78, 0x78, 0x78],
+    // $30-$3D
+    [0xFC, 0xFC, 0xFC],
+    [0xA4, 0xE4, 0xFC],
+    [0xB8, 0xB8, 0xF8],
+    [0xD8, 0xB8, 0xF8],
+    [0xF8, 0xB8, 0xF8],
+    [0xF8, 0xA4, 0xC0],
+    [0xF0, 0xD0, 0xB0],
+    [0xFC, 0xE0, 0xA8],
+    [0xF8, 0xD8, 0x78],
+    [0xD8, 0xF8, 0x78],
+    [0xB8, 0xF8, 0xB8],
+    [0xB8, 0xF8, 0xD8],
+    [0, 0xFC, 0xFC],
+    [0xF8, 0xD8, 0xF8],
+];
@@ -0,0 +1,641 @@
+//! Palette files.
+//!
+//! Custom palettes can be loaded from and saved to files
+//! in any of these formats:
+//!
+//! - GIMP palettes (`.gpl`);
+//! - JASC palettes, as used by Paint Shop Pro (`.pal`);
+//! - plain lists of hexadecimal RGB colors, one per line (`.hex`);
+//! - Adobe color tables (`.act`);
+//! - JSON documents with a list of hexadecimal `colors`,
+//!   as provided by Lospec (`.json`, reading requires the `serde_json` feature);
+//! - the palette embedded in an Aseprite sprite (`.aseprite` or `.ase`).
+//!
+//! With the `network` feature,
+//! palettes can also be fetched directly from [Lospec](https://lospec.com/palette-list)
+//! by their slug (see [`fetch_lospec_palette`]).
+use snafu::{ResultExt, Snafu};
+use std::io::Write;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::path::{Path, PathBuf};
+
+/// An error returned when loading a palette.
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum PaletteError {
+    /// failed to read palette file
+    Io { source: std::io::Error },
+    /// unrecognized palette file format
+    UnknownFormat,
+    /// invalid palette file header
+    BadHeader,
+    /// unexpected end of palette file
+    Truncated,
+    /// palette file is not valid UTF-8 text
+    NotText,
+    #[snafu(display("invalid color at line {}", line))]
+    BadColor { line: usize },
+    /// palette has no colors
+    Empty,
+    #[cfg(feature = "serde_json")]
+    #[snafu(display("invalid JSON palette: {}", source))]
+    Json { source: serde_json::Error },
+    #[snafu(display("invalid Lospec palette slug \"{}\"", slug))]
+    BadSlug { slug: String },
+    #[cfg(feature = "network")]
+    #[snafu(display("failed to fetch palette: {}", source))]
+    Fetch { source: Box<ureq::Error> },
+}
+
+/// A supported palette file format.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PaletteFormat {
+    /// GIMP palette (`.gpl`)
+    Gpl,
+    /// JASC palette (`.pal`)
+    JascPal,
+    /// One hexadecimal RGB color per line (`.hex`)
+    Hex,
+    /// Adobe color table (`.act`),
+    /// 256 RGB colors optionally followed by the number of colors in use
+    Act,
+    /// JSON document with a `colors` array of hexadecimal RGB colors (`.json`)
+    Json,
+    /// Aseprite sprite, only the palette is considered (`.aseprite`)
+    Aseprite,
+}
+
+impl PaletteFormat {
+    /// All supported palette formats.
+    pub const ALL: [PaletteFormat; 6] = [
+        PaletteFormat::Gpl,
+        PaletteFormat::JascPal,
+        PaletteFormat::Hex,
+        PaletteFormat::Act,
+        PaletteFormat::Json,
+        PaletteFormat::Aseprite,
+    ];
+
+    /// Identify the palette format from a file extension.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_lowercase().as_str() {
+            "gpl" => Some(PaletteFormat::Gpl),
+            "pal" => Some(PaletteFormat::JascPal),
+            "hex" => Some(PaletteFormat::Hex),
+            "act" => Some(PaletteFormat::Act),
+            "json" => Some(PaletteFormat::Json),
+            "aseprite" | "ase" => Some(PaletteFormat::Aseprite),
+            _ => None,
+        }
+    }
+
+    /// The file extension of this palette format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::JascPal => "pal",
+            PaletteFormat::Hex => "hex",
+            PaletteFormat::Act => "act",
+            PaletteFormat::Json => "json",
+            PaletteFormat::Aseprite => "aseprite",
+        }
+    }
+}
+
+/// Parse the contents of a palette file in the given format.
+pub fn parse_palette(data: &[u8], format: PaletteFormat) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let text = || std::str::from_utf8(data).map_err(|_| PaletteError::NotText);
+    let colors = match format {
+        PaletteFormat::Gpl => parse_gpl(text()?)?,
+        PaletteFormat::JascPal => parse_jasc_pal(text()?)?,
+        PaletteFormat::Hex => parse_hex_list(text()?)?,
+        PaletteFormat::Act => parse_act(data)?,
+        PaletteFormat::Json => parse_json(text()?)?,
+        PaletteFormat::Aseprite => parse_aseprite(data)?,
+    };
+    if colors.is_empty() {
+        return EmptySnafu.fail();
+    }
+    Ok(colors)
+}
+
+/// Load a palette from a file,
+/// identifying the format by its extension.
+///
+/// File system access is not available in WebAssembly builds for the web.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn load_palette(path: impl AsRef<Path>) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let path = path.as_ref();
+    let format = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(PaletteFormat::from_extension)
+        .ok_or(PaletteError::UnknownFormat)?;
+    let data = std::fs::read(path).context(IoSnafu)?;
+    parse_palette(&data, format)
+}
+
+/// Write a palette in the given format.
+pub fn write_palette<W>(
+    mut writer: W,
+    colors: &[[u8; 3]],
+    format: PaletteFormat,
+) -> Result<(), PaletteError>
+where
+    W: Write,
+{
+    if colors.is_empty() {
+        return EmptySnafu.fail();
+    }
+    match format {
+        PaletteFormat::Gpl => {
+            writeln!(writer, "GIMP Palette").context(IoSnafu)?;
+            writeln!(writer, "Columns: 16").context(IoSnafu)?;
+            writeln!(writer, "#").context(IoSnafu)?;
+            for [r, g, b] in colors {
+                writeln!(
+                    writer,
+                    "{:3} {:3} {:3}\t{}",
+                    r,
+                    g,
+                    b,
+                    hex_color([*r, *g, *b])
+                )
+                .context(IoSnafu)?;
+            }
+        }
+        PaletteFormat::JascPal => {
+            write!(writer, "JASC-PAL\r\n0100\r\n{}\r\n", colors.len()).context(IoSnafu)?;
+            for [r, g, b] in colors {
+                write!(writer, "{} {} {}\r\n", r, g, b).context(IoSnafu)?;
+            }
+        }
+        PaletteFormat::Hex => {
+            for &c in colors {
+                writeln!(writer, "{}", hex_color(c)).context(IoSnafu)?;
+            }
+        }
+        PaletteFormat::Act => write_act(writer, colors).context(IoSnafu)?,
+        PaletteFormat::Json => {
+            let colors = colors
+                .iter()
+                .map(|&c| format!("\"{}\"", hex_color(c)))
+                .collect::<Vec<_>>()
+                .join(",");
+            writeln!(writer, "{{\"colors\":[{}]}}", colors).context(IoSnafu)?;
+        }
+        PaletteFormat::Aseprite => write_aseprite(writer, colors).context(IoSnafu)?,
+    }
+    Ok(())
+}
+
+/// Save a palette to a file,
+/// identifying the format by its extension.
+///
+/// An empty palette is an error, and then no file is created.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn save_palette(path: impl AsRef<Path>, colors: &[[u8; 3]]) -> Result<(), PaletteError> {
+    if colors.is_empty() {
+        return EmptySnafu.fail();
+    }
+    let path = path.as_ref();
+    let format = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(PaletteFormat::from_extension)
+        .ok_or(PaletteError::UnknownFormat)?;
+    let file = std::fs::File::create(path).context(IoSnafu)?;
+    let mut writer = std::io::BufWriter::new(file);
+    write_palette(&mut writer, colors, format)?;
+    writer.flush().context(IoSnafu)
+}
+
+/// Look for a palette file with the given name (without extension)
+/// in each of the given directories, in order.
+///
+/// Returns the path to the first palette file found.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn find_palette<I>(name: &str, dirs: I) -> Option<PathBuf>
+where
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+{
+    dirs.into_iter().find_map(|dir| {
+        PaletteFormat::ALL.iter().find_map(|format| {
+            let path = dir
+                .as_ref()
+                .join(format!("{}.{}", name, format.extension()));
+            Some(path).filter(|p| p.is_file())
+        })
+    })
+}
+
+/// Obtain the URL of a Lospec palette in JSON format
+/// from its slug (e.g. `"sweetie-16"`).
+pub fn lospec_url(slug: &str) -> Result<String, PaletteError> {
+    let valid = !slug.is_empty()
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return BadSlugSnafu { slug }.fail();
+    }
+    Ok(format!("https://lospec.com/palette-list/{}.json", slug))
+}
+
+/// Fetch a palette from Lospec by its slug (e.g. `"sweetie-16"`).
+#[cfg(feature = "network")]
+pub fn fetch_lospec_palette(slug: &str) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let url = lospec_url(slug)?;
+    let text = ureq::get(&url)
+        .call()
+        .map_err(Box::new)
+        .context(FetchSnafu)?
+        .into_string()
+        .context(IoSnafu)?;
+    parse_palette(text.as_bytes(), PaletteFormat::Json)
+}
+
+/// Parse a color in hexadecimal notation (`RRGGBB` or `#RRGGBB`).
+pub fn parse_hex_color(s: &str) -> Option<[u8; 3]> {
+    let s = s.trim();
+    let s = s.strip_prefix('#').unwrap_or(s);
+    if s.len() != 6 || !s.is_ascii() {
+        return None;
+    }
+    let r = u8::from_str_radix(&s[0..2], 16).ok()?;
+    let g = u8::from_str_radix(&s[2..4], 16).ok()?;
+    let b = u8::from_str_radix(&s[4..6], 16).ok()?;
+    Some([r, g, b])
+}
+
+/// Format a color in hexadecimal notation (`rrggbb`).
+fn hex_color([r, g, b]: [u8; 3]) -> String {
+    format!("{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Parse three decimal color components separated by whitespace,
+/// ignoring anything after them.
+fn parse_decimal_color(line: &str) -> Option<[u8; 3]> {
+    let mut parts = line.split_whitespace();
+    let r = parts.next()?.parse().ok()?;
+    let g = parts.next()?.parse().ok()?;
+    let b = parts.next()?.parse().ok()?;
+    Some([r, g, b])
+}
+
+fn parse_gpl(text: &str) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == "GIMP Palette" => {}
+        _ => return BadHeaderSnafu.fail(),
+    }
+
+    lines
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| {
+            !line.is_empty()
+                && !line.starts_with('#')
+                && !line.starts_with("Name:")
+                && !line.starts_with("Columns:")
+        })
+        .map(|(i, line)| parse_decimal_color(line).ok_or(PaletteError::BadColor { line: i + 1 }))
+        .collect()
+}
+
+fn parse_jasc_pal(text: &str) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let mut lines = text.lines().map(str::trim);
+    if lines.next() != Some("JASC-PAL") {
+        return BadHeaderSnafu.fail();
+    }
+    // version, then number of colors
+    let _version = lines.next().ok_or(PaletteError::BadHeader)?;
+    let count: usize = lines
+        .next()
+        .and_then(|l| l.parse().ok())
+        .ok_or(PaletteError::BadHeader)?;
+
+    let colors = lines
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .take(count)
+        .map(|(i, line)| parse_decimal_color(line).ok_or(PaletteError::BadColor { line: i + 4 }))
+        .collect::<Result<Vec<_>, _>>()?;
+    if colors.len() != count {
+        return BadHeaderSnafu.fail();
+    }
+    Ok(colors)
+}
+
+fn parse_hex_list(text: &str) -> Result<Vec<[u8; 3]>, PaletteError> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with(';'))
+        .map(|(i, line)| parse_hex_color(line).ok_or(PaletteError::BadColor { line: i + 1 }))
+        .collect()
+}
+
+#[cfg(feature = "serde_json")]
+fn parse_json(text: &str) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let value: serde_json::Value = serde_json::from_str(text).context(JsonSnafu)?;
+    let colors = value
+        .get("colors")
+        .and_then(|colors| colors.as_array())
+        .ok_or(PaletteError::BadHeader)?;
+    colors
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            c.as_str()
+                .and_then(parse_hex_color)
+                .ok_or(PaletteError::BadColor { line: i + 1 })
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "serde_json"))]
+fn parse_json(_text: &str) -> Result<Vec<[u8; 3]>, PaletteError> {
+    UnknownFormatSnafu.fail()
+}
+
+/// The number of colors in an Adobe color table
+const ACT_COLORS: usize = 256;
+
+fn parse_act(data: &[u8]) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let table = data.get(..ACT_COLORS * 3).ok_or(PaletteError::Truncated)?;
+    // the optional trailer holds the number of colors in use
+    // and the transparent color index (both big endian)
+    let count = match data.get(ACT_COLORS * 3..ACT_COLORS * 3 + 2) {
+        Some(&[hi, lo]) => usize::from(u16::from_be_bytes([hi, lo])).min(ACT_COLORS),
+        _ => ACT_COLORS,
+    };
+    Ok(table
+        .chunks_exact(3)
+        .take(count)
+        .map(|c| [c[0], c[1], c[2]])
+        .collect())
+}
+
+fn write_act<W>(mut writer: W, colors: &[[u8; 3]]) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let colors = &colors[..colors.len().min(ACT_COLORS)];
+    for c in colors {
+        writer.write_all(c)?;
+    }
+    writer.write_all(&vec![0; (ACT_COLORS - colors.len()) * 3])?;
+    writer.write_all(&(colors.len() as u16).to_be_bytes())?;
+    // no transparent color
+    writer.write_all(&0xFFFF_u16.to_be_bytes())
+}
+
+/// Aseprite file magic number
+const ASE_MAGIC: u16 = 0xA5E0;
+/// Aseprite frame magic number
+const ASE_FRAME_MAGIC: u16 = 0xF1FA;
+/// Aseprite (new) palette chunk type
+const ASE_CHUNK_PALETTE: u16 = 0x2019;
+/// Aseprite old palette chunk type (8-bit samples)
+const ASE_CHUNK_OLD_PALETTE: u16 = 0x0004;
+/// Aseprite old palette chunk type (6-bit samples)
+const ASE_CHUNK_OLD_PALETTE_6BIT: u16 = 0x0011;
+/// The most colors read from an Aseprite palette chunk
+const ASE_MAX_COLORS: usize = 0x10000;
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, PaletteError> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(PaletteError::Truncated)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, PaletteError> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(PaletteError::Truncated)
+}
+
+/// Read the palette from the first frame of an Aseprite file.
+fn parse_aseprite(data: &[u8]) -> Result<Vec<[u8; 3]>, PaletteError> {
+    if read_u16(data, 4)? != ASE_MAGIC {
+        return BadHeaderSnafu.fail();
+    }
+
+    // first frame right after the 128-byte header
+    let frame = 128;
+    if read_u16(data, frame + 4)? != ASE_FRAME_MAGIC {
+        return BadHeaderSnafu.fail();
+    }
+    let num_chunks = match read_u32(data, frame + 12)? {
+        0 => u32::from(read_u16(data, frame + 6)?),
+        n => n,
+    };
+
+    let mut old_palette = None;
+    let mut pos = frame + 16;
+    for _ in 0..num_chunks {
+        let size = read_u32(data, pos)? as usize;
+        let kind = read_u16(data, pos + 4)?;
+        let chunk = data
+            .get(pos + 6..pos + size)
+            .ok_or(PaletteError::Truncated)?;
+        match kind {
+            ASE_CHUNK_PALETTE => return parse_aseprite_palette_chunk(chunk),
+            ASE_CHUNK_OLD_PALETTE | ASE_CHUNK_OLD_PALETTE_6BIT if old_palette.is_none() => {
+                old_palette = Some(parse_aseprite_old_palette_chunk(
+                    chunk,
+                    kind == ASE_CHUNK_OLD_PALETTE_6BIT,
+                )?);
+            }
+            _ => {}
+        }
+        pos += size;
+    }
+
+    old_palette.ok_or(PaletteError::Empty)
+}
+
+fn parse_aseprite_palette_chunk(chunk: &[u8]) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let size = read_u32(chunk, 0)? as usize;
+    let first = read_u32(chunk, 4)? as usize;
+    let last = read_u32(chunk, 8)? as usize;
+    if first > last || last >= size {
+        return BadHeaderSnafu.fail();
+    }
+    if size > ASE_MAX_COLORS {
+        return BadHeaderSnafu.fail();
+    }
+    // each entry takes at least 6 bytes after the 20-byte header
+    if last - first >= chunk.len().saturating_sub(20) / 6 {
+        return TruncatedSnafu.fail();
+    }
+
+    let mut colors = vec![[0, 0, 0]; size];
+    let mut pos = 20;
+    for color in &mut colors[first..=last] {
+        let flags = read_u16(chunk, pos)?;
+        let rgba = chunk.get(pos + 2..pos + 6).ok_or(PaletteError::Truncated)?;
+        *color = [rgba[0], rgba[1], rgba[2]];
+        pos += 6;
+        if flags & 1 != 0 {
+            // skip color name
+            pos += 2 + read_u16(chunk, pos)? as usize;
+        }
+    }
+    Ok(colors)
+}
+
+fn parse_aseprite_old_palette_chunk(
+    chunk: &[u8],
+    six_bit: bool,
+) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let num_packets = read_u16(chunk, 0)?;
+    let mut colors = Vec::new();
+    let mut index = 0;
+    let mut pos = 2;
+    for _ in 0..num_packets {
+        let header = chunk.get(pos..pos + 2).ok_or(PaletteError::Truncated)?;
+        index += usize::from(header[0]);
+        let count = match header[1] {
+            0 => 256,
+            n => usize::from(n),
+        };
+        pos += 2;
+        for _ in 0..count {
+            let rgb = chunk.get(pos..pos + 3).ok_or(PaletteError::Truncated)?;
+            let mut color = [rgb[0], rgb[1], rgb[2]];
+            if six_bit {
+                for c in &mut color {
+                    *c = (u16::from(*c) * 255 / 63) as u8;
+                }
+            }
+            if colors.len() <= index {
+                colors.resize(index + 1, [0, 0, 0]);
+            }
+            colors[index] = color;
+            index += 1;
+            pos += 3;
+        }
+    }
+    Ok(colors)
+}
+
+/// Write a single-frame, 1x1 Aseprite sprite containing the given palette.
+fn write_aseprite<W>(mut writer: W, colors: &[[u8; 3]]) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let num_colors = colors.len() as u32;
+    let chunk_size = 26 + 6 * num_colors;
+    let frame_size = 16 + chunk_size;
+    let file_size = 128 + frame_size;
+
+    // file header
+    writer.write_all(&file_size.to_le_bytes())?;
+    writer.write_all(&ASE_MAGIC.to_le_bytes())?;
+    writer.write_all(&1_u16.to_le_bytes())?; // frames
+    writer.write_all(&1_u16.to_le_bytes())?; // width
+    writer.write_all(&1_u16.to_le_bytes())?; // height
+    writer.write_all(&32_u16.to_le_bytes())?; // color depth (RGBA)
+    writer.write_all(&1_u32.to_le_bytes())?; // flags
+    writer.write_all(&100_u16.to_le_bytes())?; // speed
+    writer.write_all(&[0; 8])?;
+    writer.write_all(&[0; 4])?; // transparent index + padding
+    writer.write_all(&(num_colors.min(0xFFFF) as u16).to_le_bytes())?;
+    writer.write_all(&[1, 1])?; // pixel ratio
+    writer.write_all(&[0; 8])?; // grid
+    writer.write_all(&[0; 84])?;
+
+    // frame header
+    writer.write_all(&frame_size.to_le_bytes())?;
+    writer.write_all(&ASE_FRAME_MAGIC.to_le_bytes())?;
+    writer.write_all(&1_u16.to_le_bytes())?; // chunks
+    writer.write_all(&100_u16.to_le_bytes())?; // duration
+    writer.write_all(&[0; 2])?;
+    writer.write_all(&1_u32.to_le_bytes())?; // chunks
+
+    // palette chunk
+    writer.write_all(&chunk_size.to_le_bytes())?;
+    writer.write_all(&ASE_CHUNK_PALETTE.to_le_bytes())?;
+    writer.write_all(&num_colors.to_le_bytes())?;
+    writer.write_all(&0_u32.to_le_bytes())?;
+    writer.write_all(&(num_colors - 1).to_le_bytes())?;
+    writer.write_all(&[0; 8])?;
+    for &[r, g, b] in colors {
+        writer.write_all(&0_u16.to_le_bytes())?;
+        writer.write_all(&[r, g, b, 0xFF])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_palette, write_palette, PaletteFormat};
+
+    #[test]
+    fn test_parse_gpl() {
+        let text =
+            "GIMP Palette\nName: Test\nColumns: 2\n#\n  0   0   0\tBlack\n255 255 255\tWhite\n";
+        let colors = parse_palette(text.as_bytes(), PaletteFormat::Gpl).unwrap();
+        assert_eq!(colors, vec![[0, 0, 0], [255, 255, 255]]);
+    }
+
+    #[test]
+    fn test_parse_jasc_pal() {
+        let text = "JASC-PAL\r\n0100\r\n2\r\n0 0 170\r\n170 85 0\r\n";
+        let colors = parse_palette(text.as_bytes(), PaletteFormat::JascPal).unwrap();
+        assert_eq!(colors, vec![[0, 0, 0xAA], [0xAA, 0x55, 0]]);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_parse_lospec_json() {
+        let text = r#"{"name":"Test","author":"","colors":["1a1c2c","f4f4f4"]}"#;
+        let colors = parse_palette(text.as_bytes(), PaletteFormat::Json).unwrap();
+        assert_eq!(colors, vec![[0x1a, 0x1c, 0x2c], [0xf4, 0xf4, 0xf4]]);
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        let text = "1a1c2c\n#5D275D\n\n";
+        let colors = parse_palette(text.as_bytes(), PaletteFormat::Hex).unwrap();
+        assert_eq!(colors, vec![[0x1a, 0x1c, 0x2c], [0x5d, 0x27, 0x5d]]);
+
+        assert!(parse_palette(b"1a1c2\n", PaletteFormat::Hex).is_err());
+    }
+
+    #[test]
+    fn test_palette_round_trip() {
+        let colors = vec![[0, 0, 0], [0x55, 0xFF, 0xFF], [0xFF, 0x55, 0xFF]];
+        for &format in &[
+            PaletteFormat::Gpl,
+            PaletteFormat::JascPal,
+            PaletteFormat::Hex,
+            PaletteFormat::Act,
+            PaletteFormat::Aseprite,
+        ] {
+            let mut data = Vec::new();
+            write_palette(&mut data, &colors, format).unwrap();
+            let read = parse_palette(&data, format).unwrap();
+            assert_eq!(read, colors, "palette mismatch in {:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_parse_aseprite_palette_size() {
+        let colors = vec![[0, 0, 0], [0xFF, 0xFF, 0xFF]];
+        let mut data = Vec::new();
+        write_palette(&mut data, &colors, PaletteFormat::Aseprite).unwrap();
+
+        // the palette size of the first chunk,
+        // which may be larger than the entries in it
+        data[150..154].copy_from_slice(&256_u32.to_le_bytes());
+        let read = parse_palette(&data, PaletteFormat::Aseprite).unwrap();
+        assert_eq!(read.len(), 256);
+        assert_eq!(read[..2], colors[..]);
+
+        data[150..154].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse_palette(&data, PaletteFormat::Aseprite).is_err());
+    }
+}
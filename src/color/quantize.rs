@@ -0,0 +1,565 @@
+//! Palette optimization,
+//! the choice of a limited set of colors which best represents an image.
+//!
+//! Colors are split off the histogram of the image
+//! until the palette is full,
+//! then refined with K-means clustering.
+//! The quantizer of exoquant may give slightly different palettes
+//! from one run to the next,
+//! so with a seed the colors are split off by a deterministic median cut
+//! implemented here instead,
+//! and refined by a seeded run of the selected optimizer.
+//! The builder options trade palette quality for speed,
+//! which is handy when building palettes for big batches of images.
+//!
+//! ```
+//! use exoquant::Color;
+//! use retroimg::color::quantize::{build_palette, PaletteBuilderOptions, PaletteOptimizer};
+//!
+//! let pixels = vec![
+//!     Color { r: 255, g: 0, b: 0, a: 255 },
+//!     Color { r: 0, g: 0, b: 255, a: 255 },
+//! ];
+//! let options = PaletteBuilderOptions {
+//!     optimizer: PaletteOptimizer::None,
+//!     ..Default::default()
+//! };
+//! let palette = build_palette(&pixels, 2, None, &options);
+//! assert_eq!(palette.len(), 2);
+//! ```
+use super::dither::SplitMix64;
+use super::linear;
+use crate::progress::{self, ProgressHook, Stage};
+use exoquant::optimizer::{KMeans, Optimizer, WeightedKMeans};
+use exoquant::{Color, Histogram, Quantizer, SimpleColorSpace};
+use image::GrayImage;
+use itertools::Itertools;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// How many times the most important pixels count in the histogram
+/// when weighting it by an importance map
+const MAX_IMPORTANCE_WEIGHT: u32 = 8;
+
+/// Enumeration of palette optimizers,
+/// which refine the colors split off the histogram.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum PaletteOptimizer {
+    /// K-means clustering
+    #[default]
+    KMeans,
+    /// K-means clustering weighted towards rare colors,
+    /// keeping small details at the expense of smooth areas
+    WeightedKMeans,
+    /// No optimization, the fastest and the roughest
+    None,
+}
+
+impl std::fmt::Display for PaletteOptimizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteOptimizer::KMeans => f.write_str("kmeans"),
+            PaletteOptimizer::WeightedKMeans => f.write_str("weighted-kmeans"),
+            PaletteOptimizer::None => f.write_str("none"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`PaletteOptimizer`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct PaletteOptimizerParseError;
+
+impl std::fmt::Display for PaletteOptimizerParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            "invalid palette optimizer, should be \"kmeans\", \"weighted-kmeans\" or \"none\"",
+        )
+    }
+}
+
+impl std::error::Error for PaletteOptimizerParseError {}
+
+impl FromStr for PaletteOptimizer {
+    type Err = PaletteOptimizerParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "kmeans" | "k-means" => Ok(PaletteOptimizer::KMeans),
+            "weighted-kmeans" | "weighted" => Ok(PaletteOptimizer::WeightedKMeans),
+            "none" => Ok(PaletteOptimizer::None),
+            _ => Err(PaletteOptimizerParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for PaletteOptimizer {
+    type Error = PaletteOptimizerParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PaletteOptimizer> for String {
+    fn from(optimizer: PaletteOptimizer) -> Self {
+        optimizer.to_string()
+    }
+}
+
+/// Enumeration of color spaces in which the palette can be built.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum PaletteColorSpace {
+    /// sRGB encoded samples, as stored in the image
+    #[default]
+    Srgb,
+    /// Linear light samples,
+    /// which keep the brightness of colors averaged together
+    Linear,
+}
+
+impl std::fmt::Display for PaletteColorSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteColorSpace::Srgb => f.write_str("srgb"),
+            PaletteColorSpace::Linear => f.write_str("linear"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`PaletteColorSpace`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct PaletteColorSpaceParseError;
+
+impl std::fmt::Display for PaletteColorSpaceParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid palette color space, should be \"srgb\" or \"linear\"")
+    }
+}
+
+impl std::error::Error for PaletteColorSpaceParseError {}
+
+impl FromStr for PaletteColorSpace {
+    type Err = PaletteColorSpaceParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "srgb" => Ok(PaletteColorSpace::Srgb),
+            "linear" => Ok(PaletteColorSpace::Linear),
+            _ => Err(PaletteColorSpaceParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for PaletteColorSpace {
+    type Error = PaletteColorSpaceParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PaletteColorSpace> for String {
+    fn from(colorspace: PaletteColorSpace) -> Self {
+        colorspace.to_string()
+    }
+}
+
+/// Options of the palette builder.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct PaletteBuilderOptions {
+    /// The optimizer refining the palette
+    pub optimizer: PaletteOptimizer,
+    /// Number of optimization iterations over the final palette
+    pub iterations: usize,
+    /// Optimize the palette while it is being split
+    /// every time it reaches a multiple of this many colors
+    /// (0 to never do it, as it is very slow)
+    pub optimize_every: usize,
+    /// Number of optimization iterations while the palette is being split
+    pub split_iterations: usize,
+    /// The color space in which the palette is built
+    pub colorspace: PaletteColorSpace,
+    /// Build the palette deterministically,
+    /// with median cut refined by the selected optimizer,
+    /// using this seed for the choices left to chance.
+    ///
+    /// The optimization frequency does not apply to this quantizer.
+    pub seed: Option<u64>,
+}
+
+impl Default for PaletteBuilderOptions {
+    fn default() -> Self {
+        PaletteBuilderOptions {
+            optimizer: PaletteOptimizer::default(),
+            iterations: 8,
+            optimize_every: 256,
+            split_iterations: 16,
+            colorspace: PaletteColorSpace::default(),
+            seed: None,
+        }
+    }
+}
+
+/// Build an optimized palette of up to `num_colors` colors
+/// from the histogram of the pixels,
+/// weighted by the importance of each pixel if given
+/// (only used if it has one value per pixel).
+pub fn build_palette(
+    pixels: &[Color],
+    num_colors: u32,
+    importance: Option<&GrayImage>,
+    options: &PaletteBuilderOptions,
+) -> Vec<Color> {
+    build_palette_with_progress(pixels, num_colors, importance, options, None)
+}
+
+/// Build an optimized palette like [`build_palette`],
+/// reporting the progress of the [palette stage](Stage::Palette)
+/// to the given hook.
+pub fn build_palette_with_progress(
+    pixels: &[Color],
+    num_colors: u32,
+    importance: Option<&GrayImage>,
+    options: &PaletteBuilderOptions,
+    progress: Option<&ProgressHook>,
+) -> Vec<Color> {
+    progress::report(progress, Stage::Palette, 0.);
+    let linear = options.colorspace == PaletteColorSpace::Linear;
+    let linear_pixels;
+    let pixels = if linear {
+        linear_pixels = pixels.iter().map(|&c| linear::to_linear8(c)).collect_vec();
+        &linear_pixels
+    } else {
+        pixels
+    };
+    // count each pixel more times the more important it is
+    let importance = importance.filter(|importance| importance.len() == pixels.len());
+    let weight = |i: usize| {
+        importance.map_or(1, |importance| {
+            1 + u32::from(importance.as_raw()[i]) * (MAX_IMPORTANCE_WEIGHT - 1) / 255
+        })
+    };
+    let palette = match options.seed {
+        Some(seed) => {
+            let mut histogram = BTreeMap::new();
+            for (i, c) in pixels.iter().enumerate() {
+                *histogram.entry([c.r, c.g, c.b]).or_insert(0) += u64::from(weight(i));
+            }
+            let histogram = histogram.into_iter().collect_vec();
+            median_cut(&histogram, num_colors as usize, options, seed, progress)
+        }
+        None => {
+            let mut histogram = Histogram::new();
+            histogram.extend(
+                pixels
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, &c)| std::iter::repeat(c).take(weight(i) as usize)),
+            );
+            match options.optimizer {
+                PaletteOptimizer::KMeans => {
+                    quantize(&histogram, num_colors, Some(&KMeans), options, progress)
+                }
+                PaletteOptimizer::WeightedKMeans => quantize(
+                    &histogram,
+                    num_colors,
+                    Some(&WeightedKMeans),
+                    options,
+                    progress,
+                ),
+                PaletteOptimizer::None => {
+                    quantize::<KMeans>(&histogram, num_colors, None, options, progress)
+                }
+            }
+        }
+    };
+    progress::report(progress, Stage::Palette, 1.);
+    if linear {
+        palette.into_iter().map(linear::to_srgb8).collect()
+    } else {
+        palette
+    }
+}
+
+/// Split the histogram into a palette of up to `num_colors` colors,
+/// optimizing it with the given optimizer.
+/// Splitting counts as the first half of the progress.
+fn quantize<O>(
+    histogram: &Histogram,
+    num_colors: u32,
+    optimizer: Option<&O>,
+    options: &PaletteBuilderOptions,
+    progress: Option<&ProgressHook>,
+) -> Vec<Color>
+where
+    O: Optimizer,
+{
+    let colorspace = SimpleColorSpace::default();
+    let mut quantizer = Quantizer::new(histogram, &colorspace);
+    while quantizer.num_colors() < num_colors as usize {
+        quantizer.step();
+        // very optional optimization, !very slow!
+        if let Some(optimizer) = optimizer {
+            if options.optimize_every > 0 && quantizer.num_colors() % options.optimize_every == 0 {
+                quantizer = quantizer.optimize(optimizer, options.split_iterations);
+            }
+        }
+        let done = quantizer.num_colors() as f32 / num_colors as f32;
+        progress::report(progress, Stage::Palette, done / 2.);
+    }
+
+    let palette = quantizer.colors(&colorspace);
+    // this optimization is more useful than the above and a lot less slow
+    match optimizer {
+        Some(optimizer) => {
+            optimizer.optimize_palette(&colorspace, &palette, histogram, options.iterations)
+        }
+        None => palette,
+    }
+}
+
+/// A color of a histogram with its number of occurrences.
+type Bin = ([u8; 3], u64);
+
+/// The mean of the colors of a histogram,
+/// each weighted by the given function of its number of occurrences.
+fn centroid(bins: &[Bin], weight: impl Fn(u64) -> f64) -> [f64; 3] {
+    let mut total = 0.;
+    let mut mean = [0.; 3];
+    for &(color, n) in bins {
+        let w = weight(n);
+        total += w;
+        for (m, &c) in mean.iter_mut().zip(&color) {
+            *m += f64::from(c) * w;
+        }
+    }
+    if total > 0. {
+        mean.map(|m| m / total)
+    } else {
+        mean
+    }
+}
+
+/// The squared distance between two colors.
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter().zip(&b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// Build a palette of up to `num_colors` colors
+/// from a histogram sorted by color,
+/// by median cut refined with the K-means variant of the selected optimizer.
+/// The weighted variant weighs each color
+/// by the square root of its number of occurrences,
+/// so that rare colors pull the palette harder.
+/// The result only depends on the histogram, the options and the seed.
+/// Cutting counts as the first half of the progress.
+fn median_cut(
+    histogram: &[Bin],
+    num_colors: usize,
+    options: &PaletteBuilderOptions,
+    seed: u64,
+    progress: Option<&ProgressHook>,
+) -> Vec<Color> {
+    if histogram.is_empty() || num_colors == 0 {
+        return Vec::new();
+    }
+    let mut boxes = vec![histogram.to_vec()];
+    while boxes.len() < num_colors {
+        // split the box with the widest range of any channel
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, bins)| bins.len() > 1)
+            .map(|(i, bins)| {
+                let (channel, range) = (0..3)
+                    .map(|ch| {
+                        let (min, max) = bins
+                            .iter()
+                            .map(|(c, _)| c[ch])
+                            .minmax()
+                            .into_option()
+                            .unwrap();
+                        (ch, max - min)
+                    })
+                    .max_by_key(|&(ch, range)| (range, std::cmp::Reverse(ch)))
+                    .unwrap();
+                (range, std::cmp::Reverse(i), channel)
+            })
+            .max();
+        let (i, channel) = match widest {
+            Some((_, std::cmp::Reverse(i), channel)) => (i, channel),
+            None => break,
+        };
+        let mut bins = boxes.swap_remove(i);
+        bins.sort_by_key(|&(c, _)| (c[channel], c));
+        // cut at the weighted median, leaving at least one color on each side
+        let total: u64 = bins.iter().map(|&(_, n)| n).sum();
+        let mut acc = 0;
+        let cut = bins
+            .iter()
+            .position(|&(_, n)| {
+                acc += n;
+                acc * 2 >= total
+            })
+            .unwrap_or(0)
+            .clamp(0, bins.len() - 2)
+            + 1;
+        let upper = bins.split_off(cut);
+        boxes.push(bins);
+        boxes.push(upper);
+        let done = boxes.len() as f32 / num_colors as f32;
+        progress::report(progress, Stage::Palette, done / 2.);
+    }
+
+    let weight: fn(u64) -> f64 = match options.optimizer {
+        PaletteOptimizer::WeightedKMeans => |n: u64| (n as f64).sqrt(),
+        _ => |n: u64| n as f64,
+    };
+    let mut centers = boxes
+        .iter()
+        .map(|bins| centroid(bins, weight))
+        .collect_vec();
+    if options.optimizer != PaletteOptimizer::None {
+        let mut rng = SplitMix64(seed);
+        for iteration in 0..options.iterations {
+            let mut clusters = vec![Vec::new(); centers.len()];
+            for &(color, n) in histogram {
+                let c = color.map(f64::from);
+                let nearest = (0..centers.len())
+                    .min_by(|&a, &b| distance(c, centers[a]).total_cmp(&distance(c, centers[b])))
+                    .unwrap();
+                clusters[nearest].push((color, n));
+            }
+            for (center, cluster) in centers.iter_mut().zip(&clusters) {
+                *center = if cluster.is_empty() {
+                    // move unused centers to a color picked at random
+                    let (color, _) = histogram[(rng.next_u64() % histogram.len() as u64) as usize];
+                    color.map(f64::from)
+                } else {
+                    centroid(cluster, weight)
+                };
+            }
+            let done = (iteration + 1) as f32 / options.iterations as f32;
+            progress::report(progress, Stage::Palette, 0.5 + done / 2.);
+        }
+    }
+    centers
+        .into_iter()
+        .map(|[r, g, b]| Color {
+            r: r.round() as u8,
+            g: g.round() as u8,
+            b: b.round() as u8,
+            a: 255,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_palette, PaletteBuilderOptions, PaletteOptimizer};
+    use exoquant::Color;
+
+    #[test]
+    fn test_build_palette_optimizers() {
+        let colors = [[0, 0, 0], [0, 0, 255], [255, 0, 0], [255, 255, 255]];
+        let pixels: Vec<_> = (0..64)
+            .map(|i| {
+                let [r, g, b] = colors[i % 4];
+                Color { r, g, b, a: 255 }
+            })
+            .collect();
+        for &optimizer in &[
+            PaletteOptimizer::KMeans,
+            PaletteOptimizer::WeightedKMeans,
+            PaletteOptimizer::None,
+        ] {
+            let options = PaletteBuilderOptions {
+                optimizer,
+                ..Default::default()
+            };
+            let palette = build_palette(&pixels, 4, None, &options);
+            assert_eq!(palette.len(), 4, "{}", optimizer);
+            // each color gets an entry of its own, give or take rounding
+            for &[r, g, b] in &colors {
+                assert!(
+                    palette.iter().any(|c| {
+                        [(c.r, r), (c.g, g), (c.b, b)]
+                            .iter()
+                            .all(|&(x, y)| (i32::from(x) - i32::from(y)).abs() <= 2)
+                    }),
+                    "{}: {:?}",
+                    optimizer,
+                    [r, g, b]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_seeded_palette_is_deterministic() {
+        let options = PaletteBuilderOptions {
+            seed: Some(42),
+            ..Default::default()
+        };
+        let gradient: Vec<_> = (0..=255)
+            .map(|i| Color {
+                r: i,
+                g: 255 - i,
+                b: i / 2,
+                a: 255,
+            })
+            .collect();
+        let palette = build_palette(&gradient, 16, None, &options);
+        assert_eq!(palette.len(), 16);
+        assert_eq!(palette, build_palette(&gradient, 16, None, &options));
+
+        // the optimizer still makes a difference
+        let weighted = PaletteBuilderOptions {
+            optimizer: PaletteOptimizer::WeightedKMeans,
+            ..options
+        };
+        let mut skewed = gradient.clone();
+        skewed.extend(std::iter::repeat(gradient[0]).take(1000));
+        assert_ne!(
+            build_palette(&skewed, 4, None, &options),
+            build_palette(&skewed, 4, None, &weighted)
+        );
+
+        // distinct colors are kept exactly
+        let pixels: Vec<_> = gradient
+            .iter()
+            .step_by(64)
+            .cycle()
+            .take(64)
+            .cloned()
+            .collect();
+        let mut palette: Vec<_> = build_palette(&pixels, 4, None, &options)
+            .iter()
+            .map(|c| [c.r, c.g, c.b])
+            .collect();
+        palette.sort_unstable();
+        let mut expected: Vec<_> = pixels[..4].iter().map(|c| [c.r, c.g, c.b]).collect();
+        expected.sort_unstable();
+        assert_eq!(palette, expected);
+    }
+}
@@ -0,0 +1,174 @@
+//! Conversion of huge images in strips.
+//!
+//! Converting a whole image at once takes several copies of its pixels
+//! (the original colors, the converted colors and the palette histogram),
+//! which adds up for images of many megapixels, such as large scans.
+//! Instead, the palette can be built from a subsample of the image
+//! and the image converted in place, a strip of rows at a time,
+//! so that the memory needed besides the image itself
+//! does not grow with its height.
+use super::dither::StripRemapper;
+use super::{colors_to_image, pixels, restore_protected_colors, ColorDepth, ColorOptions};
+use crate::progress::{self, Stage};
+use exoquant::Color;
+use image::RgbImage;
+use itertools::Itertools;
+use std::borrow::Cow;
+
+/// The options for converting an image in strips.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StripOptions {
+    /// The number of rows converted at a time
+    pub strip_height: u32,
+    /// About how many pixels of the image to sample
+    /// when building the palette
+    pub max_samples: u32,
+}
+
+impl Default for StripOptions {
+    fn default() -> Self {
+        StripOptions {
+            strip_height: 64,
+            max_samples: 1 << 20,
+        }
+    }
+}
+
+/// Convert an image in place to the given color depth,
+/// a strip of rows at a time,
+/// and retrieve the palette of the conversion.
+///
+/// The palette is the one which the color depth chooses
+/// for an evenly spaced subsample of the image.
+/// The strips are then dithered over it,
+/// carrying the dithering over from each strip to the next
+/// so that there are no seams between them.
+/// If the number of colors is not limited,
+/// the color depth converts each strip on its own instead.
+///
+/// The importance map and the alpha channel of the options are not used,
+/// and color depths with constraints on the colors of each cell
+/// are only held to them without a limit on the number of colors.
+pub fn convert_image_in_strips<D>(
+    depth: &D,
+    image: &mut RgbImage,
+    options: ColorOptions,
+    strips: StripOptions,
+) -> Vec<Color>
+where
+    D: ColorDepth + ?Sized,
+{
+    let options = ColorOptions {
+        importance: None,
+        alpha: None,
+        ..options
+    };
+    let (width, height) = image.dimensions();
+
+    progress::report(options.progress.as_ref(), Stage::Palette, 0.);
+    let sample = subsample(image, strips.max_samples);
+    let sample_options = ColorOptions {
+        progress: None,
+        ..options.clone()
+    };
+    let (_, mut palette, _) = depth.convert_image_with_loss(&sample, sample_options);
+    drop(sample);
+    progress::report(options.progress.as_ref(), Stage::Palette, 1.);
+
+    let mut remapper = Some(&palette)
+        .filter(|palette| !palette.is_empty())
+        .map(|palette| StripRemapper::new(width, palette, &options));
+    let strip_height = strips.strip_height.max(1);
+    for y in (0..height).step_by(strip_height as usize) {
+        let rows = strip_height.min(height - y);
+        let strip = image::imageops::crop_imm(image, 0, y, width, rows).to_image();
+        let converted = match &mut remapper {
+            Some(remapper) => {
+                let original = pixels(&strip);
+                let mut converted = remapper
+                    .remap(&original)
+                    .into_iter()
+                    .map(|i| palette[i])
+                    .collect_vec();
+                restore_protected_colors(
+                    &original,
+                    &mut converted,
+                    &mut palette,
+                    &options.protected_colors,
+                );
+                converted
+            }
+            None => depth.convert_image(&strip, options.clone()),
+        };
+        let strip = colors_to_image(width, rows, converted);
+        image::imageops::replace(image, &strip, 0, i64::from(y));
+        progress::report(
+            options.progress.as_ref(),
+            Stage::Dither,
+            (y + rows) as f32 / height as f32,
+        );
+    }
+    palette
+}
+
+/// An evenly spaced subsample of about `max_samples` pixels of the image,
+/// or the image itself if it is not larger than that.
+fn subsample(image: &RgbImage, max_samples: u32) -> Cow<'_, RgbImage> {
+    let (width, height) = image.dimensions();
+    let pixels = u64::from(width) * u64::from(height);
+    let step = (pixels as f64 / f64::from(max_samples.max(1)))
+        .sqrt()
+        .ceil() as u32;
+    if step <= 1 {
+        return Cow::Borrowed(image);
+    }
+    Cow::Owned(RgbImage::from_fn(
+        (width + step - 1) / step,
+        (height + step - 1) / step,
+        |x, y| *image.get_pixel(x * step, y * step),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert_image_in_strips, StripOptions};
+    use crate::color::{colors_to_image, ColorDepth, ColorOptions, DitherMode, FixedPalette};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_strips_match_whole_image() {
+        let image = RgbImage::from_fn(16, 16, |x, y| {
+            Rgb([(x * 16) as u8, (y * 16) as u8, ((x + y) * 8) as u8])
+        });
+        let depth = FixedPalette::new(vec![
+            [0, 0, 0],
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 255],
+        ]);
+        for &dither in &[
+            DitherMode::FloydSteinberg,
+            DitherMode::Atkinson,
+            DitherMode::Bayer4,
+        ] {
+            let options = ColorOptions {
+                num_colors: Some(4),
+                dither,
+                seed: Some(1),
+                ..Default::default()
+            };
+            let whole = colors_to_image(16, 16, depth.convert_image(&image, options.clone()));
+
+            // strips which do not line up with the ordered dithering matrix
+            let mut strips = image.clone();
+            let strip_options = StripOptions {
+                strip_height: 5,
+                ..Default::default()
+            };
+            let palette = convert_image_in_strips(&depth, &mut strips, options, strip_options);
+            assert!(palette.len() <= 4);
+            assert_eq!(strips, whole);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Palette swatch images.
+//!
+//! A swatch image shows each color of a palette in its own cell,
+//! optionally labeled with its hexadecimal value,
+//! which is useful for documentation
+//! and for matching colors by hand.
+use crate::font::{contrasting_ink, draw_text, ADVANCE, GLYPH_HEIGHT};
+use image::{Rgb, RgbImage};
+
+/// Options for rendering a palette swatch image.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SwatchOptions {
+    /// The width and height of each color cell, in pixels
+    pub cell_size: u32,
+    /// The maximum number of cells per row
+    pub columns: u32,
+    /// Whether to label each cell with the hexadecimal value of its color
+    /// (only if the cells are large enough)
+    pub labels: bool,
+}
+
+impl Default for SwatchOptions {
+    fn default() -> Self {
+        SwatchOptions {
+            cell_size: 64,
+            columns: 16,
+            labels: true,
+        }
+    }
+}
+
+/// The width of a label of 6 characters, at scale 1
+const LABEL_WIDTH: u32 = 6 * ADVANCE - 1;
+/// The height of a label at scale 1
+const LABEL_HEIGHT: u32 = GLYPH_HEIGHT;
+/// The margin around a label at scale 1
+const LABEL_MARGIN: u32 = 1;
+
+/// Render a swatch image of the given palette.
+pub fn render_swatches(colors: &[[u8; 3]], options: &SwatchOptions) -> RgbImage {
+    let cell = options.cell_size.max(1);
+    let columns = options.columns.clamp(1, colors.len().max(1) as u32);
+    let rows = (colors.len() as u32 + columns - 1) / columns;
+    let mut image = RgbImage::new(columns * cell, rows.max(1) * cell);
+
+    let scale = cell / (LABEL_WIDTH + 2 * LABEL_MARGIN);
+    for (i, &color) in colors.iter().enumerate() {
+        let (x0, y0) = ((i as u32 % columns) * cell, (i as u32 / columns) * cell);
+        for y in y0..y0 + cell {
+            for x in x0..x0 + cell {
+                image.put_pixel(x, y, Rgb(color));
+            }
+        }
+        if options.labels && scale > 0 {
+            let x = x0 + LABEL_MARGIN * scale;
+            let y = y0 + cell - (LABEL_HEIGHT + LABEL_MARGIN) * scale;
+            draw_label(&mut image, x, y, scale, color);
+        }
+    }
+    image
+}
+
+/// Draw the hexadecimal value of a color
+/// in black or white, whichever contrasts more with it.
+fn draw_label(image: &mut RgbImage, x: u32, y: u32, scale: u32, color: [u8; 3]) {
+    let [r, g, b] = color;
+    let label = format!("{:02x}{:02x}{:02x}", r, g, b);
+    draw_text(image, x, y, scale, &label, contrasting_ink(color));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_swatches, SwatchOptions};
+
+    #[test]
+    fn test_render_swatches() {
+        let colors = [[0, 0, 0], [0x55, 0xFF, 0xFF], [0xFF, 0x55, 0xFF]];
+        let options = SwatchOptions {
+            cell_size: 8,
+            columns: 2,
+            labels: true,
+        };
+        let image = render_swatches(&colors, &options);
+
+        assert_eq!(image.dimensions(), (16, 16));
+        assert_eq!(image.get_pixel(12, 4).0, [0x55, 0xFF, 0xFF]);
+        assert_eq!(image.get_pixel(4, 12).0, [0xFF, 0x55, 0xFF]);
+    }
+}
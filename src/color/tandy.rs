@@ -0,0 +1,14 @@
+//! Tandy 1000 and IBM PCjr video modes.
+//!
+//! Both machines extend CGA with modes showing
+//! all 16 colors of the CGA master palette at once
+//! (see [`PALETTE_CGA_4BIT`](super::cga::PALETTE_CGA_4BIT)).
+
+/// The resolution of the low resolution 16 color mode,
+/// with pixels shown twice as wide as in the 320x200 mode,
+/// as `[width, height]`.
+pub const RESOLUTION_LOW: [u32; 2] = [160, 200];
+
+/// The resolution of the medium resolution 16 color mode,
+/// as `[width, height]`.
+pub const RESOLUTION_MEDIUM: [u32; 2] = [320, 200];
@@ -0,0 +1,218 @@
+//! Temporal dithering.
+//!
+//! Some old games showed more colors than the hardware had
+//! by alternating two images on every frame,
+//! which the eye blends into their average.
+//! Rather than converting two images independently,
+//! each pixel is mapped to a pair of palette colors
+//! whose average is closest to the original color,
+//! which yields two complementary frames sharing one palette.
+use super::dither;
+use super::linear::{to_linear, to_srgb};
+use super::{pixels, ColorDepth, ColorOptions, IndexedImage};
+use exoquant::Color;
+use image::RgbImage;
+use itertools::Itertools;
+
+/// The most pairs of palette colors to mix,
+/// as each pixel is compared with all of them.
+/// Half of them at most pair a color with itself.
+const MAX_PAIRS: usize = 4096;
+
+/// Two complementary frames of a temporally dithered image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlickerPair {
+    /// The pixels of the first frame
+    pub first: Vec<Color>,
+    /// The pixels of the second frame
+    pub second: Vec<Color>,
+    /// The palette shared by both frames
+    pub palette: Vec<Color>,
+    /// The loss between the original image
+    /// and the average of the two frames
+    pub loss: u64,
+}
+
+/// Convert an image to two frames of the given color depth
+/// which resemble the image when shown alternately,
+/// mixing the colors of the palette which the color depth chooses for the image
+/// (see [`convert_image_temporal_with_palette`]).
+///
+/// Color depths with constraints on the colors of each cell
+/// are not held to them,
+/// as the pairs may combine colors of different cells.
+pub fn convert_image_temporal<D>(depth: &D, image: &RgbImage, options: ColorOptions) -> FlickerPair
+where
+    D: ColorDepth + ?Sized,
+{
+    let (width, height) = image.dimensions();
+    let (converted, palette, _loss) = depth.convert_image_with_loss(image, options.clone());
+    if !palette.is_empty() {
+        return convert_image_temporal_with_palette(&palette, image, options);
+    }
+    match IndexedImage::from_colors(width, height, &converted) {
+        Some(indexed) => convert_image_temporal_with_palette(&indexed.palette, image, options),
+        None => {
+            // too many colors to pair, so both frames are the same
+            let loss = options.image_loss(&pixels(image), &converted);
+            FlickerPair {
+                first: converted.clone(),
+                second: converted,
+                palette: Vec::new(),
+                loss,
+            }
+        }
+    }
+}
+
+/// Convert an image to two frames of colors from the given palette
+/// which resemble the image when shown alternately.
+///
+/// The pixels are dithered over the averages
+/// of all pairs of palette colors,
+/// leaving out the most distant pairs (which flicker the most)
+/// beyond 4096 pairs.
+/// The colors of each pair swap frames in a checkerboard pattern,
+/// so that flat areas do not flicker as a whole.
+///
+/// # Panics
+///
+/// Panics if the palette is empty.
+pub fn convert_image_temporal_with_palette(
+    palette: &[Color],
+    image: &RgbImage,
+    options: ColorOptions,
+) -> FlickerPair {
+    assert!(!palette.is_empty(), "the palette should not be empty");
+    let width = image.width();
+    let palette = palette
+        .iter()
+        .map(|&c| Color { a: 255, ..c })
+        .unique_by(|c| [c.r, c.g, c.b])
+        .collect_vec();
+    let original = pixels(image);
+
+    // the pairs of the same color come first, as they do not flicker at all,
+    // but leave room for the pairs which mix two colors
+    let mut pairs = (0..palette.len().min(MAX_PAIRS / 2))
+        .map(|i| (i, i))
+        .collect_vec();
+    let mut mixes = (0..palette.len())
+        .flat_map(|i| (i + 1..palette.len()).map(move |j| (i, j)))
+        .collect_vec();
+    mixes.sort_by_key(|&(i, j)| options.loss.color_diff(palette[i], palette[j]));
+    mixes.truncate(MAX_PAIRS - pairs.len());
+    pairs.extend(mixes);
+    let averages = pairs
+        .iter()
+        .map(|&(i, j)| average(palette[i], palette[j]))
+        .collect_vec();
+
+    let indices = dither::remap(&original, width, &averages, &options);
+    let blended = indices.iter().map(|&k| averages[k]).collect_vec();
+    let loss = options.image_loss(&original, &blended);
+
+    let (first, second) = indices
+        .iter()
+        .enumerate()
+        .map(|(k, &index)| {
+            let (i, j) = pairs[index];
+            let (x, y) = (k as u32 % width, k as u32 / width);
+            if (x + y) % 2 == 0 {
+                (palette[i], palette[j])
+            } else {
+                (palette[j], palette[i])
+            }
+        })
+        .unzip();
+    FlickerPair {
+        first,
+        second,
+        palette,
+        loss,
+    }
+}
+
+/// The color seen when two colors alternate,
+/// which is their average in linear light.
+fn average(a: Color, b: Color) -> Color {
+    let (a, b) = (to_linear(a), to_linear(b));
+    to_srgb([(a[0] + b[0]) / 2., (a[1] + b[1]) / 2., (a[2] + b[2]) / 2.])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::convert_image_temporal_with_palette;
+    use crate::color::{ColorOptions, DitherMode};
+    use exoquant::Color;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_convert_image_temporal() {
+        // a mid gray between black and white, and pure white
+        let image = RgbImage::from_fn(4, 2, |x, _| {
+            if x < 2 {
+                Rgb([188, 188, 188])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        let palette = [
+            Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+            Color {
+                r: 255,
+                g: 255,
+                b: 255,
+                a: 255,
+            },
+        ];
+        let options = ColorOptions {
+            dither: DitherMode::None,
+            ..Default::default()
+        };
+        let frames = convert_image_temporal_with_palette(&palette, &image, options);
+        assert_eq!(frames.palette.len(), 2);
+        assert_eq!(frames.loss, 0);
+
+        for k in [0, 1, 4, 5] {
+            // black and white alternate, in opposite phases for neighbors
+            assert_ne!(frames.first[k], frames.second[k]);
+        }
+        assert_eq!(frames.first[0], frames.second[1]);
+        assert_eq!(frames.first[0], frames.second[4]);
+        for k in [2, 3, 6, 7] {
+            assert_eq!((frames.first[k].r, frames.second[k].r), (255, 255));
+        }
+    }
+
+    #[test]
+    fn test_pairs_of_a_full_palette() {
+        // 128 even grays and 128 reds, none of them the odd gray
+        let palette = (0..128)
+            .map(|v| Color {
+                r: v * 2,
+                g: v * 2,
+                b: v * 2,
+                a: 255,
+            })
+            .chain((0..128).map(|v| Color {
+                r: v * 2 + 1,
+                g: 0,
+                b: 0,
+                a: 255,
+            }))
+            .collect::<Vec<_>>();
+        let image = RgbImage::from_pixel(2, 2, Rgb([101, 101, 101]));
+        let options = ColorOptions {
+            dither: DitherMode::None,
+            ..Default::default()
+        };
+        let frames = convert_image_temporal_with_palette(&palette, &image, options);
+        assert_ne!(frames.first[0], frames.second[0]);
+    }
+}
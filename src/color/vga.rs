@@ -0,0 +1,281 @@
+//! VGA color palettes.
+use super::FixedPalette;
+
+/// The default 256 color palette of VGA mode 13h,
+/// as set up by the BIOS in the DAC.
+pub static PALETTE_VGA_256: FixedPalette<&[[u8; 3]]> = FixedPalette(VGA_256);
+
+/// The default 256 color palette of VGA mode 13h:
+/// the 16 EGA colors, a ramp of 16 grays,
+/// 9 blocks of 24 hues in 3 intensities and 3 saturations,
+/// and 8 entries of black.
+pub static VGA_256: &[[u8; 3]] = &[
+    // EGA colors
+    [0, 0, 0],
+    [0, 0, 0xAA],
+    [0, 0xAA, 0],
+    [0, 0xAA, 0xAA],
+    [0xAA, 0, 0],
+    [0xAA, 0, 0xAA],
+    [0xAA, 0x55, 0],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x55, 0x55, 0xFF],
+    [0x55, 0xFF, 0x55],
+    [0x55, 0xFF, 0xFF],
+    [0xFF, 0x55, 0x55],
+    [0xFF, 0x55, 0xFF],
+    [0xFF, 0xFF, 0x55],
+    [0xFF, 0xFF, 0xFF],
+    // gray ramp
+    [0, 0, 0],
+    [0x14, 0x14, 0x14],
+    [0x20, 0x20, 0x20],
+    [0x2C, 0x2C, 0x2C],
+    [0x38, 0x38, 0x38],
+    [0x45, 0x45, 0x45],
+    [0x51, 0x51, 0x51],
+    [0x61, 0x61, 0x61],
+    [0x71, 0x71, 0x71],
+    [0x82, 0x82, 0x82],
+    [0x92, 0x92, 0x92],
+    [0xA2, 0xA2, 0xA2],
+    [0xB6, 0xB6, 0xB6],
+    [0xCB, 0xCB, 0xCB],
+    [0xE3, 0xE3, 0xE3],
+    [0xFF, 0xFF, 0xFF],
+    // high intensity, high saturation
+    [0, 0, 0xFF],
+    [0x41, 0, 0xFF],
+    [0x7D, 0, 0xFF],
+    [0xBE, 0, 0xFF],
+    [0xFF, 0, 0xFF],
+    [0xFF, 0, 0xBE],
+    [0xFF, 0, 0x7D],
+    [0xFF, 0, 0x41],
+    [0xFF, 0, 0],
+    [0xFF, 0x41, 0],
+    [0xFF, 0x7D, 0],
+    [0xFF, 0xBE, 0],
+    [0xFF, 0xFF, 0],
+    [0xBE, 0xFF, 0],
+    [0x7D, 0xFF, 0],
+    [0x41, 0xFF, 0],
+    [0, 0xFF, 0],
+    [0, 0xFF, 0x41],
+    [0, 0xFF, 0x7D],
+    [0, 0xFF, 0xBE],
+    [0, 0xFF, 0xFF],
+    [0, 0xBE, 0xFF],
+    [0, 0x7D, 0xFF],
+    [0, 0x41, 0xFF],
+    // high intensity, medium saturation
+    [0x7D, 0x7D, 0xFF],
+    [0x9E, 0x7D, 0xFF],
+    [0xBE, 0x7D, 0xFF],
+    [0xDF, 0x7D, 0xFF],
+    [0xFF, 0x7D, 0xFF],
+    [0xFF, 0x7D, 0xDF],
+    [0xFF, 0x7D, 0xBE],
+    [0xFF, 0x7D, 0x9E],
+    [0xFF, 0x7D, 0x7D],
+    [0xFF, 0x9E, 0x7D],
+    [0xFF, 0xBE, 0x7D],
+    [0xFF, 0xDF, 0x7D],
+    [0xFF, 0xFF, 0x7D],
+    [0xDF, 0xFF, 0x7D],
+    [0xBE, 0xFF, 0x7D],
+    [0x9E, 0xFF, 0x7D],
+    [0x7D, 0xFF, 0x7D],
+    [0x7D, 0xFF, 0x9E],
+    [0x7D, 0xFF, 0xBE],
+    [0x7D, 0xFF, 0xDF],
+    [0x7D, 0xFF, 0xFF],
+    [0x7D, 0xDF, 0xFF],
+    [0x7D, 0xBE, 0xFF],
+    [0x7D, 0x9E, 0xFF],
+    // high intensity, low saturation
+    [0xB6, 0xB6, 0xFF],
+    [0xC7, 0xB6, 0xFF],
+    [0xDB, 0xB6, 0xFF],
+    [0xEB, 0xB6, 0xFF],
+    [0xFF, 0xB6, 0xFF],
+    [0xFF, 0xB6, 0xEB],
+    [0xFF, 0xB6, 0xDB],
+    [0xFF, 0xB6, 0xC7],
+    [0xFF, 0xB6, 0xB6],
+    [0xFF, 0xC7, 0xB6],
+    [0xFF, 0xDB, 0xB6],
+    [0xFF, 0xEB, 0xB6],
+    [0xFF, 0xFF, 0xB6],
+    [0xEB, 0xFF, 0xB6],
+    [0xDB, 0xFF, 0xB6],
+    [0xC7, 0xFF, 0xB6],
+    [0xB6, 0xFF, 0xB6],
+    [0xB6, 0xFF, 0xC7],
+    [0xB6, 0xFF, 0xDB],
+    [0xB6, 0xFF, 0xEB],
+    [0xB6, 0xFF, 0xFF],
+    [0xB6, 0xEB, 0xFF],
+    [0xB6, 0xDB, 0xFF],
+    [0xB6, 0xC7, 0xFF],
+    // medium intensity, high saturation
+    [0, 0, 0x71],
+    [0x1C, 0, 0x71],
+    [0x38, 0, 0x71],
+    [0x55, 0, 0x71],
+    [0x71, 0, 0x71],
+    [0x71, 0, 0x55],
+    [0x71, 0, 0x38],
+    [0x71, 0, 0x1C],
+    [0x71, 0, 0],
+    [0x71, 0x1C, 0],
+    [0x71, 0x38, 0],
+    [0x71, 0x55, 0],
+    [0x71, 0x71, 0],
+    [0x55, 0x71, 0],
+    [0x38, 0x71, 0],
+    [0x1C, 0x71, 0],
+    [0, 0x71, 0],
+    [0, 0x71, 0x1C],
+    [0, 0x71, 0x38],
+    [0, 0x71, 0x55],
+    [0, 0x71, 0x71],
+    [0, 0x55, 0x71],
+    [0, 0x38, 0x71],
+    [0, 0x1C, 0x71],
+    // medium intensity, medium saturation
+    [0x38, 0x38, 0x71],
+    [0x45, 0x38, 0x71],
+    [0x55, 0x38, 0x71],
+    [0x61, 0x38, 0x71],
+    [0x71, 0x38, 0x71],
+    [0x71, 0x38, 0x61],
+    [0x71, 0x38, 0x55],
+    [0x71, 0x38, 0x45],
+    [0x71, 0x38, 0x38],
+    [0x71, 0x45, 0x38],
+    [0x71, 0x55, 0x38],
+    [0x71, 0x61, 0x38],
+    [0x71, 0x71, 0x38],
+    [0x61, 0x71, 0x38],
+    [0x55, 0x71, 0x38],
+    [0x45, 0x71, 0x38],
+    [0x38, 0x71, 0x38],
+    [0x38, 0x71, 0x45],
+    [0x38, 0x71, 0x55],
+    [0x38, 0x71, 0x61],
+    [0x38, 0x71, 0x71],
+    [0x38, 0x61, 0x71],
+    [0x38, 0x55, 0x71],
+    [0x38, 0x45, 0x71],
+    // medium intensity, low saturation
+    [0x51, 0x51, 0x71],
+    [0x59, 0x51, 0x71],
+    [0x61, 0x51, 0x71],
+    [0x69, 0x51, 0x71],
+    [0x71, 0x51, 0x71],
+    [0x71, 0x51, 0x69],
+    [0x71, 0x51, 0x61],
+    [0x71, 0x51, 0x59],
+    [0x71, 0x51, 0x51],
+    [0x71, 0x59, 0x51],
+    [0x71, 0x61, 0x51],
+    [0x71, 0x69, 0x51],
+    [0x71, 0x71, 0x51],
+    [0x69, 0x71, 0x51],
+    [0x61, 0x71, 0x51],
+    [0x59, 0x71, 0x51],
+    [0x51, 0x71, 0x51],
+    [0x51, 0x71, 0x59],
+    [0x51, 0x71, 0x61],
+    [0x51, 0x71, 0x69],
+    [0x51, 0x71, 0x71],
+    [0x51, 0x69, 0x71],
+    [0x51, 0x61, 0x71],
+    [0x51, 0x59, 0x71],
+    // low intensity, high saturation
+    [0, 0, 0x41],
+    [0x10, 0, 0x41],
+    [0x20, 0, 0x41],
+    [0x30, 0, 0x41],
+    [0x41, 0, 0x41],
+    [0x41, 0, 0x30],
+    [0x41, 0, 0x20],
+    [0x41, 0, 0x10],
+    [0x41, 0, 0],
+    [0x41, 0x10, 0],
+    [0x41, 0x20, 0],
+    [0x41, 0x30, 0],
+    [0x41, 0x41, 0],
+    [0x30, 0x41, 0],
+    [0x20, 0x41, 0],
+    [0x10, 0x41, 0],
+    [0, 0x41, 0],
+    [0, 0x41, 0x10],
+    [0, 0x41, 0x20],
+    [0, 0x41, 0x30],
+    [0, 0x41, 0x41],
+    [0, 0x30, 0x41],
+    [0, 0x20, 0x41],
+    [0, 0x10, 0x41],
+    // low intensity, medium saturation
+    [0x20, 0x20, 0x41],
+    [0x28, 0x20, 0x41],
+    [0x30, 0x20, 0x41],
+    [0x38, 0x20, 0x41],
+    [0x41, 0x20, 0x41],
+    [0x41, 0x20, 0x38],
+    [0x41, 0x20, 0x30],
+    [0x41, 0x20, 0x28],
+    [0x41, 0x20, 0x20],
+    [0x41, 0x28, 0x20],
+    [0x41, 0x30, 0x20],
+    [0x41, 0x38, 0x20],
+    [0x41, 0x41, 0x20],
+    [0x38, 0x41, 0x20],
+    [0x30, 0x41, 0x20],
+    [0x28, 0x41, 0x20],
+    [0x20, 0x41, 0x20],
+    [0x20, 0x41, 0x28],
+    [0x20, 0x41, 0x30],
+    [0x20, 0x41, 0x38],
+    [0x20, 0x41, 0x41],
+    [0x20, 0x38, 0x41],
+    [0x20, 0x30, 0x41],
+    [0x20, 0x28, 0x41],
+    // low intensity, low saturation
+    [0x2C, 0x2C, 0x41],
+    [0x30, 0x2C, 0x41],
+    [0x34, 0x2C, 0x41],
+    [0x3C, 0x2C, 0x41],
+    [0x41, 0x2C, 0x41],
+    [0x41, 0x2C, 0x3C],
+    [0x41, 0x2C, 0x34],
+    [0x41, 0x2C, 0x30],
+    [0x41, 0x2C, 0x2C],
+    [0x41, 0x30, 0x2C],
+    [0x41, 0x34, 0x2C],
+    [0x41, 0x3C, 0x2C],
+    [0x41, 0x41, 0x2C],
+    [0x3C, 0x41, 0x2C],
+    [0x34, 0x41, 0x2C],
+    [0x30, 0x41, 0x2C],
+    [0x2C, 0x41, 0x2C],
+    [0x2C, 0x41, 0x30],
+    [0x2C, 0x41, 0x34],
+    [0x2C, 0x41, 0x3C],
+    [0x2C, 0x41, 0x41],
+    [0x2C, 0x3C, 0x41],
+    [0x2C, 0x34, 0x41],
+    [0x2C, 0x30, 0x41],
+    // black
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+];
@@ -0,0 +1,62 @@
+//! Composition of several images into one,
+//! such as contact sheets comparing color standards.
+use crate::font::{draw_text, text_width, GLYPH_HEIGHT};
+use image::{Rgb, RgbImage};
+
+/// Lay out the given images in a grid of about as many columns as rows,
+/// each with its label centered above it in white.
+///
+/// Every cell is as large as the largest image,
+/// and the text is scaled up with the size of the cells.
+pub fn labeled_grid<S>(cells: &[(S, RgbImage)]) -> RgbImage
+where
+    S: AsRef<str>,
+{
+    let cell_width = cells.iter().map(|(_, c)| c.width()).max().unwrap_or(0);
+    let cell_height = cells.iter().map(|(_, c)| c.height()).max().unwrap_or(0);
+    let columns = (1..).find(|c| c * c >= cells.len() as u32).unwrap_or(1);
+    let rows = (cells.len() as u32 + columns - 1) / columns;
+    let scale = (cell_width / 160).max(1);
+    let label_height = (GLYPH_HEIGHT + 2) * scale;
+
+    let mut sheet = RgbImage::new(columns * cell_width, rows * (cell_height + label_height));
+    for (i, (label, cell)) in cells.iter().enumerate() {
+        let x = (i as u32 % columns) * cell_width;
+        let y = (i as u32 / columns) * (cell_height + label_height);
+        let label = label.as_ref();
+        let label_x = x + cell_width.saturating_sub(text_width(label, scale)) / 2;
+        draw_text(
+            &mut sheet,
+            label_x,
+            y + scale,
+            scale,
+            label,
+            Rgb([255, 255, 255]),
+        );
+        image::imageops::replace(&mut sheet, cell, i64::from(x), i64::from(y + label_height));
+    }
+    sheet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::labeled_grid;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_labeled_grid() {
+        let cells = vec![
+            ("cga", RgbImage::from_pixel(8, 4, Rgb([255, 0, 0]))),
+            ("ega", RgbImage::from_pixel(8, 4, Rgb([0, 255, 0]))),
+            ("vga", RgbImage::from_pixel(6, 3, Rgb([0, 0, 255]))),
+        ];
+        let sheet = labeled_grid(&cells);
+        // 2 columns and 2 rows of 8x4 cells, each below a 7 pixel label
+        assert_eq!(sheet.dimensions(), (16, 22));
+        assert_eq!(*sheet.get_pixel(0, 7), Rgb([255, 0, 0]));
+        assert_eq!(*sheet.get_pixel(8, 10), Rgb([0, 255, 0]));
+        assert_eq!(*sheet.get_pixel(5, 20), Rgb([0, 0, 255]));
+        // the smaller image leaves the rest of its cell black
+        assert_eq!(*sheet.get_pixel(7, 21), Rgb([0, 0, 0]));
+    }
+}
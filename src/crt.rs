@@ -0,0 +1,285 @@
+//! CRT monitor emulation,
+//! applied to the image after it is expanded to the output resolution.
+//!
+//! Each effect has its own intensity,
+//! and is disabled when the intensity is zero:
+//!
+//! - scanlines darken the gaps between the lines of the internal image;
+//! - the phosphor mask tints columns (or dots) of the screen
+//!   towards red, green and blue;
+//! - bloom makes bright areas glow onto their surroundings;
+//! - curvature bends the image as on a convex screen.
+use image::imageops::blur;
+use image::{Rgb, RgbImage};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Enumeration of phosphor mask patterns.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum PhosphorMask {
+    /// Vertical stripes of red, green and blue phosphors (Trinitron)
+    #[default]
+    ApertureGrille,
+    /// Triads of phosphor dots, shifted on every other row
+    ShadowMask,
+}
+
+impl std::fmt::Display for PhosphorMask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PhosphorMask::ApertureGrille => f.write_str("grille"),
+            PhosphorMask::ShadowMask => f.write_str("shadow-mask"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`PhosphorMask`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct PhosphorMaskParseError;
+
+impl std::fmt::Display for PhosphorMaskParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid phosphor mask, should be \"grille\" or \"shadow-mask\"")
+    }
+}
+
+impl std::error::Error for PhosphorMaskParseError {}
+
+impl FromStr for PhosphorMask {
+    type Err = PhosphorMaskParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "grille" | "aperture-grille" => Ok(PhosphorMask::ApertureGrille),
+            "shadow-mask" | "shadow" => Ok(PhosphorMask::ShadowMask),
+            _ => Err(PhosphorMaskParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for PhosphorMask {
+    type Error = PhosphorMaskParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PhosphorMask> for String {
+    fn from(mask: PhosphorMask) -> Self {
+        mask.to_string()
+    }
+}
+
+/// Enumeration of CRT effects,
+/// for enabling them by name with their default intensity
+/// (see [`Crt::enable`]).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrtEffect {
+    /// Dark gaps between scanlines
+    Scanlines,
+    /// Phosphor mask, with the given pattern
+    Mask(PhosphorMask),
+    /// Glow around bright areas
+    Bloom,
+    /// Barrel distortion
+    Curvature,
+}
+
+impl std::fmt::Display for CrtEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrtEffect::Scanlines => f.write_str("scanlines"),
+            CrtEffect::Mask(PhosphorMask::ApertureGrille) => f.write_str("mask"),
+            CrtEffect::Mask(mask) => mask.fmt(f),
+            CrtEffect::Bloom => f.write_str("bloom"),
+            CrtEffect::Curvature => f.write_str("curvature"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`CrtEffect`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct CrtEffectParseError;
+
+impl std::fmt::Display for CrtEffectParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("no such CRT effect")
+    }
+}
+
+impl std::error::Error for CrtEffectParseError {}
+
+impl FromStr for CrtEffect {
+    type Err = CrtEffectParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "scanlines" => Ok(CrtEffect::Scanlines),
+            "mask" => Ok(CrtEffect::Mask(PhosphorMask::ApertureGrille)),
+            "bloom" => Ok(CrtEffect::Bloom),
+            "curvature" => Ok(CrtEffect::Curvature),
+            s => s
+                .parse()
+                .map(CrtEffect::Mask)
+                .map_err(|_| CrtEffectParseError),
+        }
+    }
+}
+
+/// The default darkening of the gaps between scanlines
+const DEFAULT_SCANLINES: f32 = 0.5;
+/// The default darkening of the subpixels filtered out by the phosphor mask
+const DEFAULT_MASK: f32 = 0.3;
+/// The default strength of the bloom
+const DEFAULT_BLOOM: f32 = 0.3;
+/// The default strength of the barrel distortion
+const DEFAULT_CURVATURE: f32 = 0.1;
+
+/// A combination of CRT effects.
+///
+/// The default disables all effects.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Crt {
+    /// Darkening of the gaps between scanlines, from 0 to 1
+    pub scanlines: f32,
+    /// The phosphor mask pattern
+    pub mask: PhosphorMask,
+    /// Darkening of the subpixels filtered out by the phosphor mask,
+    /// from 0 to 1
+    pub mask_intensity: f32,
+    /// Strength of the glow around bright areas, from 0 to 1
+    pub bloom: f32,
+    /// Strength of the barrel distortion (0.1 is a typical curved screen)
+    pub curvature: f32,
+}
+
+impl Crt {
+    /// Enable an effect with its default intensity.
+    pub fn enable(&mut self, effect: CrtEffect) {
+        match effect {
+            CrtEffect::Scanlines => self.scanlines = DEFAULT_SCANLINES,
+            CrtEffect::Mask(mask) => {
+                self.mask = mask;
+                self.mask_intensity = DEFAULT_MASK;
+            }
+            CrtEffect::Bloom => self.bloom = DEFAULT_BLOOM,
+            CrtEffect::Curvature => self.curvature = DEFAULT_CURVATURE,
+        }
+    }
+
+    /// Apply the effects to an image expanded to the output resolution,
+    /// where `lines` is the number of lines in the internal image.
+    pub fn apply(&self, image: &RgbImage, lines: u32) -> RgbImage {
+        let mut image = image.clone();
+        if self.scanlines > 0. {
+            scanlines(&mut image, lines, self.scanlines.min(1.));
+        }
+        if self.mask_intensity > 0. {
+            phosphor_mask(&mut image, self.mask, self.mask_intensity.min(1.));
+        }
+        if self.bloom > 0. {
+            bloom(&mut image, self.bloom);
+        }
+        if self.curvature > 0. {
+            image = curvature(&image, self.curvature);
+        }
+        image
+    }
+}
+
+fn scale(pixel: &mut Rgb<u8>, factors: [f32; 3]) {
+    for (c, f) in pixel.0.iter_mut().zip(&factors) {
+        *c = (f32::from(*c) * f).round().clamp(0., 255.) as u8;
+    }
+}
+
+fn scanlines(image: &mut RgbImage, lines: u32, intensity: f32) {
+    let height = image.height();
+    if lines == 0 || height <= lines {
+        return;
+    }
+    for (y, row) in image.enumerate_rows_mut() {
+        // position within the source line, from 0 (top) to 1 (bottom)
+        let t = ((y as f32 + 0.5) * lines as f32 / height as f32).fract();
+        let brightness = 1. - intensity * (2. * t - 1.).powi(2);
+        for (_, _, pixel) in row {
+            scale(pixel, [brightness; 3]);
+        }
+    }
+}
+
+fn phosphor_mask(image: &mut RgbImage, mask: PhosphorMask, intensity: f32) {
+    let dim = 1. - intensity;
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let column = match mask {
+            PhosphorMask::ApertureGrille => x,
+            PhosphorMask::ShadowMask if (y / 2) % 2 == 1 => x + 2,
+            PhosphorMask::ShadowMask => x,
+        };
+        let mut factors = [dim; 3];
+        factors[(column % 3) as usize] = 1.;
+        scale(pixel, factors);
+    }
+}
+
+fn bloom(image: &mut RgbImage, strength: f32) {
+    let sigma = (image.height() as f32 / 270.).max(1.);
+    let glow = blur(image, sigma);
+    for (pixel, glow) in image.pixels_mut().zip(glow.pixels()) {
+        for (c, g) in pixel.0.iter_mut().zip(&glow.0) {
+            *c = (f32::from(*c) + f32::from(*g) * strength).round().min(255.) as u8;
+        }
+    }
+}
+
+fn curvature(image: &RgbImage, strength: f32) -> RgbImage {
+    let (width, height) = image.dimensions();
+    RgbImage::from_fn(width, height, |x, y| {
+        // normalized coordinates, from -1 to 1
+        let u = (x as f32 + 0.5) / width as f32 * 2. - 1.;
+        let v = (y as f32 + 0.5) / height as f32 * 2. - 1.;
+        let k = 1. + strength * (u * u + v * v);
+        let (u, v) = (u * k / (1. + strength), v * k / (1. + strength));
+        if u.abs() > 1. || v.abs() > 1. {
+            return Rgb([0, 0, 0]);
+        }
+        let sx = ((u + 1.) / 2. * width as f32) as u32;
+        let sy = ((v + 1.) / 2. * height as f32) as u32;
+        *image.get_pixel(sx.min(width - 1), sy.min(height - 1))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Crt, CrtEffect};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_scanlines() {
+        let image = RgbImage::from_pixel(4, 40, Rgb([200, 200, 200]));
+        let mut crt = Crt::default();
+        crt.enable(CrtEffect::Scanlines);
+        let out = crt.apply(&image, 10);
+
+        // the middle of each line keeps its brightness,
+        // the edges are darker
+        assert!(out.get_pixel(0, 1).0[0] > 180);
+        assert!(out.get_pixel(0, 0).0[0] < 150);
+        assert_eq!(Crt::default().apply(&image, 10), image);
+    }
+}
@@ -0,0 +1,279 @@
+//! Export of converted images in the memory layouts of retro video hardware,
+//! so that they can be copied into video memory as is
+//! (or included in a program with `INCBIN`).
+//!
+//! Pixels are written as indices into the default palette of the video mode
+//! when all colors of the image belong to it,
+//! or as indices into the image's own palette otherwise,
+//! in which case the palette must be programmed separately.
+use crate::color::cga::{
+    CGA_4BIT, CGA_MODE4_0_HIGH, CGA_MODE4_0_LOW, CGA_MODE4_1_HIGH, CGA_MODE4_1_LOW,
+};
+use crate::color::vga::VGA_256;
+use crate::color::IndexedImage;
+use exoquant::Color;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+pub mod bmp;
+pub mod pcx;
+
+/// Offset of the odd scanlines in CGA video memory
+const CGA_BANK_SIZE: usize = 0x2000;
+
+/// An error returned when exporting an image.
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum ExportError {
+    /// the image has more colors than the format admits
+    #[snafu(display("image has {} colors, but the format admits up to {}", colors, max))]
+    TooManyColors { colors: usize, max: usize },
+    #[snafu(display("failed to write image: {}", source))]
+    Io { source: std::io::Error },
+    /// image is too large for the format
+    TooLarge,
+    /// the format does not admit this number of bits per pixel
+    #[snafu(display("unsupported number of bits per pixel: {}", bits))]
+    UnsupportedBits { bits: u8 },
+}
+
+/// Enumeration of video memory layouts.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum RawFormat {
+    /// CGA mode 4, 2 bits per pixel,
+    /// with the odd scanlines 8 KiB after the even ones
+    CgaInterleaved,
+    /// EGA, 4 bit planes of 1 bit per pixel, one after the other
+    EgaPlanar,
+    /// VGA mode 13h, 1 byte per pixel
+    Vga13h,
+}
+
+impl RawFormat {
+    /// The number of bits per pixel of this layout.
+    pub fn bits_per_pixel(self) -> u8 {
+        match self {
+            RawFormat::CgaInterleaved => 2,
+            RawFormat::EgaPlanar => 4,
+            RawFormat::Vga13h => 8,
+        }
+    }
+
+    /// The palette which the pixels of the image are written as indices of:
+    /// the default palette of the video mode if it covers all colors of the image,
+    /// or the image's own palette otherwise.
+    pub fn palette(self, image: &IndexedImage) -> Result<Vec<[u8; 3]>, ExportError> {
+        let colors: Vec<_> = image
+            .palette
+            .iter()
+            .map(|&Color { r, g, b, .. }| [r, g, b])
+            .collect();
+        let covers = |palette: &[[u8; 3]]| colors.iter().all(|c| palette.contains(c));
+        let default = match self {
+            RawFormat::CgaInterleaved => {
+                // a free background color plus one of the fixed sub-palettes
+                [
+                    CGA_MODE4_1_HIGH,
+                    CGA_MODE4_1_LOW,
+                    CGA_MODE4_0_HIGH,
+                    CGA_MODE4_0_LOW,
+                ]
+                .iter()
+                .find_map(|sub| {
+                    let mut others = colors.iter().filter(|&c| !sub.contains(c));
+                    let background = others.next().copied().unwrap_or([0, 0, 0]);
+                    if others.next().is_some() || !CGA_4BIT.contains(&background) {
+                        return None;
+                    }
+                    Some(
+                        std::iter::once(background)
+                            .chain(sub.iter().copied())
+                            .collect::<Vec<_>>(),
+                    )
+                })
+            }
+            RawFormat::EgaPlanar => Some(CGA_4BIT.to_vec()).filter(|p| covers(p)),
+            RawFormat::Vga13h => Some(VGA_256.to_vec()).filter(|p| covers(p)),
+        };
+        let max = 1 << self.bits_per_pixel();
+        match default {
+            Some(palette) => Ok(palette),
+            None if colors.len() <= max => Ok(colors),
+            None => TooManyColorsSnafu {
+                colors: colors.len(),
+                max,
+            }
+            .fail(),
+        }
+    }
+
+    /// Encode an image in this layout.
+    pub fn encode(self, image: &IndexedImage) -> Result<Vec<u8>, ExportError> {
+        let palette = self.palette(image)?;
+        // the index of each image color in the palette
+        let lookup: Vec<u8> = image
+            .palette
+            .iter()
+            .map(|&Color { r, g, b, .. }| {
+                palette
+                    .iter()
+                    .position(|&c| c == [r, g, b])
+                    .and_then(|i| u8::try_from(i).ok())
+                    .unwrap_or(0)
+            })
+            .collect();
+        let indices: Vec<u8> = image
+            .indices
+            .iter()
+            .map(|&i| lookup[usize::from(i)])
+            .collect();
+        let (width, height) = (image.width as usize, image.height as usize);
+        Ok(match self {
+            RawFormat::CgaInterleaved => cga_interleaved(width, height, &indices),
+            RawFormat::EgaPlanar => ega_planar(width, height, &indices),
+            RawFormat::Vga13h => indices,
+        })
+    }
+}
+
+impl std::fmt::Display for RawFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RawFormat::CgaInterleaved => f.write_str("cga-raw"),
+            RawFormat::EgaPlanar => f.write_str("ega-planar"),
+            RawFormat::Vga13h => f.write_str("vga13h"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`RawFormat`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct RawFormatParseError;
+
+impl std::fmt::Display for RawFormatParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid raw format, should be \"cga-raw\", \"ega-planar\" or \"vga13h\"")
+    }
+}
+
+impl std::error::Error for RawFormatParseError {}
+
+impl FromStr for RawFormat {
+    type Err = RawFormatParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cga-raw" | "cgaraw" => Ok(RawFormat::CgaInterleaved),
+            "ega-planar" | "egaplanar" => Ok(RawFormat::EgaPlanar),
+            "vga13h" | "mode13h" => Ok(RawFormat::Vga13h),
+            _ => Err(RawFormatParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for RawFormat {
+    type Error = RawFormatParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<RawFormat> for String {
+    fn from(format: RawFormat) -> Self {
+        format.to_string()
+    }
+}
+
+/// Pack a line of pixel values of `bits` bits each,
+/// most significant bits first,
+/// padding the last byte with zeros.
+fn pack_line(line: &[u8], bits: usize) -> impl Iterator<Item = u8> + '_ {
+    let mask = (1 << bits) - 1;
+    line.chunks(8 / bits).map(move |pixels| {
+        pixels.iter().enumerate().fold(0, |byte, (i, &p)| {
+            byte | ((p & mask) << (8 - bits * (i + 1)))
+        })
+    })
+}
+
+/// Lay out 2 bit pixel values as in CGA video memory:
+/// the even scanlines first, then the odd scanlines 8 KiB later.
+pub fn cga_interleaved(width: usize, height: usize, indices: &[u8]) -> Vec<u8> {
+    let lines: Vec<_> = indices.chunks(width.max(1)).take(height).collect();
+    let mut data: Vec<u8> = lines
+        .iter()
+        .step_by(2)
+        .flat_map(|line| pack_line(line, 2))
+        .collect();
+    data.resize(data.len().max(CGA_BANK_SIZE), 0);
+    data.extend(
+        lines
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .flat_map(|line| pack_line(line, 2)),
+    );
+    data
+}
+
+/// Lay out 4 bit pixel values as EGA bit planes,
+/// one after the other from plane 0 (blue) to plane 3 (intensity),
+/// with 1 bit per pixel, most significant bits first.
+pub fn ega_planar(width: usize, height: usize, indices: &[u8]) -> Vec<u8> {
+    (0..4)
+        .flat_map(|plane| {
+            indices
+                .chunks(width.max(1))
+                .take(height)
+                .flat_map(move |line| {
+                    line.chunks(8).map(move |pixels| {
+                        pixels
+                            .iter()
+                            .enumerate()
+                            .fold(0_u8, |byte, (i, &p)| byte | (((p >> plane) & 1) << (7 - i)))
+                    })
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cga_interleaved, ega_planar, RawFormat};
+    use crate::color::IndexedImage;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_raw_layouts() {
+        // 8x2 pixels: 0 1 2 3 0 1 2 3 / 3 3 3 3 0 0 0 0
+        let indices = [0, 1, 2, 3, 0, 1, 2, 3, 3, 3, 3, 3, 0, 0, 0, 0];
+        let cga = cga_interleaved(8, 2, &indices);
+        assert_eq!(cga.len(), 0x2000 + 2);
+        assert_eq!(&cga[..2], &[0b0001_1011, 0b0001_1011]);
+        assert_eq!(&cga[0x2000..], &[0xFF, 0]);
+
+        let ega = ega_planar(8, 2, &indices);
+        assert_eq!(ega, vec![0b0101_0101, 0xF0, 0b0011_0011, 0xF0, 0, 0, 0, 0]);
+
+        // white, magenta and cyan over blue map to CGA palette 1
+        let image = RgbImage::from_fn(4, 1, |x, _| match x {
+            0 => Rgb([0, 0, 0xAA]),
+            1 => Rgb([0x55, 0xFF, 0xFF]),
+            2 => Rgb([0xFF, 0x55, 0xFF]),
+            _ => Rgb([0xFF, 0xFF, 0xFF]),
+        });
+        let indexed = IndexedImage::from_image(&image).unwrap();
+        let data = RawFormat::CgaInterleaved.encode(&indexed).unwrap();
+        assert_eq!(data[0], 0b0001_1011);
+    }
+}
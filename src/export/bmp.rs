@@ -0,0 +1,141 @@
+//! Palette-indexed BMP image encoding,
+//! as accepted by Windows 3.x era programs.
+//!
+//! Unlike the true color BMP files written by the `image` crate,
+//! these keep the palette of the image in the color table,
+//! with 1, 4 or 8 bits per pixel.
+use super::{ExportError, IoSnafu, TooLargeSnafu, TooManyColorsSnafu, UnsupportedBitsSnafu};
+use crate::color::IndexedImage;
+use exoquant::Color;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::convert::TryFrom;
+use std::io::Write;
+
+/// The size of the file header in bytes
+const FILE_HEADER_SIZE: u32 = 14;
+
+/// The size of the `BITMAPINFOHEADER` in bytes
+const INFO_HEADER_SIZE: u32 = 40;
+
+/// The resolution written to the header, in pixels per meter (72 DPI)
+const PIXELS_PER_METER: u32 = 2835;
+
+/// The smallest number of bits per pixel of an indexed BMP file
+/// which fits all colors of the image.
+pub fn min_bits(image: &IndexedImage) -> u8 {
+    match image.palette.len() {
+        0..=2 => 1,
+        3..=16 => 4,
+        _ => 8,
+    }
+}
+
+/// Write an indexed image as a BMP file
+/// with `bits` bits per pixel (1, 4 or 8),
+/// or the fewest bits which fit the palette if `None`.
+///
+/// The color table always has the full `2^bits` entries,
+/// since some older programs do not handle shorter tables.
+pub fn write_bmp<W: Write>(
+    mut writer: W,
+    image: &IndexedImage,
+    bits: Option<u8>,
+) -> Result<(), ExportError> {
+    let bits = bits.unwrap_or_else(|| min_bits(image));
+    ensure!(matches!(bits, 1 | 4 | 8), UnsupportedBitsSnafu { bits });
+    let max = 1_usize << bits;
+    ensure!(
+        image.palette.len() <= max,
+        TooManyColorsSnafu {
+            colors: image.palette.len(),
+            max,
+        }
+    );
+
+    // rows are padded to a multiple of 4 bytes
+    let row_size = (u64::from(image.width) * u64::from(bits) + 31) / 32 * 4;
+    let table_size = max as u32 * 4;
+    let data_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + table_size;
+    let data_size = u32::try_from(row_size * u64::from(image.height))
+        .ok()
+        .context(TooLargeSnafu)?;
+    let file_size = data_offset.checked_add(data_size).context(TooLargeSnafu)?;
+    let width = i32::try_from(image.width).ok().context(TooLargeSnafu)?;
+    let height = i32::try_from(image.height).ok().context(TooLargeSnafu)?;
+
+    let mut header = Vec::with_capacity(data_offset as usize);
+    header.extend_from_slice(b"BM");
+    header.extend_from_slice(&file_size.to_le_bytes());
+    header.extend_from_slice(&[0; 4]);
+    header.extend_from_slice(&data_offset.to_le_bytes());
+    header.extend_from_slice(&INFO_HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&width.to_le_bytes());
+    // a positive height means that rows are stored bottom up
+    header.extend_from_slice(&height.to_le_bytes());
+    header.extend_from_slice(&1_u16.to_le_bytes());
+    header.extend_from_slice(&u16::from(bits).to_le_bytes());
+    header.extend_from_slice(&0_u32.to_le_bytes()); // no compression
+    header.extend_from_slice(&data_size.to_le_bytes());
+    header.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+    header.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+    // 0 colors used and important means all of them
+    header.extend_from_slice(&[0; 8]);
+    for i in 0..max {
+        let Color { r, g, b, .. } = image.palette.get(i).copied().unwrap_or(Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        });
+        header.extend_from_slice(&[b, g, r, 0]);
+    }
+    writer.write_all(&header).context(IoSnafu)?;
+
+    let mut line = vec![0_u8; row_size as usize];
+    let bits = usize::from(bits);
+    for row in image.indices.chunks(image.width.max(1) as usize).rev() {
+        line.iter_mut().for_each(|b| *b = 0);
+        for (x, &index) in row.iter().enumerate() {
+            let bit = x * bits;
+            line[bit / 8] |= index << (8 - bits - bit % 8);
+        }
+        writer.write_all(&line).context(IoSnafu)?;
+    }
+    writer.flush().context(IoSnafu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_bmp;
+    use crate::color::IndexedImage;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_bmp() {
+        // 3x2 pixels, with the red pixel at the bottom right
+        let image = RgbImage::from_fn(3, 2, |x, y| match (x, y) {
+            (2, 1) => Rgb([0xFF, 0, 0]),
+            _ => Rgb([0, 0, 0xAA]),
+        });
+        let indexed = IndexedImage::from_image(&image).unwrap();
+
+        let mut data = Vec::new();
+        write_bmp(&mut data, &indexed, Some(4)).unwrap();
+        assert_eq!(&data[..2], b"BM");
+        assert_eq!(data[28], 4);
+        // 16 colors in BGR order, then 4 bytes per row
+        assert_eq!(&data[54..62], &[0xAA, 0, 0, 0, 0, 0, 0xFF, 0]);
+        assert_eq!(data.len(), 54 + 16 * 4 + 2 * 4);
+        // the bottom row comes first
+        assert_eq!(&data[118..122], &[0x00, 0x10, 0, 0]);
+
+        // the decoder of the image crate reads it back as is
+        let decoded = image::load_from_memory(&data).unwrap().to_rgb8();
+        assert_eq!(decoded, image);
+
+        let mut data = Vec::new();
+        write_bmp(&mut data, &indexed, Some(8)).unwrap();
+        assert_eq!(data.len(), 54 + 256 * 4 + 2 * 4);
+        assert!(write_bmp(&mut Vec::new(), &indexed, Some(3)).is_err());
+    }
+}
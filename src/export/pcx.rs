@@ -0,0 +1,138 @@
+//! PCX image encoding,
+//! the native format of most DOS era paint programs.
+//!
+//! Images are encoded with the fewest bits per pixel which fit their palette:
+//! 1 bit for up to 2 colors,
+//! 4 bit planes (as in EGA) for up to 16 colors,
+//! or 8 bits with a 256 color palette after the image data otherwise.
+use super::{ExportError, IoSnafu, TooLargeSnafu};
+use crate::color::IndexedImage;
+use exoquant::Color;
+use snafu::{OptionExt, ResultExt};
+use std::convert::TryFrom;
+use std::io::Write;
+
+/// The size of the PCX header in bytes
+const HEADER_SIZE: usize = 128;
+
+/// The longest run which a single RLE count byte can describe
+const MAX_RUN: usize = 63;
+
+/// Marker of the 256 color palette after the image data
+const PALETTE_MARKER: u8 = 0x0C;
+
+/// Write an indexed image as a PCX file.
+pub fn write_pcx<W: Write>(mut writer: W, image: &IndexedImage) -> Result<(), ExportError> {
+    let width = u16::try_from(image.width).ok().context(TooLargeSnafu)?;
+    let height = u16::try_from(image.height).ok().context(TooLargeSnafu)?;
+    let (bits, planes) = match image.palette.len() {
+        0..=2 => (1, 1),
+        3..=16 => (1, 4),
+        _ => (8, 1),
+    };
+    // the number of bytes per line of each plane, which must be even
+    let line_size = (usize::from(width) * bits + 7) / 8;
+    let line_size = line_size + line_size % 2;
+
+    let mut header = [0_u8; HEADER_SIZE];
+    header[0] = 0x0A; // manufacturer
+    header[1] = 5; // version 3.0 and later
+    header[2] = 1; // RLE encoding
+    header[3] = bits as u8;
+    let put_u16 = |header: &mut [u8], offset: usize, value: u16| {
+        header[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    };
+    put_u16(&mut header, 8, width.saturating_sub(1));
+    put_u16(&mut header, 10, height.saturating_sub(1));
+    put_u16(&mut header, 12, 72);
+    put_u16(&mut header, 14, 72);
+    if bits == 1 {
+        for (i, &Color { r, g, b, .. }) in image.palette.iter().enumerate() {
+            header[16 + i * 3..16 + i * 3 + 3].copy_from_slice(&[r, g, b]);
+        }
+    }
+    header[65] = planes as u8;
+    put_u16(&mut header, 66, line_size as u16);
+    put_u16(&mut header, 68, 1); // color palette
+    writer.write_all(&header).context(IoSnafu)?;
+
+    let mut line = vec![0_u8; line_size];
+    for row in image.indices.chunks(usize::from(width).max(1)) {
+        for plane in 0..planes {
+            line.iter_mut().for_each(|b| *b = 0);
+            if bits == 8 {
+                line[..row.len()].copy_from_slice(row);
+            } else {
+                for (x, &index) in row.iter().enumerate() {
+                    line[x / 8] |= ((index >> plane) & 1) << (7 - x % 8);
+                }
+            }
+            writer.write_all(&encode_rle(&line)).context(IoSnafu)?;
+        }
+    }
+
+    if bits == 8 {
+        let mut palette = vec![0_u8; 1 + 256 * 3];
+        palette[0] = PALETTE_MARKER;
+        for (i, &Color { r, g, b, .. }) in image.palette.iter().take(256).enumerate() {
+            palette[1 + i * 3..1 + i * 3 + 3].copy_from_slice(&[r, g, b]);
+        }
+        writer.write_all(&palette).context(IoSnafu)?;
+    }
+    writer.flush().context(IoSnafu)
+}
+
+/// Run-length encode a line of bytes:
+/// runs and bytes with the two top bits set
+/// are written as a count byte (`0xC0 | count`) followed by the value.
+fn encode_rle(line: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(&value) = rest.first() {
+        let run = rest
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&b| b == value)
+            .count();
+        if run > 1 || value & 0xC0 == 0xC0 {
+            out.push(0xC0 | run as u8);
+        }
+        out.push(value);
+        rest = &rest[run..];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_rle, write_pcx};
+    use crate::color::IndexedImage;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_pcx() {
+        assert_eq!(
+            encode_rle(&[1, 1, 1, 2, 0xC5]),
+            vec![0xC3, 1, 2, 0xC1, 0xC5]
+        );
+        assert_eq!(encode_rle(&[0; 70]), vec![0xFF, 0, 0xC7, 0]);
+
+        // 3 colors: 4 bit planes of 2 bytes per line (one byte and padding)
+        let image = RgbImage::from_fn(3, 2, |x, _| match x {
+            0 => Rgb([0, 0, 0]),
+            1 => Rgb([0xFF, 0, 0]),
+            _ => Rgb([0, 0, 0xFF]),
+        });
+        let indexed = IndexedImage::from_image(&image).unwrap();
+        let mut data = Vec::new();
+        write_pcx(&mut data, &indexed).unwrap();
+        assert_eq!(&data[..4], &[0x0A, 5, 1, 1]);
+        assert_eq!(data[65], 4);
+        assert_eq!(&data[66..68], &[2, 0]);
+        assert_eq!(&data[19..22], &[0xFF, 0, 0]);
+        // plane 0 of the first line: only the red pixel (index 1)
+        assert_eq!(&data[128..130], &[0x40, 0]);
+        // plane 1: only the blue pixel (index 2)
+        assert_eq!(&data[130..132], &[0x20, 0]);
+    }
+}
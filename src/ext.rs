@@ -0,0 +1,95 @@
+//! Extension trait for `image` buffers.
+//!
+//! With [`RetroImageExt`] in scope,
+//! images can be converted right alongside other `imageops` calls:
+//!
+//! ```no_run
+//! use retroimg::color::ColorOptions;
+//! use retroimg::{ColorStandard, RetroImageExt};
+//!
+//! let img = image::open("photo.png").unwrap().to_rgb8();
+//! let img = image::imageops::resize(&img, 320, 200, image::imageops::FilterType::Triangle);
+//! let options = ColorOptions {
+//!     num_colors: Some(16),
+//!     ..Default::default()
+//! };
+//! let img = img.retro(ColorStandard::FullEga, options);
+//! ```
+use crate::color::{colors_to_image, ColorDepth, ColorOptions, ColorStandard};
+use image::{DynamicImage, RgbImage, RgbaImage};
+
+/// Extension trait adding retro color conversion to image types.
+pub trait RetroImageExt {
+    /// The type of the converted image.
+    type Output;
+
+    /// Convert the image to the colors of the given standard.
+    fn retro(&self, standard: ColorStandard, options: ColorOptions) -> Self::Output {
+        self.retro_with(&*standard.color_depth(), options)
+    }
+
+    /// Convert the image to the given color depth.
+    fn retro_with<D>(&self, depth: &D, options: ColorOptions) -> Self::Output
+    where
+        D: ColorDepth + ?Sized;
+}
+
+impl RetroImageExt for RgbImage {
+    type Output = RgbImage;
+
+    fn retro_with<D>(&self, depth: &D, options: ColorOptions) -> Self::Output
+    where
+        D: ColorDepth + ?Sized,
+    {
+        let colors = depth.convert_image(self, options);
+        colors_to_image(self.width(), self.height(), colors)
+    }
+}
+
+impl RetroImageExt for RgbaImage {
+    type Output = RgbaImage;
+
+    fn retro_with<D>(&self, depth: &D, options: ColorOptions) -> Self::Output
+    where
+        D: ColorDepth + ?Sized,
+    {
+        crate::convert_rgba_with_loss(self, depth, options).0
+    }
+}
+
+impl RetroImageExt for DynamicImage {
+    type Output = DynamicImage;
+
+    fn retro_with<D>(&self, depth: &D, options: ColorOptions) -> Self::Output
+    where
+        D: ColorDepth + ?Sized,
+    {
+        crate::convert(self, depth, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetroImageExt;
+    use crate::color::{ColorOptions, ColorStandard};
+
+    #[test]
+    fn test_retro_black_white() {
+        let mut image = image::RgbImage::new(8, 8);
+        for x in 0..4 {
+            for y in 0..8 {
+                image.put_pixel(x, y, image::Rgb([250, 240, 245]));
+            }
+        }
+
+        let options = ColorOptions {
+            num_colors: Some(2),
+            ..Default::default()
+        };
+        let out = image.retro(ColorStandard::BlackWhite, options);
+
+        assert_eq!(out.dimensions(), (8, 8));
+        assert_eq!(out.get_pixel(0, 0), &image::Rgb([255, 255, 255]));
+        assert_eq!(out.get_pixel(7, 7), &image::Rgb([0, 0, 0]));
+    }
+}
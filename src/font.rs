@@ -0,0 +1,109 @@
+//! A tiny 3x5 bitmap font,
+//! for labeling the generated images
+//! without depending on any font files.
+use image::{Rgb, RgbImage};
+
+/// The width of a glyph, in pixels at scale 1
+pub(crate) const GLYPH_WIDTH: u32 = 3;
+/// The height of a glyph, in pixels at scale 1
+pub(crate) const GLYPH_HEIGHT: u32 = 5;
+/// The horizontal space taken by each character, in pixels at scale 1
+pub(crate) const ADVANCE: u32 = GLYPH_WIDTH + 1;
+
+/// The glyph of a character, one row of 3 bits per byte.
+///
+/// Letters are case insensitive,
+/// and unsupported characters are rendered as blanks.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_lowercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'a' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'b' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'c' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'd' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'e' => [0b111, 0b100, 0b111, 0b100, 0b111],
+        'f' => [0b111, 0b100, 0b111, 0b100, 0b100],
+        'g' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'h' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'i' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'j' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'k' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'l' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'm' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'n' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'o' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'p' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'r' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        't' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'u' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'v' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'w' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'x' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        _ => [0; 5],
+    }
+}
+
+/// The width of a line of text, in pixels.
+pub(crate) fn text_width(text: &str, scale: u32) -> u32 {
+    (text.chars().count() as u32 * ADVANCE).saturating_sub(1) * scale
+}
+
+/// Draw a line of text with its top left corner at `(x, y)`,
+/// clipped to the image.
+pub(crate) fn draw_text(
+    image: &mut RgbImage,
+    x: u32,
+    y: u32,
+    scale: u32,
+    text: &str,
+    ink: Rgb<u8>,
+) {
+    let (width, height) = image.dimensions();
+    for (i, c) in text.chars().enumerate() {
+        let gx = x + i as u32 * ADVANCE * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let px = gx + col * scale + dx;
+                        let py = y + row as u32 * scale + dy;
+                        if px < width && py < height {
+                            image.put_pixel(px, py, ink);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Black or white, whichever contrasts more with the given color.
+pub(crate) fn contrasting_ink([r, g, b]: [u8; 3]) -> Rgb<u8> {
+    let luma = u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114;
+    if luma > 128_000 {
+        Rgb([0, 0, 0])
+    } else {
+        Rgb([255, 255, 255])
+    }
+}
@@ -7,14 +7,47 @@
 //! See the various functions in this module
 //! (and the submodule [`color`])
 //! for more information.
+use crate::color::{colors_to_image, ColorOptions};
 use image::imageops::{resize, FilterType};
-use image::{GenericImage, ImageBuffer, Pixel, RgbImage};
+use image::{
+    DynamicImage, GenericImage, GrayImage, ImageBuffer, Pixel, Rgb, RgbImage, Rgba, RgbaImage,
+};
 use num_rational::Ratio;
-use snafu::Snafu;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, Snafu};
+use std::convert::TryFrom;
+use std::str::FromStr;
 
+pub mod adjust;
+pub mod animation;
+#[cfg(feature = "capi")]
+pub mod capi;
 pub mod color;
+pub mod compose;
+pub mod crt;
+pub mod export;
+pub mod ext;
+mod font;
+pub mod modes;
+pub mod pipeline;
+pub mod preprocess;
+pub mod preview;
+pub mod progress;
+pub mod textmode;
+pub mod tonemap;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-pub use crate::color::{ColorDepth, FixedPalette};
+// threads cannot be spawned in the browser
+#[cfg(all(feature = "rayon", target_arch = "wasm32", target_os = "unknown"))]
+compile_error!(
+    "the `rayon` feature is not available in WebAssembly, build without default features"
+);
+
+pub use crate::color::{ColorDepth, ColorStandard, FixedPalette};
+pub use crate::ext::RetroImageExt;
+pub use crate::pipeline::Pipeline;
 
 /// This is just another name for a cubic resize.
 /// 
@@ -30,11 +63,308 @@ where
     resize(img, nwidth, nheight, FilterType::CatmullRom)
 }
 
+/// Enumeration of resampling filters
+/// for reducing the image to the internal resolution.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum ResizeFilter {
+    /// Nearest neighbor, keeping hard edges (best for line art)
+    Nearest,
+    /// Box filter, averaging all source pixels of each output pixel
+    /// (best for photographs)
+    Box,
+    /// Linear filter
+    Triangle,
+    /// Cubic filter
+    #[default]
+    CatmullRom,
+    /// Lanczos filter with a window of 3
+    Lanczos3,
+}
+
+impl std::fmt::Display for ResizeFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ResizeFilter::Nearest => "nearest",
+            ResizeFilter::Box => "box",
+            ResizeFilter::Triangle => "triangle",
+            ResizeFilter::CatmullRom => "catmullrom",
+            ResizeFilter::Lanczos3 => "lanczos3",
+        })
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`ResizeFilter`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ResizeFilterParseError;
+
+impl std::fmt::Display for ResizeFilterParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid filter, should be \"nearest\", \"box\", \"triangle\", \"catmullrom\" or \"lanczos3\"")
+    }
+}
+
+impl std::error::Error for ResizeFilterParseError {}
+
+impl FromStr for ResizeFilter {
+    type Err = ResizeFilterParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(ResizeFilter::Nearest),
+            "box" | "area" => Ok(ResizeFilter::Box),
+            "triangle" | "linear" => Ok(ResizeFilter::Triangle),
+            "catmullrom" | "cubic" => Ok(ResizeFilter::CatmullRom),
+            "lanczos3" | "lanczos" => Ok(ResizeFilter::Lanczos3),
+            _ => Err(ResizeFilterParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for ResizeFilter {
+    type Error = ResizeFilterParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ResizeFilter> for String {
+    fn from(filter: ResizeFilter) -> Self {
+        filter.to_string()
+    }
+}
+
+/// Resize an image to a low resolution with the given filter.
+///
+/// [`reduce`] is the same as using [`ResizeFilter::CatmullRom`].
+pub fn reduce_with(img: &RgbImage, nwidth: u32, nheight: u32, filter: ResizeFilter) -> RgbImage {
+    let filter = match filter {
+        ResizeFilter::Box => return box_resize(img, nwidth, nheight),
+        ResizeFilter::Nearest => FilterType::Nearest,
+        ResizeFilter::Triangle => FilterType::Triangle,
+        ResizeFilter::CatmullRom => FilterType::CatmullRom,
+        ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+    };
+    resize(img, nwidth, nheight, filter)
+}
+
+/// Resize an image by averaging the source pixels
+/// covered by each output pixel.
+fn box_resize(img: &RgbImage, nwidth: u32, nheight: u32) -> RgbImage {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return RgbImage::new(nwidth, nheight);
+    }
+    // the range of source pixels covered by output pixel `i` of `n`
+    let span = |i: u32, n: u32, size: u32| {
+        let start = (u64::from(i) * u64::from(size) / u64::from(n)) as u32;
+        let end = (u64::from(i + 1) * u64::from(size) / u64::from(n)) as u32;
+        start..end.max(start + 1).min(size)
+    };
+    RgbImage::from_fn(nwidth, nheight, |x, y| {
+        let mut sum = [0_u64; 3];
+        let mut count = 0;
+        for sy in span(y, nheight, height) {
+            for sx in span(x, nwidth, width) {
+                for (s, &c) in sum.iter_mut().zip(&img.get_pixel(sx, sy).0) {
+                    *s += u64::from(c);
+                }
+                count += 1;
+            }
+        }
+        Rgb(sum.map(|s| ((s + count / 2) / count.max(1)) as u8))
+    })
+}
+
 /// Crop an image to the given borders.
 pub fn crop(mut image: RgbImage, left: u32, top: u32, width: u32, height: u32) -> RgbImage {
     image::imageops::crop(&mut image, left, top, width, height).to_image()
 }
 
+/// Where a crop rectangle is placed
+/// when the image is trimmed to a narrower aspect ratio.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum Gravity {
+    /// Keep the center of the image
+    #[default]
+    Center,
+    /// Keep the top edge
+    Top,
+    /// Keep the bottom edge
+    Bottom,
+    /// Keep the left edge
+    Left,
+    /// Keep the right edge
+    Right,
+    /// Keep the top left corner
+    TopLeft,
+    /// Keep the top right corner
+    TopRight,
+    /// Keep the bottom left corner
+    BottomLeft,
+    /// Keep the bottom right corner
+    BottomRight,
+}
+
+impl Gravity {
+    /// The position of the crop rectangle along each axis,
+    /// from 0 (left or top) to 2 (right or bottom).
+    fn position(self) -> (u32, u32) {
+        match self {
+            Gravity::Center => (1, 1),
+            Gravity::Top => (1, 0),
+            Gravity::Bottom => (1, 2),
+            Gravity::Left => (0, 1),
+            Gravity::Right => (2, 1),
+            Gravity::TopLeft => (0, 0),
+            Gravity::TopRight => (2, 0),
+            Gravity::BottomLeft => (0, 2),
+            Gravity::BottomRight => (2, 2),
+        }
+    }
+}
+
+impl std::fmt::Display for Gravity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Gravity::Center => "center",
+            Gravity::Top => "top",
+            Gravity::Bottom => "bottom",
+            Gravity::Left => "left",
+            Gravity::Right => "right",
+            Gravity::TopLeft => "top-left",
+            Gravity::TopRight => "top-right",
+            Gravity::BottomLeft => "bottom-left",
+            Gravity::BottomRight => "bottom-right",
+        })
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`Gravity`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct GravityParseError;
+
+impl std::fmt::Display for GravityParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid gravity, should be \"center\", \"top\", \"bottom\", \"left\", \"right\" or a corner (e.g. \"top-left\")")
+    }
+}
+
+impl std::error::Error for GravityParseError {}
+
+impl FromStr for Gravity {
+    type Err = GravityParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "center" | "centre" => Ok(Gravity::Center),
+            "top" | "north" => Ok(Gravity::Top),
+            "bottom" | "south" => Ok(Gravity::Bottom),
+            "left" | "west" => Ok(Gravity::Left),
+            "right" | "east" => Ok(Gravity::Right),
+            "top-left" | "northwest" => Ok(Gravity::TopLeft),
+            "top-right" | "northeast" => Ok(Gravity::TopRight),
+            "bottom-left" | "southwest" => Ok(Gravity::BottomLeft),
+            "bottom-right" | "southeast" => Ok(Gravity::BottomRight),
+            _ => Err(GravityParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for Gravity {
+    type Error = GravityParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Gravity> for String {
+    fn from(gravity: Gravity) -> Self {
+        gravity.to_string()
+    }
+}
+
+/// The largest rectangle of the given aspect ratio (width / height)
+/// within the rectangle `[left, top, width, height]`,
+/// placed according to `gravity`.
+pub fn aspect_crop_rect(rect: [u32; 4], aspect: Ratio<u32>, gravity: Gravity) -> [u32; 4] {
+    let [left, top, width, height] = rect;
+    let (aw, ah) = (u64::from(*aspect.numer()), u64::from(*aspect.denom()));
+    let (w, h) = (u64::from(width), u64::from(height));
+    if aw == 0 || ah == 0 {
+        return rect;
+    }
+    let (px, py) = gravity.position();
+    if w * ah > h * aw {
+        // too wide
+        let new_width = (h * aw / ah) as u32;
+        [left + (width - new_width) * px / 2, top, new_width, height]
+    } else {
+        let new_height = (w * ah / aw) as u32;
+        [
+            left,
+            top + (height - new_height) * py / 2,
+            width,
+            new_height,
+        ]
+    }
+}
+
+/// The maximum difference in any channel
+/// for a pixel to count as part of a uniform border
+const AUTO_CROP_TOLERANCE: u8 = 16;
+
+/// The rectangle `[left, top, width, height]` of an image
+/// without its uniform borders,
+/// as in letterboxed screenshots.
+///
+/// The border color is the color of the top left pixel.
+/// If the whole image is uniform, the full rectangle is returned.
+pub fn auto_crop_rect(img: &RgbImage) -> [u32; 4] {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return [0, 0, width, height];
+    }
+    let border = *img.get_pixel(0, 0);
+    let is_border = |x: u32, y: u32| {
+        img.get_pixel(x, y)
+            .0
+            .iter()
+            .zip(&border.0)
+            .all(|(&a, &b)| a.abs_diff(b) <= AUTO_CROP_TOLERANCE)
+    };
+    let uniform_row = |y: u32| (0..width).all(|x| is_border(x, y));
+    let uniform_column = |x: u32, top: u32, bottom: u32| (top..bottom).all(|y| is_border(x, y));
+
+    let top = match (0..height).find(|&y| !uniform_row(y)) {
+        Some(top) => top,
+        None => return [0, 0, width, height],
+    };
+    let bottom = (0..height).rev().find(|&y| !uniform_row(y)).unwrap_or(top) + 1;
+    let left = (0..width)
+        .find(|&x| !uniform_column(x, top, bottom))
+        .unwrap_or(0);
+    let right = (0..width)
+        .rev()
+        .find(|&x| !uniform_column(x, top, bottom))
+        .unwrap_or(width - 1)
+        + 1;
+    [left, top, right - left, bottom - top]
+}
+
 /// This is just another name for a nearest neighbor resize.
 /// 
 /// Makes it look like it has nice, large pixels.
@@ -49,6 +379,408 @@ where
     resize(img, nwidth, nheight, FilterType::Nearest)
 }
 
+/// How each line of the internal image is shown on the output image.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum ScanMode {
+    /// Each line becomes one thick band (as in VGA double scanning)
+    #[default]
+    Double,
+    /// Each line is followed by a dark gap
+    /// (as in 15 kHz single scan displays)
+    Single,
+}
+
+impl std::fmt::Display for ScanMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanMode::Double => f.write_str("double"),
+            ScanMode::Single => f.write_str("single"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`ScanMode`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ScanModeParseError;
+
+impl std::fmt::Display for ScanModeParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid scan mode, should be \"single\" or \"double\"")
+    }
+}
+
+impl std::error::Error for ScanModeParseError {}
+
+impl FromStr for ScanMode {
+    type Err = ScanModeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "double" => Ok(ScanMode::Double),
+            "single" => Ok(ScanMode::Single),
+            _ => Err(ScanModeParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for ScanMode {
+    type Error = ScanModeParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ScanMode> for String {
+    fn from(mode: ScanMode) -> Self {
+        mode.to_string()
+    }
+}
+
+/// How the image is fit into output dimensions
+/// of different proportions.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum Fit {
+    /// Stretch the image to the output dimensions
+    #[default]
+    Stretch,
+    /// Scale the image to fit inside the output dimensions,
+    /// keeping its proportions,
+    /// and fill the borders with the given RGB color
+    /// (see [`letterbox`])
+    Pad([u8; 3]),
+}
+
+impl std::fmt::Display for Fit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fit::Stretch => f.write_str("stretch"),
+            Fit::Pad([0, 0, 0]) => f.write_str("pad"),
+            Fit::Pad([r, g, b]) => write!(f, "pad:{:02x}{:02x}{:02x}", r, g, b),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`Fit`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct FitParseError;
+
+impl std::fmt::Display for FitParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid fit mode, should be \"stretch\", \"pad\" or \"pad:RRGGBB\"")
+    }
+}
+
+impl std::error::Error for FitParseError {}
+
+impl FromStr for Fit {
+    type Err = FitParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        match s.split_once(':') {
+            Some(("pad", color)) => color::palette::parse_hex_color(color)
+                .map(Fit::Pad)
+                .ok_or(FitParseError),
+            Some(_) => Err(FitParseError),
+            None => match s.as_str() {
+                "stretch" => Ok(Fit::Stretch),
+                "pad" => Ok(Fit::Pad([0, 0, 0])),
+                _ => Err(FitParseError),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for Fit {
+    type Error = FitParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Fit> for String {
+    fn from(fit: Fit) -> Self {
+        fit.to_string()
+    }
+}
+
+/// Center an image on a canvas of the given size
+/// filled with the given color.
+///
+/// Parts of the image which do not fit in the canvas are cut off.
+pub fn letterbox(img: &RgbImage, width: u32, height: u32, color: [u8; 3]) -> RgbImage {
+    let mut canvas = RgbImage::from_pixel(width, height, Rgb(color));
+    let x = (i64::from(width) - i64::from(img.width())) / 2;
+    let y = (i64::from(height) - i64::from(img.height())) / 2;
+    image::imageops::replace(&mut canvas, img, x, y);
+    canvas
+}
+
+/// How much of the intensity is kept in the gaps between scanlines
+const SCAN_GAP_INTENSITY: u32 = 64;
+
+/// A nearest neighbor resize emulating a single scan display,
+/// where the bottom half of the band of each line is a dark gap.
+///
+/// Bands thinner than two pixels are left without a gap.
+/// An empty image expands to a black one.
+pub fn expand_single_scan(img: &RgbImage, nwidth: u32, nheight: u32) -> RgbImage {
+    if img.width() == 0 || img.height() == 0 {
+        return RgbImage::new(nwidth, nheight);
+    }
+    let (width, height) = (u64::from(img.width()), u64::from(img.height()));
+    let (nw, nh) = (u64::from(nwidth), u64::from(nheight));
+    RgbImage::from_fn(nwidth, nheight, |x, y| {
+        let (x, y) = (u64::from(x), u64::from(y));
+        let line = y * height / nh;
+        let band_start = (line * nh + height - 1) / height;
+        let band_end = ((line + 1) * nh + height - 1) / height;
+        let band = band_end - band_start;
+
+        let Rgb(mut pixel) = *img.get_pixel((x * width / nw) as u32, line as u32);
+        if band >= 2 && y - band_start >= band / 2 {
+            for c in pixel.iter_mut() {
+                *c = (u32::from(*c) * SCAN_GAP_INTENSITY / 255) as u8;
+            }
+        }
+        Rgb(pixel)
+    })
+}
+
+/// Repeat an image to fill a canvas of the given size,
+/// starting from the top left corner.
+///
+/// With `mirror`, every other tile is flipped
+/// horizontally and/or vertically,
+/// so that the edges of adjacent tiles always match.
+pub fn tile(img: &RgbImage, width: u32, height: u32, mirror: bool) -> RgbImage {
+    let (w, h) = img.dimensions();
+    if w == 0 || h == 0 {
+        return RgbImage::new(width, height);
+    }
+    RgbImage::from_fn(width, height, |x, y| {
+        let (mut tx, mut ty) = (x % w, y % h);
+        if mirror {
+            if (x / w) % 2 == 1 {
+                tx = w - 1 - tx;
+            }
+            if (y / h) % 2 == 1 {
+                ty = h - 1 - ty;
+            }
+        }
+        *img.get_pixel(tx, ty)
+    })
+}
+
+/// Blend two images of the same size according to a grayscale mask,
+/// so that only some regions of the image get the retro treatment.
+///
+/// Where the mask is white, the pixel from `converted` is taken;
+/// where it is black, the pixel from `original` is taken.
+/// Intermediate values mix the two linearly.
+///
+/// # Panic
+///
+/// Panics if the three images do not have the same dimensions.
+pub fn blend(original: &RgbImage, converted: &RgbImage, mask: &GrayImage) -> RgbImage {
+    assert_eq!(original.dimensions(), converted.dimensions());
+    assert_eq!(original.dimensions(), mask.dimensions());
+
+    RgbImage::from_fn(original.width(), original.height(), |x, y| {
+        let a = original.get_pixel(x, y).0;
+        let b = converted.get_pixel(x, y).0;
+        let m = u32::from(mask.get_pixel(x, y).0[0]);
+        let mix = |a: u8, b: u8| ((u32::from(a) * (255 - m) + u32::from(b) * m + 127) / 255) as u8;
+        Rgb([mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2])])
+    })
+}
+
+/// Composite an image with transparency onto a solid background color.
+///
+/// Images without an alpha channel are only converted to RGB.
+pub fn matte(img: &DynamicImage, color: [u8; 3]) -> RgbImage {
+    if !img.color().has_alpha() {
+        return img.to_rgb8();
+    }
+    let rgba = to_rgba8_rounded(img);
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *rgba.get_pixel(x, y);
+        let a = u32::from(a);
+        let mix = |c: u8, m: u8| ((u32::from(c) * a + u32::from(m) * (255 - a) + 127) / 255) as u8;
+        Rgb([mix(r, color[0]), mix(g, color[1]), mix(b, color[2])])
+    })
+}
+
+/// Make every pixel of the given color fully transparent,
+/// and every other pixel fully opaque.
+pub fn key_color(img: &RgbImage, color: [u8; 3]) -> RgbaImage {
+    RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        let Rgb([r, g, b]) = *img.get_pixel(x, y);
+        let a = if [r, g, b] == color { 0 } else { 255 };
+        Rgba([r, g, b, a])
+    })
+}
+
+/// Replace the pixels which are more than half transparent
+/// with the given color, dropping the alpha channel of the rest.
+///
+/// Unlike [`matte`], the edges are not blended,
+/// so that the color can be keyed out again with [`key_color`].
+pub fn fill_transparent(img: &DynamicImage, color: [u8; 3]) -> RgbImage {
+    if !img.color().has_alpha() {
+        return img.to_rgb8();
+    }
+    let rgba = to_rgba8_rounded(img);
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *rgba.get_pixel(x, y);
+        if a < 128 {
+            Rgb(color)
+        } else {
+            Rgb([r, g, b])
+        }
+    })
+}
+
+/// Video modes (by resolution) which were shown stretched to a 4:3 display.
+static MODES_4_3: &[(u32, u32)] = &[
+    // CGA low resolution
+    (160, 200),
+    // CGA, EGA and VGA mode 13h
+    (320, 200),
+    // CGA high resolution and EGA
+    (640, 200),
+    // EGA high resolution
+    (640, 350),
+    // Hercules
+    (720, 348),
+    // Apple II high resolution
+    (280, 192),
+    // Mode X and VGA
+    (320, 240),
+    (640, 480),
+];
+
+/// Identify the pixel ratio of a historical video mode
+/// from its resolution,
+/// such as 5:6 for 320x200 on a 4:3 display.
+///
+/// Returns `None` if the resolution is not of a known video mode.
+pub fn mode_pixel_ratio(width: u32, height: u32) -> Option<Ratio<u32>> {
+    match (width, height) {
+        // NES and SNES
+        (256, 240) | (256, 224) => Some(Ratio::new(8, 7)),
+        _ if MODES_4_3.contains(&(width, height)) => Some(Ratio::new(4 * height, 3 * width)),
+        _ => None,
+    }
+}
+
+/// Convert an image of any color type to the given color depth.
+///
+/// Grayscale images are treated as RGB.
+/// If the image has an alpha channel,
+/// the output is an RGBA image with the original alpha values,
+/// otherwise the output is an RGB image.
+/// Images with 16 bits per sample are rounded to the nearest 8-bit value.
+pub fn convert<D>(img: &DynamicImage, depth: &D, options: ColorOptions) -> DynamicImage
+where
+    D: ColorDepth + ?Sized,
+{
+    convert_with_loss(img, depth, options).0
+}
+
+/// Convert an image of any color type to the given color depth,
+/// and retrieve the loss from the conversion.
+///
+/// See [`convert`] for more details.
+pub fn convert_with_loss<D>(
+    img: &DynamicImage,
+    depth: &D,
+    options: ColorOptions,
+) -> (DynamicImage, u64)
+where
+    D: ColorDepth + ?Sized,
+{
+    let rgba = to_rgba8_rounded(img);
+    if img.color().has_alpha() {
+        let (out, loss) = convert_rgba_with_loss(&rgba, depth, options);
+        (DynamicImage::ImageRgba8(out), loss)
+    } else {
+        let rgb = DynamicImage::ImageRgba8(rgba).into_rgb8();
+        let (colors, _palette, loss) = depth.convert_image_with_loss(&rgb, options);
+        let out = colors_to_image(rgb.width(), rgb.height(), colors);
+        (DynamicImage::ImageRgb8(out), loss)
+    }
+}
+
+/// Convert an RGBA image to the given color depth,
+/// keeping the alpha channel untouched.
+///
+/// Fully transparent pixels do not count towards the palette or the loss.
+pub(crate) fn convert_rgba_with_loss<D>(
+    rgba: &RgbaImage,
+    depth: &D,
+    mut options: ColorOptions,
+) -> (RgbaImage, u64)
+where
+    D: ColorDepth + ?Sized,
+{
+    let rgb = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let Rgba([r, g, b, _]) = *rgba.get_pixel(x, y);
+        Rgb([r, g, b])
+    });
+    options.alpha = GrayImage::from_raw(
+        rgba.width(),
+        rgba.height(),
+        rgba.pixels().map(|p| p[3]).collect(),
+    );
+
+    let (colors, _palette, loss) = depth.convert_image_with_loss(&rgb, options);
+
+    let pixels = colors
+        .into_iter()
+        .zip(rgba.pixels())
+        .flat_map(|(c, p)| [c.r, c.g, c.b, p[3]])
+        .collect();
+    let out = ImageBuffer::from_raw(rgba.width(), rgba.height(), pixels)
+        .expect("there should be enough pixels");
+    (out, loss)
+}
+
+/// Convert an image to 8-bit RGBA,
+/// rounding 16-bit samples to the nearest 8-bit value.
+fn to_rgba8_rounded(img: &DynamicImage) -> RgbaImage {
+    match img {
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => {
+            let rgba = img.to_rgba16();
+            let samples = rgba
+                .as_raw()
+                .iter()
+                .map(|&v| ((u32::from(v) * 255 + 32767) / 65535) as u8)
+                .collect();
+            ImageBuffer::from_raw(rgba.width(), rgba.height(), samples)
+                .expect("there should be enough samples")
+        }
+        _ => img.to_rgba8(),
+    }
+}
+
 /// An error returned by [`resolve_output_resolution`].
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub))]
@@ -59,6 +791,28 @@ pub enum ResolutionError {
     RatioWithoutSide,
     /// 'pixel_ratio', 'width' and 'height' cannot be used together
     TooMany,
+    /// the scale factor must be at least 1
+    ZeroScale,
+    /// the scaled resolution is too large
+    ScaleTooLarge,
+    /// the resolution and the pixel ratio must not be zero
+    ZeroResolution,
+}
+
+/// Multiply both dimensions of a resolution by an exact factor,
+/// which must be at least 1.
+pub fn scale_resolution(
+    width: u32,
+    height: u32,
+    scale: u32,
+) -> Result<(u32, u32), ResolutionError> {
+    if scale == 0 {
+        return ZeroScaleSnafu.fail();
+    }
+    width
+        .checked_mul(scale)
+        .zip(height.checked_mul(scale))
+        .context(ScaleTooLargeSnafu)
 }
 
 /// Identify the intended output resolution based on a combination
@@ -80,6 +834,11 @@ pub fn resolve_output_resolution(
     match (pixel_ratio, output_width, output_height) {
         (None, None, None) => NonSnafu.fail(),
         (None, Some(w), Some(h)) => Ok((w, h)),
+        (r, Some(_), None) | (r, None, Some(_))
+            if width == 0 || height == 0 || r.map_or(false, |r| *r.numer() == 0) =>
+        {
+            ZeroResolutionSnafu.fail()
+        }
         (Some(r), None, Some(h)) => {
             /*
             Rule of proportions... with a twist.
@@ -136,6 +895,138 @@ pub fn resolve_output_resolution(
 
 #[cfg(test)]
 mod tests {
+    use crate::color::{ColorDepth, ColorOptions, TrueColor24Bit};
+    use image::{DynamicImage, GenericImageView};
+
+    #[test]
+    fn test_convert_keeps_alpha() {
+        let mut image = image::RgbaImage::new(4, 4);
+        image.put_pixel(1, 2, image::Rgba([255, 0, 0, 128]));
+        let image = DynamicImage::ImageRgba8(image);
+
+        let out = super::convert(&image, &TrueColor24Bit::new(), ColorOptions::default());
+
+        assert_eq!(out.color(), image::ColorType::Rgba8);
+        assert_eq!(out.get_pixel(1, 2), image::Rgba([255, 0, 0, 128]));
+        assert_eq!(out.get_pixel(0, 0), image::Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_convert_protected_colors() {
+        let mut image = image::RgbImage::from_fn(8, 8, |x, _| {
+            let v = (x * 32) as u8;
+            image::Rgb([v, v, v])
+        });
+        image.put_pixel(3, 3, image::Rgb([200, 10, 10]));
+        let options = ColorOptions {
+            num_colors: Some(2),
+            protected_colors: vec![[200, 10, 10]],
+            ..Default::default()
+        };
+        let (_, palette, _) =
+            TrueColor24Bit::new().convert_image_with_loss(&image, options.clone());
+        assert!(palette.iter().any(|c| [c.r, c.g, c.b] == [200, 10, 10]));
+
+        let image = DynamicImage::ImageRgb8(image);
+        let out = super::convert(&image, &TrueColor24Bit::new(), options);
+
+        assert_eq!(out.get_pixel(3, 3), image::Rgba([200, 10, 10, 255]));
+    }
+
+    #[test]
+    fn test_matte_and_key() {
+        let mut image = image::RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        let image = DynamicImage::ImageRgba8(image);
+
+        let flat = super::matte(&image, [0, 255, 0]);
+        assert_eq!(flat.get_pixel(0, 0), &image::Rgb([255, 0, 0]));
+        assert_eq!(flat.get_pixel(1, 0), &image::Rgb([0, 255, 0]));
+
+        let keyed = super::key_color(&flat, [0, 255, 0]);
+        assert_eq!(keyed.get_pixel(0, 0), &image::Rgba([255, 0, 0, 255]));
+        assert_eq!(keyed.get_pixel(1, 0), &image::Rgba([0, 255, 0, 0]));
+    }
+
+    #[test]
+    fn test_fill_transparent() {
+        let mut image = image::RgbaImage::new(3, 1);
+        image.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, image::Rgba([0, 0, 255, 100]));
+        let image = DynamicImage::ImageRgba8(image);
+
+        let flat = super::fill_transparent(&image, [255, 0, 255]);
+        assert_eq!(flat.get_pixel(0, 0), &image::Rgb([255, 0, 0]));
+        assert_eq!(flat.get_pixel(1, 0), &image::Rgb([255, 0, 255]));
+        assert_eq!(flat.get_pixel(2, 0), &image::Rgb([255, 0, 255]));
+    }
+
+    #[test]
+    fn test_expand_single_scan() {
+        let image = image::RgbImage::from_pixel(2, 2, image::Rgb([255, 255, 255]));
+        let out = super::expand_single_scan(&image, 4, 8);
+
+        let column: Vec<_> = (0..8).map(|y| out.get_pixel(0, y).0[0]).collect();
+        assert_eq!(column, vec![255, 255, 64, 64, 255, 255, 64, 64]);
+
+        let empty = super::expand_single_scan(&image::RgbImage::new(0, 0), 4, 8);
+        assert_eq!(empty.dimensions(), (4, 8));
+    }
+
+    #[test]
+    fn test_letterbox() {
+        let image = image::RgbImage::from_pixel(2, 2, image::Rgb([255, 255, 255]));
+        let out = super::letterbox(&image, 4, 3, [0, 0, 255]);
+
+        let row: Vec<_> = (0..4).map(|x| out.get_pixel(x, 0).0[0]).collect();
+        assert_eq!(row, vec![0, 255, 255, 0]);
+        assert_eq!(out.get_pixel(0, 2), &image::Rgb([0, 0, 255]));
+        assert_eq!("pad:0000ff".parse(), Ok(super::Fit::Pad([0, 0, 255])));
+    }
+
+    #[test]
+    fn test_reduce_box() {
+        let image = image::RgbImage::from_fn(4, 2, |x, _| image::Rgb([(x * 60) as u8, 0, 255]));
+        let out = super::reduce_with(&image, 2, 1, super::ResizeFilter::Box);
+
+        assert_eq!(out.get_pixel(0, 0), &image::Rgb([30, 0, 255]));
+        assert_eq!(out.get_pixel(1, 0), &image::Rgb([150, 0, 255]));
+    }
+
+    #[test]
+    fn test_blend() {
+        let original = image::RgbImage::from_pixel(2, 1, image::Rgb([0, 100, 200]));
+        let converted = image::RgbImage::from_pixel(2, 1, image::Rgb([255, 0, 0]));
+        let mask = image::GrayImage::from_raw(2, 1, vec![0, 255]).unwrap();
+
+        let out = super::blend(&original, &converted, &mask);
+
+        assert_eq!(out.get_pixel(0, 0), &image::Rgb([0, 100, 200]));
+        assert_eq!(out.get_pixel(1, 0), &image::Rgb([255, 0, 0]));
+    }
+
+    #[test]
+    fn test_smart_crop() {
+        use super::{aspect_crop_rect, auto_crop_rect, Gravity};
+        use num_rational::Ratio;
+
+        let rect = aspect_crop_rect([0, 0, 400, 200], Ratio::new(1, 1), Gravity::Center);
+        assert_eq!(rect, [100, 0, 200, 200]);
+        let rect = aspect_crop_rect([10, 0, 400, 200], Ratio::new(1, 1), Gravity::Right);
+        assert_eq!(rect, [210, 0, 200, 200]);
+        let rect = aspect_crop_rect([0, 0, 400, 300], Ratio::new(2, 1), Gravity::Top);
+        assert_eq!(rect, [0, 0, 400, 200]);
+
+        // a letterboxed image with a gray picture in the middle
+        let image = image::RgbImage::from_fn(10, 8, |x, y| {
+            if (2..9).contains(&x) && (1..5).contains(&y) {
+                image::Rgb([128, 128, 128])
+            } else {
+                image::Rgb([4, 0, 0])
+            }
+        });
+        assert_eq!(auto_crop_rect(&image), [2, 1, 7, 4]);
+    }
 
     #[test]
     fn test_crop() {
@@ -1,32 +1,185 @@
-use clap::Parser;
-use lib::color::{ColorOptions, LossAlgorithm};
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use image::{DynamicImage, GrayImage, ImageFormat, RgbImage};
+use lib::adjust::Adjustments;
+use lib::animation::{read_gif, write_gif, Frame};
+use lib::color::cga::CGA_MODE4_SUBPALETTE_NAMES;
+use lib::color::mono::{Monochrome, Phosphor};
+use lib::color::palette::{find_palette, load_palette, parse_hex_color, save_palette};
+use lib::color::quantize::PaletteOptimizer;
+use lib::color::swatch::{render_swatches, SwatchOptions};
+use lib::color::{
+    BackgroundStrategy, ColorStandard, ColorStandardParseError, DitherMode, IndexedImage,
+    LossAlgorithm, PaletteBuilderOptions,
+};
+use lib::crt::{Crt, CrtEffect};
+use lib::export::bmp::write_bmp;
+use lib::export::pcx::write_pcx;
+use lib::export::RawFormat;
+use lib::modes::{find_mode, VideoMode, MODES};
+use lib::pipeline::{Conversion, ConversionStats, OutputOptions, Pipeline, Presets};
+use lib::preprocess::{Denoise, GamutShift, Outline};
+use lib::preview::PreviewMode;
+use lib::progress::{ProgressHook, Stage};
+use lib::textmode::TextOptions;
+use lib::tonemap::ToneMap;
+use lib::{Fit, Gravity, GravityParseError, ResizeFilter, ScanMode};
 use num_integer::Integer;
 use num_rational::Ratio;
-use std::path::PathBuf;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::{BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 use retroimg as lib;
 
+#[cfg(feature = "tui")]
+mod tui;
+
 /// Convert images to look like in retro IBM hardware
 #[derive(Debug, Parser)]
-#[command(version)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct App {
-    /// Image file
-    #[clap(name = "FILE")]
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Image file (`-` for stdin),
+    /// or a directory or glob pattern of image files (with `--out-dir`)
+    #[clap(name = "FILE", required_unless_present = "raw_frames")]
+    input: Option<PathBuf>,
 
-    /// Output image file path
+    /// Input image format (by file extension, e.g. `png`),
+    /// detected from the contents by default
+    #[clap(long = "input-format", value_parser = parse_image_format)]
+    input_format: Option<ImageFormat>,
+
+    /// Read raw RGB24 frames of this size from stdin
+    /// and write the converted raw frames to stdout,
+    /// for use in video pipelines
+    #[clap(
+        long = "raw-frames",
+        value_parser(parse_resolution::<u32>),
+        conflicts_with_all = ["FILE", "mask", "contact_sheet"]
+    )]
+    raw_frames: Option<(u32, u32)>,
+
+    /// Output image file path (`-` for stdout)
     #[clap(short = 'o', long = "out", default_value = "out.png")]
     output: PathBuf,
 
-    /// Color standard
-    #[clap(short = 's', long = "standard", default_value = "vga")]
-    standard: ColorStandard,
+    /// Convert all input files into this directory,
+    /// keeping their file names
+    #[clap(
+        short = 'O',
+        long = "out-dir",
+        conflicts_with_all = ["raw_frames", "mask", "contact_sheet", "palette_out"]
+    )]
+    out_dir: Option<PathBuf>,
+
+    /// Output file format: the file extension when converting into a directory
+    /// or writing to stdout (png by default),
+    /// or a raw video memory layout (cga-raw, ega-planar or vga13h)
+    /// at the internal resolution
+    #[clap(long = "format", visible_alias = "output-format")]
+    format: Option<String>,
+
+    /// Bits per pixel of BMP output (1, 4 or 8),
+    /// the fewest which fit the palette by default
+    #[clap(long = "bmp-bits", value_parser = parse_bmp_bits)]
+    bmp_bits: Option<u8>,
+
+    /// Video mode preset, setting the color standard,
+    /// internal resolution and number of colors together
+    /// (cga320, cga640, tandy, colorplus, colorplus640, atarist, ega320, ega640,
+    /// vga13h, modex, vga640x480 or hercules)
+    #[clap(short = 'm', long = "mode", value_parser = parse_mode)]
+    mode: Option<&'static VideoMode>,
+
+    /// Color standard, or `file:<name>` for a palette file
+    /// in the palette search path [default: vga]
+    #[clap(short = 's', long = "standard")]
+    standard: Option<StandardArg>,
+
+    /// Custom palette file (GPL, JASC-PAL, ACT, hex list or Lospec JSON),
+    /// or `lospec:<slug>` to fetch it from Lospec (overrides `standard`)
+    #[clap(short = 'p', long = "palette")]
+    palette: Option<String>,
+
+    /// Phosphor color of the `mono` standard
+    /// (green, amber, white or paper-white)
+    #[clap(long = "phosphor", default_value = "green")]
+    phosphor: Phosphor,
+
+    /// Number of intensity levels of the `mono` standard, including black
+    #[clap(
+        long = "levels",
+        default_value = "2",
+        value_parser(clap::value_parser!(u32).range(2..=256))
+    )]
+    levels: u32,
+
+    /// Custom shades of the `gameboy` standard (RRGGBB, comma separated),
+    /// from darkest to lightest
+    #[clap(long = "shades", value_delimiter = ',', value_parser(parse_color))]
+    shades: Option<Vec<[u8; 3]>>,
+
+    /// How to map 16-bit and HDR input images to 8 bits per sample
+    /// (clip, normalize, reinhard or aces)
+    #[clap(long = "tone-map", default_value = "clip")]
+    tone_map: ToneMap,
+
+    /// Exposure adjustment of 16-bit and HDR input images, in stops
+    #[clap(long = "exposure", default_value = "0", allow_negative_numbers = true)]
+    exposure: f32,
+
+    /// Background color to composite transparent images onto (RRGGBB),
+    /// instead of dropping the alpha channel
+    #[clap(long = "matte", value_parser(parse_color))]
+    matte: Option<[u8; 3]>,
+
+    /// Turn the matte color back into transparency in the output image
+    #[clap(long = "key-matte", requires = "matte")]
+    key_matte: bool,
+
+    /// Treat this color (RRGGBB, magenta if omitted) as transparency:
+    /// transparent pixels of the input get it,
+    /// it is left out of the palette,
+    /// and it becomes transparent again in the output image
+    #[clap(
+        long = "transparent-color",
+        value_name = "RRGGBB",
+        num_args = 0..=1,
+        default_missing_value = "ff00ff",
+        value_parser(parse_color),
+        conflicts_with = "matte"
+    )]
+    transparent_color: Option<[u8; 3]>,
 
     /// Crop the input image to the rectangle (left, top, width, height)
     #[clap(short = 'C', long = "crop", value_parser(parse_rect::<u16>))]
     crop: Option<(u16, u16, u16, u16)>,
 
+    /// Trim the image to this aspect ratio (format `w:h[:gravity]`),
+    /// keeping the part given by the gravity
+    /// (center, top, bottom, left, right or a corner such as top-left)
+    #[clap(long = "crop-aspect", value_parser = parse_crop_aspect)]
+    crop_aspect: Option<([u32; 2], Gravity)>,
+
+    /// Trim uniform borders off the image, such as letterbox bars
+    #[clap(long = "auto-crop")]
+    auto_crop: bool,
+
     /// Resolution to resize the image into before color reduction
     #[clap(
         name = "internal_resolution",
@@ -36,22 +189,352 @@ pub struct App {
     )]
     resolution: Option<(u16, u16)>,
 
+    /// Resampling filter for reducing the image to the internal resolution
+    /// (nearest, box, triangle, catmullrom or lanczos3)
+    #[clap(long = "filter", default_value = "catmullrom")]
+    filter: ResizeFilter,
+
+    /// Blur the source image by this amount (standard deviation in pixels)
+    /// before reducing it
+    #[clap(long = "pre-blur", default_value = "0")]
+    pre_blur: f32,
+
+    /// Trim the image to the proportions of the output image
+    /// before resizing it to the internal resolution,
+    /// so that non-square pixels do not distort it
+    #[clap(long = "correct-aspect")]
+    correct_aspect: bool,
+
     #[clap(flatten)]
     out_size: OutSizeOpts,
 
+    /// Edge-preserving noise reduction filter to apply before color reduction
+    /// (median or bilateral)
+    #[clap(long = "denoise")]
+    denoise: Option<Denoise>,
+
+    /// Stretch the contrast of the image before color reduction
+    #[clap(long = "auto-contrast")]
+    auto_contrast: bool,
+
+    /// Equalize the histogram of the image before color reduction
+    #[clap(long = "equalize")]
+    equalize: bool,
+
+    /// Input intensity to map to black before color reduction
+    #[clap(long = "black-level", default_value = "0")]
+    black_level: u8,
+
+    /// Input intensity to map to white before color reduction
+    #[clap(long = "white-level", default_value = "255")]
+    white_level: u8,
+
+    /// Brightness offset to apply before color reduction,
+    /// from -1 (all black) to 1 (all white)
+    #[clap(long = "brightness", default_value = "0", allow_hyphen_values = true)]
+    brightness: f32,
+
+    /// Contrast multiplier to apply before color reduction
+    #[clap(long = "contrast", default_value = "1")]
+    contrast: f32,
+
+    /// Saturation multiplier to apply before color reduction
+    /// (0 for grayscale),
+    /// or of the gamut shift if enabled
+    #[clap(long = "saturation", alias = "saturation-boost")]
+    saturation: Option<f32>,
+
+    /// Gamma to apply before color reduction
+    /// (above 1 brightens the mid tones)
+    #[clap(long = "gamma", default_value = "1")]
+    gamma: f32,
+
+    /// Reduce each color channel to this number of levels
+    /// before color reduction, for a flat shaded look
+    #[clap(long = "posterize", value_parser(clap::value_parser!(u16).range(2..=256)))]
+    posterize: Option<u16>,
+
+    /// Darken the edges of the image before color reduction,
+    /// for a hand-drawn look
+    #[clap(long = "outline")]
+    outline: bool,
+
+    /// Minimum edge strength to outline (gradient magnitude, up to 1443)
+    #[clap(
+        long = "outline-threshold",
+        default_value = "128",
+        requires = "outline"
+    )]
+    outline_threshold: u32,
+
+    /// Thickness of the outlines, in pixels
+    #[clap(long = "outline-thickness", default_value = "1", requires = "outline")]
+    outline_thickness: u32,
+
+    /// Shift colors towards the gamut of the color standard
+    /// before color reduction, with defaults tuned per standard
+    #[clap(long = "gamut-shift")]
+    gamut_shift: bool,
+
+    /// How much hues are pulled towards the nearest palette hue,
+    /// between 0 and 1 (implies `--gamut-shift`)
+    #[clap(long = "hue-pull")]
+    hue_pull: Option<f32>,
+
+    /// Spend more palette entries on the salient regions of the image
+    /// (such as faces and subjects) than on the background
+    #[clap(long = "saliency")]
+    saliency: bool,
+
+    /// Grayscale image marking the important regions of the image
+    /// for the palette optimization (instead of `--saliency`)
+    #[clap(long = "saliency-map", conflicts_with = "saliency")]
+    saliency_map: Option<PathBuf>,
+
     /// Do not limit number of simultaneous colors (invalidates num_colors)
     #[clap(long = "no-color-limit", conflicts_with = "num_colors")]
     no_color_limit: bool,
 
     /// Maximum number of simultaneous colors (emulates palette indexing)
-    #[clap(short = 'c', long = "num-colors", default_value = "256")]
-    num_colors: u16,
+    /// [default: 256]
+    #[clap(short = 'c', long = "num-colors")]
+    num_colors: Option<u16>,
 
-    /// Color distance algorithm for loss calculation (L1 or L2)
+    /// Color distance algorithm for loss calculation
+    /// and nearest color selection (L1, L2, DE76 or DE2000)
     #[clap(short = 'l', long = "loss", default_value = "L2")]
     loss: LossAlgorithm,
 
+    /// Dithering mode (floyd-steinberg, atkinson, jarvis-judice-ninke,
+    /// sierra-lite, random, bayer2, bayer4, bayer8 or none)
+    #[clap(short = 'd', long = "dither", default_value = "floyd-steinberg")]
+    dither: DitherMode,
+
+    /// Seed for random dithering and palette optimization,
+    /// for results which are reproducible from one run to the next
+    #[clap(long = "seed")]
+    seed: Option<u64>,
+
+    /// Quantize and dither in linear light,
+    /// so that dithered gradients keep their brightness
+    #[clap(long = "linear")]
+    linear: bool,
+
+    /// Convert the colors this many rows at a time,
+    /// over a palette built from a sample of the image,
+    /// to save memory on huge images
+    #[clap(long = "strips", value_name = "ROWS")]
+    strips: Option<u32>,
+
+    /// Palette optimizer (kmeans, weighted-kmeans or none),
+    /// none being the fastest at the expense of quality
+    #[clap(long = "palette-optimizer", default_value = "kmeans")]
+    palette_optimizer: PaletteOptimizer,
+
+    /// Number of optimization iterations over the final palette
+    #[clap(long = "palette-iterations", default_value = "8")]
+    palette_iterations: usize,
+
+    /// How to choose the free background color in CGA mode 4:
+    /// auto (the median color), frequent (the most common color),
+    /// search (try them all) or a color (RRGGBB)
+    #[clap(long = "background", default_value = "auto")]
+    background: BackgroundStrategy,
+
+    /// Choose the colors once for all frames of an animated GIF
+    /// or raw frame stream, so that the palette does not flicker
+    #[clap(long = "global-palette")]
+    global_palette: bool,
+
+    /// Color which must survive the conversion (RRGGBB),
+    /// can be given multiple times
+    #[clap(long = "protect", value_parser(parse_color))]
+    protect: Vec<[u8; 3]>,
+
+    /// Grayscale mask image selecting the regions to convert
+    /// (white is converted, black keeps the original image)
+    #[clap(long = "mask")]
+    mask: Option<PathBuf>,
+
+    /// Convert with each of the given color standards (comma separated,
+    /// all of them if none is given) and compose the results
+    /// into a single labeled grid image
+    #[clap(
+        long = "contact-sheet",
+        visible_alias = "compare",
+        num_args = 0..=1,
+        value_delimiter = ',',
+        conflicts_with = "mask"
+    )]
+    contact_sheet: Option<Vec<ColorStandard>>,
+
+    /// Write one file per compared standard instead of a grid,
+    /// named after the output file with the standard as a suffix
+    #[clap(
+        long = "compare-files",
+        requires = "contact_sheet",
+        conflicts_with = "preview"
+    )]
+    compare_files: bool,
+
+    /// Dither over time: write two frames which resemble the image
+    /// when shown alternately, as an animated GIF file
+    /// or as two files numbered after the output file (out.0.png and out.1.png)
+    #[clap(
+        long = "temporal",
+        conflicts_with_all = ["mask", "contact_sheet", "raw_frames", "out_dir", "preview", "stats"]
+    )]
+    temporal: bool,
+
+    /// Save the final palette of the conversion to a file
+    /// (.gpl, .pal, .act, .hex, .json or .aseprite)
+    #[clap(long = "save-palette", conflicts_with_all = ["mask", "contact_sheet"])]
+    palette_out: Option<PathBuf>,
+
+    /// Convert into text mode art of this many columns and rows
+    /// (80x25 by default, implied by a .ans output file)
+    #[clap(
+        long = "text-mode",
+        num_args = 0..=1,
+        default_missing_value = "80x25",
+        value_parser(parse_resolution::<u32>),
+        conflicts_with_all = ["raw_frames", "mask", "contact_sheet"]
+    )]
+    text_mode: Option<(u32, u32)>,
+
+    /// Allow bright background colors in text mode (iCE colors)
+    #[clap(long = "ice-colors")]
+    ice_colors: bool,
+
+    /// Show the result in the terminal instead of saving it
+    /// (sixel, kitty or blocks, detected from the terminal by default)
+    #[clap(
+        long = "preview",
+        num_args = 0..=1,
+        default_missing_value = "auto",
+        conflicts_with_all = ["out_dir", "raw_frames", "format"]
+    )]
+    preview: Option<PreviewMode>,
+
+    /// Show a progress bar of each stage of the conversion on stderr
+    #[clap(long = "progress")]
+    progress: bool,
+
+    /// Print the loss, PSNR and SSIM of each stage of the conversion,
+    /// as text on stderr or as JSON on stdout
+    #[clap(
+        long = "stats",
+        num_args = 0..=1,
+        default_missing_value = "text",
+        value_parser = ["text", "json"],
+        conflicts_with_all = ["mask", "contact_sheet", "out_dir", "raw_frames", "text_mode"]
+    )]
+    stats: Option<String>,
+
+    /// Print a report of the conversion as JSON on stdout:
+    /// the input and output files, the resolutions, the color standard,
+    /// the palette, the quality metrics and the time taken by each stage
+    #[clap(
+        long = "json",
+        conflicts_with_all = [
+            "stats", "mask", "contact_sheet", "out_dir", "raw_frames",
+            "text_mode", "temporal", "preview"
+        ]
+    )]
+    json: bool,
+
+    /// Convert with the options of a named preset of the configuration file,
+    /// instead of the individual conversion flags
+    #[clap(long = "preset")]
+    preset: Option<String>,
+
+    /// Configuration file with the presets
+    /// (retroimg.toml in the current directory
+    /// or in the user's configuration directory by default)
+    #[clap(long = "config", requires = "preset")]
+    config: Option<PathBuf>,
+
+    /// Print some info to stderr
+    /// (the `RETROIMG_LOG` environment variable sets the log level instead)
+    #[clap(short = 'v', long = "verbose")]
+    verbose: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Process images with a pipeline description file (TOML or JSON)
+    Run(RunArgs),
+    /// Palette file utilities
+    #[command(subcommand)]
+    Palette(PaletteCommand),
+    /// Tune the conversion options interactively in the terminal
+    #[cfg(feature = "tui")]
+    Tui {
+        /// Image file
+        #[clap(name = "FILE")]
+        input: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum PaletteCommand {
+    /// Convert a palette to another file format (by extension)
+    Convert {
+        /// Palette file, `lospec:<slug>`,
+        /// or a color standard with a fixed palette (e.g. `ega`)
+        #[clap(name = "INPUT")]
+        input: String,
+
+        /// Output palette file
+        /// (.gpl, .pal, .act, .hex, .json or .aseprite)
+        #[clap(name = "OUTPUT")]
+        output: PathBuf,
+    },
+    /// Render a swatch image of a palette
+    Render {
+        /// Palette file, `lospec:<slug>`,
+        /// or a color standard with a fixed palette (e.g. `ega`)
+        #[clap(name = "INPUT")]
+        input: String,
+
+        /// Output image file
+        #[clap(short = 'o', long = "out", default_value = "palette.png")]
+        output: PathBuf,
+
+        /// Width and height of each color cell, in pixels
+        #[clap(long = "cell-size", default_value = "64")]
+        cell_size: u32,
+
+        /// Maximum number of cells per row
+        #[clap(long = "columns", default_value = "16")]
+        columns: u32,
+
+        /// Do not label the cells with their hexadecimal color values
+        #[clap(long = "no-labels")]
+        no_labels: bool,
+    },
+}
+
+#[derive(Debug, Args)]
+struct RunArgs {
+    /// Pipeline description file
+    #[clap(name = "PIPELINE")]
+    pipeline: PathBuf,
+
+    /// Image files
+    #[clap(name = "INPUTS", required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Directory to write the output images into
+    #[clap(short = 'O', long = "out-dir", default_value = ".")]
+    out_dir: PathBuf,
+
+    /// Show a progress bar of each stage of the conversion on stderr
+    #[clap(long = "progress")]
+    progress: bool,
+
     /// Print some info to stderr
+    /// (the `RETROIMG_LOG` environment variable sets the log level instead)
     #[clap(short = 'v', long = "verbose")]
     verbose: bool,
 }
@@ -68,10 +551,15 @@ struct OutSizeOpts {
     )]
     resolution: (u32, u32),
 
-    /// Pixel ratio (format `w:h`)
+    /// Pixel ratio (format `w:h`),
+    /// derived from the internal resolution of known video modes by default
     #[clap(short = 'r', long = "pixel-ratio", value_parser(parse_ratio::<u32>))]
     pixel_ratio: Option<Ratio<u32>>,
 
+    /// Assume square pixels when the pixel ratio is not specified
+    #[clap(long = "square-pixels", conflicts_with = "pixel_ratio")]
+    square_pixels: bool,
+
     /// Output image width (defined separately)
     #[clap(long = "width")]
     width: Option<u32>,
@@ -79,48 +567,234 @@ struct OutSizeOpts {
     /// Output image height (defined separately)
     #[clap(long = "height")]
     height: Option<u32>,
+
+    /// Multiply the internal resolution by this exact factor
+    /// instead of resizing to the output size
+    #[clap(
+        long = "scale",
+        value_parser(clap::value_parser!(u32).range(1..)),
+        conflicts_with_all = ["width", "height", "integer_scale"]
+    )]
+    scale: Option<u32>,
+
+    /// Scale by the largest integer factor which fits in the output size,
+    /// so that all pixels have the same size
+    /// (with the pixel width following the pixel ratio)
+    #[clap(long = "integer-scale")]
+    integer_scale: bool,
+
+    /// Center a scaled image on a black canvas of the output size
+    /// (short for --fit pad)
+    #[clap(long = "pad", conflicts_with = "fit")]
+    pad: bool,
+
+    /// Stretch the image to the output size (stretch),
+    /// or fit it inside keeping its proportions
+    /// with borders of the given color (pad or pad:RRGGBB)
+    #[clap(long = "fit", default_value = "stretch")]
+    fit: Fit,
+
+    /// Scan mode: each line as one thick band (double)
+    /// or followed by a dark gap (single)
+    #[clap(long = "scan", default_value = "double")]
+    scan: ScanMode,
+
+    /// Repeat the output image to fill a canvas of this size
+    #[clap(long = "tile", value_parser(parse_resolution::<u32>))]
+    tile: Option<(u32, u32)>,
+
+    /// Mirror every other tile so that their edges match
+    #[clap(long = "tile-mirror", requires = "tile")]
+    tile_mirror: bool,
+
+    /// CRT monitor effects, comma separated
+    /// (scanlines, mask, shadow-mask, bloom, curvature)
+    #[clap(long = "crt", value_delimiter = ',')]
+    crt: Vec<CrtEffect>,
+
+    /// Darkening of the gaps between scanlines, from 0 to 1
+    #[clap(long = "crt-scanlines", requires = "crt")]
+    crt_scanlines: Option<f32>,
+
+    /// Darkening by the phosphor mask, from 0 to 1
+    #[clap(long = "crt-mask", requires = "crt")]
+    crt_mask: Option<f32>,
+
+    /// Strength of the glow around bright areas, from 0 to 1
+    #[clap(long = "crt-bloom", requires = "crt")]
+    crt_bloom: Option<f32>,
+
+    /// Strength of the screen curvature
+    #[clap(long = "crt-curvature", requires = "crt")]
+    crt_curvature: Option<f32>,
+}
+
+/// A color standard,
+/// or the name of a palette file in the palette search path.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum StandardArg {
+    Standard(ColorStandard),
+    File(String),
 }
 
-/// Options for the kind of color palette to be simulated.
-/// This doesn't affect the image's resolution.
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub enum ColorStandard {
-    /// True color 24-bit RGB (8 bits per channel)
-    True24Bit,
-    /// 18-bit RGB (6 bits per channel)
-    Vga18Bit,
-    /// 16-bit RGB, also called High color (5-6-5 bits per R-G-B channel)
-    Vga16Bit,
-    /// Mode 4 of CGA: 3 colors from hardcoded sub-palettes + 1 back color
-    CgaMode4,
-    /// Mode 4 of CGA, high intensity of sub-palette 1:
-    /// white, cyan, magenta, and one arbitrary back color
-    CgaMode4High1,
-    /// Monochrome, black and white
-    BlackWhite,
-    /// All 16 colors from the CGA palette
-    FullCga,
-    /// All 64 colors from the EGA palette
-    FullEga,
-}
-
-impl FromStr for ColorStandard {
-    type Err = &'static str;
+impl FromStr for StandardArg {
+    type Err = ColorStandardParseError;
+
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "true" | "24bit" => Ok(ColorStandard::True24Bit),
-            "vga" | "18bit" => Ok(ColorStandard::Vga18Bit),
-            "high" | "16bit" => Ok(ColorStandard::Vga16Bit),
-            "cga" | "cgamode4" => Ok(ColorStandard::CgaMode4),
-            "cgamode4high1" => Ok(ColorStandard::CgaMode4High1),
-            "fullcga" => Ok(ColorStandard::FullCga),
-            "ega" => Ok(ColorStandard::FullEga),
-            "bw" => Ok(ColorStandard::BlackWhite),
-            _ => Err("no such color standard"),
+        match s.strip_prefix("file:") {
+            Some(name) => Ok(StandardArg::File(name.to_string())),
+            None => s.parse().map(StandardArg::Standard),
         }
     }
 }
 
+/// The directories where palette files are looked up, in order:
+///
+/// - the directories in the `RETROIMG_PALETTE_PATH` environment variable;
+/// - `retroimg/palettes` in the user's configuration directory
+///   (e.g. `~/.config/retroimg/palettes`).
+fn palette_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(paths) = std::env::var_os("RETROIMG_PALETTE_PATH") {
+        dirs.extend(std::env::split_paths(&paths));
+    }
+    if let Some(config_dir) = config_dir() {
+        dirs.push(config_dir.join("palettes"));
+    }
+    dirs
+}
+
+/// The user's configuration directory of the application.
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("retroimg"))
+}
+
+/// The file name of the configuration file with the presets
+const CONFIG_FILE: &str = "retroimg.toml";
+
+/// Flags which can be combined with a preset,
+/// as they are not conversion options
+const PRESET_COMPATIBLE_FLAGS: &[&str] = &[
+    "FILE",
+    "input_format",
+    "output",
+    "out_dir",
+    "raw_frames",
+    "mask",
+    "contact_sheet",
+    "compare_files",
+    "temporal",
+    "palette_out",
+    "preview",
+    "progress",
+    "stats",
+    "json",
+    "preset",
+    "config",
+    "verbose",
+];
+
+/// Load the pipeline of a named preset
+/// from the given configuration file,
+/// or from `retroimg.toml` in the current directory
+/// or in the user's configuration directory.
+fn load_preset(config: Option<&Path>, name: &str) -> Result<Pipeline, Box<dyn std::error::Error>> {
+    let path = match config {
+        Some(path) => path.to_owned(),
+        None => std::iter::once(PathBuf::from(CONFIG_FILE))
+            .chain(config_dir().map(|dir| dir.join(CONFIG_FILE)))
+            .find(|path| path.is_file())
+            .ok_or_else(|| format!("no configuration file found ({})", CONFIG_FILE))?,
+    };
+    let presets: Presets = toml::from_str(&std::fs::read_to_string(&path)?)
+        .map_err(|e| format!("invalid configuration file {}: {}", path.display(), e))?;
+    presets.get(name).cloned().ok_or_else(|| {
+        let names = presets
+            .presets
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "no preset {:?} in {} (found: {})",
+            name,
+            path.display(),
+            names
+        )
+        .into()
+    })
+}
+
+/// Resolve the standard argument into a color standard
+/// and an optional custom palette.
+fn resolve_standard(
+    standard: StandardArg,
+) -> Result<(ColorStandard, Option<Vec<[u8; 3]>>), Box<dyn std::error::Error>> {
+    match standard {
+        StandardArg::Standard(standard) => Ok((standard, None)),
+        StandardArg::File(name) => {
+            let path = find_palette(&name, palette_dirs()).ok_or_else(|| {
+                format!("palette \"{}\" not found in the palette search path", name)
+            })?;
+            let palette = load_palette(path)?;
+            Ok((ColorStandard::default(), Some(palette)))
+        }
+    }
+}
+
+/// Load the custom palette from the palette argument.
+fn load_palette_arg(palette: &str) -> Result<Vec<[u8; 3]>, Box<dyn std::error::Error>> {
+    match palette.strip_prefix("lospec:") {
+        #[cfg(feature = "network")]
+        Some(slug) => Ok(lib::color::palette::fetch_lospec_palette(slug)?),
+        #[cfg(not(feature = "network"))]
+        Some(_) => Err("fetching palettes from Lospec requires the `network` feature".into()),
+        None => Ok(load_palette(palette)?),
+    }
+}
+
+/// Load a palette from a file, `lospec:<slug>`,
+/// or the name of a color standard with a fixed palette.
+fn load_any_palette(palette: &str) -> Result<Vec<[u8; 3]>, Box<dyn std::error::Error>> {
+    if let Some(colors) = palette
+        .parse::<ColorStandard>()
+        .ok()
+        .and_then(ColorStandard::palette)
+    {
+        return Ok(colors.to_vec());
+    }
+    load_palette_arg(palette)
+}
+
+fn run_palette_command(command: PaletteCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        PaletteCommand::Convert { input, output } => {
+            let colors = load_any_palette(&input)?;
+            save_palette(output, &colors)?;
+        }
+        PaletteCommand::Render {
+            input,
+            output,
+            cell_size,
+            columns,
+            no_labels,
+        } => {
+            let colors = load_any_palette(&input)?;
+            let options = SwatchOptions {
+                cell_size,
+                columns,
+                labels: !no_labels,
+            };
+            render_swatches(&colors, &options).save(output)?;
+        }
+    }
+    Ok(())
+}
+
 fn parse_rect<T>(value: &str) -> Result<(T, T, T, T), <T as FromStr>::Err>
 where
     T: FromStr,
@@ -170,81 +844,1237 @@ where
     Ok(Ratio::new(parts[0].parse()?, parts[1].parse()?))
 }
 
+fn parse_crop_aspect(value: &str) -> Result<([u32; 2], Gravity), String> {
+    let mut parts = value.splitn(3, ':');
+    let mut next_number = || {
+        parts
+            .next()
+            .and_then(|p| p.trim().parse().ok())
+            .filter(|&n| n > 0)
+            .ok_or_else(|| format!("invalid aspect ratio {:?}, expected w:h[:gravity]", value))
+    };
+    let aspect = [next_number()?, next_number()?];
+    let gravity = match parts.next() {
+        Some(gravity) => gravity
+            .parse()
+            .map_err(|e: GravityParseError| e.to_string())?,
+        None => Gravity::default(),
+    };
+    Ok((aspect, gravity))
+}
+
+fn parse_mode(value: &str) -> Result<&'static VideoMode, String> {
+    find_mode(value).ok_or_else(|| {
+        let names: Vec<_> = MODES.iter().map(|mode| mode.name).collect();
+        format!(
+            "unknown video mode {:?}, expected one of {}",
+            value,
+            names.join(", ")
+        )
+    })
+}
+
+fn parse_image_format(value: &str) -> Result<ImageFormat, String> {
+    ImageFormat::from_extension(value).ok_or_else(|| format!("unknown image format {:?}", value))
+}
+
+fn parse_bmp_bits(value: &str) -> Result<u8, String> {
+    match value.parse() {
+        Ok(bits @ 1) | Ok(bits @ 4) | Ok(bits @ 8) => Ok(bits),
+        _ => Err(format!(
+            "invalid BMP bit depth {:?}, expected 1, 4 or 8",
+            value
+        )),
+    }
+}
+
+fn parse_color(value: &str) -> Result<[u8; 3], String> {
+    parse_hex_color(value).ok_or_else(|| format!("invalid color {:?}, expected RRGGBB", value))
+}
+
+/// A progress hook drawing a bar of the current stage on stderr.
+/// The line of the bar is finished when the hook is dropped.
+///
+/// With a label (such as the name of a file converted in parallel with others),
+/// a line starting with the label is printed as each stage is done instead,
+/// so that concurrent conversions do not draw over each other.
+fn progress_bar(label: Option<String>) -> ProgressHook {
+    const WIDTH: usize = 30;
+    let line = BarLine::default();
+    let last_done = Mutex::new(None);
+    ProgressHook::new(move |stage, done| {
+        if let Some(label) = &label {
+            let mut last_done = last_done.lock().unwrap();
+            if done >= 1. && *last_done != Some(stage) {
+                *last_done = Some(stage);
+                eprintln!("{}: {} done", label, stage);
+            }
+            return;
+        }
+        let filled = (done * WIDTH as f32).round() as usize;
+        eprint!(
+            "\r{:>7} [{}{}] {:3.0}%",
+            stage,
+            "#".repeat(filled),
+            "-".repeat(WIDTH - filled),
+            done * 100.
+        );
+        let finished = stage == Stage::Expand && done >= 1.;
+        if finished {
+            eprintln!();
+        }
+        line.0.store(!finished, Ordering::Relaxed);
+    })
+}
+
+/// Whether the line of a progress bar is unfinished,
+/// finishing it on drop.
+#[derive(Debug, Default)]
+struct BarLine(AtomicBool);
+
+impl Drop for BarLine {
+    fn drop(&mut self) {
+        if *self.0.get_mut() {
+            eprintln!();
+        }
+    }
+}
+
+/// A logger printing messages to stderr,
+/// with a prefix for errors and warnings.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            log::Level::Error => eprintln!("Error: {}", record.args()),
+            log::Level::Warn => eprintln!("Warning: {}", record.args()),
+            _ => eprintln!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Set up the logger,
+/// at the level in the `RETROIMG_LOG` environment variable
+/// (such as `debug` or `off`) if there is one,
+/// or else at the info level if verbose and the warning level if not.
+fn init_logger(verbose: bool) {
+    static LOGGER: StderrLogger = StderrLogger;
+    let level = std::env::var("RETROIMG_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(if verbose {
+            log::LevelFilter::Info
+        } else {
+            log::LevelFilter::Warn
+        });
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Load a pipeline description file,
+/// in JSON if the file has the `.json` extension,
+/// or in TOML otherwise.
+fn load_pipeline(path: &Path) -> Result<Pipeline, Box<dyn std::error::Error>> {
+    let data = std::fs::read_to_string(path)?;
+    if path.extension().map_or(false, |ext| ext == "json") {
+        Ok(serde_json::from_str(&data)?)
+    } else {
+        Ok(toml::from_str(&data)?)
+    }
+}
+
+/// Run the pipeline on a single image,
+/// also retrieving the final palette (empty if a mask is used),
+/// the CGA mode 4 sub-palette picked
+/// and the quality metrics of the conversion if asked for.
+fn process(
+    pipeline: &Pipeline,
+    img: &RgbImage,
+    mask: Option<&GrayImage>,
+    with_stats: bool,
+) -> Result<Conversion, Box<dyn std::error::Error>> {
+    if log::log_enabled!(log::Level::Info) {
+        let [in_width, in_height] = internal_resolution(pipeline, img);
+        log::info!("Emulated internal resolution: {} x {}", in_width, in_height);
+        if let Ok((out_width, out_height)) = pipeline.output_resolution(in_width, in_height) {
+            log::info!("External resolution: {} x {}", out_width, out_height);
+        }
+    }
+
+    let out = match mask {
+        Some(mask) => pipeline.run_masked(img, mask).map(|image| Conversion {
+            image,
+            indexed: None,
+            palette: Vec::new(),
+            loss: 0,
+            cga_subpalette: None,
+            stats: None,
+        }),
+        None => pipeline.run_detailed(img, with_stats),
+    };
+    let out = out.unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(-1);
+    });
+
+    if let Some(index) = out.cga_subpalette {
+        log::info!("CGA sub-palette: {}", CGA_MODE4_SUBPALETTE_NAMES[index]);
+    }
+    Ok(out)
+}
+
+/// The emulated internal resolution of the pipeline for an image,
+/// as `[width, height]`.
+fn internal_resolution(pipeline: &Pipeline, img: &RgbImage) -> [u32; 2] {
+    let (width, height) = match pipeline.image_source_rect(img) {
+        Ok([_, _, w, h]) => (w, h),
+        Err(_) => (img.width(), img.height()),
+    };
+    let (width, height) = pipeline.internal_resolution(width, height);
+    [width, height]
+}
+
+/// Print the quality metrics of a conversion,
+/// as a table on stderr or as JSON on stdout.
+fn print_stats(stats: &ConversionStats, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(stats)?);
+        return Ok(());
+    }
+    eprintln!("Loss ({}): {}", stats.loss_algorithm, stats.loss);
+    eprintln!("{:<12}{:>10}{:>8}", "Stage", "PSNR (dB)", "SSIM");
+    for (stage, metrics) in [
+        ("preprocess", stats.preprocess),
+        ("color", stats.color),
+        ("total", stats.total),
+    ] {
+        let psnr = match metrics.psnr {
+            Some(psnr) => format!("{:.2}", psnr),
+            None => "inf".to_string(),
+        };
+        eprintln!("{:<12}{:>10}{:>8.4}", stage, psnr, metrics.ssim);
+    }
+    Ok(())
+}
+
+/// A report of the conversion of an image,
+/// printed as JSON by `--json`.
+#[derive(Debug, Serialize)]
+struct Report {
+    input: String,
+    output: String,
+    standard: String,
+    custom_palette: bool,
+    /// The name of the CGA mode 4 sub-palette picked, if any
+    cga_subpalette: Option<&'static str>,
+    internal_resolution: [u32; 2],
+    output_resolution: [u32; 2],
+    /// The colors of the final palette, as `#rrggbb`
+    palette: Vec<String>,
+    stats: ConversionStats,
+    /// The time taken by each stage, in order
+    timings: Vec<StageTime>,
+}
+
+/// The time taken by a stage of the conversion.
+#[derive(Debug, Serialize)]
+struct StageTime {
+    stage: String,
+    seconds: f64,
+}
+
+impl StageTime {
+    fn new(stage: impl ToString, time: Duration) -> Self {
+        StageTime {
+            stage: stage.to_string(),
+            seconds: time.as_secs_f64(),
+        }
+    }
+}
+
+/// Measures the time taken by each stage of the pipeline,
+/// from the first to the last progress report of the stage.
+#[derive(Debug, Default, Clone)]
+struct StageTimer(Arc<Mutex<Vec<(Stage, Instant, Instant)>>>);
+
+impl StageTimer {
+    /// A progress hook timing the stages,
+    /// which passes the progress on to another hook, if any.
+    fn hook(&self, inner: Option<ProgressHook>) -> ProgressHook {
+        let times = Arc::clone(&self.0);
+        ProgressHook::new(move |stage, done| {
+            let now = Instant::now();
+            {
+                let mut times = times.lock().unwrap();
+                match times.iter_mut().find(|(s, ..)| *s == stage) {
+                    Some((_, _, last)) => *last = now,
+                    None => times.push((stage, now, now)),
+                }
+            }
+            if let Some(inner) = &inner {
+                inner.report(stage, done);
+            }
+        })
+    }
+
+    /// The time taken by each stage reported so far,
+    /// in the order in which they started.
+    fn timings(&self) -> Vec<StageTime> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|&(stage, first, last)| StageTime::new(stage, last - first))
+            .collect()
+    }
+}
+
+/// Save the output image,
+/// as a palette-indexed PNG or GIF file when it has up to 256 colors.
+///
+/// The palette indices are taken from `indexed` if given,
+/// as produced by the pipeline,
+/// or else worked out from the colors of the image.
+///
+/// The format is chosen by the file extension,
+/// or by the output format option when writing to stdout.
+fn save_output(
+    pipeline: &Pipeline,
+    img: RgbImage,
+    indexed: Option<IndexedImage>,
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let extension = if is_stdio(output) {
+        Some(
+            pipeline
+                .output
+                .format
+                .as_deref()
+                .unwrap_or("png")
+                .to_lowercase(),
+        )
+    } else {
+        output
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+    };
+    let mut data = Cursor::new(Vec::new());
+    let key = pipeline.output_key();
+    // only the indices worked out here need the transparent color
+    let (indexed, keyed) = match indexed {
+        Some(indexed) => (Some(indexed), true),
+        None => (IndexedImage::from_image(&img), false),
+    };
+    if extension.as_deref() == Some("bmp") && key.is_none() {
+        // true color unless the bit depth was asked for explicitly
+        match (indexed, pipeline.output.bmp_bits) {
+            (Some(indexed), bits) => {
+                write_bmp(&mut data, &indexed, bits)?;
+                write_output(output, data.get_ref())?;
+                return Ok(());
+            }
+            (None, Some(_)) => return Err("too many colors for an indexed BMP file".into()),
+            (None, None) => {}
+        }
+    }
+    if extension.as_deref() == Some("pcx") {
+        let indexed = indexed.ok_or("image has more than 256 colors, which PCX does not admit")?;
+        write_pcx(&mut data, &indexed)?;
+        write_output(output, data.get_ref())?;
+        return Ok(());
+    }
+    let indexed = match extension.as_deref() {
+        Some("png") | Some("gif") => indexed.map(|indexed| match key {
+            Some(color) if !keyed => indexed.with_transparent_color(color),
+            _ => indexed,
+        }),
+        _ => None,
+    };
+    match indexed {
+        Some(indexed) => {
+            if extension.as_deref() == Some("gif") {
+                indexed.write_gif(&mut data)?;
+            } else {
+                indexed.write_png(&mut data)?;
+            }
+        }
+        None => {
+            let format = extension
+                .as_deref()
+                .and_then(ImageFormat::from_extension)
+                .ok_or_else(|| {
+                    format!(
+                        "unsupported output image format: {}",
+                        extension.as_deref().unwrap_or("none")
+                    )
+                })?;
+            pipeline.key_output(img).write_to(&mut data, format)?;
+        }
+    }
+    write_output(output, data.get_ref())?;
+    Ok(())
+}
+
+/// The path of a file named after the given one
+/// with a suffix before its extension (`out.png` to `out-cga.png`).
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push("-");
+    file_name.push(suffix);
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+/// Whether the path stands for stdin or stdout (`-`).
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Read the contents of an input file, or of stdin if the path is `-`.
+fn read_input(input: &Path) -> std::io::Result<Vec<u8>> {
+    if is_stdio(input) {
+        let mut data = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut data)?;
+        Ok(data)
+    } else {
+        std::fs::read(input)
+    }
+}
+
+/// Decode an input image in the given format,
+/// or else by the extension of the path or by its contents.
+fn decode_input(
+    input: &Path,
+    data: &[u8],
+    format: Option<ImageFormat>,
+) -> image::ImageResult<DynamicImage> {
+    match format.or_else(|| ImageFormat::from_path(input).ok()) {
+        Some(format) => image::load_from_memory_with_format(data, format),
+        None => image::load_from_memory(data),
+    }
+}
+
+/// Write the contents of an output file, or to stdout if the path is `-`.
+fn write_output(output: &Path, data: &[u8]) -> std::io::Result<()> {
+    if is_stdio(output) {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(data)?;
+        stdout.flush()
+    } else {
+        std::fs::write(output, data)
+    }
+}
+
+/// Load the frames of an animated GIF file, with their delays,
+/// or `None` if the file is not an animation.
+fn load_animation(
+    pipeline: &Pipeline,
+    data: &[u8],
+) -> Result<Option<Vec<(RgbImage, u32)>>, Box<dyn std::error::Error>> {
+    if image::guess_format(data).ok() != Some(ImageFormat::Gif) {
+        return Ok(None);
+    }
+    let frames = read_gif(data)?;
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+    Ok(Some(
+        frames
+            .into_iter()
+            .map(|(image, delay)| {
+                let image = pipeline.flatten_input(&DynamicImage::ImageRgba8(image));
+                (image, delay)
+            })
+            .collect(),
+    ))
+}
+
+/// How long each frame of temporal dithering is shown, in milliseconds
+const TEMPORAL_FRAME_DELAY: u32 = 20;
+
+/// Run the pipeline with temporal dithering
+/// and save the two frames as an animated GIF file,
+/// or as two files numbered after the output file
+/// (`out.0.png` and `out.1.png`).
+///
+/// Returns the palette shared by the frames.
+fn export_temporal(
+    pipeline: &Pipeline,
+    img: &RgbImage,
+    output: &Path,
+) -> Result<Vec<[u8; 3]>, Box<dyn std::error::Error>> {
+    let (frames, palette) = pipeline.run_temporal(img)?;
+    if is_gif(pipeline, output) {
+        let frames: Vec<_> = IntoIterator::into_iter(frames)
+            .map(|image| Frame {
+                image,
+                indexed: None,
+                delay: TEMPORAL_FRAME_DELAY,
+            })
+            .collect();
+        let mut data = Vec::new();
+        write_gif(&mut data, &frames)?;
+        write_output(output, &data)?;
+    } else if is_stdio(output) {
+        return Err("temporal frames can only be written to stdout as a GIF file".into());
+    } else {
+        for (i, image) in IntoIterator::into_iter(frames).enumerate() {
+            let mut file_name = output.file_stem().unwrap_or_default().to_os_string();
+            file_name.push(format!(".{}", i));
+            if let Some(extension) = output.extension() {
+                file_name.push(".");
+                file_name.push(extension);
+            }
+            let output = output.with_file_name(file_name);
+            log::info!("Frame {}: {}", i, output.display());
+            save_output(pipeline, image, None, &output)?;
+        }
+    }
+    Ok(palette)
+}
+
+/// Run the pipeline on all frames of an animation
+/// and save them as an animated GIF file (or write it to stdout).
+fn process_animation(
+    pipeline: &Pipeline,
+    frames: Vec<(RgbImage, u32)>,
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    log::info!("Animation with {} frames", frames.len());
+    let (images, delays): (Vec<_>, Vec<_>) = frames.into_iter().unzip();
+    let images = pipeline.run_frames_indexed(&images)?;
+    let frames: Vec<_> = images
+        .into_iter()
+        .zip(delays)
+        .map(|((image, indexed), delay)| Frame {
+            image,
+            indexed,
+            delay,
+        })
+        .collect();
+    let mut data = Vec::new();
+    write_gif(&mut data, &frames)?;
+    write_output(output, &data)?;
+    Ok(())
+}
+
+/// Convert a stream of raw RGB24 frames of the given size from stdin,
+/// writing the converted raw RGB24 frames to stdout.
+///
+/// With a global palette,
+/// the colors chosen for the first frame are kept for the whole stream
+/// when the color standard allows it.
+/// Likewise, automatic cropping trims the borders of the first frame
+/// off all frames, so that the output frames keep the same size.
+fn process_raw_frames(
+    pipeline: &Pipeline,
+    (width, height): (u32, u32),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stdin = std::io::stdin();
+    let mut reader = stdin.lock();
+    let stdout = std::io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+
+    let mut buffer = vec![0; width as usize * height as usize * 3];
+    let mut pipeline = pipeline.clone();
+    let mut count = 0_u64;
+    loop {
+        match reader.read_exact(&mut buffer) {
+            Ok(()) => {}
+            // a partial frame at the end is dropped
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let frame = RgbImage::from_raw(width, height, std::mem::take(&mut buffer))
+            .ok_or("invalid frame size")?;
+
+        if count == 0 && pipeline.auto_crop {
+            pipeline = pipeline.with_frames_crop(std::slice::from_ref(&frame));
+        }
+        let out = if count == 0 && pipeline.global_palette {
+            let (out, palette) = pipeline.run_with_palette(&frame)?;
+            if let Some(locked) = pipeline.lock_palette(&palette) {
+                pipeline = locked;
+            }
+            out
+        } else {
+            pipeline.run(&frame)?
+        };
+        if count == 0 {
+            log::info!("Output frame size: {} x {}", out.width(), out.height());
+        }
+        writer.write_all(out.as_raw())?;
+
+        buffer = frame.into_raw();
+        count += 1;
+    }
+    writer.flush()?;
+
+    log::info!("Converted {} frames", count);
+    Ok(())
+}
+
+/// Whether the output is a GIF file,
+/// by the extension of the output path
+/// or by the output format when writing to stdout.
+fn is_gif(pipeline: &Pipeline, output: &Path) -> bool {
+    if is_stdio(output) {
+        return pipeline
+            .output
+            .format
+            .as_deref()
+            .map_or(false, |format| format.eq_ignore_ascii_case("gif"));
+    }
+    output
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("gif"))
+}
+
+fn is_ans(output: &Path) -> bool {
+    output
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("ans"))
+}
+
+/// Convert an image into text mode art
+/// and save it as an ANSI art file (by the `.ans` extension)
+/// or as a rendered image,
+/// or print it to the terminal.
+fn export_text(
+    pipeline: &Pipeline,
+    img: &RgbImage,
+    options: &TextOptions,
+    output: &Path,
+    preview: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let screen = pipeline.run_text(img, options)?;
+    if preview {
+        std::io::stdout().write_all(screen.to_terminal().as_bytes())?;
+    } else if is_ans(output) {
+        write_output(output, &screen.to_ans())?;
+    } else {
+        save_output(pipeline, screen.render(), None, output)?;
+    }
+    Ok(())
+}
+
+fn run_pipeline(args: RunArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let RunArgs {
+        pipeline,
+        inputs,
+        out_dir,
+        progress,
+        verbose,
+    } = args;
+
+    init_logger(verbose);
+    let pipeline = load_pipeline(&pipeline)?;
+    process_batch(&pipeline, &inputs, &out_dir, progress)
+}
+
+/// Expand an input path into the image files it refers to:
+/// all image files in a directory,
+/// all files matching a glob pattern,
+/// or just the file itself.
+fn expand_input(input: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let pattern = input
+        .to_str()
+        .filter(|s| !input.exists() && s.contains(|c| matches!(c, '*' | '?' | '[')));
+    let mut files = if input.is_dir() {
+        std::fs::read_dir(input)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .filter(|path| match path {
+                Ok(path) => ImageFormat::from_path(path).is_ok(),
+                Err(_) => true,
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    } else if let Some(pattern) = pattern {
+        glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?
+    } else {
+        return Ok(vec![input.to_path_buf()]);
+    };
+    files.retain(|path| path.is_file());
+    files.sort();
+    Ok(files)
+}
+
+/// Run the pipeline on many image files,
+/// in parallel if possible,
+/// and save the outputs into a directory under the same file names
+/// (with the extension of the output format).
+///
+/// A summary of the loss of each conversion is printed at the end.
+///
+/// With `progress`, each file gets a progress bar of its own,
+/// labeled with the file name when files are converted in parallel.
+fn process_batch(
+    pipeline: &Pipeline,
+    inputs: &[PathBuf],
+    out_dir: &Path,
+    progress: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let extension = match (raw_format(pipeline), pipeline.output.format.as_deref()) {
+        (Some(_), _) => "bin",
+        (None, format) => format.unwrap_or("png"),
+    };
+    let outputs = inputs
+        .iter()
+        .map(|input| {
+            let mut file_name = input
+                .file_stem()
+                .ok_or("invalid input file name")?
+                .to_os_string();
+            file_name.push(".");
+            file_name.push(extension);
+            let output = out_dir.join(file_name);
+            if is_same_file(input, &output) {
+                return Err(format!("refusing to overwrite input file {}", input.display()).into());
+            }
+            Ok(output)
+        })
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+    // files with the same name and different extensions
+    // would be converted into the same file
+    let mut seen = HashSet::new();
+    for (input, output) in inputs.iter().zip(&outputs) {
+        if !seen.insert(output) {
+            return Err(format!(
+                "more than one input file would be written to {} (including {})",
+                output.display(),
+                input.display()
+            )
+            .into());
+        }
+    }
+
+    let parallel = cfg!(feature = "rayon") && inputs.len() > 1;
+    let convert = |(input, output): (&PathBuf, &PathBuf)| {
+        log::info!("{} -> {}", input.display(), output.display());
+        let label = Some(input.display().to_string()).filter(|_| parallel);
+        let pipeline = Pipeline {
+            progress: progress.then(|| progress_bar(label)),
+            ..pipeline.clone()
+        };
+        process_file(&pipeline, input, output).map_err(|e| e.to_string())
+    };
+    #[cfg(feature = "rayon")]
+    let results: Vec<_> = inputs.par_iter().zip(&outputs).map(convert).collect();
+    #[cfg(not(feature = "rayon"))]
+    let results: Vec<_> = inputs.iter().zip(&outputs).map(convert).collect();
+
+    let mut failed = 0;
+    for (input, result) in inputs.iter().zip(results) {
+        match result {
+            Ok(Some(loss)) => println!("{}: loss {}", input.display(), loss),
+            Ok(None) => println!("{}: animation", input.display()),
+            Err(e) => {
+                log::error!("{}: {}", input.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("failed to convert {} of {} files", failed, inputs.len()).into());
+    }
+    Ok(())
+}
+
+/// Whether two paths lead to the same existing file.
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Run the pipeline on an image file and save the output,
+/// retrieving the loss of the conversion
+/// (`None` for animations).
+fn process_file(
+    pipeline: &Pipeline,
+    input: &Path,
+    output: &Path,
+) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    let data = read_input(input)?;
+    if is_gif(pipeline, output) {
+        if let Some(frames) = load_animation(pipeline, &data)? {
+            process_animation(pipeline, frames, output)?;
+            return Ok(None);
+        }
+    }
+    let img = pipeline.flatten_input(&decode_input(input, &data, None)?);
+    if let Some(format) = raw_format(pipeline) {
+        let (loss, _) = export_raw(pipeline, &img, format, output)?;
+        return Ok(Some(loss));
+    }
+    if let Some(options) = &pipeline.output.text_mode {
+        export_text(pipeline, &img, options, output, false)?;
+        return Ok(None);
+    }
+    let conversion = pipeline.run_detailed(&img, false)?;
+    save_output(pipeline, conversion.image, conversion.indexed, output)?;
+    Ok(Some(conversion.loss))
+}
+
+/// The raw video memory layout named by the output format, if any.
+fn raw_format(pipeline: &Pipeline) -> Option<RawFormat> {
+    pipeline.output.format.as_deref()?.parse().ok()
+}
+
+/// Run the pipeline on an image up to the color conversion
+/// and save the result in a raw video memory layout,
+/// retrieving the loss and the palette which the pixels index.
+fn export_raw(
+    pipeline: &Pipeline,
+    img: &RgbImage,
+    format: RawFormat,
+    output: &Path,
+) -> Result<(u64, Vec<[u8; 3]>), Box<dyn std::error::Error>> {
+    let (indexed, loss) = pipeline.run_unexpanded_indexed(img)?;
+    let indexed = indexed.ok_or("too many colors for a raw format")?;
+    write_output(output, &format.encode(&indexed)?)?;
+    Ok((loss, format.palette(&indexed)?))
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = App::command().get_matches();
+    let explicit_flags = matches
+        .ids()
+        .map(|id| id.as_str())
+        .filter(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        .collect::<Vec<_>>();
     let App {
+        command,
         input,
+        input_format,
+        raw_frames,
         output,
+        out_dir,
+        format,
+        bmp_bits,
+        tone_map,
+        exposure,
+        matte,
+        key_matte,
+        transparent_color,
         crop,
+        crop_aspect,
+        auto_crop,
         resolution,
+        correct_aspect,
+        filter,
+        pre_blur,
         out_size:
             OutSizeOpts {
                 resolution: (res_out_width, res_out_height),
                 pixel_ratio,
+                square_pixels,
                 width: out_width,
                 height: out_height,
+                scale,
+                integer_scale,
+                pad,
+                fit,
+                scan,
+                tile,
+                tile_mirror,
+                crt,
+                crt_scanlines,
+                crt_mask,
+                crt_bloom,
+                crt_curvature,
             },
+        mode,
         standard,
+        palette: custom_palette,
+        phosphor,
+        levels,
+        shades,
+        denoise,
+        auto_contrast,
+        equalize,
+        black_level,
+        white_level,
+        brightness,
+        contrast,
+        saturation,
+        gamma,
+        posterize,
+        outline,
+        outline_threshold,
+        outline_thickness,
+        gamut_shift,
+        hue_pull,
+        saliency,
+        saliency_map,
         no_color_limit,
         num_colors,
         loss,
+        dither,
+        seed,
+        linear,
+        strips,
+        palette_optimizer,
+        palette_iterations,
+        background,
+        global_palette,
+        protect,
+        mask,
+        contact_sheet,
+        compare_files,
+        temporal,
+        palette_out,
+        text_mode,
+        ice_colors,
+        preview,
+        progress,
+        stats,
+        json,
+        preset,
+        config,
         verbose,
-    } = App::parse();
-
-    let mut img = image::open(input)?.to_rgb8();
+    } = App::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
-    if let Some((left, top, width, height)) = crop {
-        img = lib::crop(
-            img,
-            u32::from(left),
-            u32::from(top),
-            u32::from(width),
-            u32::from(height),
-        );
+    match command {
+        Some(Command::Run(args)) => return run_pipeline(args),
+        Some(Command::Palette(command)) => return run_palette_command(command),
+        #[cfg(feature = "tui")]
+        Some(Command::Tui { input }) => {
+            if let Some(flags) = tui::run(&input)? {
+                println!("{}", flags);
+            }
+            return Ok(());
+        }
+        None => {}
     }
-
-    let (in_width, in_height) = match resolution {
-        Some((w, h)) => (w as u32, h as u32),
-        None => (img.width(), img.height()),
-    };
-
-    if verbose {
-        eprintln!("Emulated internal resolution: {} x {}", in_width, in_height);
+    init_logger(verbose);
+    // individual flags override the video mode preset
+    let standard = standard.unwrap_or_else(|| {
+        StandardArg::Standard(mode.map_or_else(ColorStandard::default, |m| m.standard))
+    });
+    let resolution = resolution
+        .map(|(w, h)| [u32::from(w), u32::from(h)])
+        .or_else(|| mode.map(|m| m.resolution));
+    let num_colors = num_colors
+        .map(u32::from)
+        .or_else(|| mode.map(|m| m.num_colors))
+        .unwrap_or(256);
+    let (standard, mut palette) = resolve_standard(standard)?;
+    if let Some(p) = custom_palette {
+        palette = Some(load_palette_arg(&p)?);
     }
 
-    let (out_width, out_height) = match (pixel_ratio, out_width, out_height) {
-        (None, None, None) => (res_out_width, res_out_height),
-        _ => {
-            lib::resolve_output_resolution(in_width, in_height, out_width, out_height, pixel_ratio)
-                .unwrap_or_else(|e| {
-                    eprintln!("Error: {}", e);
-                    std::process::exit(-1);
-                })
+    // ANSI art files imply text mode
+    let ans_output = is_ans(&output) || format.as_deref() == Some("ans");
+    let pipeline = Pipeline {
+        tone_map,
+        exposure,
+        matte,
+        key_matte,
+        transparent_color,
+        crop: crop.map(|(left, top, width, height)| {
+            [
+                u32::from(left),
+                u32::from(top),
+                u32::from(width),
+                u32::from(height),
+            ]
+        }),
+        crop_aspect: crop_aspect.map(|(aspect, _)| aspect),
+        crop_gravity: crop_aspect.map_or_else(Gravity::default, |(_, gravity)| gravity),
+        auto_crop,
+        resolution,
+        correct_aspect,
+        filter,
+        pre_blur,
+        standard,
+        palette,
+        locked_palette: None,
+        mono: Monochrome { phosphor, levels },
+        shades,
+        adjust: Adjustments {
+            brightness,
+            contrast,
+            // the gamut shift takes over the saturation multiplier
+            saturation: saturation
+                .filter(|_| !gamut_shift && hue_pull.is_none())
+                .unwrap_or(1.),
+            gamma,
+        },
+        denoise,
+        auto_contrast,
+        equalize,
+        black_level,
+        white_level,
+        posterize,
+        outline: Some(Outline {
+            threshold: outline_threshold,
+            thickness: outline_thickness,
+        })
+        .filter(|_| outline),
+        gamut_shift: Some(GamutShift {
+            saturation,
+            hue_pull,
+        })
+        .filter(|_| gamut_shift || hue_pull.is_some()),
+        saliency,
+        importance_map: match saliency_map {
+            Some(path) => Some(image::open(path)?.to_luma8()),
+            None => None,
+        },
+        num_colors,
+        no_color_limit,
+        loss,
+        dither,
+        seed,
+        linear,
+        strip_height: strips,
+        palette_builder: PaletteBuilderOptions {
+            optimizer: palette_optimizer,
+            iterations: palette_iterations,
+            ..Default::default()
+        },
+        background,
+        global_palette,
+        protected_colors: protect,
+        output: OutputOptions {
+            size: Some([res_out_width, res_out_height]),
+            pixel_ratio: pixel_ratio.map(|r| [*r.numer(), *r.denom()]),
+            square_pixels,
+            width: out_width,
+            height: out_height,
+            scale,
+            integer_scale,
+            fit: if pad { Fit::Pad([0, 0, 0]) } else { fit },
+            scan,
+            crt: if crt.is_empty() {
+                None
+            } else {
+                let mut options = Crt::default();
+                for effect in crt {
+                    options.enable(effect);
+                }
+                options.scanlines = crt_scanlines.unwrap_or(options.scanlines);
+                options.mask_intensity = crt_mask.unwrap_or(options.mask_intensity);
+                options.bloom = crt_bloom.unwrap_or(options.bloom);
+                options.curvature = crt_curvature.unwrap_or(options.curvature);
+                Some(options)
+            },
+            tile: tile.map(|(w, h)| [w, h]),
+            tile_mirror,
+            format,
+            bmp_bits,
+            text_mode: match (text_mode, ans_output) {
+                (Some((columns, rows)), _) => Some(TextOptions {
+                    columns,
+                    rows,
+                    ice_colors,
+                    ..Default::default()
+                }),
+                (None, true) => Some(TextOptions {
+                    ice_colors,
+                    ..Default::default()
+                }),
+                (None, false) => None,
+            },
+        },
+        progress: progress.then(|| progress_bar(None)),
+    };
+    let pipeline = match preset {
+        Some(name) => {
+            let conversion_flags = explicit_flags
+                .iter()
+                .copied()
+                .filter(|id| !PRESET_COMPATIBLE_FLAGS.contains(id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            if !conversion_flags.is_empty() {
+                return Err(format!(
+                    "conversion flags cannot be combined with a preset: {}",
+                    conversion_flags
+                )
+                .into());
+            }
+            Pipeline {
+                progress: pipeline.progress,
+                ..load_preset(config.as_deref(), &name)?
+            }
         }
+        None => pipeline,
+    };
+    let timer = StageTimer::default();
+    let pipeline = if json {
+        let hook = timer.hook(pipeline.progress.clone());
+        pipeline.with_progress(hook)
+    } else {
+        pipeline
     };
 
-    if verbose {
-        eprintln!("External resolution: {} x {}", out_width, out_height);
+    if let Some(size) = raw_frames {
+        return process_raw_frames(&pipeline, size);
+    }
+    let input = input.expect("input file is required");
+    let inputs = expand_input(&input)?;
+    match out_dir {
+        Some(out_dir) => return process_batch(&pipeline, &inputs, &out_dir, progress),
+        None if inputs != [input.clone()] => {
+            return Err("an output directory (--out-dir) is required for several files".into())
+        }
+        None => {}
     }
-    let img = lib::reduce(&img, in_width, in_height);
-
-    let num_colors = Some(num_colors as u32).filter(|_| !no_color_limit);
 
-    let depth: Box<dyn lib::ColorDepth> = match standard {
-        ColorStandard::True24Bit => Box::new(lib::color::TrueColor24Bit::default()),
-        ColorStandard::Vga18Bit => Box::new(lib::color::Vga18Bit::default()),
-        ColorStandard::Vga16Bit => Box::new(lib::color::Vga16Bit::default()),
-        ColorStandard::FullEga => Box::new(lib::color::ega::PALETTE_EGA_6BIT),
-        ColorStandard::FullCga => Box::new(lib::color::cga::PALETTE_CGA_4BIT),
-        ColorStandard::CgaMode4 => Box::new(lib::color::cga::PALETTE_CGA_MODE4),
-        ColorStandard::CgaMode4High1 => Box::new(lib::color::cga::PALETTE_CGA_MODE4_1_HIGH),
-        ColorStandard::BlackWhite => Box::new(lib::color::PALETTE_BW_1BIT),
+    let mask = match mask {
+        Some(path) => Some(image::open(path)?.to_luma8()),
+        None => None,
+    };
+    let text_mode = pipeline.output.text_mode;
+    if (json || stats.as_deref() == Some("json")) && is_stdio(&output) {
+        return Err("JSON output cannot be printed along with the image on stdout".into());
+    }
+    if json && (raw_format(&pipeline).is_some() || text_mode.is_some()) {
+        return Err("JSON reports cannot be combined with raw formats or text mode".into());
+    }
+    let decode_start = Instant::now();
+    let data = read_input(&input)?;
+    if is_gif(&pipeline, &output)
+        && mask.is_none()
+        && contact_sheet.is_none()
+        && preview.is_none()
+        && text_mode.is_none()
+        && !temporal
+    {
+        if let Some(frames) = load_animation(&pipeline, &data)? {
+            if json {
+                return Err("JSON reports are not available for animations".into());
+            }
+            return process_animation(&pipeline, frames, &output);
+        }
+    }
+    let img = pipeline.flatten_input(&decode_input(&input, &data, input_format)?);
+    let decode_time = decode_start.elapsed();
+    if temporal {
+        if raw_format(&pipeline).is_some() || text_mode.is_some() {
+            return Err(
+                "temporal dithering cannot be combined with raw formats or text mode".into(),
+            );
+        }
+        let colors = export_temporal(&pipeline, &img, &output)?;
+        if let Some(path) = palette_out {
+            save_palette(path, &colors)?;
+        }
+        return Ok(());
+    }
+    if let Some(format) = raw_format(&pipeline) {
+        if mask.is_some() || contact_sheet.is_some() {
+            return Err("raw formats cannot be combined with masks or contact sheets".into());
+        }
+        let (_, colors) = export_raw(&pipeline, &img, format, &output)?;
+        if let Some(path) = palette_out {
+            save_palette(path, &colors)?;
+        }
+        return Ok(());
+    }
+    if let Some(options) = &text_mode {
+        if mask.is_some() || contact_sheet.is_some() {
+            return Err("text mode cannot be combined with masks or contact sheets".into());
+        }
+        return export_text(&pipeline, &img, options, &output, preview.is_some());
+    }
+    let contact_sheet = contact_sheet.map(|standards| {
+        if standards.is_empty() {
+            ColorStandard::ALL.to_vec()
+        } else {
+            standards
+        }
+    });
+    let mut report = None;
+    let (img, indexed) = match contact_sheet {
+        Some(standards) if compare_files => {
+            let images = pipeline.compare(&img, &standards)?;
+            for (standard, img) in standards.iter().zip(images) {
+                let output = suffixed_path(&output, &standard.to_string());
+                log::info!("{}: {}", standard, output.display());
+                save_output(&pipeline, img, None, &output)?;
+            }
+            return Ok(());
+        }
+        Some(standards) => (pipeline.contact_sheet(&img, &standards)?, None),
+        None => {
+            let Conversion {
+                image: out,
+                indexed,
+                palette: colors,
+                cga_subpalette,
+                stats: conversion_stats,
+                ..
+            } = process(&pipeline, &img, mask.as_ref(), stats.is_some() || json)?;
+            if let (Some(format), Some(conversion_stats)) = (&stats, &conversion_stats) {
+                print_stats(conversion_stats, format == "json")?;
+            }
+            if json {
+                let mut timings = vec![StageTime::new("decode", decode_time)];
+                timings.extend(timer.timings());
+                report = Some(Report {
+                    input: input.display().to_string(),
+                    output: output.display().to_string(),
+                    standard: pipeline.standard.to_string(),
+                    custom_palette: pipeline.palette.is_some(),
+                    cga_subpalette: cga_subpalette.map(|index| CGA_MODE4_SUBPALETTE_NAMES[index]),
+                    internal_resolution: internal_resolution(&pipeline, &img),
+                    output_resolution: [out.width(), out.height()],
+                    palette: colors
+                        .iter()
+                        .map(|[r, g, b]| format!("#{:02x}{:02x}{:02x}", r, g, b))
+                        .collect(),
+                    stats: conversion_stats.ok_or("missing quality metrics")?,
+                    timings,
+                });
+            }
+            if let Some(path) = palette_out {
+                save_palette(path, &colors)?;
+            }
+            (out, indexed)
+        }
     };
 
-    let colorbuffer = depth.convert_image(&img, ColorOptions { num_colors, loss });
-    let img = lib::color::colors_to_image(img.width(), img.height(), colorbuffer);
-    let img = lib::expand(&img, out_width, out_height);
-
-    img.save(output)?;
+    match preview {
+        Some(mode) => {
+            let columns = std::env::var("COLUMNS")
+                .ok()
+                .and_then(|c| c.parse().ok())
+                .unwrap_or(80);
+            let rendered = match &indexed {
+                Some(indexed) => mode.render_indexed(indexed, columns),
+                None => mode.render(&img, columns),
+            };
+            std::io::stdout().write_all(rendered.as_bytes())?;
+        }
+        None => {
+            let save_start = Instant::now();
+            save_output(&pipeline, img, indexed, &output)?;
+            if let Some(mut report) = report {
+                report
+                    .timings
+                    .push(StageTime::new("save", save_start.elapsed()));
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+        }
+    }
 
     Ok(())
 }
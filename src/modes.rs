@@ -0,0 +1,158 @@
+//! Presets of historical video modes,
+//! each tying a color standard to the internal resolution
+//! and pixel ratio of the mode.
+//!
+//! ```
+//! use retroimg::modes::find_mode;
+//! use retroimg::Pipeline;
+//!
+//! let mode = find_mode("ega640").unwrap();
+//! let pipeline = Pipeline::default().with_mode(mode);
+//! assert_eq!(pipeline.resolution, Some([640, 350]));
+//! ```
+use crate::color::ColorStandard;
+
+/// A historical video mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VideoMode {
+    /// The name of the mode, as given to `--mode`
+    pub name: &'static str,
+    /// A short description of the mode
+    pub description: &'static str,
+    /// The color standard of the mode
+    pub standard: ColorStandard,
+    /// The resolution of the mode, as `[width, height]`
+    pub resolution: [u32; 2],
+    /// The pixel ratio of the mode on a 4:3 display, as `[width, height]`
+    pub pixel_ratio: [u32; 2],
+    /// The maximum number of simultaneous colors
+    pub num_colors: u32,
+}
+
+/// All known video mode presets.
+pub static MODES: &[VideoMode] = &[
+    VideoMode {
+        name: "cga320",
+        description: "CGA mode 4, 4 colors at 320x200",
+        standard: ColorStandard::CgaMode4,
+        resolution: [320, 200],
+        pixel_ratio: [5, 6],
+        num_colors: 4,
+    },
+    VideoMode {
+        name: "cga640",
+        description: "CGA mode 6, black and white at 640x200",
+        standard: ColorStandard::BlackWhite,
+        resolution: [640, 200],
+        pixel_ratio: [5, 12],
+        num_colors: 2,
+    },
+    VideoMode {
+        name: "tandy",
+        description: "Tandy 1000 and PCjr, 16 colors at 320x200",
+        standard: ColorStandard::Tandy,
+        resolution: [320, 200],
+        pixel_ratio: [5, 6],
+        num_colors: 16,
+    },
+    VideoMode {
+        name: "colorplus",
+        description: "Plantronics ColorPlus, 16 colors at 320x200",
+        standard: ColorStandard::ColorPlus,
+        resolution: [320, 200],
+        pixel_ratio: [5, 6],
+        num_colors: 16,
+    },
+    VideoMode {
+        name: "colorplus640",
+        description: "Plantronics ColorPlus, 4 colors at 640x200",
+        standard: ColorStandard::ColorPlusHigh,
+        resolution: [640, 200],
+        pixel_ratio: [5, 12],
+        num_colors: 4,
+    },
+    VideoMode {
+        name: "atarist",
+        description: "Atari ST low resolution, 16 of 512 colors at 320x200",
+        standard: ColorStandard::AtariSt,
+        resolution: [320, 200],
+        pixel_ratio: [5, 6],
+        num_colors: 16,
+    },
+    VideoMode {
+        name: "ega320",
+        description: "EGA mode 0Dh, 16 colors at 320x200",
+        standard: ColorStandard::Ega16,
+        resolution: [320, 200],
+        pixel_ratio: [5, 6],
+        num_colors: 16,
+    },
+    VideoMode {
+        name: "ega640",
+        description: "EGA mode 10h, 16 of 64 colors at 640x350",
+        standard: ColorStandard::Ega16,
+        resolution: [640, 350],
+        pixel_ratio: [35, 48],
+        num_colors: 16,
+    },
+    VideoMode {
+        name: "vga13h",
+        description: "VGA mode 13h, 256 of 262144 colors at 320x200",
+        standard: ColorStandard::Vga18Bit,
+        resolution: [320, 200],
+        pixel_ratio: [5, 6],
+        num_colors: 256,
+    },
+    VideoMode {
+        name: "modex",
+        description: "VGA mode X, 256 of 262144 colors at 320x240",
+        standard: ColorStandard::Vga18Bit,
+        resolution: [320, 240],
+        pixel_ratio: [1, 1],
+        num_colors: 256,
+    },
+    VideoMode {
+        name: "vga640x480",
+        description: "VGA mode 12h, 16 of 262144 colors at 640x480",
+        standard: ColorStandard::Vga18Bit,
+        resolution: [640, 480],
+        pixel_ratio: [1, 1],
+        num_colors: 16,
+    },
+    VideoMode {
+        name: "hercules",
+        description: "Hercules Graphics Card, black and white at 720x348",
+        standard: ColorStandard::Hercules,
+        resolution: [720, 348],
+        pixel_ratio: [29, 45],
+        num_colors: 2,
+    },
+];
+
+/// Look up a video mode preset by name (case insensitive).
+pub fn find_mode(name: &str) -> Option<&'static VideoMode> {
+    MODES
+        .iter()
+        .find(|mode| mode.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MODES;
+    use crate::mode_pixel_ratio;
+    use num_rational::Ratio;
+
+    #[test]
+    fn test_modes_pixel_ratio() {
+        for mode in MODES {
+            let [width, height] = mode.resolution;
+            let [w, h] = mode.pixel_ratio;
+            assert_eq!(
+                mode_pixel_ratio(width, height),
+                Some(Ratio::new(w, h)),
+                "{}",
+                mode.name
+            );
+        }
+    }
+}
@@ -0,0 +1,1878 @@
+//! Declarative description of the full image processing pipeline.
+//!
+//! A [`Pipeline`] gathers every option of the conversion process
+//! (cropping, internal resolution, color standard, output size)
+//! in a single value which can be applied to any number of images.
+//!
+//! With the `serde` feature,
+//! pipelines can also be written down in a document
+//! (such as TOML or JSON)
+//! and shared as a reusable "look":
+//!
+//! ```toml
+//! standard = "ega"
+//! resolution = [320, 200]
+//! num_colors = 16
+//!
+//! [output]
+//! height = 1080
+//! pixel_ratio = [5, 6]
+//! format = "png"
+//! ```
+use crate::adjust::Adjustments;
+use crate::color::cga;
+use crate::color::metrics::Metrics;
+use crate::color::mono::{Monochrome, ShadePalette};
+use crate::color::strips::{self, StripOptions};
+use crate::color::temporal;
+use crate::color::{
+    colors_to_image, pixels, BackgroundStrategy, ColorDepth, ColorOptions, ColorStandard,
+    DitherMode, FixedPalette, IndexedImage, LossAlgorithm, PaletteBuilderOptions,
+};
+use crate::compose;
+use crate::crt::Crt;
+use crate::modes::VideoMode;
+use crate::preprocess::{self, Denoise, GamutShift, Outline};
+use crate::progress::{self, ProgressHook, Stage};
+use crate::textmode::{TextOptions, TextScreen};
+use crate::tonemap::{tone_map, ToneMap};
+use crate::{
+    aspect_crop_rect, auto_crop_rect, blend, expand, expand_single_scan, fill_transparent,
+    key_color, letterbox, matte, mode_pixel_ratio, reduce_with, resolve_output_resolution,
+    scale_resolution, tile, Fit, Gravity, ResizeFilter, ResolutionError, ScanMode,
+    ZeroResolutionSnafu,
+};
+use exoquant::Color;
+use image::imageops::{resize, FilterType};
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+use itertools::Itertools;
+use num_rational::Ratio;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The saturation multiplier and hue pull
+/// when shifting towards the gamut of a custom palette.
+const CUSTOM_PALETTE_GAMUT_SHIFT: (f32, f32) = (1.1, 0.2);
+
+/// A full description of the image processing chain.
+///
+/// The default pipeline converts the image to VGA colors (up to 256)
+/// without changing its resolution.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Pipeline {
+    /// How to map 16-bit and floating point input images to 8 bits per sample
+    pub tone_map: ToneMap,
+
+    /// Exposure adjustment of 16-bit and floating point input images,
+    /// in stops (each one doubles the light)
+    pub exposure: f32,
+
+    /// Background color to composite transparent images onto
+    /// before processing
+    pub matte: Option<[u8; 3]>,
+
+    /// Turn the matte color back into transparency in the output
+    /// (requires `matte`)
+    pub key_matte: bool,
+
+    /// Color standing for transparency (ignored if `matte` is set).
+    ///
+    /// Transparent pixels of the input are replaced with it,
+    /// pixels of this color are left out of the palette
+    /// and turned back into transparency in the output,
+    /// as the transparent palette index of indexed images.
+    pub transparent_color: Option<[u8; 3]>,
+
+    /// Crop the input image to the rectangle `[left, top, width, height]`
+    pub crop: Option<[u32; 4]>,
+
+    /// Trim the (cropped) input image to this aspect ratio,
+    /// as `[width, height]`
+    pub crop_aspect: Option<[u32; 2]>,
+
+    /// Which part of the image to keep when trimming it
+    /// to an aspect ratio
+    pub crop_gravity: Gravity,
+
+    /// Trim uniform borders off the (cropped) input image,
+    /// such as the black bars of letterboxed screenshots
+    pub auto_crop: bool,
+
+    /// Resolution to resize the image into before color reduction,
+    /// as `[width, height]`.
+    ///
+    /// Defaults to the resolution of the color standard
+    /// (see [`ColorStandard::resolution`]),
+    /// or to the resolution of the input image otherwise
+    pub resolution: Option<[u32; 2]>,
+
+    /// Trim the source image to the proportions of the output image
+    /// before resizing it into the internal resolution,
+    /// so that non-square pixels do not distort it
+    pub correct_aspect: bool,
+
+    /// Resampling filter for reducing the image to the internal resolution
+    pub filter: ResizeFilter,
+
+    /// Standard deviation of a Gaussian blur
+    /// applied to the source image before reducing it,
+    /// in source pixels (0 for no blur)
+    pub pre_blur: f32,
+
+    /// Color standard
+    pub standard: ColorStandard,
+
+    /// Custom palette of RGB colors,
+    /// used instead of the color standard if defined
+    pub palette: Option<Vec<[u8; 3]>>,
+
+    /// Palette from a previous run to map the colors to,
+    /// instead of choosing them again
+    /// (see [`Pipeline::lock_palette`]).
+    ///
+    /// Unlike `palette`, the image is still reduced and preprocessed
+    /// for the color standard.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub locked_palette: Option<Vec<[u8; 3]>>,
+
+    /// Phosphor and intensity levels of the `mono` color standard
+    pub mono: Monochrome,
+
+    /// Custom shades of the `gameboy` color standard,
+    /// from darkest to lightest
+    pub shades: Option<Vec<[u8; 3]>>,
+
+    /// Brightness, contrast, saturation and gamma adjustments,
+    /// applied before color conversion
+    pub adjust: Adjustments,
+
+    /// Edge-preserving noise reduction filter,
+    /// applied before color conversion
+    pub denoise: Option<Denoise>,
+
+    /// Stretch the contrast of the image to the full range of intensities
+    /// before color conversion
+    pub auto_contrast: bool,
+
+    /// Equalize the histogram of the image before color conversion
+    pub equalize: bool,
+
+    /// Input intensity mapped to black before color conversion
+    pub black_level: u8,
+
+    /// Input intensity mapped to white before color conversion
+    pub white_level: u8,
+
+    /// Darken the edges of the image before color conversion
+    pub outline: Option<Outline>,
+
+    /// Shift the colors towards the gamut of the target palette
+    /// before color conversion
+    pub gamut_shift: Option<GamutShift>,
+
+    /// Reduce each channel to this number of levels before color conversion,
+    /// for a flat shaded look
+    pub posterize: Option<u16>,
+
+    /// Weight the palette optimization by an automatic saliency map,
+    /// so that palette entries are spent on the subject of the image
+    /// (ignored if `importance_map` is defined)
+    pub saliency: bool,
+
+    /// Importance map covering the input image (before cropping),
+    /// used to weight the palette optimization
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub importance_map: Option<GrayImage>,
+
+    /// Maximum number of simultaneous colors (emulates palette indexing)
+    pub num_colors: u32,
+
+    /// Do not limit number of simultaneous colors (invalidates `num_colors`)
+    pub no_color_limit: bool,
+
+    /// Color distance algorithm for loss calculation
+    pub loss: LossAlgorithm,
+
+    /// Dithering mode
+    pub dither: DitherMode,
+
+    /// Seed for the stochastic processes (such as random dithering)
+    pub seed: Option<u64>,
+
+    /// Quantize, dither and measure the loss in linear light
+    pub linear: bool,
+
+    /// Convert the colors a strip of this many rows at a time,
+    /// over a palette built from a subsample of the image,
+    /// to save memory on huge images
+    /// (the importance map is not used then)
+    pub strip_height: Option<u32>,
+
+    /// Palette optimization options, trading quality for speed
+    pub palette_builder: PaletteBuilderOptions,
+
+    /// How to choose the free background color (in CGA mode 4)
+    pub background: BackgroundStrategy,
+
+    /// Choose the colors once for all frames of an animation,
+    /// so that the palette does not change from one frame to the next
+    pub global_palette: bool,
+
+    /// RGB colors which must survive the conversion exactly
+    pub protected_colors: Vec<[u8; 3]>,
+
+    /// Output stage options
+    pub output: OutputOptions,
+
+    /// A callback receiving the progress of each conversion
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub progress: Option<ProgressHook>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            tone_map: ToneMap::default(),
+            exposure: 0.,
+            matte: None,
+            key_matte: false,
+            transparent_color: None,
+            crop: None,
+            crop_aspect: None,
+            crop_gravity: Gravity::default(),
+            auto_crop: false,
+            resolution: None,
+            correct_aspect: false,
+            filter: ResizeFilter::default(),
+            pre_blur: 0.,
+            standard: ColorStandard::default(),
+            palette: None,
+            locked_palette: None,
+            mono: Monochrome::default(),
+            shades: None,
+            adjust: Adjustments::default(),
+            denoise: None,
+            auto_contrast: false,
+            equalize: false,
+            black_level: 0,
+            white_level: 255,
+            outline: None,
+            gamut_shift: None,
+            posterize: None,
+            saliency: false,
+            importance_map: None,
+            num_colors: 256,
+            no_color_limit: false,
+            loss: LossAlgorithm::default(),
+            dither: DitherMode::default(),
+            seed: None,
+            linear: false,
+            strip_height: None,
+            palette_builder: PaletteBuilderOptions::default(),
+            background: BackgroundStrategy::default(),
+            global_palette: false,
+            protected_colors: Vec::new(),
+            output: OutputOptions::default(),
+            progress: None,
+        }
+    }
+}
+
+/// An image cropped, reduced to the internal resolution and preprocessed,
+/// ready for color conversion.
+struct Prepared {
+    image: RgbImage,
+    /// The image only cropped and reduced, without preprocessing,
+    /// if it was asked for
+    reduced: Option<RgbImage>,
+    /// The importance map at the internal resolution
+    importance: Option<GrayImage>,
+    /// The pixels which are transparent (0) or opaque (255)
+    /// at the internal resolution,
+    /// if there is a transparent color and any pixel has it
+    alpha: Option<GrayImage>,
+    /// The output resolution
+    out_size: (u32, u32),
+}
+
+/// A prepared image after color conversion,
+/// still at the internal resolution.
+struct Converted {
+    image: RgbImage,
+    /// The palette index of each pixel,
+    /// unless there are more than 256 colors
+    indexed: Option<IndexedImage>,
+    /// The final palette
+    palette: Vec<[u8; 3]>,
+    loss: u64,
+    /// The CGA mode 4 sub-palette picked, if any
+    cga_subpalette: Option<usize>,
+}
+
+/// A collection of named pipelines,
+/// such as the presets of a configuration file:
+///
+/// ```toml
+/// [presets.my-cga-look]
+/// standard = "cga"
+/// resolution = [320, 200]
+/// dither = "bayer4"
+///
+/// [presets.my-cga-look.output]
+/// height = 1080
+/// pixel_ratio = [5, 6]
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Presets {
+    /// The pipelines by name
+    pub presets: BTreeMap<String, Pipeline>,
+}
+
+impl Presets {
+    /// The pipeline of the preset with the given name.
+    pub fn get(&self, name: &str) -> Option<&Pipeline> {
+        self.presets.get(name)
+    }
+}
+
+/// Quality metrics of a conversion,
+/// measured at the internal resolution
+/// against the image cropped and reduced without preprocessing.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ConversionStats {
+    /// The loss algorithm of the pipeline
+    pub loss_algorithm: LossAlgorithm,
+    /// The loss of the color conversion,
+    /// against the preprocessed image
+    pub loss: u64,
+    /// The preprocessed image against the reduced image
+    pub preprocess: Metrics,
+    /// The converted image against the preprocessed image
+    pub color: Metrics,
+    /// The converted image against the reduced image
+    pub total: Metrics,
+}
+
+/// The outcome of a conversion by [`Pipeline::run_detailed`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversion {
+    /// The output image
+    pub image: RgbImage,
+    /// The output image as a palette and an index per pixel,
+    /// as in [`Pipeline::run_indexed`]
+    pub indexed: Option<IndexedImage>,
+    /// The final palette, as in [`Pipeline::run_with_palette`]
+    pub palette: Vec<[u8; 3]>,
+    /// The loss of the color conversion at the internal resolution
+    pub loss: u64,
+    /// The CGA mode 4 sub-palette picked, if any,
+    /// as in [`Pipeline::cga_subpalette`]
+    pub cga_subpalette: Option<usize>,
+    /// The quality metrics, if they were asked for
+    pub stats: Option<ConversionStats>,
+}
+
+/// Options for the last stage of the pipeline.
+///
+/// Only the following combinations of output dimensions are valid:
+///
+/// - none of them (no resizing, unless `size` is defined);
+/// - `width` and `height`;
+/// - `pixel_ratio` and `width`;
+/// - `pixel_ratio` and `height`.
+///
+/// See also [`resolve_output_resolution`].
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct OutputOptions {
+    /// Output image size, as `[width, height]`,
+    /// used when none of the other dimensions are defined
+    pub size: Option<[u32; 2]>,
+
+    /// Pixel ratio, as `[width, height]`.
+    ///
+    /// If only one of `width` and `height` is defined,
+    /// this defaults to the pixel ratio of the color standard
+    /// (see [`ColorStandard::pixel_ratio`])
+    /// or of the video mode with the internal resolution
+    /// (see [`mode_pixel_ratio`]).
+    pub pixel_ratio: Option<[u32; 2]>,
+
+    /// Assume square pixels instead of deriving the pixel ratio
+    /// from the internal resolution
+    pub square_pixels: bool,
+
+    /// Output image width (defined separately)
+    pub width: Option<u32>,
+
+    /// Output image height (defined separately)
+    pub height: Option<u32>,
+
+    /// Multiply the internal resolution by this exact factor,
+    /// instead of resizing to the output dimensions
+    pub scale: Option<u32>,
+
+    /// Scale the internal resolution by the largest integer factor
+    /// which fits in the output dimensions,
+    /// so that all pixels have the same size.
+    /// The factor applies to the pixel height,
+    /// and the pixel width follows the pixel ratio.
+    pub integer_scale: bool,
+
+    /// Stretch the image to the output dimensions,
+    /// or fit it inside them keeping its proportions
+    /// and fill the borders with a color
+    /// (with `scale` or `integer_scale`,
+    /// the scaled image is centered on a canvas of the output dimensions)
+    pub fit: Fit,
+
+    /// How each line of the internal image is shown on the output image
+    pub scan: ScanMode,
+
+    /// CRT monitor effects, applied after expanding to the output resolution
+    pub crt: Option<Crt>,
+
+    /// Repeat the image to fill a canvas of this size, as `[width, height]`
+    pub tile: Option<[u32; 2]>,
+
+    /// Mirror every other tile, so that the edges of the tiles match
+    pub tile_mirror: bool,
+
+    /// Output image format, by file extension (e.g. `"png"`)
+    pub format: Option<String>,
+
+    /// Bits per pixel of indexed BMP output (1, 4 or 8),
+    /// or the fewest which fit the palette if `None`
+    pub bmp_bits: Option<u8>,
+
+    /// Convert into text mode art instead
+    /// (see [`Pipeline::run_text`])
+    pub text_mode: Option<TextOptions>,
+}
+
+/// Chainable constructors,
+/// for building a pipeline step by step in code.
+///
+/// ```no_run
+/// # use retroimg::{ColorStandard, Pipeline};
+/// # use retroimg::adjust::Adjustments;
+/// let pipeline = Pipeline::new(ColorStandard::FullEga)
+///     .with_resolution(320, 200)
+///     .with_adjustments(Adjustments {
+///         contrast: 1.2,
+///         ..Default::default()
+///     })
+///     .with_num_colors(16)
+///     .with_output_size(1280, 960);
+/// # let image = image::RgbImage::new(640, 480);
+/// let out = pipeline.run(&image)?;
+/// # Ok::<(), retroimg::ResolutionError>(())
+/// ```
+impl Pipeline {
+    /// Create a pipeline converting to the given color standard,
+    /// with all other options left to their defaults.
+    pub fn new(standard: ColorStandard) -> Self {
+        Pipeline {
+            standard,
+            ..Default::default()
+        }
+    }
+
+    /// Crop the input image to the given rectangle first.
+    pub fn with_crop(self, left: u32, top: u32, width: u32, height: u32) -> Self {
+        Pipeline {
+            crop: Some([left, top, width, height]),
+            ..self
+        }
+    }
+
+    /// Resize the image to the given internal resolution
+    /// before color reduction.
+    pub fn with_resolution(self, width: u32, height: u32) -> Self {
+        Pipeline {
+            resolution: Some([width, height]),
+            ..self
+        }
+    }
+
+    /// Apply the given adjustments before color reduction.
+    pub fn with_adjustments(self, adjust: Adjustments) -> Self {
+        Pipeline { adjust, ..self }
+    }
+
+    /// Convert to a custom palette instead of the color standard.
+    pub fn with_palette(self, palette: Vec<[u8; 3]>) -> Self {
+        Pipeline {
+            palette: Some(palette),
+            ..self
+        }
+    }
+
+    /// Optimize the palette with the given options.
+    pub fn with_palette_builder(self, palette_builder: PaletteBuilderOptions) -> Self {
+        Pipeline {
+            palette_builder,
+            ..self
+        }
+    }
+
+    /// Treat the given color as transparency.
+    pub fn with_transparent_color(self, color: [u8; 3]) -> Self {
+        Pipeline {
+            transparent_color: Some(color),
+            ..self
+        }
+    }
+
+    /// Report the progress of each conversion to the given hook.
+    pub fn with_progress(self, progress: ProgressHook) -> Self {
+        Pipeline {
+            progress: Some(progress),
+            ..self
+        }
+    }
+
+    /// Limit the number of simultaneous colors.
+    pub fn with_num_colors(self, num_colors: u32) -> Self {
+        Pipeline {
+            num_colors,
+            no_color_limit: false,
+            ..self
+        }
+    }
+
+    /// Do not limit the number of simultaneous colors.
+    pub fn without_color_limit(self) -> Self {
+        Pipeline {
+            no_color_limit: true,
+            ..self
+        }
+    }
+
+    /// Use the given color distance algorithm.
+    pub fn with_loss(self, loss: LossAlgorithm) -> Self {
+        Pipeline { loss, ..self }
+    }
+
+    /// Use the given dithering mode.
+    pub fn with_dither(self, dither: DitherMode) -> Self {
+        Pipeline { dither, ..self }
+    }
+
+    /// Expand the converted image to the given output resolution.
+    pub fn with_output_size(mut self, width: u32, height: u32) -> Self {
+        self.output.width = Some(width);
+        self.output.height = Some(height);
+        self.output.pixel_ratio = None;
+        self
+    }
+
+    /// Expand the converted image to the given output height,
+    /// with pixels of the given aspect ratio (width / height).
+    pub fn with_output_height(mut self, height: u32, pixel_ratio: [u32; 2]) -> Self {
+        self.output.width = None;
+        self.output.height = Some(height);
+        self.output.pixel_ratio = Some(pixel_ratio);
+        self
+    }
+
+    /// Convert as in the given video mode,
+    /// with its color standard, resolution and number of colors.
+    ///
+    /// The pixel ratio of the mode applies
+    /// when only one of the output width and height is defined.
+    pub fn with_mode(mut self, mode: &VideoMode) -> Self {
+        self.standard = mode.standard;
+        self.palette = None;
+        self.resolution = Some(mode.resolution);
+        self.num_colors = mode.num_colors;
+        self.no_color_limit = false;
+        if self.output.width.is_some() != self.output.height.is_some() {
+            self.output.pixel_ratio = Some(mode.pixel_ratio);
+        }
+        self
+    }
+
+    /// Use the given scan mode when expanding the converted image.
+    pub fn with_scan(mut self, scan: ScanMode) -> Self {
+        self.output.scan = scan;
+        self
+    }
+}
+
+impl Pipeline {
+    /// Obtain the color options to pass to the color depth converter.
+    pub fn color_options(&self) -> ColorOptions {
+        ColorOptions {
+            num_colors: Some(self.num_colors).filter(|_| !self.no_color_limit),
+            loss: self.loss,
+            protected_colors: self.protected_colors.clone(),
+            importance: None,
+            alpha: None,
+            dither: self.dither,
+            seed: self.seed,
+            linear: self.linear,
+            palette_builder: self.palette_builder,
+            background: self.background,
+            progress: self.progress.clone(),
+        }
+    }
+
+    /// Create the color depth converter for this pipeline.
+    pub fn color_depth(&self) -> Box<dyn ColorDepth> {
+        if let Some(palette) = &self.locked_palette {
+            return Box::new(FixedPalette::new(palette.clone()));
+        }
+        match (&self.palette, self.standard) {
+            (Some(palette), _) => Box::new(FixedPalette::new(palette.clone())),
+            (None, ColorStandard::Mono) => Box::new(self.mono),
+            (None, ColorStandard::GameBoy) => match &self.shades {
+                Some(shades) => Box::new(ShadePalette::new(shades.clone())),
+                None => self.standard.color_depth(),
+            },
+            (None, standard) => standard.color_depth(),
+        }
+    }
+
+    /// A copy of this pipeline which maps colors
+    /// to a palette obtained from a previous run,
+    /// without choosing the colors again
+    /// but otherwise processing images in the same way.
+    ///
+    /// The transparent color is left out of the palette,
+    /// as the transparent pixels get it anyway.
+    ///
+    /// Returns `None` if the number of colors is not limited,
+    /// or if the color standard constrains the colors
+    /// in other ways than a palette
+    /// (such as per cell or per line),
+    /// in which case the palette cannot be reused as is.
+    pub fn lock_palette(&self, palette: &[[u8; 3]]) -> Option<Pipeline> {
+        let key = self.output_key();
+        let palette = palette
+            .iter()
+            .copied()
+            .filter(|&c| Some(c) != key)
+            .collect_vec();
+        if !self.can_lock_palette() || palette.is_empty() {
+            return None;
+        }
+        Some(Pipeline {
+            num_colors: palette.len() as u32,
+            locked_palette: Some(palette),
+            ..self.clone()
+        })
+    }
+
+    /// Whether the colors can be mapped to a palette from a previous run
+    /// (see [`Pipeline::lock_palette`]).
+    fn can_lock_palette(&self) -> bool {
+        let plain = self.palette.is_some()
+            || self.locked_palette.is_some()
+            || matches!(
+                self.standard,
+                ColorStandard::True24Bit
+                    | ColorStandard::Vga18Bit
+                    | ColorStandard::Vga16Bit
+                    | ColorStandard::FullEga
+                    | ColorStandard::FullCga
+                    | ColorStandard::CgaMode4
+                    | ColorStandard::CgaMode4High1
+                    | ColorStandard::BlackWhite
+                    | ColorStandard::Ega16
+                    | ColorStandard::Vga256
+                    | ColorStandard::Nes
+                    | ColorStandard::Tandy
+                    | ColorStandard::TandyLow
+                    | ColorStandard::ColorPlus
+                    | ColorStandard::ColorPlusHigh
+                    | ColorStandard::Amiga
+                    | ColorStandard::AmigaEhb
+                    | ColorStandard::AtariSt
+            );
+        plain && !self.no_color_limit
+    }
+
+    /// Identify the internal (emulated) resolution
+    /// for an input image with the given dimensions
+    /// (after cropping).
+    pub fn internal_resolution(&self, width: u32, height: u32) -> (u32, u32) {
+        self.resolution
+            .or_else(|| {
+                self.standard
+                    .resolution()
+                    .filter(|_| self.palette.is_none())
+            })
+            .map(|[w, h]| (w, h))
+            .unwrap_or((width, height))
+    }
+
+    /// Identify the output resolution
+    /// from the given internal resolution.
+    pub fn output_resolution(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<(u32, u32), ResolutionError> {
+        let OutputOptions {
+            size,
+            pixel_ratio,
+            width: out_width,
+            height: out_height,
+            ..
+        } = self.output;
+        // a ratio which is not given explicitly
+        // only applies when a single output dimension is given
+        let pixel_ratio = if pixel_ratio.is_some() || out_width.is_some() != out_height.is_some() {
+            self.display_pixel_ratio(width, height)
+        } else {
+            None
+        };
+        let scaled = match self.output.scale {
+            Some(n) => Some(scale_resolution(width, height, n)?),
+            None => None,
+        };
+        match (scaled, pixel_ratio, out_width, out_height) {
+            (Some(scaled), ..) if !matches!(self.output.fit, Fit::Pad(_)) => Ok(scaled),
+            (_, None, None, None) => Ok(size.map(|[w, h]| (w, h)).unwrap_or((width, height))),
+            _ => resolve_output_resolution(width, height, out_width, out_height, pixel_ratio),
+        }
+    }
+
+    /// Identify the region of an input image with the given dimensions
+    /// which goes through the pipeline, as `[left, top, width, height]`.
+    ///
+    /// This is the crop rectangle,
+    /// trimmed to the aspect ratio of `crop_aspect` if defined,
+    /// and further trimmed to the proportions of the output image
+    /// if `correct_aspect` is set.
+    ///
+    /// Automatic cropping is not applied,
+    /// since it depends on the contents of the image
+    /// (see [`Pipeline::image_source_rect`]).
+    pub fn source_rect(&self, width: u32, height: u32) -> Result<[u32; 4], ResolutionError> {
+        self.trim_rect(self.crop.unwrap_or([0, 0, width, height]))
+    }
+
+    /// Identify the region of an input image
+    /// which goes through the pipeline, as `[left, top, width, height]`,
+    /// as in [`Pipeline::source_rect`]
+    /// but also trimming uniform borders if `auto_crop` is set.
+    pub fn image_source_rect(&self, image: &RgbImage) -> Result<[u32; 4], ResolutionError> {
+        let rect = self.crop.unwrap_or([0, 0, image.width(), image.height()]);
+        if !self.auto_crop {
+            return self.trim_rect(rect);
+        }
+        let [left, top, width, height] = rect;
+        let cropped = image::imageops::crop_imm(image, left, top, width, height).to_image();
+        let [l, t, w, h] = auto_crop_rect(&cropped);
+        self.trim_rect([left + l, top + t, w, h])
+    }
+
+    /// Trim a rectangle of the input image
+    /// to the aspect ratio of `crop_aspect`
+    /// and to the proportions of the output image.
+    fn trim_rect(&self, rect: [u32; 4]) -> Result<[u32; 4], ResolutionError> {
+        let rect = match self.crop_aspect {
+            Some([w, h]) if w > 0 && h > 0 => {
+                aspect_crop_rect(rect, Ratio::new(w, h), self.crop_gravity)
+            }
+            _ => rect,
+        };
+        if !self.correct_aspect {
+            return Ok(rect);
+        }
+
+        let [_, _, width, height] = rect;
+        let (in_width, in_height) = self.internal_resolution(width, height);
+        let (out_width, out_height) = self.output_resolution(in_width, in_height)?;
+        if out_width == 0 || out_height == 0 {
+            return ZeroResolutionSnafu.fail();
+        }
+        Ok(aspect_crop_rect(
+            rect,
+            Ratio::new(out_width, out_height),
+            self.crop_gravity,
+        ))
+    }
+
+    /// Prepare an input image of any color type for the pipeline,
+    /// tone mapping it if it has more than 8 bits per sample
+    /// and compositing it onto the matte color if it has transparency,
+    /// or else replacing its transparent pixels with the transparent color.
+    ///
+    /// Without either color, the alpha channel is simply dropped.
+    pub fn flatten_input(&self, image: &DynamicImage) -> RgbImage {
+        let image = &*tone_map(image, self.tone_map, self.exposure);
+        match (self.matte, self.transparent_color) {
+            (Some(color), _) => matte(image, color),
+            (None, Some(color)) => fill_transparent(image, color),
+            (None, None) => image.to_rgb8(),
+        }
+    }
+
+    /// The color turned back into transparency in the output, if any:
+    /// the matte color if `key_matte` is set,
+    /// or else the transparent color.
+    pub fn output_key(&self) -> Option<[u8; 3]> {
+        match self.matte {
+            Some(color) => Some(color).filter(|_| self.key_matte),
+            None => self.transparent_color,
+        }
+    }
+
+    /// Prepare the output of the pipeline for saving,
+    /// turning the [output key](Self::output_key) back into transparency.
+    pub fn key_output(&self, image: RgbImage) -> DynamicImage {
+        match self.output_key() {
+            Some(color) => DynamicImage::ImageRgba8(key_color(&image, color)),
+            None => DynamicImage::ImageRgb8(image),
+        }
+    }
+
+    /// Apply the preprocessing steps to the reduced image.
+    fn preprocess(&self, image: &mut RgbImage) {
+        if let Some(denoise) = self.denoise {
+            *image = denoise.apply(image);
+        }
+        if self.auto_contrast {
+            preprocess::auto_contrast(image);
+        }
+        if self.equalize {
+            preprocess::equalize(image);
+        }
+        preprocess::levels(image, self.black_level, self.white_level);
+        self.adjust.apply(image);
+        if let Some(outline) = &self.outline {
+            outline.apply(image);
+        }
+        if let Some(shift) = &self.gamut_shift {
+            match &self.palette {
+                Some(palette) => shift.apply(image, palette, CUSTOM_PALETTE_GAMUT_SHIFT),
+                None => shift.apply(
+                    image,
+                    &GamutShift::target_colors(self.standard),
+                    GamutShift::defaults(self.standard),
+                ),
+            }
+        }
+        if let Some(levels) = self.posterize {
+            preprocess::posterize(image, levels);
+        }
+    }
+
+    /// Apply the full pipeline to an image.
+    pub fn run(&self, image: &RgbImage) -> Result<RgbImage, ResolutionError> {
+        self.run_with_palette(image).map(|(image, _)| image)
+    }
+
+    /// Apply the full pipeline to an image,
+    /// also retrieving the final palette of the conversion.
+    ///
+    /// If the number of colors was not limited,
+    /// the palette holds the distinct colors of the converted image,
+    /// or is empty if there are more than 256 of them.
+    pub fn run_with_palette(
+        &self,
+        image: &RgbImage,
+    ) -> Result<(RgbImage, Vec<[u8; 3]>), ResolutionError> {
+        let (image, palette, _loss) = self.convert(image)?;
+        Ok((self.finish(image), palette))
+    }
+
+    /// Apply the full pipeline to an image,
+    /// also retrieving the loss of the color conversion
+    /// at the internal resolution.
+    pub fn run_with_loss(&self, image: &RgbImage) -> Result<(RgbImage, u64), ResolutionError> {
+        let (image, _palette, loss) = self.convert(image)?;
+        Ok((self.finish(image), loss))
+    }
+
+    /// Apply the full pipeline to an image,
+    /// also retrieving the final palette of the conversion
+    /// and quality metrics of each stage at the internal resolution.
+    pub fn run_with_stats(
+        &self,
+        image: &RgbImage,
+    ) -> Result<(RgbImage, Vec<[u8; 3]>, ConversionStats), ResolutionError> {
+        let Conversion {
+            image,
+            palette,
+            stats,
+            ..
+        } = self.run_detailed(image, true)?;
+        Ok((image, palette, stats.expect("stats should be measured")))
+    }
+
+    /// Apply the full pipeline to an image,
+    /// also retrieving the final palette, the palette index of each pixel,
+    /// the CGA mode 4 sub-palette picked by the conversion,
+    /// and the quality metrics of each stage if `with_stats` is set.
+    pub fn run_detailed(
+        &self,
+        image: &RgbImage,
+        with_stats: bool,
+    ) -> Result<Conversion, ResolutionError> {
+        let prepared = self.prepare_with(image, with_stats)?;
+        let converted = self.convert_reduced(
+            &prepared.image,
+            prepared.importance.clone(),
+            prepared.alpha.clone(),
+        );
+        let stats = with_stats.then(|| {
+            let reduced = prepared.reduced.as_ref().unwrap_or(&prepared.image);
+            ConversionStats {
+                loss_algorithm: self.loss,
+                loss: converted.loss,
+                preprocess: Metrics::measure(reduced, &prepared.image),
+                color: Metrics::measure(&prepared.image, &converted.image),
+                total: Metrics::measure(reduced, &converted.image),
+            }
+        });
+        let (image, indexed) = self.expand_converted(&converted, prepared.out_size);
+        Ok(Conversion {
+            image,
+            indexed,
+            palette: converted.palette,
+            loss: converted.loss,
+            cga_subpalette: converted.cga_subpalette,
+            stats,
+        })
+    }
+
+    /// Apply the pipeline to an image up to the color conversion,
+    /// leaving it at the internal resolution,
+    /// also retrieving the loss of the conversion.
+    pub fn run_unexpanded(&self, image: &RgbImage) -> Result<(RgbImage, u64), ResolutionError> {
+        let prepared = self.prepare(image)?;
+        let converted = self.convert_reduced(&prepared.image, prepared.importance, prepared.alpha);
+        Ok((converted.image, converted.loss))
+    }
+
+    /// Apply the pipeline to an image up to the color conversion,
+    /// leaving it at the internal resolution
+    /// as a palette and an index per pixel,
+    /// also retrieving the loss of the conversion.
+    ///
+    /// The image is `None` if it has more than 256 colors.
+    pub fn run_unexpanded_indexed(
+        &self,
+        image: &RgbImage,
+    ) -> Result<(Option<IndexedImage>, u64), ResolutionError> {
+        let prepared = self.prepare(image)?;
+        let converted = self.convert_reduced(&prepared.image, prepared.importance, prepared.alpha);
+        let indexed = converted.indexed.map(|indexed| self.key_indexed(indexed));
+        Ok((indexed, converted.loss))
+    }
+
+    /// Apply the full pipeline to an image,
+    /// keeping the result as a palette and an index per pixel,
+    /// with the palette indices chosen by the color depth
+    /// (see [`ColorDepth::convert_image_indexed`]).
+    ///
+    /// Returns `None` if the output has more than 256 colors
+    /// (such as after CRT effects).
+    pub fn run_indexed(&self, image: &RgbImage) -> Result<Option<IndexedImage>, ResolutionError> {
+        Ok(self.run_detailed(image, false)?.indexed)
+    }
+
+    /// Apply the full pipeline to an image with temporal dithering,
+    /// retrieving two frames which resemble the image when shown alternately
+    /// and the palette which they share.
+    ///
+    /// With a custom palette, or a color standard of fixed colors
+    /// which all fit in the number of colors,
+    /// any two of those colors can be mixed.
+    /// Otherwise, the colors mixed are the ones
+    /// which the color depth chooses for the image.
+    pub fn run_temporal(
+        &self,
+        image: &RgbImage,
+    ) -> Result<([RgbImage; 2], Vec<[u8; 3]>), ResolutionError> {
+        let prepared = self.prepare(image)?;
+        let mut options = self.color_options();
+        options.importance = prepared.importance;
+        options.alpha = prepared.alpha.clone();
+        let key = self.active_key(prepared.alpha.as_ref());
+        if key.is_some() {
+            // the transparent color takes one of the palette entries
+            options.num_colors = options.num_colors.map(|n| n.saturating_sub(1).max(1));
+        }
+        let fixed_colors = match &self.palette {
+            Some(palette) => Some(&palette[..]),
+            None => self.standard.palette(),
+        }
+        .filter(|colors| self.no_color_limit || colors.len() <= self.num_colors as usize);
+        let mut frames = match fixed_colors {
+            Some(colors) => {
+                let palette = colors
+                    .iter()
+                    .map(|&[r, g, b]| Color { r, g, b, a: 255 })
+                    .collect_vec();
+                temporal::convert_image_temporal_with_palette(&palette, &prepared.image, options)
+            }
+            None => {
+                temporal::convert_image_temporal(&*self.color_depth(), &prepared.image, options)
+            }
+        };
+
+        let (width, height) = prepared.image.dimensions();
+        let expand = |pixels: Vec<Color>| {
+            let image = colors_to_image(width, height, pixels);
+            self.finish(self.expand_output(&image, prepared.out_size))
+        };
+        if let (Some(key), Some(alpha)) = (key, &prepared.alpha) {
+            key_colors(&mut frames.first, alpha, key);
+            key_colors(&mut frames.second, alpha, key);
+        }
+        let palette = key
+            .into_iter()
+            .chain(frames.palette.iter().map(|&c| {
+                let c = key.map_or(c, |key| avoid_key(c, key));
+                [c.r, c.g, c.b]
+            }))
+            .unique()
+            .collect();
+        Ok(([expand(frames.first), expand(frames.second)], palette))
+    }
+
+    /// Identify the CGA mode 4 sub-palette which the pipeline picks for an image,
+    /// as an index into [`CGA_MODE4_SUBPALETTE_NAMES`].
+    ///
+    /// Returns `None` if the pipeline does not pick a sub-palette
+    /// (with any color standard other than CGA mode 4
+    /// and the 4 color ColorPlus mode, or a custom palette).
+    ///
+    /// [`CGA_MODE4_SUBPALETTE_NAMES`]: crate::color::cga::CGA_MODE4_SUBPALETTE_NAMES
+    ///
+    /// This converts the image again,
+    /// so prefer [`Pipeline::run_detailed`]
+    /// to find the sub-palette along with the output image.
+    pub fn cga_subpalette(&self, image: &RgbImage) -> Result<Option<usize>, ResolutionError> {
+        if !self.picks_cga_subpalette() {
+            return Ok(None);
+        }
+        let prepared = self.prepare(image)?;
+        let mut options = self.color_options();
+        options.importance = prepared.importance;
+        options.alpha = prepared.alpha;
+        let (index, ..) = cga::PALETTE_CGA_MODE4.convert_image_best(&prepared.image, options);
+        Ok(Some(index))
+    }
+
+    /// Whether the color conversion picks one of the CGA mode 4 sub-palettes.
+    fn picks_cga_subpalette(&self) -> bool {
+        self.palette.is_none()
+            && self.locked_palette.is_none()
+            && matches!(
+                self.standard,
+                ColorStandard::CgaMode4 | ColorStandard::ColorPlusHigh
+            )
+    }
+
+    /// Apply the pipeline to an image up to the preprocessing steps,
+    /// at the pixel size of a text mode screen,
+    /// and convert it into text mode art.
+    ///
+    /// The color standard, number of colors and dithering mode
+    /// do not apply,
+    /// since text mode has its own 16 color palette.
+    pub fn run_text(
+        &self,
+        image: &RgbImage,
+        options: &TextOptions,
+    ) -> Result<TextScreen, ResolutionError> {
+        let (width, height) = options.pixel_size();
+        let pipeline = Pipeline {
+            resolution: Some([width, height]),
+            ..self.clone()
+        };
+        let prepared = pipeline.prepare(image)?;
+        Ok(TextScreen::from_image(&prepared.image, options))
+    }
+
+    /// Apply the full pipeline to each frame of an animation.
+    ///
+    /// All frames must have the same dimensions.
+    /// With `auto_crop`,
+    /// the frames are cropped to the region with the contents of all of them,
+    /// so that the output frames have the same dimensions too.
+    /// With `global_palette`,
+    /// the colors are chosen once for all frames together
+    /// and each frame is then mapped to them
+    /// (see [`Pipeline::lock_palette`]).
+    /// Otherwise,
+    /// or if the color standard does not allow reusing a palette,
+    /// each frame is converted on its own.
+    pub fn run_frames(&self, frames: &[RgbImage]) -> Result<Vec<RgbImage>, ResolutionError> {
+        let frames = self.run_frames_indexed(frames)?;
+        Ok(frames.into_iter().map(|(image, _)| image).collect())
+    }
+
+    /// Apply the full pipeline to each frame of an animation
+    /// like [`Pipeline::run_frames`],
+    /// also keeping each output frame as a palette and an index per pixel
+    /// unless it has more than 256 colors.
+    pub fn run_frames_indexed(
+        &self,
+        frames: &[RgbImage],
+    ) -> Result<Vec<(RgbImage, Option<IndexedImage>)>, ResolutionError> {
+        if self.auto_crop {
+            return self.with_frames_crop(frames).run_frames_indexed(frames);
+        }
+        if !self.global_palette || !self.can_lock_palette() {
+            return frames
+                .iter()
+                .map(|frame| {
+                    let conversion = self.run_detailed(frame, false)?;
+                    Ok((conversion.image, conversion.indexed))
+                })
+                .collect();
+        }
+
+        let reduced = frames
+            .iter()
+            .map(|frame| self.prepare(frame))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (width, height) = match reduced.first() {
+            Some(first) => first.image.dimensions(),
+            None => return Ok(Vec::new()),
+        };
+
+        // choose the colors from all frames stacked into a single image,
+        // skipping the dithering
+        let count = reduced.len() as u32;
+        let mut stack = RgbImage::new(width, height * count);
+        let mut importance = reduced
+            .iter()
+            .all(|r| r.importance.is_some())
+            .then(|| GrayImage::new(width, height * count));
+        // frames without transparent pixels are fully opaque
+        let mut alpha = reduced
+            .iter()
+            .any(|r| r.alpha.is_some())
+            .then(|| GrayImage::from_pixel(width, height * count, Luma([255])));
+        for (i, frame) in reduced.iter().enumerate() {
+            let y = i64::from(i as u32 * height);
+            image::imageops::replace(&mut stack, &frame.image, 0, y);
+            if let (Some(stacked), Some(map)) = (&mut importance, &frame.importance) {
+                image::imageops::replace(stacked, map, 0, y);
+            }
+            if let (Some(stacked), Some(map)) = (&mut alpha, &frame.alpha) {
+                image::imageops::replace(stacked, map, 0, y);
+            }
+        }
+        let undithered = Pipeline {
+            dither: DitherMode::None,
+            progress: None,
+            ..self.clone()
+        };
+        let palette = undithered
+            .convert_reduced(&stack, importance, alpha)
+            .palette;
+        drop(stack);
+
+        // then dither each frame on its own
+        let locked = self.lock_palette(&palette);
+        let pipeline = locked.as_ref().unwrap_or(self);
+        Ok(reduced
+            .into_iter()
+            .map(|frame| {
+                let converted =
+                    pipeline.convert_reduced(&frame.image, frame.importance, frame.alpha);
+                self.expand_converted(&converted, frame.out_size)
+            })
+            .collect())
+    }
+
+    /// A copy of this pipeline which crops all frames of an animation
+    /// to the union of their automatic crop rectangles,
+    /// instead of trimming the borders of each frame on its own.
+    pub fn with_frames_crop(&self, frames: &[RgbImage]) -> Pipeline {
+        let rect = frames
+            .iter()
+            .map(|frame| {
+                let [left, top, width, height] =
+                    self.crop.unwrap_or([0, 0, frame.width(), frame.height()]);
+                let cropped = image::imageops::crop_imm(frame, left, top, width, height);
+                let [l, t, w, h] = auto_crop_rect(&cropped.to_image());
+                [left + l, top + t, left + l + w, top + t + h]
+            })
+            .reduce(|[l0, t0, r0, b0], [l1, t1, r1, b1]| {
+                [l0.min(l1), t0.min(t1), r0.max(r1), b0.max(b1)]
+            });
+        Pipeline {
+            crop: rect.map(|[left, top, right, bottom]| [left, top, right - left, bottom - top]),
+            auto_crop: false,
+            ..self.clone()
+        }
+    }
+
+    /// Apply the pipeline to an image up to the expansion to the output resolution,
+    /// also retrieving the final palette and the loss.
+    fn convert(&self, image: &RgbImage) -> Result<(RgbImage, Vec<[u8; 3]>, u64), ResolutionError> {
+        let reduced = self.prepare(image)?;
+        let converted = self.convert_reduced(&reduced.image, reduced.importance, reduced.alpha);
+        let image = self.expand_output(&converted.image, reduced.out_size);
+        Ok((image, converted.palette, converted.loss))
+    }
+
+    /// Crop, reduce and preprocess an image for color conversion.
+    fn prepare(&self, image: &RgbImage) -> Result<Prepared, ResolutionError> {
+        self.prepare_with(image, false)
+    }
+
+    /// Crop, reduce and preprocess an image for color conversion,
+    /// also keeping the image before preprocessing if `keep_reduced` is set.
+    fn prepare_with(
+        &self,
+        image: &RgbImage,
+        keep_reduced: bool,
+    ) -> Result<Prepared, ResolutionError> {
+        progress::report(self.progress.as_ref(), Stage::Reduce, 0.);
+        let source_size = image.dimensions();
+        let rect = self.image_source_rect(image)?;
+        let cropped;
+        let image = match rect {
+            [0, 0, width, height] if (width, height) == image.dimensions() => image,
+            [left, top, width, height] => {
+                cropped = image::imageops::crop_imm(image, left, top, width, height).to_image();
+                &cropped
+            }
+        };
+
+        let (in_width, in_height) = self.internal_resolution(image.width(), image.height());
+        let out_size = self.output_resolution(in_width, in_height)?;
+
+        let alpha = self
+            .output_key()
+            .and_then(|color| reduced_alpha(image, color, in_width, in_height));
+        let reduced = if keep_reduced {
+            Some(reduce_with(image, in_width, in_height, self.filter))
+        } else {
+            None
+        };
+        let blurred;
+        let image = if self.pre_blur > 0. {
+            blurred = image::imageops::blur(image, self.pre_blur);
+            &blurred
+        } else {
+            image
+        };
+        let mut image = reduce_with(image, in_width, in_height, self.filter);
+        self.preprocess(&mut image);
+        let importance = match &self.importance_map {
+            Some(map) => {
+                let map = if map.dimensions() == source_size {
+                    map.clone()
+                } else {
+                    resize(map, source_size.0, source_size.1, FilterType::Triangle)
+                };
+                let [left, top, width, height] = rect;
+                let map = image::imageops::crop_imm(&map, left, top, width, height).to_image();
+                Some(resize(&map, in_width, in_height, FilterType::Triangle))
+            }
+            None if self.saliency => Some(preprocess::saliency(&image)),
+            None => None,
+        };
+        progress::report(self.progress.as_ref(), Stage::Reduce, 1.);
+        Ok(Prepared {
+            image,
+            reduced,
+            importance,
+            alpha,
+            out_size,
+        })
+    }
+
+    /// Convert the colors of a prepared image.
+    ///
+    /// The transparent pixels in `alpha` get the transparent color,
+    /// which leads the palette.
+    fn convert_reduced(
+        &self,
+        image: &RgbImage,
+        importance: Option<GrayImage>,
+        alpha: Option<GrayImage>,
+    ) -> Converted {
+        let (in_width, in_height) = image.dimensions();
+        let mut options = self.color_options();
+        options.importance = importance;
+        options.alpha = alpha.clone();
+        let key = self.active_key(alpha.as_ref());
+        if key.is_some() && self.locked_palette.is_none() {
+            // the transparent color takes one of the palette entries
+            options.num_colors = options.num_colors.map(|n| n.saturating_sub(1).max(1));
+        }
+        // the palette index of each pixel, when the color depth provides them
+        let mut indices = None;
+        let (mut colors, palette, loss, cga_subpalette) = if self.picks_cga_subpalette() {
+            let (index, colors, palette, loss) =
+                cga::PALETTE_CGA_MODE4.convert_image_best(image, options);
+            (colors, palette, loss, Some(index))
+        } else if let Some(strip_height) = self.strip_height {
+            let strip_options = StripOptions {
+                strip_height,
+                ..Default::default()
+            };
+            let mut converted = image.clone();
+            let palette = strips::convert_image_in_strips(
+                &*self.color_depth(),
+                &mut converted,
+                options.clone(),
+                strip_options,
+            );
+            let colors = pixels(&converted);
+            let loss = options.image_loss(&pixels(image), &colors);
+            (colors, palette, loss, None)
+        } else {
+            let depth = self.color_depth();
+            let indexed = options
+                .num_colors
+                .filter(|&n| n <= 256)
+                .and_then(|_| depth.convert_image_indexed(image, options.clone()));
+            match indexed {
+                Some((indexed, loss)) => {
+                    let colors = indexed
+                        .indices
+                        .iter()
+                        .map(|&i| indexed.palette[usize::from(i)])
+                        .collect_vec();
+                    indices = Some(indexed.indices);
+                    (colors, indexed.palette, loss, None)
+                }
+                None => {
+                    let (colors, palette, loss) = depth.convert_image_with_loss(image, options);
+                    (colors, palette, loss, None)
+                }
+            }
+        };
+        if let (Some(key), Some(alpha)) = (key, &alpha) {
+            key_colors(&mut colors, alpha, key);
+            // the transparent color is prepended to the palette
+            indices = indices.filter(|_| palette.len() < 256).map(|indices| {
+                indices
+                    .iter()
+                    .zip(alpha.iter())
+                    .map(|(&i, &a)| if a == 0 { 0 } else { i + 1 })
+                    .collect()
+            });
+        }
+        let palette = match key {
+            _ if palette.is_empty() => palette,
+            Some([r, g, b]) => std::iter::once(Color { r, g, b, a: 255 })
+                .chain(palette.into_iter().map(|c| avoid_key(c, [r, g, b])))
+                .collect(),
+            None => palette,
+        };
+        let indexed = match indices {
+            Some(indices) => Some(IndexedImage {
+                width: in_width,
+                height: in_height,
+                palette: palette.clone(),
+                indices,
+                transparent: None,
+            }),
+            // prefer the order of the conversion palette
+            None => IndexedImage::from_palette(in_width, in_height, &colors, &palette)
+                .or_else(|| IndexedImage::from_colors(in_width, in_height, &colors)),
+        };
+        let palette = match &indexed {
+            Some(indexed) if palette.is_empty() => indexed.palette.clone(),
+            _ => palette,
+        };
+        let palette = palette
+            .into_iter()
+            .map(|c| [c.r, c.g, c.b])
+            .unique()
+            .collect();
+        Converted {
+            image: colors_to_image(in_width, in_height, colors),
+            indexed,
+            palette,
+            loss,
+            cga_subpalette,
+        }
+    }
+
+    /// The color to give the transparent pixels in `alpha`
+    /// (see [`Pipeline::output_key`]), unless there are none.
+    fn active_key(&self, alpha: Option<&GrayImage>) -> Option<[u8; 3]> {
+        self.output_key().filter(|_| alpha.is_some())
+    }
+
+    /// Expand a converted image to the final output,
+    /// along with its palette indices
+    /// (see [`Pipeline::expand_indexed`]).
+    fn expand_converted(
+        &self,
+        converted: &Converted,
+        out_size: (u32, u32),
+    ) -> (RgbImage, Option<IndexedImage>) {
+        let image = self.finish(self.expand_output(&converted.image, out_size));
+        let indexed = converted
+            .indexed
+            .as_ref()
+            .and_then(|indexed| self.expand_indexed(indexed, &image, out_size))
+            .map(|indexed| self.key_indexed(indexed));
+        (image, indexed)
+    }
+
+    /// Index the final output of a converted image,
+    /// keeping the palette index chosen by the conversion
+    /// for each pixel which still has its color,
+    /// so that palettes with repeated colors keep their indices.
+    /// The colors added by the output stage are appended to the palette.
+    ///
+    /// Returns `None` if the output has more than 256 colors.
+    fn expand_indexed(
+        &self,
+        indexed: &IndexedImage,
+        output: &RgbImage,
+        out_size: (u32, u32),
+    ) -> Option<IndexedImage> {
+        let (width, height) = output.dimensions();
+        let colors = pixels(output);
+        let mut expanded = IndexedImage::from_palette(width, height, &colors, &indexed.palette)?;
+        if self.output.crt.is_some() {
+            return Some(expanded);
+        }
+        // the indices go through the same expansion, in the red channel
+        let index_map = RgbImage::from_fn(indexed.width, indexed.height, |x, y| {
+            Rgb([indexed.indices[(y * indexed.width + x) as usize], 0, 0])
+        });
+        let index_map = self.finish(self.expand_to_canvas(&index_map, out_size));
+        if index_map.dimensions() != (width, height) {
+            return Some(expanded);
+        }
+        for ((index, c), hint) in expanded
+            .indices
+            .iter_mut()
+            .zip(&colors)
+            .zip(index_map.pixels())
+        {
+            let hint = hint.0[0];
+            let same = |p: &Color| [p.r, p.g, p.b] == [c.r, c.g, c.b];
+            if indexed.palette.get(usize::from(hint)).map_or(false, same) {
+                *index = hint;
+            }
+        }
+        Some(expanded)
+    }
+
+    /// Mark the [output key](Self::output_key) of an indexed image
+    /// as transparent.
+    fn key_indexed(&self, indexed: IndexedImage) -> IndexedImage {
+        match self.output_key() {
+            Some(color) => indexed.with_transparent_color(color),
+            None => indexed,
+        }
+    }
+
+    /// Expand a converted image to the output resolution,
+    /// applying the CRT effects if enabled.
+    fn expand_output(&self, image: &RgbImage, out_size: (u32, u32)) -> RgbImage {
+        progress::report(self.progress.as_ref(), Stage::Expand, 0.);
+        let expanded = self.expand_to_canvas(image, out_size);
+        progress::report(self.progress.as_ref(), Stage::Expand, 1.);
+        expanded
+    }
+
+    /// Expand a converted image and fit it to the output canvas.
+    fn expand_to_canvas(&self, image: &RgbImage, out_size: (u32, u32)) -> RgbImage {
+        let (out_width, out_height) = self.scaled_size(image.dimensions(), out_size);
+        let expanded = match self.output.scan {
+            ScanMode::Double => expand(image, out_width, out_height),
+            ScanMode::Single => expand_single_scan(image, out_width, out_height),
+        };
+        let expanded = match &self.output.crt {
+            Some(crt) => crt.apply(&expanded, image.height()),
+            None => expanded,
+        };
+        let (canvas_width, canvas_height) = out_size;
+        let color = match self.output.fit {
+            Fit::Pad(color) => color,
+            Fit::Stretch => return expanded,
+        };
+        if (out_width, out_height) == out_size
+            || out_width > canvas_width
+            || out_height > canvas_height
+        {
+            return expanded;
+        }
+        letterbox(&expanded, canvas_width, canvas_height, color)
+    }
+
+    /// The size of the expanded image,
+    /// which differs from the output resolution with integer scaling.
+    fn scaled_size(&self, (width, height): (u32, u32), out_size: (u32, u32)) -> (u32, u32) {
+        let (out_width, out_height) = out_size;
+        // the width of the image as shown, in units of its pixel height
+        let display_width = || {
+            self.display_pixel_ratio(width, height)
+                .unwrap_or_else(|| Ratio::from_integer(1))
+                * width
+        };
+        match self.output.scale {
+            // validated along with the output resolution
+            Some(n) => scale_resolution(width, height, n).unwrap_or(out_size),
+            None if self.output.integer_scale && width > 0 && height > 0 => {
+                // a single factor for the pixel height,
+                // with the pixel width following the pixel ratio
+                let display_width = display_width();
+                let (numer, denom) = (
+                    u64::from(*display_width.numer()),
+                    u64::from(*display_width.denom()),
+                );
+                let n = u64::from(out_height / height)
+                    .min(u64::from(out_width) * denom / numer.max(1))
+                    .max(1);
+                (((n * numer + denom / 2) / denom) as u32, height * n as u32)
+            }
+            None if matches!(self.output.fit, Fit::Pad(_)) && width > 0 && height > 0 => {
+                let display_width = display_width();
+                let (w, h) = (u64::from(out_width), u64::from(out_height));
+                let (dw, dh) = (
+                    u64::from(*display_width.numer()),
+                    u64::from(*display_width.denom()) * u64::from(height),
+                );
+                if dw * h > w * dh {
+                    (out_width, ((w * dh + dw / 2) / dw) as u32)
+                } else {
+                    (((h * dw + dh / 2) / dh) as u32, out_height)
+                }
+            }
+            None => out_size,
+        }
+    }
+
+    /// The pixel ratio with which an image of the given internal resolution
+    /// is shown: the one given in the output options,
+    /// or the one implied by the color standard or the video mode
+    /// (none for square pixels or if `square_pixels` is set).
+    fn display_pixel_ratio(&self, width: u32, height: u32) -> Option<Ratio<u32>> {
+        match self.output.pixel_ratio {
+            Some([w, h]) => Some(Ratio::new(w, h)),
+            None if self.output.square_pixels => None,
+            None => self
+                .standard
+                .pixel_ratio()
+                .filter(|_| self.palette.is_none())
+                .or_else(|| mode_pixel_ratio(width, height)),
+        }
+    }
+
+    /// Apply the full pipeline to an image,
+    /// but only in the regions selected by a grayscale mask
+    /// (see [`blend`](crate::blend)).
+    ///
+    /// The mask covers the input image (before cropping),
+    /// and is resized if its dimensions differ.
+    /// The original image is resized smoothly to the output resolution
+    /// in the unselected regions.
+    pub fn run_masked(
+        &self,
+        image: &RgbImage,
+        mask: &GrayImage,
+    ) -> Result<RgbImage, ResolutionError> {
+        let (converted, _, _) = self.convert(image)?;
+        let (width, height) = converted.dimensions();
+
+        let mask = if mask.dimensions() == image.dimensions() {
+            mask.clone()
+        } else {
+            resize(mask, image.width(), image.height(), FilterType::Triangle)
+        };
+        let [left, top, w, h] = self.image_source_rect(image)?;
+        let original = image::imageops::crop_imm(image, left, top, w, h).to_image();
+        let mask = image::imageops::crop_imm(&mask, left, top, w, h).to_image();
+        let original = resize(&original, width, height, FilterType::CatmullRom);
+        let mask = resize(&mask, width, height, FilterType::Triangle);
+
+        Ok(self.finish(blend(&original, &converted, &mask)))
+    }
+
+    /// Apply the pipeline to an image once per color standard,
+    /// in the same order.
+    ///
+    /// The custom palette, if any, is ignored.
+    pub fn compare(
+        &self,
+        image: &RgbImage,
+        standards: &[ColorStandard],
+    ) -> Result<Vec<RgbImage>, ResolutionError> {
+        standards
+            .iter()
+            .map(|&standard| {
+                let pipeline = Pipeline {
+                    standard,
+                    palette: None,
+                    ..self.clone()
+                };
+                pipeline.run(image)
+            })
+            .collect()
+    }
+
+    /// Apply the pipeline to an image once per color standard,
+    /// and compose the results into a single grid image,
+    /// each labeled with the name of its standard.
+    ///
+    /// The custom palette, if any, is ignored.
+    pub fn contact_sheet(
+        &self,
+        image: &RgbImage,
+        standards: &[ColorStandard],
+    ) -> Result<RgbImage, ResolutionError> {
+        let cells = standards
+            .iter()
+            .map(ToString::to_string)
+            .zip(self.compare(image, standards)?)
+            .collect_vec();
+        Ok(compose::labeled_grid(&cells))
+    }
+
+    /// Apply the last steps of the pipeline to the expanded image.
+    fn finish(&self, image: RgbImage) -> RgbImage {
+        match self.output.tile {
+            Some([width, height]) => tile(&image, width, height, self.output.tile_mirror),
+            None => image,
+        }
+    }
+}
+
+/// The transparent (0) and opaque (255) pixels of an image
+/// in which the given color stands for transparency,
+/// reduced to the given resolution,
+/// or `None` if no pixel has that color.
+fn reduced_alpha(image: &RgbImage, color: [u8; 3], width: u32, height: u32) -> Option<GrayImage> {
+    if !image.pixels().any(|p| p.0 == color) {
+        return None;
+    }
+    let alpha = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        Luma([if image.get_pixel(x, y).0 == color {
+            0
+        } else {
+            255
+        }])
+    });
+    let mut alpha = resize(&alpha, width, height, FilterType::Triangle);
+    for a in alpha.iter_mut() {
+        *a = if *a < 128 { 0 } else { 255 };
+    }
+    Some(alpha)
+}
+
+/// Give the transparent pixels in `alpha` the transparent color,
+/// nudging the opaque pixels off it.
+fn key_colors(colors: &mut [Color], alpha: &GrayImage, key: [u8; 3]) {
+    for (c, &a) in colors.iter_mut().zip(alpha.iter()) {
+        *c = if a == 0 {
+            Color {
+                r: key[0],
+                g: key[1],
+                b: key[2],
+                a: 255,
+            }
+        } else {
+            avoid_key(*c, key)
+        };
+    }
+}
+
+/// Nudge an opaque color off the transparent color,
+/// so that it is not keyed out with the transparent pixels.
+fn avoid_key(c: Color, key: [u8; 3]) -> Color {
+    if [c.r, c.g, c.b] == key {
+        Color { b: c.b ^ 1, ..c }
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OutputOptions, Pipeline};
+    use crate::color::ColorStandard;
+    use image::{Rgb, RgbImage};
+    use itertools::Itertools;
+
+    #[test]
+    fn test_lock_palette() {
+        let image = RgbImage::from_fn(64, 40, |x, y| {
+            Rgb([(x * 4) as u8, (y * 6) as u8, ((x + y) * 2) as u8])
+        });
+        let pipeline = Pipeline {
+            standard: ColorStandard::CgaMode4,
+            gamut_shift: Some(Default::default()),
+            ..Default::default()
+        };
+        let (first, _) = pipeline.run_unexpanded(&image).unwrap();
+        let (_, palette) = pipeline.run_with_palette(&image).unwrap();
+        let locked = pipeline.lock_palette(&palette).unwrap();
+
+        let (out, _) = locked.run_unexpanded(&image).unwrap();
+        assert_eq!(out.dimensions(), first.dimensions());
+        assert!(out.pixels().all(|p| palette.contains(&p.0)));
+    }
+
+    #[test]
+    fn test_transparent_color_takes_a_palette_entry() {
+        let key = [255, 0, 255];
+        let image = RgbImage::from_fn(16, 8, |x, y| {
+            if x < 8 {
+                Rgb(key)
+            } else {
+                Rgb([(x * 16) as u8, (y * 32) as u8, 40])
+            }
+        });
+        let pipeline = Pipeline {
+            transparent_color: Some(key),
+            num_colors: 4,
+            ..Default::default()
+        };
+        let (_, palette) = pipeline.run_with_palette(&image).unwrap();
+        assert_eq!(palette[0], key);
+        assert!(palette.len() <= 4);
+    }
+
+    #[test]
+    fn test_integer_scale() {
+        let image = RgbImage::new(320, 200);
+        let pipeline = Pipeline {
+            output: OutputOptions {
+                size: Some([1920, 1080]),
+                integer_scale: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // 5 times the height, with 5:6 pixels
+        let out = pipeline.run(&image).unwrap();
+        assert_eq!(out.dimensions(), (1333, 1000));
+
+        let zero = Pipeline {
+            output: OutputOptions {
+                scale: Some(0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(zero.run(&image).is_err());
+    }
+
+    #[test]
+    fn test_zero_output_resolution() {
+        let pipeline = Pipeline {
+            correct_aspect: true,
+            output: OutputOptions {
+                size: Some([0, 200]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(pipeline.source_rect(320, 200).is_err());
+        assert!(crate::resolve_output_resolution(0, 200, Some(640), None, None).is_err());
+    }
+
+    #[test]
+    fn test_run_indexed() {
+        let image = RgbImage::from_fn(24, 20, |x, y| {
+            Rgb([(x * 10) as u8, (y * 12) as u8, ((x + y) * 5) as u8])
+        });
+        let pipeline = Pipeline {
+            num_colors: 8,
+            ..Default::default()
+        };
+        let (out, palette) = pipeline.run_with_palette(&image).unwrap();
+        let indexed = pipeline.run_indexed(&image).unwrap().unwrap();
+        assert_eq!(indexed.to_image(), out);
+        let rgb = indexed.palette_rgb();
+        assert!(palette.iter().all(|c| rgb.chunks(3).any(|p| p == c)));
+    }
+
+    #[test]
+    fn test_strip_height() {
+        let image = RgbImage::from_fn(24, 20, |x, y| {
+            Rgb([(x * 10) as u8, (y * 12) as u8, ((x + y) * 5) as u8])
+        });
+        let pipeline = Pipeline {
+            num_colors: 8,
+            ..Default::default()
+        };
+        let (whole, whole_loss) = pipeline.run_unexpanded(&image).unwrap();
+        let strips = Pipeline {
+            strip_height: Some(7),
+            ..pipeline
+        };
+        let (out, loss) = strips.run_unexpanded(&image).unwrap();
+        assert_eq!(out, whole);
+        assert_eq!(loss, whole_loss);
+    }
+
+    #[test]
+    fn test_frames_global_palette() {
+        let frames: Vec<_> = [0, 128]
+            .iter()
+            .map(|&shift| {
+                RgbImage::from_fn(16, 16, |x, y| {
+                    Rgb([(x * 8) as u8 + shift, (y * 8) as u8, 128 - shift])
+                })
+            })
+            .collect();
+        let pipeline = Pipeline {
+            num_colors: 6,
+            global_palette: true,
+            ..Default::default()
+        };
+        let out = pipeline.run_frames(&frames).unwrap();
+        let colors = out
+            .iter()
+            .flat_map(|frame| frame.pixels().map(|p| p.0))
+            .unique()
+            .count();
+        assert!(colors <= 6);
+    }
+
+    #[test]
+    fn test_frames_auto_crop() {
+        // the same picture on borders of different widths
+        let frames: Vec<_> = [1, 3]
+            .iter()
+            .map(|&border| {
+                RgbImage::from_fn(12, 12, |x, y| {
+                    if x < border || y < border || x >= 12 - border || y >= 12 - border {
+                        Rgb([0, 0, 0])
+                    } else {
+                        Rgb([(x * 20) as u8, (y * 20) as u8, 200])
+                    }
+                })
+            })
+            .collect();
+        for &global_palette in &[false, true] {
+            let pipeline = Pipeline {
+                auto_crop: true,
+                global_palette,
+                ..Default::default()
+            };
+            let out = pipeline.run_frames(&frames).unwrap();
+            assert_eq!(out[0].dimensions(), out[1].dimensions());
+        }
+    }
+}
@@ -0,0 +1,604 @@
+//! Image preprocessing operations,
+//! applied to the reduced image before color conversion.
+//!
+//! These are meant to help small palettes represent the image better,
+//! rather than to faithfully reproduce it.
+use crate::color::cga::{CGA_MODE4_0_HIGH, CGA_MODE4_0_LOW, CGA_MODE4_1_HIGH, CGA_MODE4_1_LOW};
+use crate::color::ColorStandard;
+use image::{GrayImage, Luma, Rgb, RgbImage};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Enumeration of edge-preserving noise reduction filters.
+///
+/// These prevent dithering from amplifying noise
+/// and compression artifacts into speckles.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum Denoise {
+    /// 3x3 median filter, best against isolated speckles
+    Median,
+    /// 5x5 bilateral filter, smooths gradients while keeping sharp edges
+    Bilateral,
+}
+
+impl std::fmt::Display for Denoise {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Denoise::Median => f.write_str("median"),
+            Denoise::Bilateral => f.write_str("bilateral"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`Denoise`] filter from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct DenoiseParseError;
+
+impl std::fmt::Display for DenoiseParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid denoise filter, should be \"median\" or \"bilateral\"")
+    }
+}
+
+impl std::error::Error for DenoiseParseError {}
+
+impl FromStr for Denoise {
+    type Err = DenoiseParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "median" => Ok(Denoise::Median),
+            "bilateral" => Ok(Denoise::Bilateral),
+            _ => Err(DenoiseParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for Denoise {
+    type Error = DenoiseParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Denoise> for String {
+    fn from(denoise: Denoise) -> Self {
+        denoise.to_string()
+    }
+}
+
+/// Spatial standard deviation of the bilateral filter, in pixels
+const BILATERAL_SIGMA_SPACE: f32 = 1.5;
+/// Intensity standard deviation of the bilateral filter
+const BILATERAL_SIGMA_RANGE: f32 = 24.;
+
+impl Denoise {
+    /// Apply the filter to an image.
+    pub fn apply(self, image: &RgbImage) -> RgbImage {
+        match self {
+            Denoise::Median => median(image),
+            Denoise::Bilateral => bilateral(image),
+        }
+    }
+}
+
+/// The pixels in the square window of the given radius around `(x, y)`,
+/// clamped to the image borders, along with their offsets.
+fn window(
+    image: &RgbImage,
+    x: u32,
+    y: u32,
+    radius: i64,
+) -> impl Iterator<Item = (i64, i64, [u8; 3])> + '_ {
+    let (w, h) = (i64::from(image.width()), i64::from(image.height()));
+    (-radius..=radius).flat_map(move |dy| {
+        (-radius..=radius).map(move |dx| {
+            let sx = (i64::from(x) + dx).clamp(0, w - 1) as u32;
+            let sy = (i64::from(y) + dy).clamp(0, h - 1) as u32;
+            (dx, dy, image.get_pixel(sx, sy).0)
+        })
+    })
+}
+
+fn median(image: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let mut channels = [[0_u8; 9]; 3];
+        for (i, (_, _, pixel)) in window(image, x, y, 1).enumerate() {
+            for (channel, &sample) in channels.iter_mut().zip(&pixel) {
+                channel[i] = sample;
+            }
+        }
+        let mut out = [0; 3];
+        for (o, samples) in out.iter_mut().zip(&mut channels) {
+            samples.sort_unstable();
+            *o = samples[4];
+        }
+        Rgb(out)
+    })
+}
+
+fn bilateral(image: &RgbImage) -> RgbImage {
+    let space = -0.5 / (BILATERAL_SIGMA_SPACE * BILATERAL_SIGMA_SPACE);
+    let range = -0.5 / (BILATERAL_SIGMA_RANGE * BILATERAL_SIGMA_RANGE);
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let center = image.get_pixel(x, y).0;
+        let mut sum = [0_f32; 3];
+        let mut total = 0.;
+        for (dx, dy, pixel) in window(image, x, y, 2) {
+            let dist = (dx * dx + dy * dy) as f32;
+            let diff: f32 = center
+                .iter()
+                .zip(&pixel)
+                .map(|(&a, &b)| (f32::from(a) - f32::from(b)).powi(2))
+                .sum();
+            let weight = (dist * space + diff * range).exp();
+            for (s, &p) in sum.iter_mut().zip(&pixel) {
+                *s += weight * f32::from(p);
+            }
+            total += weight;
+        }
+        Rgb([
+            (sum[0] / total).round() as u8,
+            (sum[1] / total).round() as u8,
+            (sum[2] / total).round() as u8,
+        ])
+    })
+}
+
+/// Options for the outline stylization,
+/// which darkens the edges of the image
+/// so that it looks more like hand-drawn pixel art.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Outline {
+    /// Minimum edge strength (Sobel gradient magnitude of the luma)
+    /// for a pixel to be outlined
+    pub threshold: u32,
+    /// Thickness of the outlines, in pixels
+    pub thickness: u32,
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Outline {
+            threshold: 128,
+            thickness: 1,
+        }
+    }
+}
+
+/// How much of the original intensity is kept in outlined pixels
+const OUTLINE_INTENSITY: f32 = 0.25;
+
+impl Outline {
+    /// Apply the outline stylization to an image.
+    pub fn apply(&self, image: &mut RgbImage) {
+        let (w, h) = image.dimensions();
+        let luma: Vec<i32> = image
+            .pixels()
+            .map(|Rgb([r, g, b])| {
+                (i32::from(*r) * 299 + i32::from(*g) * 587 + i32::from(*b) * 114) / 1000
+            })
+            .collect();
+        let at = |x: i64, y: i64| {
+            let x = x.clamp(0, i64::from(w) - 1) as usize;
+            let y = y.clamp(0, i64::from(h) - 1) as usize;
+            luma[y * w as usize + x]
+        };
+
+        let mut edges = vec![false; luma.len()];
+        for y in 0..i64::from(h) {
+            for x in 0..i64::from(w) {
+                let gx = at(x + 1, y - 1) + 2 * at(x + 1, y) + at(x + 1, y + 1)
+                    - at(x - 1, y - 1)
+                    - 2 * at(x - 1, y)
+                    - at(x - 1, y + 1);
+                let gy = at(x - 1, y + 1) + 2 * at(x, y + 1) + at(x + 1, y + 1)
+                    - at(x - 1, y - 1)
+                    - 2 * at(x, y - 1)
+                    - at(x + 1, y - 1);
+                let magnitude = f64::from(gx * gx + gy * gy).sqrt();
+                edges[(y * i64::from(w) + x) as usize] = magnitude >= f64::from(self.threshold);
+            }
+        }
+
+        // edges are one pixel thick on each side of the transition,
+        // grow them for thicker outlines
+        let radius = i64::from(self.thickness.saturating_sub(1));
+        for (x, y, Rgb(pixel)) in image.enumerate_pixels_mut() {
+            let (x, y) = (i64::from(x), i64::from(y));
+            let outlined = (-radius..=radius).any(|dy| {
+                (-radius..=radius).any(|dx| {
+                    let (sx, sy) = (x + dx, y + dy);
+                    sx >= 0
+                        && sy >= 0
+                        && sx < i64::from(w)
+                        && sy < i64::from(h)
+                        && edges[(sy * i64::from(w) + sx) as usize]
+                })
+            });
+            if outlined {
+                for c in pixel.iter_mut() {
+                    *c = (f32::from(*c) * OUTLINE_INTENSITY).round() as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Options for shifting the image colors
+/// towards the gamut of the target palette.
+///
+/// Unset fields take the defaults tuned for the color standard
+/// (see [`GamutShift::defaults`]).
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct GamutShift {
+    /// Saturation multiplier (1 keeps the original saturation)
+    pub saturation: Option<f32>,
+    /// How much each hue is pulled towards the nearest palette hue,
+    /// from 0 (not at all) to 1 (all the way)
+    pub hue_pull: Option<f32>,
+}
+
+impl GamutShift {
+    /// The saturation multiplier and hue pull tuned for a color standard.
+    pub fn defaults(standard: ColorStandard) -> (f32, f32) {
+        match standard {
+            ColorStandard::CgaMode4High1 => (1.5, 0.6),
+            ColorStandard::CgaMode4 => (1.4, 0.3),
+            ColorStandard::FullCga => (1.25, 0.3),
+            ColorStandard::FullEga => (1.1, 0.15),
+            ColorStandard::CgaComposite => (1.25, 0.),
+            ColorStandard::C64Hires => (1.2, 0.2),
+            ColorStandard::C64Multicolor => (1.2, 0.2),
+            ColorStandard::Ega16 => (1.1, 0.15),
+            ColorStandard::Nes => (1.1, 0.1),
+            ColorStandard::NesStrict => (1.1, 0.1),
+            ColorStandard::AppleII => (1.2, 0.2),
+            ColorStandard::MsxScreen2 => (1.2, 0.2),
+            ColorStandard::Tandy => (1.25, 0.3),
+            ColorStandard::TandyLow => (1.25, 0.3),
+            ColorStandard::ColorPlus => (1.25, 0.3),
+            ColorStandard::ColorPlusHigh => (1.4, 0.3),
+            ColorStandard::BlackWhite
+            | ColorStandard::True24Bit
+            | ColorStandard::Vga18Bit
+            | ColorStandard::Vga16Bit
+            | ColorStandard::Vga256
+            | ColorStandard::Mono
+            | ColorStandard::Hercules
+            | ColorStandard::GameBoy
+            | ColorStandard::Amiga
+            | ColorStandard::AmigaEhb
+            | ColorStandard::AmigaHam
+            | ColorStandard::AtariSt => (1., 0.),
+        }
+    }
+
+    /// The colors which the image should be shifted towards
+    /// when targeting a color standard.
+    pub fn target_colors(standard: ColorStandard) -> Vec<[u8; 3]> {
+        match standard {
+            ColorStandard::CgaMode4 | ColorStandard::ColorPlusHigh => [
+                CGA_MODE4_0_LOW,
+                CGA_MODE4_0_HIGH,
+                CGA_MODE4_1_LOW,
+                CGA_MODE4_1_HIGH,
+            ]
+            .concat(),
+            ColorStandard::CgaMode4High1 => CGA_MODE4_1_HIGH.to_vec(),
+            standard => standard.palette().map(<[_]>::to_vec).unwrap_or_default(),
+        }
+    }
+
+    /// Apply the shift to an image,
+    /// towards the given target colors.
+    ///
+    /// `default` provides the saturation multiplier and hue pull
+    /// for the fields which are not set.
+    pub fn apply(&self, image: &mut RgbImage, target: &[[u8; 3]], default: (f32, f32)) {
+        let saturation = self.saturation.unwrap_or(default.0);
+        let hue_pull = self.hue_pull.unwrap_or(default.1).clamp(0., 1.);
+
+        // only reasonably saturated colors have a meaningful hue
+        let target_hues: Vec<f32> = target
+            .iter()
+            .map(|&c| rgb_to_hsv(c))
+            .filter(|&(_, s, v)| s >= 0.25 && v >= 0.2)
+            .map(|(h, _, _)| h)
+            .collect();
+
+        for Rgb(pixel) in image.pixels_mut() {
+            let (mut h, s, v) = rgb_to_hsv(*pixel);
+            if hue_pull > 0. {
+                if let Some(delta) = target_hues
+                    .iter()
+                    .map(|&t| hue_delta(h, t))
+                    .min_by(|a, b| a.abs().partial_cmp(&b.abs()).unwrap())
+                {
+                    h = (h + delta * hue_pull).rem_euclid(360.);
+                }
+            }
+            let s = (s * saturation).clamp(0., 1.);
+            *pixel = hsv_to_rgb(h, s, v);
+        }
+    }
+}
+
+/// The fraction of samples ignored at each end of the histogram
+/// when stretching the contrast of an image,
+/// so that a few outliers do not prevent the stretch.
+const AUTO_CONTRAST_CLIP: f32 = 0.005;
+
+/// Stretch the contrast of an image linearly
+/// so that it covers the full range of intensities.
+///
+/// The same stretch is applied to all channels,
+/// which keeps the hues of the image.
+pub fn auto_contrast(image: &mut RgbImage) {
+    let mut histogram = [0_u64; 256];
+    for &sample in image.as_raw() {
+        histogram[usize::from(sample)] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    let clip = (total as f32 * AUTO_CONTRAST_CLIP) as u64;
+
+    let mut acc = 0;
+    let low = histogram
+        .iter()
+        .position(|&n| {
+            acc += n;
+            acc > clip
+        })
+        .unwrap_or(0);
+    let mut acc = 0;
+    let high = 255
+        - histogram
+            .iter()
+            .rev()
+            .position(|&n| {
+                acc += n;
+                acc > clip
+            })
+            .unwrap_or(0);
+    if high <= low {
+        return;
+    }
+
+    let (low, high) = (low as f32, high as f32);
+    let lut: Vec<u8> = (0..=255_u8)
+        .map(|v| {
+            ((f32::from(v) - low) * 255. / (high - low))
+                .round()
+                .clamp(0., 255.) as u8
+        })
+        .collect();
+    for sample in image.iter_mut() {
+        *sample = lut[usize::from(*sample)];
+    }
+}
+
+/// Equalize the histogram of an image,
+/// so that its brightness levels are evenly distributed.
+///
+/// Only the value component (as in HSV) is equalized,
+/// which keeps the hue and saturation of each pixel.
+pub fn equalize(image: &mut RgbImage) {
+    let mut histogram = [0_u64; 256];
+    for Rgb([r, g, b]) in image.pixels() {
+        histogram[usize::from(*r.max(g).max(b))] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    let first = histogram.iter().copied().find(|&n| n > 0).unwrap_or(0);
+    if total == first {
+        // a single brightness level, nothing to spread
+        return;
+    }
+
+    let mut acc = 0;
+    let lut: Vec<f32> = histogram
+        .iter()
+        .map(|&n| {
+            acc += n;
+            acc.saturating_sub(first) as f32 / (total - first) as f32
+        })
+        .collect();
+    for Rgb(pixel) in image.pixels_mut() {
+        let (h, s, v) = rgb_to_hsv(*pixel);
+        let v = lut[(v * 255.).round() as usize];
+        *pixel = hsv_to_rgb(h, s, v);
+    }
+}
+
+/// Remap the intensities of an image
+/// so that `black` becomes 0 and `white` becomes 255,
+/// clipping anything outside of that range.
+pub fn levels(image: &mut RgbImage, black: u8, white: u8) {
+    if white <= black || (black, white) == (0, 255) {
+        return;
+    }
+    let (black, white) = (f32::from(black), f32::from(white));
+    let lut: Vec<u8> = (0..=255_u8)
+        .map(|v| {
+            ((f32::from(v) - black) * 255. / (white - black))
+                .round()
+                .clamp(0., 255.) as u8
+        })
+        .collect();
+    for sample in image.iter_mut() {
+        *sample = lut[usize::from(*sample)];
+    }
+}
+
+/// Reduce each channel of an image to the given number of evenly spaced levels
+/// (between 2 and 256).
+pub fn posterize(image: &mut RgbImage, levels: u16) {
+    let levels = f32::from(levels.clamp(2, 256));
+    let steps = levels - 1.;
+    for sample in image.iter_mut() {
+        let level = (f32::from(*sample) * steps / 255.).round();
+        *sample = (level * 255. / steps).round() as u8;
+    }
+}
+
+/// Estimate which regions of an image draw the most attention,
+/// for weighting the palette optimization.
+///
+/// This is a frequency-tuned saliency map:
+/// the distance of each (slightly blurred) pixel
+/// to the mean color of the image,
+/// weighted towards the center of the image
+/// and normalized to the full range.
+pub fn saliency(image: &RgbImage) -> GrayImage {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return GrayImage::new(w, h);
+    }
+    let blurred = image::imageops::blur(image, 1.);
+    let n = f64::from(w) * f64::from(h);
+    let mut mean = [0_f64; 3];
+    for Rgb(p) in blurred.pixels() {
+        for (m, &c) in mean.iter_mut().zip(p) {
+            *m += f64::from(c) / n;
+        }
+    }
+
+    let (cx, cy) = (f64::from(w) / 2., f64::from(h) / 2.);
+    let radius = cx.hypot(cy);
+    let values: Vec<f64> = blurred
+        .enumerate_pixels()
+        .map(|(x, y, Rgb(p))| {
+            let dist = mean
+                .iter()
+                .zip(p)
+                .map(|(&m, &c)| (f64::from(c) - m).powi(2))
+                .sum::<f64>()
+                .sqrt();
+            // subjects tend to be near the center
+            let center = (f64::from(x) + 0.5 - cx).hypot(f64::from(y) + 0.5 - cy) / radius;
+            dist * (1. - 0.5 * center)
+        })
+        .collect();
+
+    let max = values.iter().cloned().fold(0., f64::max);
+    let scale = if max > 0. { 255. / max } else { 0. };
+    GrayImage::from_fn(w, h, |x, y| {
+        Luma([(values[(y * w + x) as usize] * scale).round() as u8])
+    })
+}
+
+/// The signed difference from hue `from` to hue `to`, in degrees,
+/// in the range [-180, 180].
+fn hue_delta(from: f32, to: f32) -> f32 {
+    let d = (to - from).rem_euclid(360.);
+    if d > 180. {
+        d - 360.
+    } else {
+        d
+    }
+}
+
+/// Convert an RGB color to HSV
+/// (hue in degrees, saturation and value between 0 and 1).
+pub(crate) fn rgb_to_hsv([r, g, b]: [u8; 3]) -> (f32, f32, f32) {
+    let (r, g, b) = (
+        f32::from(r) / 255.,
+        f32::from(g) / 255.,
+        f32::from(b) / 255.,
+    );
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+    let h = if chroma == 0. {
+        0.
+    } else if max == r {
+        60. * ((g - b) / chroma).rem_euclid(6.)
+    } else if max == g {
+        60. * ((b - r) / chroma + 2.)
+    } else {
+        60. * ((r - g) / chroma + 4.)
+    };
+    let s = if max == 0. { 0. } else { chroma / max };
+    (h, s, max)
+}
+
+/// Convert an HSV color
+/// (hue in degrees, saturation and value between 0 and 1)
+/// to RGB.
+pub(crate) fn hsv_to_rgb(h: f32, s: f32, v: f32) -> [u8; 3] {
+    let chroma = v * s;
+    let h = h.rem_euclid(360.) / 60.;
+    let x = chroma * (1. - (h.rem_euclid(2.) - 1.).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    };
+    let m = v - chroma;
+    let to_u8 = |c: f32| ((c + m) * 255.).round().max(0.).min(255.) as u8;
+    [to_u8(r), to_u8(g), to_u8(b)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{auto_contrast, hsv_to_rgb, posterize, rgb_to_hsv, Denoise};
+    use image::RgbImage;
+
+    #[test]
+    fn test_hsv_round_trip() {
+        for &c in &[
+            [0, 0, 0],
+            [255, 255, 255],
+            [0x55, 0xFF, 0xFF],
+            [0xFF, 0x55, 0xFF],
+            [0xAA, 0x55, 0],
+            [12, 200, 99],
+        ] {
+            let (h, s, v) = rgb_to_hsv(c);
+            assert_eq!(hsv_to_rgb(h, s, v), c);
+        }
+    }
+
+    #[test]
+    fn test_auto_contrast() {
+        let mut image = RgbImage::from_raw(2, 1, vec![64, 64, 96, 128, 160, 192]).unwrap();
+        auto_contrast(&mut image);
+        assert_eq!(image.as_raw(), &[0, 0, 64, 128, 191, 255]);
+    }
+
+    #[test]
+    fn test_median_removes_speckle() {
+        let mut image = RgbImage::from_pixel(3, 3, image::Rgb([10, 20, 30]));
+        image.put_pixel(1, 1, image::Rgb([255, 255, 255]));
+        let out = Denoise::Median.apply(&image);
+        assert!(out.pixels().all(|p| p.0 == [10, 20, 30]));
+    }
+
+    #[test]
+    fn test_posterize() {
+        let mut image = RgbImage::from_raw(2, 1, vec![0, 40, 100, 128, 200, 255]).unwrap();
+        posterize(&mut image, 3);
+        assert_eq!(image.as_raw(), &[0, 0, 128, 128, 255, 255]);
+    }
+}
@@ -0,0 +1,312 @@
+//! Rendering of images directly in the terminal,
+//! for quick previews of a conversion.
+//!
+//! Terminals with graphics support show the image pixel by pixel,
+//! with the Kitty graphics protocol or Sixel.
+//! Any other terminal with true color support
+//! can show it with half block characters,
+//! two pixels per character cell.
+use crate::color::IndexedImage;
+use image::imageops::{resize, FilterType};
+use image::{Rgb, RgbImage};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// The number of base64 bytes in each chunk of a Kitty graphics command
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Enumeration of ways to show an image in the terminal.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum PreviewMode {
+    /// Pick the best mode supported by the terminal,
+    /// going by its environment variables
+    #[default]
+    Auto,
+    /// Sixel graphics, up to 256 colors
+    Sixel,
+    /// The Kitty graphics protocol
+    Kitty,
+    /// True color half block characters
+    Blocks,
+}
+
+impl PreviewMode {
+    /// Resolve [`PreviewMode::Auto`] to the best mode
+    /// which the terminal is known to support
+    /// according to the `TERM`, `TERM_PROGRAM` and `KITTY_WINDOW_ID`
+    /// environment variables.
+    /// Other modes are returned as is.
+    pub fn detect(self) -> PreviewMode {
+        if self != PreviewMode::Auto {
+            return self;
+        }
+        let term = std::env::var("TERM").unwrap_or_default();
+        let program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+        if std::env::var_os("KITTY_WINDOW_ID").is_some()
+            || term == "xterm-kitty"
+            || term == "xterm-ghostty"
+            || program == "WezTerm"
+        {
+            PreviewMode::Kitty
+        } else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") {
+            PreviewMode::Sixel
+        } else {
+            PreviewMode::Blocks
+        }
+    }
+
+    /// Render an image as a string to write to the terminal.
+    ///
+    /// With half blocks, the image is scaled down
+    /// to fit in `columns` character cells.
+    /// Images with more than 256 colors cannot be shown with Sixel,
+    /// so they are rendered with half blocks instead.
+    pub fn render(self, image: &RgbImage, columns: u32) -> String {
+        match self.detect() {
+            PreviewMode::Kitty => kitty(image),
+            PreviewMode::Sixel => match IndexedImage::from_image(image) {
+                Some(indexed) => sixel(&indexed),
+                None => half_blocks(&fit_width(image, columns)),
+            },
+            _ => half_blocks(&fit_width(image, columns)),
+        }
+    }
+
+    /// Render an indexed image as a string to write to the terminal,
+    /// like [`PreviewMode::render`],
+    /// but with Sixel keeping the palette indices of the image.
+    pub fn render_indexed(self, image: &IndexedImage, columns: u32) -> String {
+        match self.detect() {
+            PreviewMode::Sixel => sixel(image),
+            mode => mode.render(&image.to_image(), columns),
+        }
+    }
+}
+
+impl std::fmt::Display for PreviewMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreviewMode::Auto => f.write_str("auto"),
+            PreviewMode::Sixel => f.write_str("sixel"),
+            PreviewMode::Kitty => f.write_str("kitty"),
+            PreviewMode::Blocks => f.write_str("blocks"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`PreviewMode`] from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct PreviewModeParseError;
+
+impl std::fmt::Display for PreviewModeParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid preview mode, should be \"auto\", \"sixel\", \"kitty\" or \"blocks\"")
+    }
+}
+
+impl std::error::Error for PreviewModeParseError {}
+
+impl FromStr for PreviewMode {
+    type Err = PreviewModeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(PreviewMode::Auto),
+            "sixel" => Ok(PreviewMode::Sixel),
+            "kitty" => Ok(PreviewMode::Kitty),
+            "blocks" | "halfblocks" | "half-blocks" => Ok(PreviewMode::Blocks),
+            _ => Err(PreviewModeParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for PreviewMode {
+    type Error = PreviewModeParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PreviewMode> for String {
+    fn from(mode: PreviewMode) -> Self {
+        mode.to_string()
+    }
+}
+
+/// Scale an image down with nearest neighbor sampling
+/// so that it is at most `width` pixels wide.
+fn fit_width(image: &RgbImage, width: u32) -> RgbImage {
+    let (w, h) = image.dimensions();
+    if w <= width || w == 0 {
+        return image.clone();
+    }
+    let height = (u64::from(h) * u64::from(width) / u64::from(w)).max(1) as u32;
+    resize(image, width.max(1), height, FilterType::Nearest)
+}
+
+/// Render an image with true color half block characters,
+/// each showing one pixel above the other.
+pub fn half_blocks(image: &RgbImage) -> String {
+    let mut out = String::new();
+    for y in (0..image.height()).step_by(2) {
+        for x in 0..image.width() {
+            let Rgb([r, g, b]) = *image.get_pixel(x, y);
+            let _ = write!(out, "\x1b[38;2;{};{};{}m", r, g, b);
+            if y + 1 < image.height() {
+                let Rgb([r, g, b]) = *image.get_pixel(x, y + 1);
+                let _ = write!(out, "\x1b[48;2;{};{};{}m", r, g, b);
+            } else {
+                out.push_str("\x1b[49m");
+            }
+            out.push('▀');
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+/// Render an indexed image with Sixel graphics.
+pub fn sixel(image: &IndexedImage) -> String {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let mut out = format!("\x1bPq\"1;1;{};{}", width, height);
+    for (i, color) in image.palette.iter().enumerate() {
+        let percent = |c: u8| (u32::from(c) * 100 + 127) / 255;
+        let _ = write!(
+            out,
+            "#{};2;{};{};{}",
+            i,
+            percent(color.r),
+            percent(color.g),
+            percent(color.b)
+        );
+    }
+    for band in (0..height).step_by(6) {
+        let rows = (height - band).min(6);
+        let band_indices = &image.indices[band * width..(band + rows) * width];
+        for (i, _) in image.palette.iter().enumerate() {
+            if !band_indices.iter().any(|&p| usize::from(p) == i) {
+                continue;
+            }
+            let _ = write!(out, "#{}", i);
+            // the sixel of each column, as 6 bits from the top row down
+            let sixels = (0..width).map(|x| {
+                (0..rows)
+                    .filter(|&dy| usize::from(band_indices[dy * width + x]) == i)
+                    .fold(0_u8, |bits, dy| bits | (1 << dy))
+            });
+            push_runs(&mut out, sixels);
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Write sixels to the output,
+/// collapsing runs of the same sixel with the repeat introducer.
+fn push_runs(out: &mut String, sixels: impl Iterator<Item = u8>) {
+    let mut sixels = sixels.peekable();
+    while let Some(bits) = sixels.next() {
+        let mut count = 1;
+        while sixels.peek() == Some(&bits) {
+            sixels.next();
+            count += 1;
+        }
+        let c = char::from(b'?' + bits);
+        if count > 3 {
+            let _ = write!(out, "!{}{}", count, c);
+        } else {
+            (0..count).for_each(|_| out.push(c));
+        }
+    }
+}
+
+/// Render an image with the Kitty graphics protocol,
+/// as raw RGB data sent in chunks.
+pub fn kitty(image: &RgbImage) -> String {
+    let data = base64(image.as_raw());
+    let mut out = String::new();
+    let chunks = data.as_bytes().chunks(KITTY_CHUNK_SIZE);
+    let last = chunks.len().saturating_sub(1);
+    for (i, chunk) in chunks.enumerate() {
+        let more = if i == last { 0 } else { 1 };
+        if i == 0 {
+            let _ = write!(
+                out,
+                "\x1b_Gf=24,a=T,q=2,s={},v={},m={};",
+                image.width(),
+                image.height(),
+                more
+            );
+        } else {
+            let _ = write!(out, "\x1b_Gm={};", more);
+        }
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push_str("\x1b\\");
+    }
+    out.push('\n');
+    out
+}
+
+/// Encode bytes in base64, with padding.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (u32::from(bytes[0]) << 16) | (u32::from(bytes[1]) << 8) | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(char::from(ALPHABET[((n >> (18 - 6 * i)) & 0x3F) as usize]));
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64, half_blocks, sixel};
+    use crate::color::IndexedImage;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_preview() {
+        assert_eq!(base64(b"retro"), "cmV0cm8=");
+        assert_eq!(base64(b"img"), "aW1n");
+
+        let image = RgbImage::from_fn(5, 3, |x, _| {
+            if x < 4 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        let blocks = half_blocks(&image);
+        assert_eq!(blocks.lines().count(), 2);
+        assert_eq!(blocks.matches('▀').count(), 10);
+
+        let indexed = IndexedImage::from_image(&image).unwrap();
+        assert_eq!(
+            sixel(&indexed),
+            "\x1bPq\"1;1;5;3#0;2;0;0;0#1;2;100;100;100#0!4F?$#1!4?F$-\x1b\\"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Progress reporting of long conversions.
+//!
+//! A [`ProgressHook`] given to the pipeline (or in the color options)
+//! is called as each stage of the conversion advances,
+//! so that applications can show a progress bar.
+//!
+//! ```
+//! use retroimg::progress::{ProgressHook, Stage};
+//! use retroimg::Pipeline;
+//!
+//! let pipeline = Pipeline::default().with_progress(ProgressHook::new(|stage, done| {
+//!     eprintln!("{}: {:.0}%", stage, done * 100.);
+//! }));
+//! # let _ = pipeline;
+//! ```
+use std::sync::Arc;
+
+/// A stage of the conversion.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Stage {
+    /// Cropping, resizing and preprocessing
+    Reduce,
+    /// Palette optimization
+    Palette,
+    /// Mapping the pixels to the palette
+    Dither,
+    /// Scaling up to the output resolution and applying effects
+    Expand,
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Reduce => f.write_str("reduce"),
+            Stage::Palette => f.write_str("palette"),
+            Stage::Dither => f.write_str("dither"),
+            Stage::Expand => f.write_str("expand"),
+        }
+    }
+}
+
+/// A callback receiving the progress of a conversion:
+/// the current stage and the fraction of it done, from 0 to 1.
+///
+/// Color depths which try several palettes
+/// report each stage once per candidate,
+/// scaled to the whole of the stage.
+/// With the `rayon` feature,
+/// the progress of concurrent candidates may interleave,
+/// so it does not always increase.
+#[derive(Clone)]
+pub struct ProgressHook(Arc<dyn Fn(Stage, f32) + Send + Sync>);
+
+impl ProgressHook {
+    /// Create a progress hook from a callback.
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(Stage, f32) + Send + Sync + 'static,
+    {
+        ProgressHook(Arc::new(callback))
+    }
+
+    /// Report the progress of a stage.
+    pub fn report(&self, stage: Stage, done: f32) {
+        (self.0)(stage, done.clamp(0., 1.))
+    }
+
+    /// A hook for the `index`-th of `count` equal parts of the work,
+    /// reporting to this hook the progress of the whole.
+    pub fn part(&self, index: usize, count: usize) -> ProgressHook {
+        let hook = self.clone();
+        let count = count.max(1) as f32;
+        ProgressHook::new(move |stage, done| hook.report(stage, (index as f32 + done) / count))
+    }
+}
+
+impl std::fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressHook")
+    }
+}
+
+/// Hooks are equal only if they are the same callback.
+impl PartialEq for ProgressHook {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(
+            Arc::as_ptr(&self.0) as *const u8,
+            Arc::as_ptr(&other.0) as *const u8,
+        )
+    }
+}
+
+impl Eq for ProgressHook {}
+
+/// Report the progress of a stage to the hook, if any.
+pub(crate) fn report(hook: Option<&ProgressHook>, stage: Stage, done: f32) {
+    if let Some(hook) = hook {
+        hook.report(stage, done);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProgressHook, Stage};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_progress_parts() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let hook = {
+            let events = Arc::clone(&events);
+            ProgressHook::new(move |stage, done| events.lock().unwrap().push((stage, done)))
+        };
+        hook.part(1, 4).report(Stage::Palette, 0.5);
+        hook.part(3, 4).report(Stage::Dither, 1.);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [(Stage::Palette, 0.375), (Stage::Dither, 1.)]
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Text mode art,
+//! made of code page 437 block and shade characters
+//! in the 16 colors of the IBM PC text modes.
+//!
+//! Each character cell of the screen is converted on its own,
+//! choosing the glyph, foreground and background color
+//! which best approximate the pixels of the cell.
+//! Shade characters are treated as an even mix
+//! of the foreground and background colors.
+//!
+//! The result can be saved as an ANSI art file (`.ans`),
+//! printed to a terminal with ANSI escape codes,
+//! or rendered back into an image.
+use crate::color::cga::CGA_4BIT;
+use image::imageops::{resize, FilterType};
+use image::{Rgb, RgbImage};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// The code page 437 glyphs used for text mode art
+const GLYPHS: [u8; 9] = [
+    0x20, // space
+    0xDB, // full block
+    0xDF, // upper half block
+    0xDC, // lower half block
+    0xDD, // left half block
+    0xDE, // right half block
+    0xB0, // light shade
+    0xB1, // medium shade
+    0xB2, // dark shade
+];
+
+/// The ANSI color number of each color of the IBM PC palette,
+/// which orders red and blue the other way around
+const ANSI_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+/// Options of the text mode conversion.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct TextOptions {
+    /// Number of character columns
+    pub columns: u32,
+    /// Number of character rows
+    pub rows: u32,
+    /// Size of a character cell in pixels, as `[width, height]`
+    pub cell: [u32; 2],
+    /// Allow the 8 bright colors in the background
+    /// (iCE colors, instead of blinking text)
+    pub ice_colors: bool,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions {
+            columns: 80,
+            rows: 25,
+            cell: [8, 16],
+            ice_colors: false,
+        }
+    }
+}
+
+impl TextOptions {
+    /// The size of the screen in pixels.
+    pub fn pixel_size(&self) -> (u32, u32) {
+        (self.columns * self.cell[0], self.rows * self.cell[1])
+    }
+}
+
+/// A character cell of a text mode screen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TextCell {
+    /// The code page 437 character
+    pub glyph: u8,
+    /// The foreground color, as an index into the 16 color palette
+    pub fg: u8,
+    /// The background color, as an index into the 16 color palette
+    pub bg: u8,
+}
+
+/// A screen of text mode art.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextScreen {
+    /// Number of character columns
+    pub columns: u32,
+    /// Number of character rows
+    pub rows: u32,
+    /// Size of a character cell in pixels, as `[width, height]`
+    pub cell: [u32; 2],
+    /// The character cells, in row major order
+    pub cells: Vec<TextCell>,
+}
+
+impl TextScreen {
+    /// Convert an image into text mode art.
+    ///
+    /// The image is resized to the pixel size of the screen
+    /// if its dimensions differ.
+    pub fn from_image(image: &RgbImage, options: &TextOptions) -> Self {
+        let (width, height) = options.pixel_size();
+        let resized;
+        let image = if image.dimensions() == (width, height) {
+            image
+        } else {
+            resized = resize(image, width, height, FilterType::Triangle);
+            &resized
+        };
+        let [cell_width, cell_height] = options.cell;
+        let backgrounds = if options.ice_colors { 16 } else { 8 };
+        let cells = (0..options.rows)
+            .flat_map(|row| (0..options.columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let stats = CellStats::new(
+                    image,
+                    column * cell_width,
+                    row * cell_height,
+                    cell_width,
+                    cell_height,
+                );
+                stats.best_cell(backgrounds)
+            })
+            .collect();
+        TextScreen {
+            columns: options.columns,
+            rows: options.rows,
+            cell: options.cell,
+            cells,
+        }
+    }
+
+    /// Render the screen into an image,
+    /// drawing the shade characters as dither patterns.
+    pub fn render(&self) -> RgbImage {
+        let [cell_width, cell_height] = self.cell;
+        RgbImage::from_fn(
+            self.columns * cell_width,
+            self.rows * cell_height,
+            |x, y| {
+                let cell = self.cells[((y / cell_height) * self.columns + x / cell_width) as usize];
+                let lit = glyph_pixel(
+                    cell.glyph,
+                    x % cell_width,
+                    y % cell_height,
+                    cell_width,
+                    cell_height,
+                );
+                Rgb(CGA_4BIT[usize::from(if lit { cell.fg } else { cell.bg })])
+            },
+        )
+    }
+
+    /// Write the screen as an ANSI art file:
+    /// code page 437 characters with SGR escape codes for the colors,
+    /// where bright backgrounds are expressed with the blink attribute.
+    pub fn to_ans(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for row in self.cells.chunks(self.columns.max(1) as usize) {
+            let mut last = None;
+            for cell in row {
+                if last != Some((cell.fg, cell.bg)) {
+                    let mut sgr = String::from("\x1b[0");
+                    if cell.fg >= 8 {
+                        sgr.push_str(";1");
+                    }
+                    if cell.bg >= 8 {
+                        sgr.push_str(";5");
+                    }
+                    let _ = write!(
+                        sgr,
+                        ";3{};4{}m",
+                        ANSI_COLORS[usize::from(cell.fg % 8)],
+                        ANSI_COLORS[usize::from(cell.bg % 8)]
+                    );
+                    out.extend_from_slice(sgr.as_bytes());
+                    last = Some((cell.fg, cell.bg));
+                }
+                out.push(cell.glyph);
+            }
+            out.extend_from_slice(b"\x1b[0m\r\n");
+        }
+        // end of file marker for DOS programs
+        out.push(0x1A);
+        out
+    }
+
+    /// Write the screen as text for a terminal with true color support,
+    /// with the Unicode equivalents of the code page 437 characters.
+    pub fn to_terminal(&self) -> String {
+        let mut out = String::new();
+        for row in self.cells.chunks(self.columns.max(1) as usize) {
+            for cell in row {
+                let [r, g, b] = CGA_4BIT[usize::from(cell.fg)];
+                let _ = write!(out, "\x1b[38;2;{};{};{}m", r, g, b);
+                let [r, g, b] = CGA_4BIT[usize::from(cell.bg)];
+                let _ = write!(out, "\x1b[48;2;{};{};{}m", r, g, b);
+                out.push(glyph_char(cell.glyph));
+            }
+            out.push_str("\x1b[0m\n");
+        }
+        out
+    }
+}
+
+/// The Unicode character of a glyph.
+fn glyph_char(glyph: u8) -> char {
+    match glyph {
+        0xDB => '█',
+        0xDF => '▀',
+        0xDC => '▄',
+        0xDD => '▌',
+        0xDE => '▐',
+        0xB0 => '░',
+        0xB1 => '▒',
+        0xB2 => '▓',
+        _ => ' ',
+    }
+}
+
+/// Whether the pixel of a glyph at the given position within the cell
+/// is drawn in the foreground color.
+fn glyph_pixel(glyph: u8, x: u32, y: u32, width: u32, height: u32) -> bool {
+    let light = (x + 2 * (y % 2)) % 4 == 0;
+    match glyph {
+        0xDB => true,
+        0xDF => y < height / 2,
+        0xDC => y >= height / 2,
+        0xDD => x < width / 2,
+        0xDE => x >= width / 2,
+        0xB0 => light,
+        0xB1 => (x + y) % 2 == 0,
+        0xB2 => !light,
+        _ => false,
+    }
+}
+
+/// Statistics of the pixels in each quadrant of a character cell,
+/// from which the error of any glyph made of halves
+/// and any pair of colors follows directly.
+struct CellStats {
+    /// Number of pixels, sum of the colors and sum of the squared samples
+    /// in the top left, top right, bottom left and bottom right quadrants
+    quadrants: [(f64, [f64; 3], f64); 4],
+}
+
+impl CellStats {
+    fn new(image: &RgbImage, left: u32, top: u32, width: u32, height: u32) -> Self {
+        let mut quadrants = [(0., [0.; 3], 0.); 4];
+        for y in 0..height {
+            for x in 0..width {
+                let q = usize::from(y >= height / 2) * 2 + usize::from(x >= width / 2);
+                let Rgb(pixel) = *image.get_pixel(left + x, top + y);
+                let (n, sum, squares) = &mut quadrants[q];
+                *n += 1.;
+                for (s, &c) in sum.iter_mut().zip(&pixel) {
+                    let c = f64::from(c);
+                    *s += c;
+                    *squares += c * c;
+                }
+            }
+        }
+        CellStats { quadrants }
+    }
+
+    /// The squared error of painting the given quadrants with one color.
+    fn error(&self, quadrants: &[usize], color: [f64; 3]) -> f64 {
+        quadrants
+            .iter()
+            .map(|&q| {
+                let (n, sum, squares) = self.quadrants[q];
+                let dot: f64 = sum.iter().zip(&color).map(|(s, c)| s * c).sum();
+                let norm: f64 = color.iter().map(|c| c * c).sum();
+                squares - 2. * dot + n * norm
+            })
+            .sum()
+    }
+
+    /// The glyph and colors which approximate the cell best,
+    /// with the background among the first `backgrounds` colors.
+    fn best_cell(&self, backgrounds: u8) -> TextCell {
+        let palette = CGA_4BIT.map(|c| c.map(f64::from));
+        let mix = |fg: usize, bg: usize, amount: f64| {
+            let mut color = [0.; 3];
+            for (i, c) in color.iter_mut().enumerate() {
+                *c = palette[fg][i] * amount + palette[bg][i] * (1. - amount);
+            }
+            color
+        };
+        let all = [0, 1, 2, 3];
+        let mut best = (
+            f64::INFINITY,
+            TextCell {
+                glyph: 0x20,
+                fg: 7,
+                bg: 0,
+            },
+        );
+        for &glyph in &GLYPHS {
+            for fg in 0..16_u8 {
+                for bg in 0..backgrounds {
+                    let (f, b) = (usize::from(fg), usize::from(bg));
+                    let error = match glyph {
+                        0x20 => self.error(&all, palette[b]),
+                        0xDB => self.error(&all, palette[f]),
+                        0xDF => self.error(&[0, 1], palette[f]) + self.error(&[2, 3], palette[b]),
+                        0xDC => self.error(&[2, 3], palette[f]) + self.error(&[0, 1], palette[b]),
+                        0xDD => self.error(&[0, 2], palette[f]) + self.error(&[1, 3], palette[b]),
+                        0xDE => self.error(&[1, 3], palette[f]) + self.error(&[0, 2], palette[b]),
+                        0xB0 => self.error(&all, mix(f, b, 0.25)),
+                        0xB1 => self.error(&all, mix(f, b, 0.5)),
+                        _ => self.error(&all, mix(f, b, 0.75)),
+                    };
+                    if error < best.0 {
+                        best = (error, TextCell { glyph, fg, bg });
+                    }
+                }
+            }
+        }
+        best.1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TextOptions, TextScreen};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_text_mode() {
+        let options = TextOptions {
+            columns: 2,
+            rows: 1,
+            ..Default::default()
+        };
+        // bright red over blue, then all bright white
+        let image = RgbImage::from_fn(16, 16, |x, y| match (x < 8, y < 8) {
+            (true, true) => Rgb([0xFF, 0x55, 0x55]),
+            (true, false) => Rgb([0, 0, 0xAA]),
+            (false, _) => Rgb([0xFF, 0xFF, 0xFF]),
+        });
+        let screen = TextScreen::from_image(&image, &options);
+        assert_eq!(screen.cells[0].glyph, 0xDF);
+        assert_eq!((screen.cells[0].fg, screen.cells[0].bg), (12, 1));
+        // bright white is not available in the background
+        assert_eq!((screen.cells[1].glyph, screen.cells[1].fg), (0xDB, 15));
+        assert_eq!(screen.render(), image);
+
+        let ans = screen.to_ans();
+        assert!(ans.starts_with(b"\x1b[0;1;31;44m\xDF"));
+        assert_eq!(ans.last(), Some(&0x1A));
+    }
+}
@@ -0,0 +1,214 @@
+//! Tone mapping of high bit depth images.
+//!
+//! 16-bit and floating point images (such as scans and HDR renders)
+//! hold more shades than 8 bits per sample can,
+//! and floating point images may go beyond the displayable range.
+//! Before entering the pipeline,
+//! they are mapped to 8 bits per sample in linear light,
+//! with an exposure and a tone mapping operator,
+//! rounding each sample to the nearest value rather than truncating it.
+use image::{DynamicImage, Rgba, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Enumeration of tone mapping operators,
+/// which bring the light of high bit depth images to the displayable range.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub enum ToneMap {
+    /// Clip the light above white, keeping the rest as is
+    #[default]
+    Clip,
+    /// Scale the light so that the brightest sample becomes white
+    Normalize,
+    /// The Reinhard operator, compressing highlights smoothly
+    Reinhard,
+    /// An approximation of the ACES filmic curve,
+    /// with more contrast than Reinhard
+    Aces,
+}
+
+impl std::fmt::Display for ToneMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToneMap::Clip => f.write_str("clip"),
+            ToneMap::Normalize => f.write_str("normalize"),
+            ToneMap::Reinhard => f.write_str("reinhard"),
+            ToneMap::Aces => f.write_str("aces"),
+        }
+    }
+}
+
+/// An error returned by a failed attempt at
+/// creating a [`ToneMap`] operator from a string.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ToneMapParseError;
+
+impl std::fmt::Display for ToneMapParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid tone map, should be \"clip\", \"normalize\", \"reinhard\" or \"aces\"")
+    }
+}
+
+impl std::error::Error for ToneMapParseError {}
+
+impl FromStr for ToneMap {
+    type Err = ToneMapParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "clip" => Ok(ToneMap::Clip),
+            "normalize" => Ok(ToneMap::Normalize),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(ToneMapParseError),
+        }
+    }
+}
+
+impl TryFrom<String> for ToneMap {
+    type Error = ToneMapParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ToneMap> for String {
+    fn from(tone_map: ToneMap) -> Self {
+        tone_map.to_string()
+    }
+}
+
+impl ToneMap {
+    /// Map a linear light sample, where 1 is white,
+    /// to the displayable range from 0 to 1.
+    fn apply(self, v: f32) -> f32 {
+        let v = match self {
+            ToneMap::Clip | ToneMap::Normalize => v,
+            ToneMap::Reinhard => v / (1. + v),
+            ToneMap::Aces => (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14),
+        };
+        v.clamp(0., 1.)
+    }
+}
+
+/// Map an image to 8 bits per sample, keeping the alpha channel.
+///
+/// Images with 8 bits per sample are returned as they are.
+/// Other samples are brought to linear light
+/// (floating point samples are linear already),
+/// scaled by 2 to the power of `exposure`,
+/// mapped to the displayable range with the given operator
+/// and encoded back to sRGB.
+pub fn tone_map(image: &DynamicImage, operator: ToneMap, exposure: f32) -> Cow<'_, DynamicImage> {
+    let linear_input = match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => true,
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => false,
+        _ => return Cow::Borrowed(image),
+    };
+    let mut light = image.to_rgba32f();
+    let gain = exposure.exp2();
+    for p in light.pixels_mut() {
+        for c in &mut p.0[..3] {
+            let v = if linear_input { *c } else { decode(*c) };
+            // also clears NaN samples
+            *c = v.max(0.) * gain;
+        }
+    }
+    let scale = match operator {
+        ToneMap::Normalize => {
+            let peak = light
+                .pixels()
+                .flat_map(|p| p.0[..3].iter().copied())
+                .fold(0., f32::max);
+            if peak > 0. {
+                1. / peak
+            } else {
+                1.
+            }
+        }
+        _ => 1.,
+    };
+
+    let to_u8 = |v: f32| (v * 255.).round().clamp(0., 255.) as u8;
+    let out = RgbaImage::from_fn(light.width(), light.height(), |x, y| {
+        let [r, g, b, a] = light.get_pixel(x, y).0;
+        let map = |v: f32| to_u8(encode(operator.apply(v * scale)));
+        Rgba([map(r), map(g), map(b), to_u8(a)])
+    });
+    Cow::Owned(DynamicImage::ImageRgba8(out))
+}
+
+/// Convert an sRGB encoded sample to linear light.
+fn decode(c: f32) -> f32 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a linear light sample to sRGB encoding.
+fn encode(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tone_map, ToneMap};
+    use image::{DynamicImage, ImageBuffer, Rgb, Rgb32FImage, RgbImage};
+
+    #[test]
+    fn test_tone_map() {
+        // 8-bit images are left alone
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 1, Rgb([10, 20, 30])));
+        assert_eq!(*tone_map(&image, ToneMap::Aces, 2.), image);
+
+        // 16-bit samples are rounded, not truncated
+        let image =
+            DynamicImage::ImageRgb16(ImageBuffer::from_pixel(1, 1, Rgb([0x8242, 0, 65535])));
+        let out = tone_map(&image, ToneMap::Clip, 0.).to_rgba8();
+        assert_eq!(out.get_pixel(0, 0).0, [0x82, 0, 255, 255]);
+
+        // light beyond white
+        let hdr = DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgb([4., 4., 4.])
+            } else {
+                Rgb([1., 0.25, 0.])
+            }
+        }));
+        let clipped = tone_map(&hdr, ToneMap::Clip, 0.).to_rgba8();
+        assert_eq!(clipped.get_pixel(0, 0).0, [255, 255, 255, 255]);
+        assert_eq!(clipped.get_pixel(1, 0).0, [255, 137, 0, 255]);
+
+        let normalized = tone_map(&hdr, ToneMap::Normalize, 0.).to_rgba8();
+        assert_eq!(normalized.get_pixel(0, 0).0, [255, 255, 255, 255]);
+        assert_eq!(normalized.get_pixel(1, 0).0[0], 137);
+
+        // highlights are compressed instead of clipped
+        let reinhard = tone_map(&hdr, ToneMap::Reinhard, 0.).to_rgba8();
+        assert!(reinhard.get_pixel(0, 0).0[0] < 255);
+        assert!(reinhard.get_pixel(1, 0).0[0] < reinhard.get_pixel(0, 0).0[0]);
+
+        // an exposure of -2 stops brings 4 down to white
+        let darker = tone_map(&hdr, ToneMap::Clip, -2.).to_rgba8();
+        assert_eq!(darker.get_pixel(0, 0).0, [255, 255, 255, 255]);
+        assert_eq!(darker.get_pixel(1, 0).0[0], 137);
+    }
+}
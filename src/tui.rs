@@ -0,0 +1,419 @@
+//! Interactive terminal interface for tuning the conversion options.
+//!
+//! The converted image is previewed in the best mode
+//! which the terminal supports (see [`PreviewMode::detect`]):
+//! pixel by pixel with Sixel or Kitty graphics,
+//! or else with half-block characters in true color.
+use crate::CONFIG_FILE;
+use crossterm::cursor::MoveTo;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, window_size, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use crossterm::{ExecutableCommand, QueueableCommand};
+use image::RgbImage;
+use ratatui::backend::CrosstermBackend;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Widget};
+use ratatui::{Frame, Terminal};
+use retroimg::color::{ColorStandard, IndexedImage};
+use retroimg::pipeline::{Pipeline, Presets};
+use retroimg::preview::PreviewMode;
+use std::io::{stdout, Write};
+use std::path::Path;
+
+/// The name of the preset saved from the interface
+const PRESET_NAME: &str = "tui";
+
+/// The output height assumed when exporting a pixel ratio
+const EXPORT_HEIGHT: u32 = 1080;
+
+/// Internal resolutions to cycle through (`None` is the source resolution)
+static RESOLUTIONS: &[Option<[u32; 2]>] = &[
+    Some([320, 200]),
+    Some([640, 200]),
+    Some([640, 350]),
+    Some([640, 480]),
+    Some([160, 200]),
+    None,
+];
+
+/// Pixel ratios to cycle through (`None` is square pixels)
+static PIXEL_RATIOS: &[Option<[u32; 2]>] = &[None, Some([5, 6]), Some([4, 5]), Some([1, 2])];
+
+/// Numbers of colors to cycle through
+static NUM_COLORS: &[u32] = &[2, 4, 8, 16, 32, 64, 128, 256];
+
+/// The size of a character cell in pixels,
+/// when the terminal does not report it
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+
+/// The state of the interface.
+struct App {
+    source: RgbImage,
+    pipeline: Pipeline,
+    pixel_ratio: Option<[u32; 2]>,
+    preview: RgbImage,
+    /// how the preview is shown, `Blocks` if the terminal has no graphics
+    /// or the preview has too many colors for Sixel
+    mode: PreviewMode,
+    message: String,
+    exported: Option<String>,
+}
+
+impl App {
+    fn new(source: RgbImage) -> Self {
+        let pipeline = Pipeline {
+            resolution: RESOLUTIONS[0],
+            ..Pipeline::default()
+        };
+        let mut app = App {
+            preview: RgbImage::new(1, 1),
+            mode: PreviewMode::Blocks,
+            source,
+            pipeline,
+            pixel_ratio: None,
+            message: String::new(),
+            exported: None,
+        };
+        app.update();
+        app
+    }
+
+    /// Convert the source image again with the current settings.
+    fn update(&mut self) {
+        self.preview = self
+            .pipeline
+            .run(&self.source)
+            .expect("preview pipeline has no output resizing");
+        self.mode = match PreviewMode::Auto.detect() {
+            PreviewMode::Sixel if IndexedImage::from_image(&self.preview).is_none() => {
+                PreviewMode::Blocks
+            }
+            mode => mode,
+        };
+    }
+
+    /// The width:height ratio of each preview pixel.
+    fn pixel_ratio(&self) -> f64 {
+        self.pixel_ratio
+            .map_or(1., |[w, h]| f64::from(w) / f64::from(h))
+    }
+
+    /// The pipeline as it should be exported,
+    /// including the pixel ratio.
+    fn export_pipeline(&self) -> Pipeline {
+        let mut pipeline = self.pipeline.clone();
+        if let Some(ratio) = self.pixel_ratio {
+            pipeline.output.pixel_ratio = Some(ratio);
+            pipeline.output.height = Some(EXPORT_HEIGHT);
+        }
+        pipeline
+    }
+
+    /// The command line flags equivalent to the current settings.
+    fn cli_flags(&self) -> String {
+        let pipeline = &self.pipeline;
+        let mut flags = format!("-s {}", pipeline.standard);
+        if let Some([w, h]) = pipeline.resolution {
+            flags += &format!(" -R {}x{}", w, h);
+        }
+        if pipeline.no_color_limit {
+            flags += " --no-color-limit";
+        } else {
+            flags += &format!(" -c {}", pipeline.num_colors);
+        }
+        if let Some([w, h]) = self.pixel_ratio {
+            flags += &format!(" -r {}:{} --height {}", w, h, EXPORT_HEIGHT);
+        }
+        flags
+    }
+
+    /// Handle a key press, returns `false` if the application should quit.
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('s') => {
+                self.pipeline.standard = cycle(&ColorStandard::ALL, &self.pipeline.standard, 1)
+            }
+            KeyCode::Char('S') => {
+                self.pipeline.standard = cycle(&ColorStandard::ALL, &self.pipeline.standard, -1)
+            }
+            KeyCode::Char('c') => {
+                self.pipeline.num_colors = cycle(NUM_COLORS, &self.pipeline.num_colors, 1)
+            }
+            KeyCode::Char('C') => {
+                self.pipeline.num_colors = cycle(NUM_COLORS, &self.pipeline.num_colors, -1)
+            }
+            KeyCode::Char('n') => self.pipeline.no_color_limit = !self.pipeline.no_color_limit,
+            KeyCode::Char('r') => {
+                self.pipeline.resolution = cycle(RESOLUTIONS, &self.pipeline.resolution, 1)
+            }
+            KeyCode::Char('R') => {
+                self.pipeline.resolution = cycle(RESOLUTIONS, &self.pipeline.resolution, -1)
+            }
+            KeyCode::Char('p') => {
+                self.pixel_ratio = cycle(PIXEL_RATIOS, &self.pixel_ratio, 1);
+                return true;
+            }
+            KeyCode::Char('e') => {
+                let flags = self.cli_flags();
+                self.message = flags.clone();
+                self.exported = Some(flags);
+                return true;
+            }
+            KeyCode::Char('w') => {
+                self.message = match save_preset(Path::new(CONFIG_FILE), &self.export_pipeline()) {
+                    Ok(()) => format!("Saved preset {} to {}", PRESET_NAME, CONFIG_FILE),
+                    Err(e) => format!("Error: {}", e),
+                };
+                return true;
+            }
+            _ => return true,
+        }
+        self.update();
+        true
+    }
+}
+
+/// Pick the element next to `current` in `values`,
+/// or the first one if it is not there.
+fn cycle<T>(values: &[T], current: &T, step: isize) -> T
+where
+    T: Clone + PartialEq,
+{
+    let len = values.len() as isize;
+    let index = values
+        .iter()
+        .position(|v| v == current)
+        .map(|i| (i as isize + step).rem_euclid(len) as usize)
+        .unwrap_or(0);
+    values[index].clone()
+}
+
+/// Save the pipeline as a preset of the configuration file,
+/// keeping any other presets in it.
+fn save_preset(path: &Path, pipeline: &Pipeline) -> Result<(), Box<dyn std::error::Error>> {
+    let mut presets: Presets = if path.exists() {
+        toml::from_str(&std::fs::read_to_string(path)?)?
+    } else {
+        Presets::default()
+    };
+    presets
+        .presets
+        .insert(PRESET_NAME.to_string(), pipeline.clone());
+    std::fs::write(path, toml::to_string_pretty(&presets)?)?;
+    Ok(())
+}
+
+/// The largest size of an image with the given dimensions and pixel ratio
+/// which fits in `max_w` x `max_h` square pixels.
+fn fit(image: &RgbImage, pixel_ratio: f64, max_w: u32, max_h: u32) -> (u32, u32) {
+    let (iw, ih) = image.dimensions();
+    let aspect = f64::from(iw) * pixel_ratio / f64::from(ih);
+    let (max_w, max_h) = (f64::from(max_w), f64::from(max_h));
+    let (w, h) = if max_w / max_h > aspect {
+        (max_h * aspect, max_h)
+    } else {
+        (max_w, max_w / aspect)
+    };
+    (w.max(1.) as u32, h.max(1.) as u32)
+}
+
+/// A widget showing an image with half-block characters,
+/// scaled to fit the area.
+struct Preview<'a> {
+    image: &'a RgbImage,
+    /// the width:height ratio of each image pixel
+    pixel_ratio: f64,
+}
+
+impl Widget for Preview<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let (iw, ih) = self.image.dimensions();
+        if area.width == 0 || area.height == 0 || iw == 0 || ih == 0 {
+            return;
+        }
+
+        // each cell holds two vertically stacked pixels,
+        // which makes them roughly square
+        let (w, h) = fit(
+            self.image,
+            self.pixel_ratio,
+            u32::from(area.width),
+            u32::from(area.height) * 2,
+        );
+        let scaled = retroimg::expand(self.image, w, h);
+
+        let x0 = area.x + (area.width - w as u16) / 2;
+        let y0 = area.y + (area.height - ((h + 1) / 2) as u16) / 2;
+        for y in (0..h).step_by(2) {
+            for x in 0..w {
+                let top = scaled.get_pixel(x, y).0;
+                let cell = buf.get_mut(x0 + x as u16, y0 + (y / 2) as u16);
+                cell.set_char('▀')
+                    .set_fg(Color::Rgb(top[0], top[1], top[2]));
+                if y + 1 < h {
+                    let bottom = scaled.get_pixel(x, y + 1).0;
+                    cell.set_bg(Color::Rgb(bottom[0], bottom[1], bottom[2]));
+                }
+            }
+        }
+    }
+}
+
+/// Show the preview with terminal graphics in the given area,
+/// which the interface leaves blank for it.
+fn draw_graphics(app: &App, area: Rect) -> Result<(), Box<dyn std::error::Error>> {
+    let (iw, ih) = app.preview.dimensions();
+    if area.width == 0 || area.height == 0 || iw == 0 || ih == 0 {
+        return Ok(());
+    }
+    let size = window_size()?;
+    let (cell_w, cell_h) = if size.width > 0 && size.height > 0 {
+        (
+            u32::from(size.width / size.columns.max(1)).max(1),
+            u32::from(size.height / size.rows.max(1)).max(1),
+        )
+    } else {
+        DEFAULT_CELL_SIZE
+    };
+    let (max_w, max_h) = (
+        u32::from(area.width) * cell_w,
+        u32::from(area.height) * cell_h,
+    );
+    let (w, h) = fit(&app.preview, app.pixel_ratio(), max_w, max_h);
+    let scaled = retroimg::expand(&app.preview, w, h);
+
+    let x = area.x + ((max_w - w) / 2 / cell_w) as u16;
+    let y = area.y + ((max_h - h) / 2 / cell_h) as u16;
+    let mut out = stdout();
+    if app.mode == PreviewMode::Kitty {
+        // replace the previous preview
+        out.write_all(b"\x1b_Ga=d,q=2\x1b\\")?;
+    }
+    out.queue(MoveTo(x, y))?;
+    out.write_all(app.mode.render(&scaled, u32::from(area.width)).as_bytes())?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Draw the interface, returning the area of the preview.
+fn ui(f: &mut Frame, app: &App) -> Rect {
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(20), Constraint::Length(36)])
+        .split(f.size());
+
+    if app.mode == PreviewMode::Blocks {
+        f.render_widget(
+            Preview {
+                image: &app.preview,
+                pixel_ratio: app.pixel_ratio(),
+            },
+            chunks[0],
+        );
+    }
+
+    let pipeline = &app.pipeline;
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let setting = |key: &'static str, name: &'static str, value: String| {
+        Line::from(vec![
+            Span::styled(format!("[{}] ", key), bold),
+            Span::raw(format!("{}: ", name)),
+            Span::raw(value),
+        ])
+    };
+    let mut lines = vec![
+        setting("s/S", "standard", pipeline.standard.to_string()),
+        setting(
+            "c/C",
+            "colors",
+            if pipeline.no_color_limit {
+                "no limit".to_string()
+            } else {
+                pipeline.num_colors.to_string()
+            },
+        ),
+        setting(
+            "r/R",
+            "resolution",
+            match pipeline.resolution {
+                Some([w, h]) => format!("{}x{}", w, h),
+                None => "source".to_string(),
+            },
+        ),
+        setting(
+            "p",
+            "pixel ratio",
+            match app.pixel_ratio {
+                Some([w, h]) => format!("{}:{}", w, h),
+                None => "1:1".to_string(),
+            },
+        ),
+        Line::from(""),
+        Line::from(vec![
+            Span::styled("[n] ", bold),
+            Span::raw("toggle color limit"),
+        ]),
+        Line::from(vec![
+            Span::styled("[e] ", bold),
+            Span::raw("show CLI flags"),
+        ]),
+        Line::from(vec![
+            Span::styled("[w] ", bold),
+            Span::raw(format!("write preset {} to {}", PRESET_NAME, CONFIG_FILE)),
+        ]),
+        Line::from(vec![Span::styled("[q] ", bold), Span::raw("quit")]),
+        Line::from(""),
+    ];
+    lines.push(Line::from(app.message.clone()));
+
+    let settings = Paragraph::new(lines)
+        .wrap(ratatui::widgets::Wrap { trim: false })
+        .block(Block::default().borders(Borders::ALL).title("retroimg"));
+    f.render_widget(settings, chunks[1]);
+    chunks[0]
+}
+
+/// Run the interactive interface on the given image file.
+///
+/// Returns the command line flags of the last settings
+/// when they were exported.
+pub fn run(input: &Path) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let source = Pipeline::default().flatten_input(&image::open(input)?);
+    let mut app = App::new(source);
+
+    enable_raw_mode()?;
+    stdout().execute(EnterAlternateScreen)?;
+    let result = event_loop(&mut app);
+    disable_raw_mode()?;
+    stdout().execute(LeaveAlternateScreen)?;
+    result?;
+
+    Ok(app.exported)
+}
+
+fn event_loop(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    loop {
+        let mut preview_area = Rect::default();
+        terminal.draw(|f| preview_area = ui(f, app))?;
+        if app.mode != PreviewMode::Blocks {
+            draw_graphics(app, preview_area)?;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !app.handle_key(key.code) {
+                return Ok(());
+            }
+            if app.mode != PreviewMode::Blocks {
+                // graphics are not tracked by the interface,
+                // so a smaller preview would leave parts of the last one
+                terminal.clear()?;
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! WebAssembly bindings.
+//!
+//! This module exposes a [`convert`] function to JavaScript,
+//! which takes the bytes of an encoded image file
+//! and returns the bytes of the converted image,
+//! so that the whole process can run in a web browser.
+//! [`convert_pipeline`] does the same
+//! with all the options of a [`Pipeline`],
+//! as written in pipeline files.
+//!
+//! Enable the `wasm` feature and build the dynamic library
+//! for `wasm32-unknown-unknown`
+//! (with `cargo rustc --lib --crate-type cdylib`)
+//! to use it through `wasm-bindgen`.
+use crate::color::{ColorStandard, LossAlgorithm};
+use crate::pipeline::{OutputOptions, Pipeline};
+use image::{ImageFormat, ImageOutputFormat};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+
+/// The options accepted by [`convert`],
+/// as a plain JavaScript object.
+///
+/// All properties are optional.
+/// They mirror the options of the command line application.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ConvertOptions {
+    /// Color standard (e.g. `"vga"`, `"ega"`, `"cga"`)
+    pub standard: Option<String>,
+    /// Crop rectangle `[left, top, width, height]`
+    pub crop: Option<[u32; 4]>,
+    /// Resolution to resize the image into before color reduction,
+    /// as `[width, height]`
+    pub resolution: Option<[u32; 2]>,
+    /// Output image size, as `[width, height]`
+    pub out_size: Option<[u32; 2]>,
+    /// Pixel ratio, as `[width, height]`
+    pub pixel_ratio: Option<[u32; 2]>,
+    /// Output image width (defined separately)
+    pub width: Option<u32>,
+    /// Output image height (defined separately)
+    pub height: Option<u32>,
+    /// Maximum number of simultaneous colors (256 by default)
+    pub num_colors: Option<u32>,
+    /// Do not limit number of simultaneous colors
+    pub no_color_limit: bool,
+    /// Color distance algorithm for loss calculation (`"L1"` or `"L2"`)
+    pub loss: Option<String>,
+    /// Output image format by extension (`"png"` by default)
+    pub format: Option<String>,
+}
+
+/// Convert an encoded image to look like in retro IBM hardware.
+///
+/// `bytes` is the content of an image file in any of the supported formats.
+/// `options` is an optional object (see [`ConvertOptions`]).
+/// Returns the content of the converted image file.
+#[wasm_bindgen]
+pub fn convert(bytes: &[u8], options: JsValue) -> Result<Vec<u8>, JsError> {
+    let options: ConvertOptions = if options.is_undefined() || options.is_null() {
+        ConvertOptions::default()
+    } else {
+        serde_wasm_bindgen::from_value(options).map_err(|e| JsError::new(&e.to_string()))?
+    };
+
+    run(bytes, &Pipeline::try_from(options)?)
+}
+
+/// Convert an encoded image with a full pipeline description.
+///
+/// `pipeline` is an object with the same properties as a pipeline file
+/// (see [`Pipeline`]), such as
+/// `{ standard: "ega", resolution: [320, 200], dither: "bayer4" }`.
+/// The output format is `output.format` (PNG by default).
+#[wasm_bindgen(js_name = convertPipeline)]
+pub fn convert_pipeline(bytes: &[u8], pipeline: JsValue) -> Result<Vec<u8>, JsError> {
+    let pipeline: Pipeline = if pipeline.is_undefined() || pipeline.is_null() {
+        Pipeline::default()
+    } else {
+        serde_wasm_bindgen::from_value(pipeline).map_err(|e| JsError::new(&e.to_string()))?
+    };
+    run(bytes, &pipeline)
+}
+
+/// Decode, convert and encode an image with the given pipeline.
+fn run(bytes: &[u8], pipeline: &Pipeline) -> Result<Vec<u8>, JsError> {
+    let format = match &pipeline.output.format {
+        Some(ext) => ImageFormat::from_extension(ext)
+            .ok_or_else(|| JsError::new("unsupported output format"))?,
+        None => ImageFormat::Png,
+    };
+
+    let img = pipeline.flatten_input(&image::load_from_memory(bytes)?);
+    let img = pipeline.run(&img)?;
+
+    let mut out = Cursor::new(Vec::new());
+    pipeline
+        .key_output(img)
+        .write_to(&mut out, ImageOutputFormat::from(format))?;
+    Ok(out.into_inner())
+}
+
+impl TryFrom<ConvertOptions> for Pipeline {
+    type Error = JsError;
+
+    fn try_from(options: ConvertOptions) -> Result<Self, Self::Error> {
+        let standard = match &options.standard {
+            Some(s) => s.parse::<ColorStandard>()?,
+            None => ColorStandard::default(),
+        };
+        let loss = match &options.loss {
+            Some(s) => s.parse::<LossAlgorithm>()?,
+            None => LossAlgorithm::default(),
+        };
+
+        Ok(Pipeline {
+            crop: options.crop,
+            resolution: options.resolution,
+            standard,
+            num_colors: options.num_colors.unwrap_or(256),
+            no_color_limit: options.no_color_limit,
+            loss,
+            output: OutputOptions {
+                size: options.out_size,
+                pixel_ratio: options.pixel_ratio,
+                width: options.width,
+                height: options.height,
+                format: options.format,
+                ..OutputOptions::default()
+            },
+            ..Pipeline::default()
+        })
+    }
+}